@@ -20,6 +20,7 @@ use garage_util::error::Error as GarageError;
 use crate::generic_server::*;
 
 use crate::admin::bucket::*;
+use crate::admin::changes::*;
 use crate::admin::cluster::*;
 use crate::admin::error::*;
 use crate::admin::key::*;
@@ -121,6 +122,7 @@ impl ApiHandler for AdminApiServer {
 		&self,
 		req: Request<Body>,
 		endpoint: Endpoint,
+		_client_addr: SocketAddr,
 	) -> Result<Response<Body>, Error> {
 		let expected_auth_header =
 			match endpoint.authorization_type() {
@@ -155,6 +157,14 @@ impl ApiHandler for AdminApiServer {
 			Endpoint::UpdateClusterLayout => handle_update_cluster_layout(&self.garage, req).await,
 			Endpoint::ApplyClusterLayout => handle_apply_cluster_layout(&self.garage, req).await,
 			Endpoint::RevertClusterLayout => handle_revert_cluster_layout(&self.garage, req).await,
+			// Metadata
+			Endpoint::CreateMetadataSnapshot => handle_create_metadata_snapshot(&self.garage).await,
+			Endpoint::GetMetadataSync => handle_get_metadata_sync(&self.garage).await,
+			// Change log
+			Endpoint::GetChanges { after, limit } => {
+				handle_get_changes(&self.garage, after, limit).await
+			}
+			Endpoint::StreamChanges { after } => handle_stream_changes(&self.garage, after).await,
 			// Keys
 			Endpoint::ListKeys => handle_list_keys(&self.garage).await,
 			Endpoint::GetKeyInfo { id, search } => {
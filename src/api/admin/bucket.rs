@@ -10,6 +10,7 @@ use garage_util::time::*;
 
 use garage_table::*;
 
+use garage_block::compression::Compression;
 use garage_model::bucket_alias_table::*;
 use garage_model::bucket_table::*;
 use garage_model::garage::Garage;
@@ -85,6 +86,19 @@ struct ApiBucketQuotas {
 	max_objects: Option<u64>,
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiBucketColdStorage {
+	after_days: Option<u32>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiBucketReplication {
+	replication_factor: usize,
+	write_quorum: usize,
+}
+
 pub async fn handle_get_bucket_info(
 	garage: &Arc<Garage>,
 	id: Option<String>,
@@ -182,6 +196,16 @@ async fn bucket_info_results(
 					error_document: wsc.error_document,
 				}
 			}),
+			anonymous_read: *state.anonymous_read.get(),
+			compression: state.compression.get().map(|c| c.to_string()),
+			content_defined_chunking: *state.content_defined_chunking.get(),
+			cold_storage: ApiBucketColdStorage {
+				after_days: *state.cold_storage_after.get(),
+			},
+			replication: state.replication.get().map(|r| ApiBucketReplication {
+				replication_factor: r.replication_factor,
+				write_quorum: r.write_quorum,
+			}),
 			keys: relevant_keys
 				.into_iter()
 				.map(|(_, key)| {
@@ -231,6 +255,11 @@ struct GetBucketInfoResult {
 	website_access: bool,
 	#[serde(default)]
 	website_config: Option<GetBucketInfoWebsiteResult>,
+	anonymous_read: bool,
+	compression: Option<String>,
+	content_defined_chunking: bool,
+	cold_storage: ApiBucketColdStorage,
+	replication: Option<ApiBucketReplication>,
 	keys: Vec<GetBucketInfoKey>,
 	objects: i64,
 	bytes: i64,
@@ -427,6 +456,36 @@ pub async fn handle_update_bucket(
 		}
 	}
 
+	if let Some(ar) = req.anonymous_read {
+		state.anonymous_read.update(ar);
+	}
+
+	if let Some(c) = req.compression {
+		if c == "default" {
+			state.compression.update(None);
+		} else {
+			let compression = c.parse::<Compression>().map_err(Error::bad_request)?;
+			state.compression.update(Some(compression));
+		}
+	}
+
+	if let Some(cdc) = req.content_defined_chunking {
+		state.content_defined_chunking.update(cdc);
+	}
+
+	if let Some(cs) = req.cold_storage {
+		state.cold_storage_after.update(cs.after_days);
+	}
+
+	if let Some(r) = req.replication {
+		if r == "default" {
+			state.replication.update(None);
+		} else {
+			let replication = garage.bucket_helper().parse_block_replication(&r)?;
+			state.replication.update(Some(replication));
+		}
+	}
+
 	if let Some(q) = req.quotas {
 		state.quotas.update(BucketQuotas {
 			max_size: q.max_size,
@@ -443,6 +502,11 @@ pub async fn handle_update_bucket(
 #[serde(rename_all = "camelCase")]
 struct UpdateBucketRequest {
 	website_access: Option<UpdateBucketWebsiteAccess>,
+	anonymous_read: Option<bool>,
+	compression: Option<String>,
+	content_defined_chunking: Option<bool>,
+	cold_storage: Option<ApiBucketColdStorage>,
+	replication: Option<String>,
 	quotas: Option<ApiBucketQuotas>,
 }
 
@@ -0,0 +1,150 @@
+use std::sync::Arc;
+
+use futures::stream::{self, Stream};
+use hyper::{Body, Response};
+use serde::Serialize;
+use tokio::sync::watch;
+
+use garage_util::error::Error as GarageError;
+use garage_util::time::msec_to_rfc3339;
+
+use garage_table::change_log::{Change, ChangeLog};
+
+use garage_model::garage::Garage;
+
+use crate::admin::error::*;
+use crate::helpers::json_ok_response;
+
+const DEFAULT_CHANGES_LIMIT: usize = 1000;
+const MAX_CHANGES_LIMIT: usize = 10000;
+
+pub async fn handle_get_changes(
+	garage: &Arc<Garage>,
+	after: Option<u64>,
+	limit: Option<usize>,
+) -> Result<Response<Body>, Error> {
+	let limit = limit
+		.unwrap_or(DEFAULT_CHANGES_LIMIT)
+		.clamp(1, MAX_CHANGES_LIMIT);
+
+	let changes = garage.change_log.read(after, limit)?;
+
+	let res = GetChangesResponse {
+		node: hex::encode(garage.system.id),
+		cursor: changes.last().map(|(seq, _)| *seq).or(after),
+		changes: changes
+			.into_iter()
+			.map(|(seq, change)| ChangeResp::new(seq, change))
+			.collect(),
+	};
+
+	Ok(json_ok_response(&res)?)
+}
+
+/// Stream the changes of the change log of this node, starting after
+/// the change with sequence number `after`, as newline-delimited JSON objects.
+/// The response never ends: new changes are sent as they are recorded.
+pub async fn handle_stream_changes(
+	garage: &Arc<Garage>,
+	after: Option<u64>,
+) -> Result<Response<Body>, Error> {
+	let stream = change_stream(garage.change_log.clone(), after);
+
+	Ok(Response::builder()
+		.status(hyper::StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/x-ndjson")
+		.body(Body::wrap_stream(stream))?)
+}
+
+fn change_stream(
+	change_log: Arc<ChangeLog>,
+	after: Option<u64>,
+) -> impl Stream<Item = Result<String, GarageError>> {
+	let state = ChangeStreamState {
+		last_seq: change_log.watch_last_seq(),
+		change_log,
+		after,
+		failed: false,
+	};
+
+	stream::unfold(state, |mut state| async move {
+		if state.failed {
+			return None;
+		}
+		loop {
+			let changes = match state.change_log.read(state.after, DEFAULT_CHANGES_LIMIT) {
+				Ok(changes) => changes,
+				Err(e) => {
+					state.failed = true;
+					return Some((Err(e), state));
+				}
+			};
+
+			if let Some((seq, _)) = changes.last() {
+				state.after = Some(*seq);
+				let mut lines = String::new();
+				for (seq, change) in changes {
+					match serde_json::to_string(&ChangeResp::new(seq, change)) {
+						Ok(line) => {
+							lines.push_str(&line);
+							lines.push('\n');
+						}
+						Err(e) => {
+							state.failed = true;
+							return Some((Err(e.into()), state));
+						}
+					}
+				}
+				return Some((Ok(lines), state));
+			}
+
+			// No new changes: wait until a change is recorded in the log
+			if state.last_seq.changed().await.is_err() {
+				return None;
+			}
+		}
+	})
+}
+
+struct ChangeStreamState {
+	change_log: Arc<ChangeLog>,
+	last_seq: watch::Receiver<u64>,
+	after: Option<u64>,
+	failed: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GetChangesResponse {
+	node: String,
+	cursor: Option<u64>,
+	changes: Vec<ChangeResp>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ChangeResp {
+	seq: u64,
+	timestamp: String,
+	table: String,
+	partition_hash: String,
+	sort_key: Option<String>,
+	sort_key_hex: String,
+	old_tombstone: Option<bool>,
+	new_tombstone: Option<bool>,
+}
+
+impl ChangeResp {
+	fn new(seq: u64, change: Change) -> Self {
+		Self {
+			seq,
+			timestamp: msec_to_rfc3339(change.timestamp),
+			table: change.table,
+			partition_hash: hex::encode(change.partition),
+			sort_key_hex: hex::encode(&change.sort_key),
+			sort_key: String::from_utf8(change.sort_key.into_vec()).ok(),
+			old_tombstone: change.old_tombstone,
+			new_tombstone: change.new_tombstone,
+		}
+	}
+}
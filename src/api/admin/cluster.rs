@@ -7,10 +7,15 @@ use serde::{Deserialize, Serialize};
 
 use garage_util::crdt::*;
 use garage_util::data::*;
+use garage_util::time::msec_to_rfc3339;
 
 use garage_rpc::layout::*;
 
+use garage_table::replication::TableReplication;
+use garage_table::*;
+
 use garage_model::garage::Garage;
+use garage_model::snapshot::snapshot_metadata;
 
 use crate::admin::error::*;
 use crate::helpers::{json_ok_response, parse_json_body};
@@ -191,3 +196,92 @@ type UpdateClusterLayoutRequest = HashMap<String, Option<NodeRole>>;
 struct ApplyRevertLayoutRequest {
 	version: u64,
 }
+
+// ---- METADATA SNAPSHOTS ----
+
+pub async fn handle_create_metadata_snapshot(
+	garage: &Arc<Garage>,
+) -> Result<Response<Body>, Error> {
+	let path = snapshot_metadata(garage).await?;
+
+	let res = CreateMetadataSnapshotResponse {
+		node: hex::encode(garage.system.id),
+		path: path.to_string_lossy().to_string(),
+	};
+
+	Ok(json_ok_response(&res)?)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateMetadataSnapshotResponse {
+	node: String,
+	path: String,
+}
+
+pub async fn handle_get_metadata_sync(garage: &Arc<Garage>) -> Result<Response<Body>, Error> {
+	#[allow(unused_mut)]
+	let mut tables = vec![
+		table_sync_status(&garage.bucket_table),
+		table_sync_status(&garage.bucket_alias_table),
+		table_sync_status(&garage.key_table),
+		table_sync_status(&garage.object_table),
+		table_sync_status(&garage.object_counter_table.table),
+		table_sync_status(&garage.version_table),
+		table_sync_status(&garage.block_ref_table),
+	];
+	#[cfg(feature = "k2v")]
+	{
+		tables.push(table_sync_status(&garage.k2v.item_table));
+		tables.push(table_sync_status(&garage.k2v.counter_table.table));
+	}
+
+	let res = GetMetadataSyncResponse {
+		node: hex::encode(garage.system.id),
+		tables,
+	};
+
+	Ok(json_ok_response(&res)?)
+}
+
+fn table_sync_status<F, R>(table: &Table<F, R>) -> TableSyncResp
+where
+	F: TableSchema + 'static,
+	R: TableReplication + 'static,
+{
+	TableSyncResp {
+		table: F::TABLE_NAME.to_string(),
+		partitions: table
+			.syncer
+			.sync_status()
+			.into_iter()
+			.map(|p| PartitionSyncResp {
+				partition: p.partition,
+				last_sync: p.last_sync.map(msec_to_rfc3339),
+				last_change: p.last_change.map(msec_to_rfc3339),
+			})
+			.collect(),
+	}
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GetMetadataSyncResponse {
+	node: String,
+	tables: Vec<TableSyncResp>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TableSyncResp {
+	table: String,
+	partitions: Vec<PartitionSyncResp>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PartitionSyncResp {
+	partition: u16,
+	last_sync: Option<String>,
+	last_change: Option<String>,
+}
@@ -3,5 +3,6 @@ mod error;
 mod router;
 
 mod bucket;
+mod changes;
 mod cluster;
 mod key;
@@ -24,6 +24,17 @@ pub enum Endpoint {
 	UpdateClusterLayout,
 	ApplyClusterLayout,
 	RevertClusterLayout,
+	// Metadata
+	CreateMetadataSnapshot,
+	GetMetadataSync,
+	// Change log
+	GetChanges {
+		after: Option<u64>,
+		limit: Option<usize>,
+	},
+	StreamChanges {
+		after: Option<u64>,
+	},
 	// Keys
 	ListKeys,
 	CreateKey,
@@ -96,6 +107,12 @@ impl Endpoint {
 			POST "/v0/layout" => UpdateClusterLayout,
 			POST "/v0/layout/apply" => ApplyClusterLayout,
 			POST "/v0/layout/revert" => RevertClusterLayout,
+			// Metadata endpoints
+			POST "/v0/metadata/snapshot" => CreateMetadataSnapshot,
+			GET "/v0/metadata/sync" => GetMetadataSync,
+			// Change log endpoints
+			GET "/v0/changes" => GetChanges (opt_parse::after, opt_parse::limit),
+			GET "/v0/changes/stream" => StreamChanges (opt_parse::after),
 			// API key endpoints
 			GET "/v0/key" if id => GetKeyInfo (query_opt::id, query_opt::search),
 			GET "/v0/key" if search => GetKeyInfo (query_opt::id, query_opt::search),
@@ -141,5 +158,7 @@ generateQueryParameters! {
 	"search" => search,
 	"globalAlias" => global_alias,
 	"alias" => alias,
-	"accessKeyId" => access_key_id
+	"accessKeyId" => access_key_id,
+	"after" => after,
+	"limit" => limit
 }
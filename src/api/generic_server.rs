@@ -45,6 +45,7 @@ pub(crate) trait ApiHandler: Send + Sync + 'static {
 		&self,
 		req: Request<Body>,
 		endpoint: Self::Endpoint,
+		client_addr: SocketAddr,
 	) -> Result<Response<Body>, Self::Error>;
 }
 
@@ -139,7 +140,7 @@ impl<A: ApiHandler> ApiServer<A> {
 			.start(&tracer);
 
 		let res = self
-			.handler_stage2(req)
+			.handler_stage2(req, addr)
 			.with_context(Context::current_with_span(span))
 			.await;
 
@@ -168,7 +169,11 @@ impl<A: ApiHandler> ApiServer<A> {
 		}
 	}
 
-	async fn handler_stage2(&self, req: Request<Body>) -> Result<Response<Body>, A::Error> {
+	async fn handler_stage2(
+		&self,
+		req: Request<Body>,
+		addr: SocketAddr,
+	) -> Result<Response<Body>, A::Error> {
 		let endpoint = self.api_handler.parse_endpoint(&req)?;
 		debug!("Endpoint: {}", endpoint.name());
 
@@ -186,7 +191,7 @@ impl<A: ApiHandler> ApiServer<A> {
 
 		let res = self
 			.api_handler
-			.handle(req, endpoint)
+			.handle(req, endpoint, addr)
 			.record_duration(&self.request_duration, &metrics_tags[..])
 			.await;
 
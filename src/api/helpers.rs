@@ -1,3 +1,4 @@
+use hyper::body::{Bytes, HttpBody};
 use hyper::{Body, Request, Response};
 use idna::domain_to_unicode;
 use serde::{Deserialize, Serialize};
@@ -144,6 +145,22 @@ pub async fn parse_json_body<T: for<'de> Deserialize<'de>>(req: Request<Body>) -
 	Ok(resp)
 }
 
+/// Read the whole body of a request, failing if it is larger than `max_size` bytes
+pub async fn read_body_limited(mut body: Body, max_size: usize) -> Result<Bytes, Error> {
+	let mut buf = Vec::new();
+	while let Some(chunk) = body.data().await {
+		let chunk = chunk?;
+		if buf.len() + chunk.len() > max_size {
+			return Err(Error::bad_request(format!(
+				"Request body is too large (maximum: {} bytes)",
+				max_size
+			)));
+		}
+		buf.extend_from_slice(&chunk);
+	}
+	Ok(buf.into())
+}
+
 pub fn json_ok_response<T: Serialize>(res: &T) -> Result<Response<Body>, Error> {
 	let resp_json = serde_json::to_string_pretty(res).map_err(garage_util::error::Error::from)?;
 	Ok(Response::builder()
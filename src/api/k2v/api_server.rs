@@ -68,6 +68,7 @@ impl ApiHandler for K2VApiServer {
 		&self,
 		req: Request<Body>,
 		endpoint: K2VApiEndpoint,
+		_client_addr: SocketAddr,
 	) -> Result<Response<Body>, Error> {
 		let K2VApiEndpoint {
 			bucket_name,
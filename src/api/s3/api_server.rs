@@ -26,10 +26,15 @@ use crate::s3::copy::*;
 use crate::s3::cors::*;
 use crate::s3::delete::*;
 use crate::s3::get::*;
+use crate::s3::lifecycle::*;
 use crate::s3::list::*;
+use crate::s3::object_lock::*;
+use crate::s3::policy::*;
 use crate::s3::post_object::handle_post_object;
 use crate::s3::put::*;
 use crate::s3::router::Endpoint;
+use crate::s3::select::handle_select_object_content;
+use crate::s3::tagging::*;
 use crate::s3::website::*;
 
 pub struct S3ApiServer {
@@ -64,6 +69,116 @@ impl S3ApiServer {
 			endpoint => Err(Error::NotImplemented(endpoint.name().to_owned())),
 		}
 	}
+
+	/// Handle a request that is not signed with an access key. Such requests
+	/// can only read and list the objects of buckets that have anonymous
+	/// read access enabled, and are referred to by one of their global aliases.
+	async fn handle_anonymous_request(
+		&self,
+		req: Request<Body>,
+		bucket_name: Option<String>,
+		endpoint: Endpoint,
+		client_addr: SocketAddr,
+	) -> Result<Response<Body>, Error> {
+		let forbidden = || Error::forbidden("Anonymous access is not allowed for this request");
+
+		let bucket_name = bucket_name.ok_or_else(forbidden)?;
+		let bucket_id = self
+			.garage
+			.bucket_helper()
+			.resolve_global_bucket_name(&bucket_name)
+			.await?
+			.ok_or_else(forbidden)?;
+		let bucket = self
+			.garage
+			.bucket_helper()
+			.get_existing_bucket(bucket_id)
+			.await?;
+
+		RequestAuthorization::anonymous(&bucket, client_addr.ip())
+			.check_endpoint(&endpoint)
+			.map_err(|_| forbidden())?;
+
+		let matching_cors_rule = find_matching_cors_rule(&bucket, &req)?;
+
+		let garage = self.garage.clone();
+		let resp = match endpoint {
+			Endpoint::HeadObject {
+				key,
+				part_number,
+				version_id: None,
+			} => handle_head(garage, &req, bucket_id, &key, None, part_number).await,
+			Endpoint::GetObject {
+				key,
+				part_number,
+				version_id: None,
+			} => handle_get(garage, &req, &bucket, &key, None, part_number).await,
+			Endpoint::ListObjects {
+				delimiter,
+				encoding_type,
+				marker,
+				max_keys,
+				prefix,
+			} => {
+				handle_list(
+					garage,
+					&ListObjectsQuery {
+						common: ListQueryCommon {
+							bucket_name,
+							bucket_id,
+							delimiter: delimiter.map(|d| d.to_string()),
+							page_size: max_keys.map(|p| p.clamp(1, 1000)).unwrap_or(1000),
+							prefix: prefix.unwrap_or_default(),
+							urlencode_resp: encoding_type.map(|e| e == "url").unwrap_or(false),
+						},
+						is_v2: false,
+						marker,
+						continuation_token: None,
+						start_after: None,
+					},
+				)
+				.await
+			}
+			Endpoint::ListObjectsV2 {
+				delimiter,
+				encoding_type,
+				max_keys,
+				prefix,
+				continuation_token,
+				start_after,
+				list_type,
+				..
+			} if list_type == "2" => {
+				handle_list(
+					garage,
+					&ListObjectsQuery {
+						common: ListQueryCommon {
+							bucket_name,
+							bucket_id,
+							delimiter: delimiter.map(|d| d.to_string()),
+							page_size: max_keys.map(|p| p.clamp(1, 1000)).unwrap_or(1000),
+							urlencode_resp: encoding_type.map(|e| e == "url").unwrap_or(false),
+							prefix: prefix.unwrap_or_default(),
+						},
+						is_v2: true,
+						marker: None,
+						continuation_token,
+						start_after,
+					},
+				)
+				.await
+			}
+			_ => Err(forbidden()),
+		};
+
+		let mut resp_ok = resp?;
+		if let Some(rule) = matching_cors_rule {
+			add_cors_headers(&mut resp_ok, rule)
+				.ok_or_internal_error("Invalid bucket CORS configuration")?;
+		}
+
+		Ok(resp_ok)
+	}
 }
 
 #[async_trait]
@@ -104,6 +219,7 @@ impl ApiHandler for S3ApiServer {
 		&self,
 		req: Request<Body>,
 		endpoint: S3ApiEndpoint,
+		client_addr: SocketAddr,
 	) -> Result<Response<Body>, Error> {
 		let S3ApiEndpoint {
 			bucket_name,
@@ -113,15 +229,21 @@ impl ApiHandler for S3ApiServer {
 
 		// Some endpoints are processed early, before we even check for an API key
 		if let Endpoint::PostObject = endpoint {
-			return handle_post_object(garage, req, bucket_name.unwrap()).await;
+			return handle_post_object(garage, req, bucket_name.unwrap(), client_addr).await;
 		}
 		if let Endpoint::Options = endpoint {
 			return handle_options_s3api(garage, &req, bucket_name).await;
 		}
 
 		let (api_key, mut content_sha256) = check_payload_signature(&garage, "s3", &req).await?;
-		let api_key = api_key
-			.ok_or_else(|| Error::forbidden("Garage does not support anonymous access yet"))?;
+		let api_key = match api_key {
+			Some(api_key) => api_key,
+			None => {
+				return self
+					.handle_anonymous_request(req, bucket_name, endpoint, client_addr)
+					.await
+			}
+		};
 
 		let req = parse_streaming_body(
 			&api_key,
@@ -154,26 +276,47 @@ impl ApiHandler for S3ApiServer {
 			.get_existing_bucket(bucket_id)
 			.await?;
 
-		let allowed = match endpoint.authorization_type() {
-			Authorization::Read => api_key.allow_read(&bucket_id),
-			Authorization::Write => api_key.allow_write(&bucket_id),
-			Authorization::Owner => api_key.allow_owner(&bucket_id),
-			_ => unreachable!(),
-		};
-
-		if !allowed {
-			return Err(Error::forbidden("Operation is not allowed for this key."));
+		let authorization = RequestAuthorization::new(&api_key, &bucket, client_addr.ip());
+		match endpoint {
+			// For DeleteObjects, the bucket policy is evaluated
+			// for each of the objects to be deleted
+			Endpoint::DeleteObjects {} if authorization.has_policy() => (),
+			_ => authorization.check_endpoint(&endpoint)?,
 		}
 
 		let matching_cors_rule = find_matching_cors_rule(&bucket, &req)?;
 
 		let resp = match endpoint {
 			Endpoint::HeadObject {
-				key, part_number, ..
-			} => handle_head(garage, &req, bucket_id, &key, part_number).await,
+				key,
+				part_number,
+				version_id,
+			} => {
+				handle_head(
+					garage,
+					&req,
+					bucket_id,
+					&key,
+					version_id.as_deref(),
+					part_number,
+				)
+				.await
+			}
 			Endpoint::GetObject {
-				key, part_number, ..
-			} => handle_get(garage, &req, bucket_id, &key, part_number).await,
+				key,
+				part_number,
+				version_id,
+			} => {
+				handle_get(
+					garage,
+					&req,
+					&bucket,
+					&key,
+					version_id.as_deref(),
+					part_number,
+				)
+				.await
+			}
 			Endpoint::UploadPart {
 				key,
 				part_number,
@@ -182,7 +325,7 @@ impl ApiHandler for S3ApiServer {
 				handle_put_part(
 					garage,
 					req,
-					bucket_id,
+					&bucket,
 					&key,
 					part_number,
 					&upload_id,
@@ -191,7 +334,7 @@ impl ApiHandler for S3ApiServer {
 				.await
 			}
 			Endpoint::CopyObject { key } => {
-				handle_copy(garage, &api_key, &req, bucket_id, &key).await
+				handle_copy(garage, &api_key, &req, client_addr.ip(), &bucket, &key).await
 			}
 			Endpoint::UploadPartCopy {
 				key,
@@ -202,7 +345,8 @@ impl ApiHandler for S3ApiServer {
 					garage,
 					&api_key,
 					&req,
-					bucket_id,
+					client_addr.ip(),
+					&bucket,
 					&key,
 					part_number,
 					&upload_id,
@@ -215,9 +359,78 @@ impl ApiHandler for S3ApiServer {
 			Endpoint::AbortMultipartUpload { key, upload_id } => {
 				handle_abort_multipart_upload(garage, bucket_id, &key, &upload_id).await
 			}
-			Endpoint::DeleteObject { key, .. } => handle_delete(garage, bucket_id, &key).await,
+			Endpoint::DeleteObject { key, version_id } => {
+				let bypass_governance =
+					bypass_governance_retention(req.headers(), &api_key, &bucket_id);
+				handle_delete(
+					garage,
+					&bucket,
+					&key,
+					version_id.as_deref(),
+					bypass_governance,
+				)
+				.await
+			}
+			Endpoint::SelectObjectContent { key, select_type } => {
+				if select_type == "2" {
+					handle_select_object_content(garage, req, &bucket, &key, content_sha256).await
+				} else {
+					Err(Error::bad_request(format!(
+						"Invalid endpoint: select-type={}",
+						select_type
+					)))
+				}
+			}
+			Endpoint::GetObjectTagging { key, version_id } => {
+				handle_get_object_tagging(garage, bucket_id, &key, version_id.as_deref()).await
+			}
+			Endpoint::PutObjectTagging { key, version_id } => {
+				handle_put_object_tagging(
+					garage,
+					req,
+					bucket_id,
+					&key,
+					version_id.as_deref(),
+					content_sha256,
+				)
+				.await
+			}
+			Endpoint::DeleteObjectTagging { key, version_id } => {
+				handle_delete_object_tagging(garage, bucket_id, &key, version_id.as_deref()).await
+			}
+			Endpoint::GetObjectRetention { key, version_id } => {
+				handle_get_object_retention(garage, bucket_id, &key, version_id.as_deref()).await
+			}
+			Endpoint::PutObjectRetention { key, version_id } => {
+				let bypass_governance =
+					bypass_governance_retention(req.headers(), &api_key, &bucket_id);
+				handle_put_object_retention(
+					garage,
+					req,
+					&bucket,
+					&key,
+					version_id.as_deref(),
+					bypass_governance,
+					content_sha256,
+				)
+				.await
+			}
+			Endpoint::GetObjectLegalHold { key, version_id } => {
+				handle_get_object_legal_hold(garage, bucket_id, &key, version_id.as_deref()).await
+			}
+			Endpoint::PutObjectLegalHold { key, version_id } => {
+				handle_put_object_legal_hold(
+					garage,
+					req,
+					&bucket,
+					&key,
+					version_id.as_deref(),
+					content_sha256,
+				)
+				.await
+			}
 			Endpoint::CreateMultipartUpload { key } => {
-				handle_create_multipart_upload(garage, &req, &bucket_name, bucket_id, &key).await
+				handle_create_multipart_upload(garage, &req, &bucket_name, &bucket, &key).await
 			}
 			Endpoint::CompleteMultipartUpload { key, upload_id } => {
 				handle_complete_multipart_upload(
@@ -241,7 +454,10 @@ impl ApiHandler for S3ApiServer {
 				handle_delete_bucket(&garage, bucket_id, bucket_name, api_key).await
 			}
 			Endpoint::GetBucketLocation {} => handle_get_bucket_location(garage),
-			Endpoint::GetBucketVersioning {} => handle_get_bucket_versioning(),
+			Endpoint::GetBucketVersioning {} => handle_get_bucket_versioning(&bucket),
+			Endpoint::PutBucketVersioning {} => {
+				handle_put_bucket_versioning(garage, bucket_id, req, content_sha256).await
+			}
 			Endpoint::ListObjects {
 				delimiter,
 				encoding_type,
@@ -329,6 +545,31 @@ impl ApiHandler for S3ApiServer {
 				)
 				.await
 			}
+			Endpoint::ListObjectVersions {
+				delimiter,
+				encoding_type,
+				key_marker,
+				max_keys,
+				prefix,
+				version_id_marker,
+			} => {
+				handle_list_object_versions(
+					garage,
+					&ListObjectVersionsQuery {
+						common: ListQueryCommon {
+							bucket_name,
+							bucket_id,
+							delimiter: delimiter.map(|d| d.to_string()),
+							page_size: max_keys.map(|p| p.clamp(1, 1000)).unwrap_or(1000) as usize,
+							prefix: prefix.unwrap_or_default(),
+							urlencode_resp: encoding_type.map(|e| e == "url").unwrap_or(false),
+						},
+						key_marker,
+						version_id_marker,
+					},
+				)
+				.await
+			}
 			Endpoint::ListParts {
 				key,
 				max_parts,
@@ -349,8 +590,23 @@ impl ApiHandler for S3ApiServer {
 				.await
 			}
 			Endpoint::DeleteObjects {} => {
-				handle_delete_objects(garage, bucket_id, req, content_sha256).await
+				let bypass_governance =
+					bypass_governance_retention(req.headers(), &api_key, &bucket_id);
+				handle_delete_objects(
+					garage,
+					&bucket,
+					&authorization,
+					req,
+					bypass_governance,
+					content_sha256,
+				)
+				.await
+			}
+			Endpoint::GetBucketPolicy {} => handle_get_bucket_policy(&bucket, &bucket_name).await,
+			Endpoint::PutBucketPolicy {} => {
+				handle_put_bucket_policy(garage, bucket_id, req, content_sha256).await
 			}
+			Endpoint::DeleteBucketPolicy {} => handle_delete_bucket_policy(garage, bucket_id).await,
 			Endpoint::GetBucketWebsite {} => handle_get_website(&bucket).await,
 			Endpoint::PutBucketWebsite {} => {
 				handle_put_website(garage, bucket_id, req, content_sha256).await
@@ -361,6 +617,15 @@ impl ApiHandler for S3ApiServer {
 				handle_put_cors(garage, bucket_id, req, content_sha256).await
 			}
 			Endpoint::DeleteBucketCors {} => handle_delete_cors(garage, bucket_id).await,
+			Endpoint::GetObjectLockConfiguration {} => handle_get_object_lock_config(&bucket).await,
+			Endpoint::PutObjectLockConfiguration {} => {
+				handle_put_object_lock_config(garage, bucket_id, req, content_sha256).await
+			}
+			Endpoint::GetBucketLifecycleConfiguration {} => handle_get_lifecycle(&bucket).await,
+			Endpoint::PutBucketLifecycleConfiguration {} => {
+				handle_put_lifecycle(garage, bucket_id, req, content_sha256).await
+			}
+			Endpoint::DeleteBucketLifecycle {} => handle_delete_lifecycle(garage, bucket_id).await,
 			endpoint => Err(Error::NotImplemented(endpoint.name().to_owned())),
 		};
 
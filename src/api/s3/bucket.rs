@@ -2,9 +2,10 @@ use std::collections::HashMap;
 use std::sync::Arc;
 
 use hyper::{Body, Request, Response, StatusCode};
+use quick_xml::de::from_reader;
 
 use garage_model::bucket_alias_table::*;
-use garage_model::bucket_table::Bucket;
+use garage_model::bucket_table::{Bucket, BucketVersioning};
 use garage_model::garage::Garage;
 use garage_model::key_table::Key;
 use garage_model::permission::BucketKeyPerm;
@@ -30,11 +31,17 @@ pub fn handle_get_bucket_location(garage: Arc<Garage>) -> Result<Response<Body>,
 		.body(Body::from(xml.into_bytes()))?)
 }
 
-pub fn handle_get_bucket_versioning() -> Result<Response<Body>, Error> {
-	let versioning = s3_xml::VersioningConfiguration {
-		xmlns: (),
-		status: None,
+pub fn handle_get_bucket_versioning(bucket: &Bucket) -> Result<Response<Body>, Error> {
+	let param = bucket
+		.params()
+		.ok_or_internal_error("Bucket should not be deleted at this point")?;
+
+	let status = match param.versioning.get() {
+		BucketVersioning::Unversioned => None,
+		BucketVersioning::Enabled => Some(s3_xml::Value("Enabled".to_string())),
+		BucketVersioning::Suspended => Some(s3_xml::Value("Suspended".to_string())),
 	};
+	let versioning = s3_xml::VersioningConfiguration { xmlns: (), status };
 
 	let xml = s3_xml::to_xml_with_header(&versioning)?;
 
@@ -43,6 +50,50 @@ pub fn handle_get_bucket_versioning() -> Result<Response<Body>, Error> {
 		.body(Body::from(xml.into_bytes()))?)
 }
 
+pub async fn handle_put_bucket_versioning(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	req: Request<Body>,
+	content_sha256: Option<Hash>,
+) -> Result<Response<Body>, Error> {
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	let conf: s3_xml::VersioningConfiguration = from_reader(&body as &[u8])?;
+	let new_state = match conf.status.as_ref().map(|s| s.0.as_str()) {
+		Some("Enabled") => BucketVersioning::Enabled,
+		Some("Suspended") => BucketVersioning::Suspended,
+		_ => {
+			return Err(Error::bad_request(
+				"Bad XML: versioning status must be Enabled or Suspended",
+			))
+		}
+	};
+
+	let mut bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	let param = bucket.params_mut().unwrap();
+
+	if param.object_lock_enabled.get() && new_state != BucketVersioning::Enabled {
+		return Err(Error::InvalidBucketState(
+			"Versioning cannot be suspended on a bucket with Object Lock enabled".into(),
+		));
+	}
+
+	param.versioning.update(new_state);
+	garage.bucket_table.insert(&bucket).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(Body::empty())?)
+}
+
 pub async fn handle_list_buckets(garage: &Garage, api_key: &Key) -> Result<Response<Body>, Error> {
 	let key_p = api_key.params().ok_or_internal_error(
 		"Key should not be in deleted state at this point (in handle_list_buckets)",
@@ -119,6 +170,12 @@ pub async fn handle_create_bucket(
 	api_key: Key,
 	bucket_name: String,
 ) -> Result<Response<Body>, Error> {
+	let object_lock_enabled = req
+		.headers()
+		.get("x-amz-bucket-object-lock-enabled")
+		.map(|h| h == "true")
+		.unwrap_or(false);
+
 	let body = hyper::body::to_bytes(req.into_body()).await?;
 
 	if let Some(content_sha256) = content_sha256 {
@@ -169,7 +226,13 @@ pub async fn handle_create_bucket(
 			)));
 		}
 
-		let bucket = Bucket::new();
+		let mut bucket = Bucket::new();
+		if object_lock_enabled {
+			// Object Lock requires versioning to be enabled
+			let param = bucket.params_mut().unwrap();
+			param.object_lock_enabled.set();
+			param.versioning.update(BucketVersioning::Enabled);
+		}
 		garage.bucket_table.insert(&bucket).await?;
 
 		garage
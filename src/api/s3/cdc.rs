@@ -0,0 +1,149 @@
+//! Content-defined chunking of object data, using the FastCDC algorithm.
+//!
+//! Cut points between blocks are chosen depending on the content of the data
+//! (using a rolling "gear" hash), instead of at fixed offsets, so that identical
+//! data stored in different objects at different offsets is split in identical
+//! blocks, which are stored only once.
+
+/// Gear hash table, made of pseudo-random values generated from a fixed seed.
+/// It must never change, otherwise blocks of data written by different versions
+/// of Garage would not be deduplicated.
+static GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+	// SplitMix64
+	let mut table = [0u64; 256];
+	let mut state: u64 = 0x6761_7261_6765_6364;
+	let mut i = 0;
+	while i < 256 {
+		state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+		let mut z = state;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+		table[i] = z ^ (z >> 31);
+		i += 1;
+	}
+	table
+}
+
+/// Parameters for content-defined chunking
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FastCdc {
+	min_size: usize,
+	avg_size: usize,
+	max_size: usize,
+	/// Mask used before reaching the average size, with more bits set
+	/// to make cut points less likely
+	mask_small: u64,
+	/// Mask used after reaching the average size, with fewer bits set
+	/// to make cut points more likely
+	mask_large: u64,
+}
+
+impl FastCdc {
+	/// Chunking that produces blocks of at most `max_size` bytes,
+	/// and of about a quarter of that size on average.
+	/// Blocks are never smaller than `min_size` bytes, except at the end of the data.
+	pub(crate) fn new(max_size: usize, min_size: usize) -> Self {
+		let avg_size = std::cmp::max(max_size / 4, 1);
+		let bits = (usize::BITS - 1 - avg_size.leading_zeros()) as usize;
+		Self {
+			min_size: std::cmp::min(std::cmp::max(avg_size / 4, min_size), avg_size),
+			avg_size,
+			max_size,
+			mask_small: high_bits_mask(bits + 2),
+			mask_large: high_bits_mask(bits.saturating_sub(2)),
+		}
+	}
+
+	/// Length of the first chunk of `data`. If the end of the data is
+	/// reached before a cut point is found, the whole data is one chunk:
+	/// `data` must therefore contain at least `max_size` bytes,
+	/// unless it is the end of the stream.
+	pub(crate) fn cut_point(&self, data: &[u8]) -> usize {
+		if data.len() <= self.min_size {
+			return data.len();
+		}
+		let end = std::cmp::min(data.len(), self.max_size);
+		let normal = std::cmp::min(self.avg_size, end);
+
+		let mut hash = 0u64;
+		for (i, b) in data.iter().enumerate().take(normal).skip(self.min_size) {
+			hash = (hash << 1).wrapping_add(GEAR[*b as usize]);
+			if hash & self.mask_small == 0 {
+				return i + 1;
+			}
+		}
+		for (i, b) in data.iter().enumerate().take(end).skip(normal) {
+			hash = (hash << 1).wrapping_add(GEAR[*b as usize]);
+			if hash & self.mask_large == 0 {
+				return i + 1;
+			}
+		}
+		end
+	}
+}
+
+/// Mask with the `n` most significant bits set: the gear hash is shifted
+/// left at each byte, so its high bits depend on the most bytes
+fn high_bits_mask(n: usize) -> u64 {
+	match n {
+		0 => 0,
+		n if n >= 64 => !0,
+		n => !0u64 << (64 - n),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn pseudo_random(len: usize, seed: u32) -> Vec<u8> {
+		let mut x = seed;
+		(0..len)
+			.map(|_| {
+				x = x.wrapping_mul(1103515245).wrapping_add(12345);
+				(x >> 16) as u8
+			})
+			.collect()
+	}
+
+	fn chunks(cdc: &FastCdc, mut data: &[u8]) -> Vec<Vec<u8>> {
+		let mut ret = vec![];
+		while !data.is_empty() {
+			let len = cdc.cut_point(data);
+			ret.push(data[..len].to_vec());
+			data = &data[len..];
+		}
+		ret
+	}
+
+	#[test]
+	fn test_chunk_sizes() {
+		let cdc = FastCdc::new(64 * 1024, 0);
+		let data = pseudo_random(4 * 1024 * 1024, 1);
+		let chunks = chunks(&cdc, &data);
+
+		assert_eq!(chunks.concat(), data);
+		for c in chunks[..chunks.len() - 1].iter() {
+			assert!(c.len() >= cdc.min_size && c.len() <= cdc.max_size);
+		}
+		let avg = data.len() / chunks.len();
+		assert!(avg > cdc.avg_size / 2 && avg < cdc.avg_size * 2);
+	}
+
+	#[test]
+	fn test_shifted_data() {
+		let cdc = FastCdc::new(64 * 1024, 0);
+		let data = pseudo_random(2 * 1024 * 1024, 2);
+		let mut shifted = b"a few more bytes".to_vec();
+		shifted.extend_from_slice(&data);
+
+		let chunks1 = chunks(&cdc, &data);
+		let chunks2 = chunks(&cdc, &shifted);
+
+		// Once the shift has been absorbed, cut points are the same
+		let common = chunks1.iter().filter(|c| chunks2.contains(c)).count();
+		assert!(common >= chunks1.len() - 2);
+	}
+}
@@ -1,3 +1,4 @@
+use std::net::IpAddr;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
@@ -12,43 +13,67 @@ use serde::Serialize;
 use garage_rpc::netapp::bytes_buf::BytesBuf;
 use garage_rpc::rpc_helper::OrderTag;
 use garage_table::*;
+use garage_util::crdt::Lww;
 use garage_util::data::*;
 use garage_util::time::*;
 
+use garage_block::manager::{BlockReplication, BlockWriteParams};
+use garage_model::bucket_table::Bucket;
 use garage_model::garage::Garage;
 use garage_model::key_table::Key;
 use garage_model::s3::block_ref_table::*;
 use garage_model::s3::object_table::*;
 use garage_model::s3::version_table::*;
 
-use crate::helpers::parse_bucket_key;
+use crate::helpers::{parse_bucket_key, Authorization};
+use crate::s3::encryption::EncryptionParams;
 use crate::s3::error::*;
-use crate::s3::put::{decode_upload_id, get_headers};
+use crate::s3::get::{add_version_id_header, encode_version_id, find_object_version};
+use crate::s3::object_lock::new_version_lock;
+use crate::s3::policy::RequestAuthorization;
+use crate::s3::put::{block_write_params, decode_upload_id, get_headers};
+use crate::s3::tagging::parse_tagging_header;
 use crate::s3::xml::{self as s3_xml, xmlns_tag};
 
 pub async fn handle_copy(
 	garage: Arc<Garage>,
 	api_key: &Key,
 	req: &Request<Body>,
-	dest_bucket_id: Uuid,
+	source_ip: IpAddr,
+	dest_bucket: &Bucket,
 	dest_key: &str,
 ) -> Result<Response<Body>, Error> {
 	let copy_precondition = CopyPreconditionHeaders::parse(req)?;
 
-	let source_object = get_copy_source(&garage, api_key, req).await?;
+	let (source_bucket, source_object, source_version_id) =
+		get_copy_source(&garage, api_key, req, source_ip).await?;
 
 	let (source_version, source_version_data, source_version_meta) =
-		extract_source_info(&source_object)?;
+		find_object_version(&source_object, source_version_id.as_deref())?;
+
+	let dest_bucket_id = dest_bucket.id;
+	let dest_versioned = dest_bucket
+		.params()
+		.map(|p| p.versioning_enabled())
+		.unwrap_or(false);
 
 	// Check precondition, e.g. x-amz-copy-source-if-match
 	copy_precondition.check(source_version, &source_version_meta.etag)?;
 
+	// Check encryption parameters of source and destination objects.
+	// If either of them is encrypted, the data has to be re-encrypted
+	// and cannot simply be shared between source and destination.
+	let source_encryption = EncryptionParams::new_from_copy_source_headers(req.headers())?
+		.check_decrypt(&source_version_meta.headers.encryption)?;
+	let dest_encryption = EncryptionParams::new_from_headers(req.headers())?;
+	let must_recrypt = source_encryption.is_encrypted() || dest_encryption.is_encrypted();
+
 	// Generate parameters for copied object
 	let new_uuid = gen_uuid();
 	let new_timestamp = now_msec();
 
 	// Implement x-amz-metadata-directive: REPLACE
-	let new_meta = match req.headers().get("x-amz-metadata-directive") {
+	let mut new_meta = match req.headers().get("x-amz-metadata-directive") {
 		Some(v) if v == hyper::header::HeaderValue::from_static("REPLACE") => ObjectVersionMeta {
 			headers: get_headers(req.headers())?,
 			size: source_version_meta.size,
@@ -56,20 +81,39 @@ pub async fn handle_copy(
 		},
 		_ => source_version_meta.clone(),
 	};
+	new_meta.headers.encryption = dest_encryption.object_encryption();
 
-	let etag = new_meta.etag.to_string();
+	// Implement x-amz-tagging-directive: REPLACE
+	let new_tags = match req.headers().get("x-amz-tagging-directive") {
+		Some(v) if v == hyper::header::HeaderValue::from_static("REPLACE") => {
+			parse_tagging_header(req.headers())?
+		}
+		_ => source_version.tags.get().clone(),
+	};
+	let new_tags = Lww::new(new_tags);
+	let new_lock = new_version_lock(dest_bucket, req.headers())?;
 
 	// Save object copy
-	match source_version_data {
+	let etag = match source_version_data {
 		ObjectVersionData::DeleteMarker => unreachable!(),
 		ObjectVersionData::Inline(_meta, bytes) => {
+			let bytes = if must_recrypt {
+				let data = source_encryption.decrypt_block(bytes.clone().into())?;
+				new_meta.etag = dest_encryption.etag_from_md5(&Md5::digest(&data[..]));
+				dest_encryption.encrypt_block(data)?.to_vec()
+			} else {
+				bytes.clone()
+			};
+
+			let etag = new_meta.etag.clone();
 			let dest_object_version = ObjectVersion {
 				uuid: new_uuid,
 				timestamp: new_timestamp,
-				state: ObjectVersionState::Complete(ObjectVersionData::Inline(
-					new_meta,
-					bytes.clone(),
-				)),
+				state: ObjectVersionState::Complete(ObjectVersionData::Inline(new_meta, bytes)),
+				versioned: dest_versioned,
+				tags: new_tags,
+				lock: new_lock,
+				conditional_write: None,
 			};
 			let dest_object = Object::new(
 				dest_bucket_id,
@@ -77,6 +121,8 @@ pub async fn handle_copy(
 				vec![dest_object_version],
 			);
 			garage.object_table.insert(&dest_object).await?;
+
+			etag
 		}
 		ObjectVersionData::FirstBlock(_meta, first_block_hash) => {
 			// Get block list from source version
@@ -93,6 +139,10 @@ pub async fn handle_copy(
 				uuid: new_uuid,
 				timestamp: new_timestamp,
 				state: ObjectVersionState::Uploading(new_meta.headers.clone()),
+				versioned: dest_versioned,
+				tags: new_tags.clone(),
+				lock: new_lock.clone(),
+				conditional_write: None,
 			};
 			let tmp_dest_object = Object::new(
 				dest_bucket_id,
@@ -110,9 +160,31 @@ pub async fn handle_copy(
 			garage.version_table.insert(&dest_version).await?;
 
 			// Fill in block list for version and insert block refs
-			for (bk, bv) in source_version.blocks.items().iter() {
-				dest_version.blocks.put(*bk, *bv);
-			}
+			let dest_block_params = block_write_params(dest_bucket, &dest_encryption);
+			let first_block_hash = if must_recrypt {
+				let md5sum = recrypt_blocks(
+					&garage,
+					&source_version,
+					&mut dest_version,
+					&source_encryption,
+					&dest_encryption,
+					source_bucket.block_replication(),
+					dest_block_params,
+				)
+				.await?;
+				new_meta.etag = dest_encryption.etag_from_md5(&md5sum);
+				dest_version
+					.blocks
+					.items()
+					.first()
+					.map(|(_, b)| b.hash)
+					.ok_or_internal_error("Source object has no data blocks")?
+			} else {
+				for (bk, bv) in source_version.blocks.items().iter() {
+					dest_version.blocks.put(*bk, *bv);
+				}
+				*first_block_hash
+			};
 			let dest_block_refs = dest_version
 				.blocks
 				.items()
@@ -121,6 +193,7 @@ pub async fn handle_copy(
 					block: b.1.hash,
 					version: new_uuid,
 					deleted: false.into(),
+					replication_factor: dest_block_params.replication.map(|r| r.replication_factor),
 				})
 				.collect::<Vec<_>>();
 			futures::try_join!(
@@ -134,13 +207,18 @@ pub async fn handle_copy(
 			// it to update the modification timestamp for instance). If we did this concurrently
 			// with the stuff before, the block's reference counts could be decremented before
 			// they are incremented again for the new version, leading to data being deleted.
+			let etag = new_meta.etag.clone();
 			let dest_object_version = ObjectVersion {
 				uuid: new_uuid,
 				timestamp: new_timestamp,
 				state: ObjectVersionState::Complete(ObjectVersionData::FirstBlock(
 					new_meta,
-					*first_block_hash,
+					first_block_hash,
 				)),
+				versioned: dest_versioned,
+				tags: new_tags,
+				lock: new_lock,
+				conditional_write: None,
 			};
 			let dest_object = Object::new(
 				dest_bucket_id,
@@ -148,8 +226,10 @@ pub async fn handle_copy(
 				vec![dest_object_version],
 			);
 			garage.object_table.insert(&dest_object).await?;
+
+			etag
 		}
-	}
+	};
 
 	let last_modified = msec_to_rfc3339(new_timestamp);
 	let result = CopyObjectResult {
@@ -158,32 +238,34 @@ pub async fn handle_copy(
 	};
 	let xml = s3_xml::to_xml_with_header(&result)?;
 
-	Ok(Response::builder()
-		.header("Content-Type", "application/xml")
-		.header("x-amz-version-id", hex::encode(new_uuid))
-		.header(
-			"x-amz-copy-source-version-id",
-			hex::encode(source_version.uuid),
-		)
-		.body(Body::from(xml))?)
+	let resp = dest_encryption
+		.add_response_headers(Response::builder())
+		.header("Content-Type", "application/xml");
+	let resp = add_version_id_header(resp, new_uuid, dest_versioned).header(
+		"x-amz-copy-source-version-id",
+		encode_version_id(source_version.uuid, source_version.versioned),
+	);
+	Ok(resp.body(Body::from(xml))?)
 }
 
 pub async fn handle_upload_part_copy(
 	garage: Arc<Garage>,
 	api_key: &Key,
 	req: &Request<Body>,
-	dest_bucket_id: Uuid,
+	source_ip: IpAddr,
+	dest_bucket: &Bucket,
 	dest_key: &str,
 	part_number: u64,
 	upload_id: &str,
 ) -> Result<Response<Body>, Error> {
+	let dest_bucket_id = dest_bucket.id;
 	let copy_precondition = CopyPreconditionHeaders::parse(req)?;
 
 	let dest_version_uuid = decode_upload_id(upload_id)?;
 
 	let dest_key = dest_key.to_string();
-	let (source_object, dest_object) = futures::try_join!(
-		get_copy_source(&garage, api_key, req),
+	let ((source_bucket, source_object, source_version_id), dest_object) = futures::try_join!(
+		get_copy_source(&garage, api_key, req, source_ip),
 		garage
 			.object_table
 			.get(&dest_bucket_id, &dest_key)
@@ -192,7 +274,7 @@ pub async fn handle_upload_part_copy(
 	let dest_object = dest_object.ok_or(Error::NoSuchKey)?;
 
 	let (source_object_version, source_version_data, source_version_meta) =
-		extract_source_info(&source_object)?;
+		find_object_version(&source_object, source_version_id.as_deref())?;
 
 	// Check precondition on source, e.g. x-amz-copy-source-if-match
 	copy_precondition.check(source_object_version, &source_version_meta.etag)?;
@@ -218,13 +300,23 @@ pub async fn handle_upload_part_copy(
 	};
 
 	// Check destination version is indeed in uploading state
-	if !dest_object
+	let dest_object_version = dest_object
 		.versions()
 		.iter()
-		.any(|v| v.uuid == dest_version_uuid && v.is_uploading())
-	{
-		return Err(Error::NoSuchUpload);
-	}
+		.find(|v| v.uuid == dest_version_uuid && v.is_uploading())
+		.ok_or(Error::NoSuchUpload)?;
+
+	// Check encryption parameters of source object and of destination upload
+	let source_encryption = EncryptionParams::new_from_copy_source_headers(req.headers())?
+		.check_decrypt(&source_version_meta.headers.encryption)?;
+	let dest_encryption = match &dest_object_version.state {
+		ObjectVersionState::Uploading(headers) => {
+			EncryptionParams::new_from_headers(req.headers())?.check_decrypt(&headers.encryption)?
+		}
+		_ => unreachable!(),
+	};
+	let must_recrypt = source_encryption.is_encrypted() || dest_encryption.is_encrypted();
+	let block_params = block_write_params(dest_bucket, &dest_encryption);
 
 	// Check source version is not inlined
 	match source_version_data {
@@ -264,6 +356,7 @@ pub async fn handle_upload_part_copy(
 	// However, we still need to get the data from these blocks
 	// because we need to know it to calculate the MD5sum of the part
 	// which is used as its ETag.
+	// Blocks can never be reused if the data has to be re-encrypted.
 
 	// First, calculate what blocks we want to keep,
 	// and the subrange of the block to take, if the bounds of the
@@ -308,18 +401,28 @@ pub async fn handle_upload_part_copy(
 	// if and only if the block returned is a block that already existed
 	// in the Garage data store (thus we don't need to save it again).
 	let garage2 = garage.clone();
+	let source_replication = source_bucket.block_replication();
 	let order_stream = OrderTag::stream();
 	let source_blocks = stream::iter(blocks_to_copy)
 		.enumerate()
 		.flat_map(|(i, (block_hash, range_to_copy))| {
 			let garage3 = garage2.clone();
+			let source_encryption = source_encryption.clone();
 			stream::once(async move {
 				let data = garage3
 					.block_manager
-					.rpc_get_block(&block_hash, Some(order_stream.order(i as u64)))
+					.rpc_get_block(
+						&block_hash,
+						Some(order_stream.order(i as u64)),
+						source_replication,
+					)
 					.await?;
+				let data = source_encryption
+					.decrypt_block(data)
+					.map_err(|e| garage_util::error::Error::Message(e.to_string()))?;
 				match range_to_copy {
 					Some(r) => Ok((data.slice(r), None)),
+					None if must_recrypt => Ok((data, None)),
 					None => Ok((data, Some(block_hash))),
 				}
 			})
@@ -344,8 +447,14 @@ pub async fn handle_upload_part_copy(
 		}
 
 		md5hasher.update(&data[..]);
+		let data_len = data.len() as u64;
 
 		let must_upload = existing_block_hash.is_none();
+		let data = if must_upload {
+			dest_encryption.encrypt_block(data)?
+		} else {
+			data
+		};
 		let final_hash = existing_block_hash.unwrap_or_else(|| blake2sum(&data[..]));
 
 		let mut version = Version::new(dest_version_uuid, dest_bucket_id, dest_key.clone(), false);
@@ -356,15 +465,16 @@ pub async fn handle_upload_part_copy(
 			},
 			VersionBlock {
 				hash: final_hash,
-				size: data.len() as u64,
+				size: data_len,
 			},
 		);
-		current_offset += data.len() as u64;
+		current_offset += data_len;
 
 		let block_ref = BlockRef {
 			block: final_hash,
 			version: dest_version_uuid,
 			deleted: false.into(),
+			replication_factor: block_params.replication.map(|r| r.replication_factor),
 		};
 
 		let garage2 = garage.clone();
@@ -373,7 +483,10 @@ pub async fn handle_upload_part_copy(
 			// we need to insert that data as a new block.
 			async move {
 				if must_upload {
-					garage2.block_manager.rpc_put_block(final_hash, data).await
+					garage2
+						.block_manager
+						.rpc_put_block(final_hash, data, block_params)
+						.await
 				} else {
 					Ok(())
 				}
@@ -389,7 +502,7 @@ pub async fn handle_upload_part_copy(
 	}
 
 	let data_md5sum = md5hasher.finalize();
-	let etag = hex::encode(data_md5sum);
+	let etag = dest_encryption.etag_from_md5(&data_md5sum);
 
 	// Put the part's ETag in the Versiontable
 	let mut version = Version::new(dest_version_uuid, dest_bucket_id, dest_key.clone(), false);
@@ -403,21 +516,75 @@ pub async fn handle_upload_part_copy(
 		last_modified: s3_xml::Value(msec_to_rfc3339(source_object_version.timestamp)),
 	})?;
 
-	Ok(Response::builder()
+	let resp = dest_encryption
+		.add_response_headers(Response::builder())
 		.header("Content-Type", "application/xml")
 		.header(
 			"x-amz-copy-source-version-id",
-			hex::encode(source_object_version.uuid),
-		)
-		.body(Body::from(resp_xml))?)
+			encode_version_id(source_object_version.uuid, source_object_version.versioned),
+		);
+	Ok(resp.body(Body::from(resp_xml))?)
 }
 
+/// Copy all blocks of a source version into a destination version,
+/// decrypting them with the source encryption parameters and re-encrypting
+/// them with the destination parameters. Returns the MD5 of the plaintext data.
+async fn recrypt_blocks(
+	garage: &Garage,
+	source_version: &Version,
+	dest_version: &mut Version,
+	source_encryption: &EncryptionParams,
+	dest_encryption: &EncryptionParams,
+	source_replication: Option<BlockReplication>,
+	block_params: BlockWriteParams,
+) -> Result<Vec<u8>, Error> {
+	let mut md5hasher = Md5::new();
+	let order_stream = OrderTag::stream();
+
+	for (i, (bk, bv)) in source_version.blocks.items().iter().enumerate() {
+		let data = garage
+			.block_manager
+			.rpc_get_block(
+				&bv.hash,
+				Some(order_stream.order(i as u64)),
+				source_replication,
+			)
+			.await?;
+		let data = source_encryption.decrypt_block(data)?;
+		md5hasher.update(&data[..]);
+
+		let data = dest_encryption.encrypt_block(data)?;
+		let hash = blake2sum(&data[..]);
+		garage
+			.block_manager
+			.rpc_put_block(hash, data, block_params)
+			.await?;
+
+		dest_version.blocks.put(
+			*bk,
+			VersionBlock {
+				hash,
+				size: bv.size,
+			},
+		);
+	}
+
+	Ok(md5hasher.finalize().to_vec())
+}
+
+/// Get the object referenced by the x-amz-copy-source header and its bucket,
+/// as well as the source version id if one was specified
 async fn get_copy_source(
 	garage: &Garage,
 	api_key: &Key,
 	req: &Request<Body>,
-) -> Result<Object, Error> {
+	source_ip: IpAddr,
+) -> Result<(Bucket, Object, Option<String>), Error> {
 	let copy_source = req.headers().get("x-amz-copy-source").unwrap().to_str()?;
+	let (copy_source, source_version_id) = match copy_source.rsplit_once("?versionId=") {
+		Some((src, vid)) => (src, Some(vid.to_string())),
+		None => (copy_source, None),
+	};
 	let copy_source = percent_encoding::percent_decode_str(copy_source).decode_utf8()?;
 
 	let (source_bucket, source_key) = parse_bucket_key(&copy_source, None)?;
@@ -426,48 +593,36 @@ async fn get_copy_source(
 		.resolve_bucket(&source_bucket.to_string(), api_key)
 		.await?;
 
-	if !api_key.allow_read(&source_bucket_id) {
-		return Err(Error::forbidden(format!(
-			"Reading from bucket {} not allowed for this key",
-			source_bucket
-		)));
-	}
-
 	let source_key = source_key.ok_or_bad_request("No source key specified")?;
 
+	let source_bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(source_bucket_id)
+		.await?;
+	check_copy_source_allowed(api_key, &source_bucket, source_ip, source_key)?;
+
 	let source_object = garage
 		.object_table
 		.get(&source_bucket_id, &source_key.to_string())
 		.await?
 		.ok_or(Error::NoSuchKey)?;
 
-	Ok(source_object)
+	Ok((source_bucket, source_object, source_version_id))
 }
 
-fn extract_source_info(
-	source_object: &Object,
-) -> Result<(&ObjectVersion, &ObjectVersionData, &ObjectVersionMeta), Error> {
-	let source_version = source_object
-		.versions()
-		.iter()
-		.rev()
-		.find(|v| v.is_complete())
-		.ok_or(Error::NoSuchKey)?;
-
-	let source_version_data = match &source_version.state {
-		ObjectVersionState::Complete(x) => x,
-		_ => unreachable!(),
-	};
-
-	let source_version_meta = match source_version_data {
-		ObjectVersionData::DeleteMarker => {
-			return Err(Error::NoSuchKey);
-		}
-		ObjectVersionData::Inline(meta, _bytes) => meta,
-		ObjectVersionData::FirstBlock(meta, _fbh) => meta,
-	};
-
-	Ok((source_version, source_version_data, source_version_meta))
+/// Check that the source object of a copy can be read with this key,
+/// in the same way as if it was read with GetObject
+fn check_copy_source_allowed(
+	api_key: &Key,
+	source_bucket: &Bucket,
+	source_ip: IpAddr,
+	source_key: &str,
+) -> Result<(), Error> {
+	RequestAuthorization::new(api_key, source_bucket, source_ip).check(
+		Authorization::Read,
+		"s3:GetObject",
+		Some(source_key),
+	)
 }
 
 struct CopyPreconditionHeaders {
@@ -631,6 +786,68 @@ pub struct CopyPartResult {
 mod tests {
 	use super::*;
 	use crate::s3::xml::to_xml_with_header;
+	use garage_model::permission::BucketKeyPerm;
+
+	#[test]
+	fn copy_source_authorization() -> Result<(), Error> {
+		let mut source_bucket = Bucket::new();
+		let mut reader = Key::new("reader");
+		reader.params_mut().unwrap().authorized_buckets.put(
+			source_bucket.id,
+			BucketKeyPerm {
+				timestamp: 1,
+				allow_read: true,
+				allow_write: false,
+				allow_owner: false,
+			},
+		);
+		let other = Key::new("other");
+		let ip: IpAddr = "192.168.1.12".parse().unwrap();
+
+		// Without a bucket policy, only keys allowed to read the bucket can copy from it
+		assert!(check_copy_source_allowed(&reader, &source_bucket, ip, "a/x").is_ok());
+		assert!(check_copy_source_allowed(&other, &source_bucket, ip, "a/x").is_err());
+
+		let policy = crate::s3::policy::parse_policy_json(
+			format!(
+				r#"{{
+  "Statement": [
+    {{
+      "Effect": "Deny",
+      "Principal": "*",
+      "Action": "s3:GetObject",
+      "Resource": "arn:aws:s3:::source/private/*"
+    }},
+    {{
+      "Effect": "Allow",
+      "Principal": {{"AWS": ["{}"]}},
+      "Action": "s3:GetObject",
+      "Resource": "arn:aws:s3:::source/shared/*"
+    }}
+  ]
+}}"#,
+				other.key_id
+			)
+			.as_bytes(),
+			&["source"],
+		)?;
+		source_bucket
+			.params_mut()
+			.unwrap()
+			.policy
+			.update(Some(policy));
+
+		// Reading an object denied by the policy can't be bypassed by copying it
+		assert!(check_copy_source_allowed(&reader, &source_bucket, ip, "private/x").is_err());
+		assert!(check_copy_source_allowed(&reader, &source_bucket, ip, "shared/x").is_ok());
+
+		// Keys allowed to read objects by the policy can copy them
+		assert!(check_copy_source_allowed(&other, &source_bucket, ip, "shared/x").is_ok());
+		assert!(check_copy_source_allowed(&other, &source_bucket, ip, "private/x").is_err());
+		assert!(check_copy_source_allowed(&other, &source_bucket, ip, "a/x").is_err());
+
+		Ok(())
+	}
 
 	#[test]
 	fn copy_object_result() -> Result<(), Error> {
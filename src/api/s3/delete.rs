@@ -5,23 +5,54 @@ use hyper::{Body, Request, Response, StatusCode};
 use garage_util::data::*;
 use garage_util::time::*;
 
+use garage_model::bucket_table::Bucket;
 use garage_model::garage::Garage;
 use garage_model::s3::object_table::*;
 
+use crate::helpers::Authorization;
 use crate::s3::error::*;
+use crate::s3::get::{encode_version_id, find_version_by_id};
+use crate::s3::object_lock::check_version_deletable;
+use crate::s3::policy::RequestAuthorization;
 use crate::s3::xml as s3_xml;
 use crate::signature::verify_signed_content;
 
+/// Result of deleting an object or an object version, with version ids
+/// as they are sent to clients
+struct DeletedObject {
+	/// The version that was deleted or hidden by the delete marker
+	deleted_version: Option<String>,
+	/// The delete marker that was created, or the delete marker that was
+	/// permanently removed when deleting a specific version
+	delete_marker_version: Option<String>,
+}
+
 async fn handle_delete_internal(
 	garage: &Garage,
-	bucket_id: Uuid,
+	bucket: &Bucket,
 	key: &str,
-) -> Result<(Uuid, Uuid), Error> {
-	let object = garage
+	version_id: Option<&str>,
+	bypass_governance: bool,
+) -> Result<DeletedObject, Error> {
+	if let Some(vid) = version_id {
+		return handle_delete_version(garage, bucket.id, key, vid, bypass_governance).await;
+	}
+
+	let versioned = bucket
+		.params()
+		.map(|p| p.versioning_enabled())
+		.unwrap_or(false);
+
+	let object = match garage
 		.object_table
-		.get(&bucket_id, &key.to_string())
+		.get(&bucket.id, &key.to_string())
 		.await?
-		.ok_or(Error::NoSuchKey)?; // No need to delete
+	{
+		Some(o) => o,
+		// In a versioned bucket, a delete marker is added even if the object doesn't exist
+		None if versioned => Object::new(bucket.id, key.into(), vec![]),
+		None => return Err(Error::NoSuchKey), // No need to delete
+	};
 
 	let interesting_versions = object.versions().iter().filter(|v| {
 		!matches!(
@@ -35,36 +66,107 @@ async fn handle_delete_internal(
 	let mut timestamp = now_msec();
 	for v in interesting_versions {
 		if v.timestamp + 1 > timestamp || version_to_delete.is_none() {
-			version_to_delete = Some(v.uuid);
+			version_to_delete = Some(encode_version_id(v.uuid, v.versioned));
 		}
 		timestamp = std::cmp::max(timestamp, v.timestamp + 1);
 	}
+	// The new delete marker must also come after previous delete markers
+	// to become the current version of the object
+	if let Some(v) = object.versions().last() {
+		timestamp = std::cmp::max(timestamp, v.timestamp + 1);
+	}
 
-	let deleted_version = version_to_delete.ok_or(Error::NoSuchKey)?;
+	if version_to_delete.is_none() && !versioned {
+		return Err(Error::NoSuchKey);
+	}
 
 	let version_uuid = gen_uuid();
 
 	let object = Object::new(
-		bucket_id,
+		bucket.id,
 		key.into(),
 		vec![ObjectVersion {
 			uuid: version_uuid,
 			timestamp,
 			state: ObjectVersionState::Complete(ObjectVersionData::DeleteMarker),
+			versioned,
+			tags: Default::default(),
+			lock: Default::default(),
+			conditional_write: None,
 		}],
 	);
 
 	garage.object_table.insert(&object).await?;
 
-	Ok((deleted_version, version_uuid))
+	Ok(DeletedObject {
+		deleted_version: version_to_delete,
+		delete_marker_version: Some(encode_version_id(version_uuid, versioned)),
+	})
+}
+
+/// Permanently delete a specific version of an object, by marking it as aborted.
+/// Versions protected by Object Lock cannot be deleted.
+async fn handle_delete_version(
+	garage: &Garage,
+	bucket_id: Uuid,
+	key: &str,
+	version_id: &str,
+	bypass_governance: bool,
+) -> Result<DeletedObject, Error> {
+	let object = garage
+		.object_table
+		.get(&bucket_id, &key.to_string())
+		.await?
+		.ok_or(Error::NoSuchVersion)?;
+
+	let version = find_version_by_id(&object, version_id)?;
+
+	check_version_deletable(version, bypass_governance)?;
+
+	let was_delete_marker = version.is_delete_marker();
+
+	let mut deleted_version = version.clone();
+	deleted_version.state = ObjectVersionState::Aborted;
+	let object = Object::new(bucket_id, key.into(), vec![deleted_version]);
+	garage.object_table.insert(&object).await?;
+
+	Ok(DeletedObject {
+		deleted_version: Some(version_id.to_string()),
+		delete_marker_version: if was_delete_marker {
+			Some(version_id.to_string())
+		} else {
+			None
+		},
+	})
 }
 
 pub async fn handle_delete(
 	garage: Arc<Garage>,
-	bucket_id: Uuid,
+	bucket: &Bucket,
 	key: &str,
+	version_id: Option<&str>,
+	bypass_governance: bool,
 ) -> Result<Response<Body>, Error> {
-	match handle_delete_internal(&garage, bucket_id, key).await {
+	let versioning_used = version_id.is_some()
+		|| bucket
+			.params()
+			.map(|p| p.versioning_enabled())
+			.unwrap_or(false);
+
+	match handle_delete_internal(&garage, bucket, key, version_id, bypass_governance).await {
+		Ok(deleted) if versioning_used => {
+			let mut resp = Response::builder().status(StatusCode::NO_CONTENT);
+			if let Some(dm) = deleted.delete_marker_version {
+				resp = resp.header("x-amz-delete-marker", "true");
+				if version_id.is_none() {
+					resp = resp.header("x-amz-version-id", dm);
+				}
+			}
+			if let Some(vid) = version_id {
+				resp = resp.header("x-amz-version-id", vid);
+			}
+			Ok(resp.body(Body::from(vec![]))?)
+		}
 		Ok(_) | Err(Error::NoSuchKey) => Ok(Response::builder()
 			.status(StatusCode::NO_CONTENT)
 			.body(Body::from(vec![]))
@@ -75,8 +177,10 @@ pub async fn handle_delete(
 
 pub async fn handle_delete_objects(
 	garage: Arc<Garage>,
-	bucket_id: Uuid,
+	bucket: &Bucket,
+	authorization: &RequestAuthorization<'_>,
 	req: Request<Body>,
+	bypass_governance: bool,
 	content_sha256: Option<Hash>,
 ) -> Result<Response<Body>, Error> {
 	let body = hyper::body::to_bytes(req.into_body()).await?;
@@ -92,15 +196,31 @@ pub async fn handle_delete_objects(
 	let mut ret_errors = Vec::new();
 
 	for obj in cmd.objects.iter() {
-		match handle_delete_internal(&garage, bucket_id, &obj.key).await {
-			Ok((deleted_version, delete_marker_version)) => {
+		let res = async {
+			authorization.check(Authorization::Write, "s3:DeleteObject", Some(&obj.key))?;
+			handle_delete_internal(
+				&garage,
+				bucket,
+				&obj.key,
+				obj.version_id.as_deref(),
+				bypass_governance,
+			)
+			.await
+		}
+		.await;
+		match res {
+			Ok(deleted) => {
 				if cmd.quiet {
 					continue;
 				}
 				ret_deleted.push(s3_xml::Deleted {
 					key: s3_xml::Value(obj.key.clone()),
-					version_id: s3_xml::Value(hex::encode(deleted_version)),
-					delete_marker_version_id: s3_xml::Value(hex::encode(delete_marker_version)),
+					version_id: deleted.deleted_version.map(s3_xml::Value),
+					delete_marker: deleted
+						.delete_marker_version
+						.as_ref()
+						.map(|_| s3_xml::Value("true".into())),
+					delete_marker_version_id: deleted.delete_marker_version.map(s3_xml::Value),
 				});
 			}
 			Err(e) => {
@@ -108,7 +228,7 @@ pub async fn handle_delete_objects(
 					code: s3_xml::Value(e.aws_code().to_string()),
 					key: Some(s3_xml::Value(obj.key.clone())),
 					message: s3_xml::Value(format!("{}", e)),
-					version_id: None,
+					version_id: obj.version_id.clone().map(s3_xml::Value),
 				});
 			}
 		}
@@ -132,6 +252,7 @@ struct DeleteRequest {
 
 struct DeleteObject {
 	key: String,
+	version_id: Option<String>,
 }
 
 fn parse_delete_objects_xml(xml: &roxmltree::Document) -> Option<DeleteRequest> {
@@ -151,8 +272,13 @@ fn parse_delete_objects_xml(xml: &roxmltree::Document) -> Option<DeleteRequest>
 		if item.has_tag_name("Object") {
 			let key = item.children().find(|e| e.has_tag_name("Key"))?;
 			let key_str = key.text()?;
+			let version_id = match item.children().find(|e| e.has_tag_name("VersionId")) {
+				Some(v) => Some(v.text()?.to_string()),
+				None => None,
+			};
 			ret.objects.push(DeleteObject {
 				key: key_str.to_string(),
+				version_id,
 			});
 		} else if item.has_tag_name("Quiet") {
 			if item.text()? == "true" {
@@ -0,0 +1,275 @@
+//! Server-side encryption with customer-provided keys (SSE-C)
+use aes_gcm::{
+	aead::{Aead, AeadCore, KeyInit, OsRng},
+	Aes256Gcm, Key, Nonce,
+};
+use http::header::{HeaderMap, HeaderValue};
+use hyper::body::Bytes;
+use md5::{Digest as Md5Digest, Md5};
+
+use garage_util::data::*;
+
+use garage_model::s3::object_table::ObjectVersionEncryption;
+
+use crate::s3::error::*;
+
+const X_AMZ_SSE_C_ALGORITHM: &str = "x-amz-server-side-encryption-customer-algorithm";
+const X_AMZ_SSE_C_KEY: &str = "x-amz-server-side-encryption-customer-key";
+const X_AMZ_SSE_C_KEY_MD5: &str = "x-amz-server-side-encryption-customer-key-md5";
+
+const X_AMZ_COPY_SOURCE_SSE_C_ALGORITHM: &str =
+	"x-amz-copy-source-server-side-encryption-customer-algorithm";
+const X_AMZ_COPY_SOURCE_SSE_C_KEY: &str = "x-amz-copy-source-server-side-encryption-customer-key";
+const X_AMZ_COPY_SOURCE_SSE_C_KEY_MD5: &str =
+	"x-amz-copy-source-server-side-encryption-customer-key-md5";
+
+const CUSTOMER_ALGORITHM_AES256: &str = "AES256";
+
+/// Size of the nonce that is stored in front of each encrypted block
+const NONCE_SIZE: usize = 12;
+
+/// How data of an object is encrypted (or not)
+#[derive(Clone)]
+pub enum EncryptionParams {
+	Plaintext,
+	SseC {
+		client_key: Key<Aes256Gcm>,
+		/// base64-encoded MD5 of the key, as sent back to clients
+		client_key_md5: String,
+	},
+}
+
+impl EncryptionParams {
+	/// Parse the SSE-C headers of a request (PutObject, GetObject, etc.)
+	pub fn new_from_headers(headers: &HeaderMap<HeaderValue>) -> Result<Self, Error> {
+		Self::parse_headers(
+			headers,
+			X_AMZ_SSE_C_ALGORITHM,
+			X_AMZ_SSE_C_KEY,
+			X_AMZ_SSE_C_KEY_MD5,
+		)
+	}
+
+	/// Parse the SSE-C headers that apply to the source object of a CopyObject
+	/// or UploadPartCopy request
+	pub fn new_from_copy_source_headers(headers: &HeaderMap<HeaderValue>) -> Result<Self, Error> {
+		Self::parse_headers(
+			headers,
+			X_AMZ_COPY_SOURCE_SSE_C_ALGORITHM,
+			X_AMZ_COPY_SOURCE_SSE_C_KEY,
+			X_AMZ_COPY_SOURCE_SSE_C_KEY_MD5,
+		)
+	}
+
+	fn parse_headers(
+		headers: &HeaderMap<HeaderValue>,
+		alg_header: &str,
+		key_header: &str,
+		md5_header: &str,
+	) -> Result<Self, Error> {
+		let alg = headers.get(alg_header).map(|x| x.to_str()).transpose()?;
+		let key = headers.get(key_header).map(|x| x.to_str()).transpose()?;
+		let key_md5 = headers.get(md5_header).map(|x| x.to_str()).transpose()?;
+
+		match (alg, key, key_md5) {
+			(None, None, None) => Ok(Self::Plaintext),
+			(Some(alg), Some(key), Some(key_md5)) => {
+				if alg != CUSTOMER_ALGORITHM_AES256 {
+					return Err(Error::bad_request(format!(
+						"Unsupported server-side encryption algorithm: {} (only {} is supported)",
+						alg, CUSTOMER_ALGORITHM_AES256
+					)));
+				}
+
+				let key_bytes = base64::decode(key)
+					.ok_or_bad_request("Invalid server-side encryption key")?;
+				if key_bytes.len() != 32 {
+					return Err(Error::bad_request(
+						"Server-side encryption key must be 256 bits long",
+					));
+				}
+
+				let computed_md5 = base64::encode(Md5::digest(&key_bytes));
+				if computed_md5 != key_md5 {
+					return Err(Error::bad_request(
+						"Server-side encryption key MD5 does not match the key",
+					));
+				}
+
+				Ok(Self::SseC {
+					client_key: *Key::<Aes256Gcm>::from_slice(&key_bytes),
+					client_key_md5: computed_md5,
+				})
+			}
+			_ => Err(Error::bad_request(
+				"Missing server-side encryption headers: algorithm, key and key MD5 must all be given",
+			)),
+		}
+	}
+
+	/// Check that the encryption parameters given by the client correspond to
+	/// how the object was encrypted. Returns the parameters to use to read the object.
+	pub fn check_decrypt(self, stored: &Option<ObjectVersionEncryption>) -> Result<Self, Error> {
+		match (&self, stored) {
+			(Self::Plaintext, None) => Ok(self),
+			(Self::Plaintext, Some(_)) => Err(Error::bad_request(
+				"The object was stored using server-side encryption with a customer-provided key, \
+				the correct encryption parameters must be provided to access it",
+			)),
+			(Self::SseC { .. }, None) => Err(Error::bad_request(
+				"The object was not stored using server-side encryption with a customer-provided key, \
+				encryption parameters must not be provided to access it",
+			)),
+			(Self::SseC { client_key, .. }, Some(enc)) => {
+				if key_fingerprint(client_key) == enc.key_fingerprint {
+					Ok(self)
+				} else {
+					Err(Error::forbidden(
+						"The server-side encryption key does not match the key the object was stored with",
+					))
+				}
+			}
+		}
+	}
+
+	pub fn is_encrypted(&self) -> bool {
+		!matches!(self, Self::Plaintext)
+	}
+
+	/// The encryption information to store with an object that is written using these parameters
+	pub fn object_encryption(&self) -> Option<ObjectVersionEncryption> {
+		match self {
+			Self::Plaintext => None,
+			Self::SseC { client_key, .. } => Some(ObjectVersionEncryption {
+				key_fingerprint: key_fingerprint(client_key),
+			}),
+		}
+	}
+
+	/// Add the SSE-C headers that S3 sends back in responses
+	/// to requests on encrypted objects
+	pub fn add_response_headers(&self, resp: http::response::Builder) -> http::response::Builder {
+		match self {
+			Self::Plaintext => resp,
+			Self::SseC { client_key_md5, .. } => resp
+				.header(X_AMZ_SSE_C_ALGORITHM, CUSTOMER_ALGORITHM_AES256)
+				.header(X_AMZ_SSE_C_KEY_MD5, client_key_md5.as_str()),
+		}
+	}
+
+	/// The ETag of an object or part given the MD5 of its data.
+	/// For encrypted objects, the ETag must not leak information about the plaintext,
+	/// so it is generated randomly.
+	pub fn etag_from_md5(&self, md5sum: &[u8]) -> String {
+		match self {
+			Self::Plaintext => hex::encode(md5sum),
+			Self::SseC { .. } => hex::encode(&gen_uuid().as_slice()[..16]),
+		}
+	}
+
+	/// Encrypt a block of data. The nonce is stored in front of the ciphertext.
+	pub fn encrypt_block(&self, block: Bytes) -> Result<Bytes, Error> {
+		match self {
+			Self::Plaintext => Ok(block),
+			Self::SseC { client_key, .. } => {
+				let cipher = Aes256Gcm::new(client_key);
+				let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+				let ciphertext = cipher
+					.encrypt(&nonce, &block[..])
+					.ok_or_internal_error("Encryption failed")?;
+				let mut ret = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
+				ret.extend_from_slice(nonce.as_slice());
+				ret.extend_from_slice(&ciphertext[..]);
+				Ok(ret.into())
+			}
+		}
+	}
+
+	/// Decrypt a block of data that was encrypted using `encrypt_block`
+	pub fn decrypt_block(&self, block: Bytes) -> Result<Bytes, Error> {
+		match self {
+			Self::Plaintext => Ok(block),
+			Self::SseC { client_key, .. } => {
+				if block.len() < NONCE_SIZE {
+					return Err(Error::internal_error("Encrypted block is too short"));
+				}
+				let cipher = Aes256Gcm::new(client_key);
+				let nonce = Nonce::from_slice(&block[..NONCE_SIZE]);
+				let plaintext = cipher
+					.decrypt(nonce, &block[NONCE_SIZE..])
+					.ok_or_internal_error("Decryption failed")?;
+				Ok(plaintext.into())
+			}
+		}
+	}
+}
+
+fn key_fingerprint(key: &Key<Aes256Gcm>) -> Hash {
+	blake2sum(&[&b"garage-sse-c-key:"[..], key.as_slice()].concat())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn sse_c_headers(key: &[u8]) -> HeaderMap<HeaderValue> {
+		let mut headers = HeaderMap::new();
+		headers.insert(X_AMZ_SSE_C_ALGORITHM, HeaderValue::from_static("AES256"));
+		headers.insert(
+			X_AMZ_SSE_C_KEY,
+			HeaderValue::from_str(&base64::encode(key)).unwrap(),
+		);
+		headers.insert(
+			X_AMZ_SSE_C_KEY_MD5,
+			HeaderValue::from_str(&base64::encode(Md5::digest(key))).unwrap(),
+		);
+		headers
+	}
+
+	#[test]
+	fn test_encrypt_decrypt() -> Result<(), Error> {
+		let params = EncryptionParams::new_from_headers(&sse_c_headers(&[42u8; 32]))?;
+		assert!(params.is_encrypted());
+
+		let data = Bytes::from(vec![1u8; 1000]);
+		let encrypted = params.encrypt_block(data.clone())?;
+		assert_ne!(encrypted, data);
+		assert_eq!(params.decrypt_block(encrypted)?, data);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_check_decrypt() -> Result<(), Error> {
+		let params = EncryptionParams::new_from_headers(&sse_c_headers(&[42u8; 32]))?;
+		let stored = params.object_encryption();
+
+		let same = EncryptionParams::new_from_headers(&sse_c_headers(&[42u8; 32]))?;
+		assert!(same.check_decrypt(&stored).is_ok());
+
+		let other = EncryptionParams::new_from_headers(&sse_c_headers(&[43u8; 32]))?;
+		assert!(other.check_decrypt(&stored).is_err());
+
+		assert!(EncryptionParams::Plaintext.check_decrypt(&stored).is_err());
+		assert!(params.check_decrypt(&None).is_err());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_invalid_headers() {
+		let mut headers = sse_c_headers(&[42u8; 32]);
+		headers.insert(
+			X_AMZ_SSE_C_KEY_MD5,
+			HeaderValue::from_str(&base64::encode(Md5::digest(&[43u8; 32]))).unwrap(),
+		);
+		assert!(EncryptionParams::new_from_headers(&headers).is_err());
+
+		let mut headers = sse_c_headers(&[42u8; 32]);
+		headers.remove(X_AMZ_SSE_C_KEY);
+		assert!(EncryptionParams::new_from_headers(&headers).is_err());
+
+		let headers = sse_c_headers(&[42u8; 16]);
+		assert!(EncryptionParams::new_from_headers(&headers).is_err());
+	}
+}
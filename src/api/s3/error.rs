@@ -32,6 +32,30 @@ pub enum Error {
 	#[error(display = "Upload not found")]
 	NoSuchUpload,
 
+	/// The object version requested don't exists
+	#[error(display = "Version not found")]
+	NoSuchVersion,
+
+	/// The bucket has no lifecycle configuration
+	#[error(display = "The lifecycle configuration does not exist")]
+	NoSuchLifecycleConfiguration,
+
+	/// Object Lock is not enabled on the bucket
+	#[error(display = "Object Lock configuration does not exist for this bucket")]
+	ObjectLockConfigurationNotFound,
+
+	/// The object version has no retention period
+	#[error(display = "The specified object does not have an Object Lock configuration")]
+	NoSuchObjectLockConfiguration,
+
+	/// The bucket has no bucket policy
+	#[error(display = "The bucket policy does not exist")]
+	NoSuchBucketPolicy,
+
+	/// The operation is not possible in the current state of the bucket
+	#[error(display = "Invalid bucket state: {}", _0)]
+	InvalidBucketState(String),
+
 	/// Precondition failed (e.g. x-amz-copy-source-if-match)
 	#[error(display = "At least one of the preconditions you specified did not hold")]
 	PreconditionFailed,
@@ -62,6 +86,10 @@ pub enum Error {
 	#[error(display = "Invalid XML: {}", _0)]
 	InvalidXml(String),
 
+	/// The client sent an invalid bucket policy
+	#[error(display = "Invalid policy: {}", _0)]
+	MalformedPolicy(String),
+
 	/// The client sent a header with invalid value
 	#[error(display = "Invalid header value: {}", _0)]
 	InvalidHeader(#[error(source)] hyper::header::ToStrError),
@@ -110,6 +138,12 @@ impl From<quick_xml::de::DeError> for Error {
 	}
 }
 
+impl From<serde_json::Error> for Error {
+	fn from(err: serde_json::Error) -> Self {
+		Self::MalformedPolicy(format!("{}", err))
+	}
+}
+
 impl From<SignatureError> for Error {
 	fn from(err: SignatureError) -> Self {
 		match err {
@@ -135,6 +169,12 @@ impl Error {
 			Error::Common(c) => c.aws_code(),
 			Error::NoSuchKey => "NoSuchKey",
 			Error::NoSuchUpload => "NoSuchUpload",
+			Error::NoSuchVersion => "NoSuchVersion",
+			Error::NoSuchLifecycleConfiguration => "NoSuchLifecycleConfiguration",
+			Error::ObjectLockConfigurationNotFound => "ObjectLockConfigurationNotFoundError",
+			Error::NoSuchObjectLockConfiguration => "NoSuchObjectLockConfiguration",
+			Error::NoSuchBucketPolicy => "NoSuchBucketPolicy",
+			Error::InvalidBucketState(_) => "InvalidBucketState",
 			Error::PreconditionFailed => "PreconditionFailed",
 			Error::InvalidPart => "InvalidPart",
 			Error::InvalidPartOrder => "InvalidPartOrder",
@@ -142,6 +182,7 @@ impl Error {
 			Error::AuthorizationHeaderMalformed(_) => "AuthorizationHeaderMalformed",
 			Error::NotImplemented(_) => "NotImplemented",
 			Error::InvalidXml(_) => "MalformedXML",
+			Error::MalformedPolicy(_) => "MalformedPolicy",
 			Error::InvalidRange(_) => "InvalidRange",
 			Error::InvalidUtf8Str(_) | Error::InvalidUtf8String(_) | Error::InvalidHeader(_) => {
 				"InvalidRequest"
@@ -155,7 +196,14 @@ impl ApiError for Error {
 	fn http_status_code(&self) -> StatusCode {
 		match self {
 			Error::Common(c) => c.http_status_code(),
-			Error::NoSuchKey | Error::NoSuchUpload => StatusCode::NOT_FOUND,
+			Error::NoSuchKey
+			| Error::NoSuchUpload
+			| Error::NoSuchVersion
+			| Error::NoSuchLifecycleConfiguration
+			| Error::ObjectLockConfigurationNotFound
+			| Error::NoSuchObjectLockConfiguration
+			| Error::NoSuchBucketPolicy => StatusCode::NOT_FOUND,
+			Error::InvalidBucketState(_) => StatusCode::CONFLICT,
 			Error::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
 			Error::InvalidRange(_) => StatusCode::RANGE_NOT_SATISFIABLE,
 			Error::NotImplemented(_) => StatusCode::NOT_IMPLEMENTED,
@@ -164,6 +212,7 @@ impl ApiError for Error {
 			| Error::InvalidPartOrder
 			| Error::EntityTooSmall
 			| Error::InvalidXml(_)
+			| Error::MalformedPolicy(_)
 			| Error::InvalidUtf8Str(_)
 			| Error::InvalidUtf8String(_)
 			| Error::InvalidHeader(_) => StatusCode::BAD_REQUEST,
@@ -5,8 +5,8 @@ use std::time::{Duration, UNIX_EPOCH};
 use futures::future;
 use futures::stream::{self, StreamExt};
 use http::header::{
-	ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE,
-	IF_NONE_MATCH, LAST_MODIFIED, RANGE,
+	HeaderValue, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG,
+	IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RANGE,
 };
 use hyper::{Body, Request, Response, StatusCode};
 use tokio::sync::mpsc;
@@ -16,17 +16,27 @@ use garage_table::EmptyKey;
 use garage_util::data::*;
 use garage_util::error::OkOrMessage;
 
+use garage_block::manager::BlockReplication;
+use garage_model::bucket_table::Bucket;
 use garage_model::garage::Garage;
 use garage_model::s3::object_table::*;
 use garage_model::s3::version_table::*;
 
+use crate::s3::encryption::EncryptionParams;
 use crate::s3::error::*;
+use crate::s3::put::decode_upload_id;
+use crate::s3::tagging::X_AMZ_TAGGING_COUNT;
 
 const X_AMZ_MP_PARTS_COUNT: &str = "x-amz-mp-parts-count";
+const X_AMZ_VERSION_ID: &str = "x-amz-version-id";
+
+/// Maximum number of ranges that are answered in a multipart/byteranges response
+const MAX_RANGES: usize = 64;
 
 fn object_headers(
 	version: &ObjectVersion,
 	version_meta: &ObjectVersionMeta,
+	encryption: &EncryptionParams,
 ) -> http::response::Builder {
 	debug!("Version meta: {:?}", version_meta);
 
@@ -37,6 +47,7 @@ fn object_headers(
 		.header(CONTENT_TYPE, version_meta.headers.content_type.to_string())
 		.header(LAST_MODIFIED, date_str)
 		.header(ACCEPT_RANGES, "bytes".to_string());
+	resp = add_version_id_header(resp, version.uuid, version.versioned);
 
 	if !version_meta.etag.is_empty() {
 		resp = resp.header(ETAG, format!("\"{}\"", version_meta.etag));
@@ -46,7 +57,12 @@ fn object_headers(
 		resp = resp.header(k, v.to_string());
 	}
 
-	resp
+	let tags = version.tags.get();
+	if !tags.0.is_empty() {
+		resp = resp.header(X_AMZ_TAGGING_COUNT, tags.0.len().to_string());
+	}
+
+	encryption.add_response_headers(resp)
 }
 
 fn try_answer_cached(
@@ -88,12 +104,89 @@ fn try_answer_cached(
 	}
 }
 
+/// Version id of the versions written while versioning was not enabled on the bucket
+pub(crate) const NULL_VERSION_ID: &str = "null";
+
+/// Encode the version id of an object version, as sent to clients
+pub(crate) fn encode_version_id(version_uuid: Uuid, versioned: bool) -> String {
+	if versioned {
+		hex::encode(version_uuid)
+	} else {
+		NULL_VERSION_ID.to_string()
+	}
+}
+
+/// Find the complete version of an object with the version id given by the
+/// client in a versionId query parameter. The "null" version id designates the
+/// last version written while versioning was not enabled on the bucket.
+pub(crate) fn find_version_by_id<'a>(
+	object: &'a Object,
+	version_id: &str,
+) -> Result<&'a ObjectVersion, Error> {
+	if version_id == NULL_VERSION_ID {
+		return object
+			.versions()
+			.iter()
+			.rev()
+			.find(|v| v.is_complete() && !v.versioned)
+			.ok_or(Error::NoSuchVersion);
+	}
+	let uuid = decode_upload_id(version_id).map_err(|_| Error::NoSuchVersion)?;
+	object
+		.find_version(&uuid)
+		.filter(|v| v.is_complete() && v.versioned)
+		.ok_or(Error::NoSuchVersion)
+}
+
+/// Add the x-amz-version-id header to a response about a version of an object
+pub(crate) fn add_version_id_header(
+	resp: http::response::Builder,
+	version_uuid: Uuid,
+	versioned: bool,
+) -> http::response::Builder {
+	resp.header(X_AMZ_VERSION_ID, encode_version_id(version_uuid, versioned))
+}
+
+/// Find the version of an object a request refers to: the version with the given
+/// version id if one was specified, or the current version of the object otherwise.
+/// Returns an error if that version is a delete marker.
+pub(crate) fn find_object_version<'a>(
+	object: &'a Object,
+	version_id: Option<&str>,
+) -> Result<
+	(
+		&'a ObjectVersion,
+		&'a ObjectVersionData,
+		&'a ObjectVersionMeta,
+	),
+	Error,
+> {
+	let object_version = match version_id {
+		Some(vid) => find_version_by_id(object, vid)?,
+		None => object.current_version().ok_or(Error::NoSuchKey)?,
+	};
+
+	let version_data = match &object_version.state {
+		ObjectVersionState::Complete(x) => x,
+		_ => unreachable!(),
+	};
+
+	let version_meta = match version_data {
+		ObjectVersionData::DeleteMarker => return Err(Error::NoSuchKey),
+		ObjectVersionData::Inline(meta, _) => meta,
+		ObjectVersionData::FirstBlock(meta, _) => meta,
+	};
+
+	Ok((object_version, version_data, version_meta))
+}
+
 /// Handle HEAD request
 pub async fn handle_head(
 	garage: Arc<Garage>,
 	req: &Request<Body>,
 	bucket_id: Uuid,
 	key: &str,
+	version_id: Option<&str>,
 	part_number: Option<u64>,
 ) -> Result<Response<Body>, Error> {
 	let object = garage
@@ -102,23 +195,10 @@ pub async fn handle_head(
 		.await?
 		.ok_or(Error::NoSuchKey)?;
 
-	let object_version = object
-		.versions()
-		.iter()
-		.rev()
-		.find(|v| v.is_data())
-		.ok_or(Error::NoSuchKey)?;
+	let (object_version, version_data, version_meta) = find_object_version(&object, version_id)?;
 
-	let version_data = match &object_version.state {
-		ObjectVersionState::Complete(c) => c,
-		_ => unreachable!(),
-	};
-
-	let version_meta = match version_data {
-		ObjectVersionData::Inline(meta, _) => meta,
-		ObjectVersionData::FirstBlock(meta, _) => meta,
-		_ => unreachable!(),
-	};
+	let encryption = EncryptionParams::new_from_headers(req.headers())?
+		.check_decrypt(&version_meta.headers.encryption)?;
 
 	if let Some(cached) = try_answer_cached(object_version, version_meta, req) {
 		return Ok(cached);
@@ -126,15 +206,15 @@ pub async fn handle_head(
 
 	if let Some(pn) = part_number {
 		match version_data {
-			ObjectVersionData::Inline(_, bytes) => {
+			ObjectVersionData::Inline(_, _) => {
 				if pn != 1 {
 					return Err(Error::InvalidPart);
 				}
-				Ok(object_headers(object_version, version_meta)
-					.header(CONTENT_LENGTH, format!("{}", bytes.len()))
+				Ok(object_headers(object_version, version_meta, &encryption)
+					.header(CONTENT_LENGTH, format!("{}", version_meta.size))
 					.header(
 						CONTENT_RANGE,
-						format!("bytes 0-{}/{}", bytes.len() - 1, bytes.len()),
+						format!("bytes 0-{}/{}", version_meta.size - 1, version_meta.size),
 					)
 					.header(X_AMZ_MP_PARTS_COUNT, "1")
 					.status(StatusCode::PARTIAL_CONTENT)
@@ -151,7 +231,7 @@ pub async fn handle_head(
 					calculate_part_bounds(&version, pn).ok_or(Error::InvalidPart)?;
 				let n_parts = version.parts_etags.items().len();
 
-				Ok(object_headers(object_version, version_meta)
+				Ok(object_headers(object_version, version_meta, &encryption)
 					.header(CONTENT_LENGTH, format!("{}", part_end - part_offset))
 					.header(
 						CONTENT_RANGE,
@@ -169,7 +249,7 @@ pub async fn handle_head(
 			_ => unreachable!(),
 		}
 	} else {
-		Ok(object_headers(object_version, version_meta)
+		Ok(object_headers(object_version, version_meta, &encryption)
 			.header(CONTENT_LENGTH, format!("{}", version_meta.size))
 			.status(StatusCode::OK)
 			.body(Body::empty())?)
@@ -180,68 +260,83 @@ pub async fn handle_head(
 pub async fn handle_get(
 	garage: Arc<Garage>,
 	req: &Request<Body>,
-	bucket_id: Uuid,
+	bucket: &Bucket,
 	key: &str,
+	version_id: Option<&str>,
 	part_number: Option<u64>,
 ) -> Result<Response<Body>, Error> {
 	let object = garage
 		.object_table
-		.get(&bucket_id, &key.to_string())
+		.get(&bucket.id, &key.to_string())
 		.await?
 		.ok_or(Error::NoSuchKey)?;
 
-	let last_v = object
-		.versions()
-		.iter()
-		.rev()
-		.find(|v| v.is_complete())
-		.ok_or(Error::NoSuchKey)?;
+	let (last_v, last_v_data, last_v_meta) = find_object_version(&object, version_id)?;
+	let replication = bucket.block_replication();
 
-	let last_v_data = match &last_v.state {
-		ObjectVersionState::Complete(x) => x,
-		_ => unreachable!(),
-	};
-	let last_v_meta = match last_v_data {
-		ObjectVersionData::DeleteMarker => return Err(Error::NoSuchKey),
-		ObjectVersionData::Inline(meta, _) => meta,
-		ObjectVersionData::FirstBlock(meta, _) => meta,
-	};
+	let encryption = EncryptionParams::new_from_headers(req.headers())?
+		.check_decrypt(&last_v_meta.headers.encryption)?;
 
 	if let Some(cached) = try_answer_cached(last_v, last_v_meta, req) {
 		return Ok(cached);
 	}
 
-	match (part_number, parse_range_header(req, last_v_meta.size)?) {
-		(Some(_), Some(_)) => {
+	let ranges = parse_range_header(req, last_v_meta.size)?;
+	match (part_number, &ranges[..]) {
+		(Some(_), [_, ..]) => {
 			return Err(Error::bad_request(
 				"Cannot specify both partNumber and Range header",
 			));
 		}
-		(Some(pn), None) => {
-			return handle_get_part(garage, last_v, last_v_data, last_v_meta, pn).await;
+		(Some(pn), []) => {
+			return handle_get_part(
+				garage,
+				last_v,
+				last_v_data,
+				last_v_meta,
+				encryption,
+				replication,
+				pn,
+			)
+			.await;
 		}
-		(None, Some(range)) => {
+		(None, [range]) => {
 			return handle_get_range(
 				garage,
 				last_v,
 				last_v_data,
 				last_v_meta,
+				encryption,
+				replication,
 				range.start,
 				range.start + range.length,
 			)
 			.await;
 		}
-		(None, None) => (),
+		(None, [_, _, ..]) => {
+			return handle_get_multi_range(
+				garage,
+				last_v,
+				last_v_data,
+				last_v_meta,
+				encryption,
+				replication,
+				&ranges,
+			)
+			.await;
+		}
+		(None, []) => (),
 	}
 
-	let resp_builder = object_headers(last_v, last_v_meta)
+	let resp_builder = object_headers(last_v, last_v_meta, &encryption)
 		.header(CONTENT_LENGTH, format!("{}", last_v_meta.size))
 		.status(StatusCode::OK);
 
 	match &last_v_data {
 		ObjectVersionData::DeleteMarker => unreachable!(),
 		ObjectVersionData::Inline(_, bytes) => {
-			let body: Body = Body::from(bytes.to_vec());
+			let bytes = encryption.decrypt_block(bytes.to_vec().into())?;
+			let body: Body = Body::from(bytes);
 			Ok(resp_builder.body(body)?)
 		}
 		ObjectVersionData::FirstBlock(_, first_block_hash) => {
@@ -258,20 +353,28 @@ pub async fn handle_get(
 						garage2.version_table.get(&version_uuid, &EmptyKey).await
 					});
 
-					let stream_block_0 = garage
-						.block_manager
-						.rpc_get_block_streaming(&first_block_hash, Some(order_stream.order(0)))
-						.await?;
+					let stream_block_0 = get_block_stream(
+						&garage,
+						&encryption,
+						replication,
+						&first_block_hash,
+						order_stream.order(0),
+					)
+					.await?;
 					tx.send(stream_block_0)
 						.await
 						.ok_or_message("channel closed")?;
 
 					let version = version_fut.await.unwrap()?.ok_or(Error::NoSuchKey)?;
 					for (i, (_, vb)) in version.blocks.items().iter().enumerate().skip(1) {
-						let stream_block_i = garage
-							.block_manager
-							.rpc_get_block_streaming(&vb.hash, Some(order_stream.order(i as u64)))
-							.await?;
+						let stream_block_i = get_block_stream(
+							&garage,
+							&encryption,
+							replication,
+							&vb.hash,
+							order_stream.order(i as u64),
+						)
+						.await?;
 						tx.send(stream_block_i)
 							.await
 							.ok_or_message("channel closed")?;
@@ -307,10 +410,12 @@ async fn handle_get_range(
 	version: &ObjectVersion,
 	version_data: &ObjectVersionData,
 	version_meta: &ObjectVersionMeta,
+	encryption: EncryptionParams,
+	replication: Option<BlockReplication>,
 	begin: u64,
 	end: u64,
 ) -> Result<Response<Body>, Error> {
-	let resp_builder = object_headers(version, version_meta)
+	let resp_builder = object_headers(version, version_meta, &encryption)
 		.header(CONTENT_LENGTH, format!("{}", end - begin))
 		.header(
 			CONTENT_RANGE,
@@ -321,6 +426,7 @@ async fn handle_get_range(
 	match &version_data {
 		ObjectVersionData::DeleteMarker => unreachable!(),
 		ObjectVersionData::Inline(_meta, bytes) => {
+			let bytes = encryption.decrypt_block(bytes.to_vec().into())?;
 			if end as usize <= bytes.len() {
 				let body: Body = Body::from(bytes[begin as usize..end as usize].to_vec());
 				Ok(resp_builder.body(body)?)
@@ -337,27 +443,150 @@ async fn handle_get_range(
 				.await?
 				.ok_or(Error::NoSuchKey)?;
 
-			let body = body_from_blocks_range(garage, version.blocks.items(), begin, end);
+			let body = body_from_blocks_range(
+				garage,
+				encryption,
+				replication,
+				version.blocks.items(),
+				begin,
+				end,
+			);
 			Ok(resp_builder.body(body)?)
 		}
 	}
 }
 
+/// Answer a request for several ranges of an object with a multipart/byteranges
+/// response, in which each range is sent as a separate part
+async fn handle_get_multi_range(
+	garage: Arc<Garage>,
+	version: &ObjectVersion,
+	version_data: &ObjectVersionData,
+	version_meta: &ObjectVersionMeta,
+	encryption: EncryptionParams,
+	replication: Option<BlockReplication>,
+	ranges: &[http_range::HttpRange],
+) -> Result<Response<Body>, Error> {
+	let boundary = hex::encode(&gen_uuid().as_slice()[..12]);
+	let (part_headers, closing) = multipart_byteranges_delimiters(
+		&boundary,
+		&version_meta.headers.content_type,
+		ranges,
+		version_meta.size,
+	);
+	let content_length = part_headers.iter().map(|h| h.len() as u64).sum::<u64>()
+		+ ranges.iter().map(|r| r.length).sum::<u64>()
+		+ closing.len() as u64;
+
+	let mut resp_builder = object_headers(version, version_meta, &encryption)
+		.header(CONTENT_LENGTH, format!("{}", content_length))
+		.status(StatusCode::PARTIAL_CONTENT);
+	resp_builder.headers_mut().unwrap().insert(
+		CONTENT_TYPE,
+		format!("multipart/byteranges; boundary={}", boundary)
+			.parse::<HeaderValue>()
+			.ok_or_internal_error("Invalid multipart boundary")?,
+	);
+
+	let range_bodies: Vec<Body> = match &version_data {
+		ObjectVersionData::DeleteMarker => unreachable!(),
+		ObjectVersionData::Inline(_meta, bytes) => {
+			let bytes = encryption.decrypt_block(bytes.to_vec().into())?;
+			ranges
+				.iter()
+				.map(|r| {
+					let (begin, end) = (r.start as usize, (r.start + r.length) as usize);
+					if end <= bytes.len() {
+						Ok(Body::from(bytes.slice(begin..end)))
+					} else {
+						Err(Error::internal_error(
+							"Requested range not present in inline bytes when it should have been",
+						))
+					}
+				})
+				.collect::<Result<_, Error>>()?
+		}
+		ObjectVersionData::FirstBlock(_meta, _first_block_hash) => {
+			let version = garage
+				.version_table
+				.get(&version.uuid, &EmptyKey)
+				.await?
+				.ok_or(Error::NoSuchKey)?;
+
+			ranges
+				.iter()
+				.map(|r| {
+					body_from_blocks_range(
+						garage.clone(),
+						encryption.clone(),
+						replication,
+						version.blocks.items(),
+						r.start,
+						r.start + r.length,
+					)
+				})
+				.collect()
+		}
+	};
+
+	// Each range body is preceded by the header of its part,
+	// and the closing delimiter is sent at the end
+	let mut parts = Vec::with_capacity(2 * ranges.len() + 1);
+	for (header, body) in part_headers.into_iter().zip(range_bodies.into_iter()) {
+		parts.push(Body::from(header));
+		parts.push(body);
+	}
+	parts.push(Body::from(closing));
+
+	let body = Body::wrap_stream(stream::iter(parts).flatten());
+	Ok(resp_builder.body(body)?)
+}
+
+/// Build the header of each part of a multipart/byteranges body,
+/// as well as the closing delimiter of the body
+fn multipart_byteranges_delimiters(
+	boundary: &str,
+	content_type: &str,
+	ranges: &[http_range::HttpRange],
+	total_size: u64,
+) -> (Vec<String>, String) {
+	let part_headers = ranges
+		.iter()
+		.enumerate()
+		.map(|(i, r)| {
+			format!(
+				"{}--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+				if i == 0 { "" } else { "\r\n" },
+				boundary,
+				content_type,
+				r.start,
+				r.start + r.length - 1,
+				total_size
+			)
+		})
+		.collect();
+	let closing = format!("\r\n--{}--\r\n", boundary);
+	(part_headers, closing)
+}
+
 async fn handle_get_part(
 	garage: Arc<Garage>,
 	object_version: &ObjectVersion,
 	version_data: &ObjectVersionData,
 	version_meta: &ObjectVersionMeta,
+	encryption: EncryptionParams,
+	replication: Option<BlockReplication>,
 	part_number: u64,
 ) -> Result<Response<Body>, Error> {
-	let resp_builder =
-		object_headers(object_version, version_meta).status(StatusCode::PARTIAL_CONTENT);
+	let resp_builder = object_headers(object_version, version_meta, &encryption)
+		.status(StatusCode::PARTIAL_CONTENT);
 
 	match version_data {
 		ObjectVersionData::Inline(_, bytes) => {
 			if part_number != 1 {
 				return Err(Error::InvalidPart);
 			}
+			let bytes = encryption.decrypt_block(bytes.to_vec().into())?;
 			Ok(resp_builder
 				.header(CONTENT_LENGTH, format!("{}", bytes.len()))
 				.header(
@@ -365,7 +594,7 @@ async fn handle_get_part(
 					format!("bytes {}-{}/{}", 0, bytes.len() - 1, bytes.len()),
 				)
 				.header(X_AMZ_MP_PARTS_COUNT, "1")
-				.body(Body::from(bytes.to_vec()))?)
+				.body(Body::from(bytes))?)
 		}
 		ObjectVersionData::FirstBlock(_, _) => {
 			let version = garage
@@ -378,7 +607,14 @@ async fn handle_get_part(
 				calculate_part_bounds(&version, part_number).ok_or(Error::InvalidPart)?;
 			let n_parts = version.parts_etags.items().len();
 
-			let body = body_from_blocks_range(garage, version.blocks.items(), begin, end);
+			let body = body_from_blocks_range(
+				garage,
+				encryption,
+				replication,
+				version.blocks.items(),
+				begin,
+				end,
+			);
 
 			Ok(resp_builder
 				.header(CONTENT_LENGTH, format!("{}", end - begin))
@@ -393,26 +629,27 @@ async fn handle_get_part(
 	}
 }
 
+/// Parse the ranges requested in the Range header, if any
 fn parse_range_header(
 	req: &Request<Body>,
 	total_size: u64,
-) -> Result<Option<http_range::HttpRange>, Error> {
-	let range = match req.headers().get(RANGE) {
+) -> Result<Vec<http_range::HttpRange>, Error> {
+	let ranges = match req.headers().get(RANGE) {
 		Some(range) => {
 			let range_str = range.to_str()?;
-			let mut ranges =
+			let ranges =
 				http_range::HttpRange::parse(range_str, total_size).map_err(|e| (e, total_size))?;
-			if ranges.len() > 1 {
-				// garage does not support multi-range requests yet, so we respond with the entire
-				// object when multiple ranges are requested
-				None
+			if ranges.len() > MAX_RANGES {
+				// Answering requests for too many ranges would be very inefficient,
+				// so we respond with the entire object instead
+				vec![]
 			} else {
-				ranges.pop()
+				ranges
 			}
 		}
-		None => None,
+		None => vec![],
 	};
-	Ok(range)
+	Ok(ranges)
 }
 
 fn calculate_part_bounds(v: &Version, part_number: u64) -> Option<(u64, u64)> {
@@ -431,8 +668,10 @@ fn calculate_part_bounds(v: &Version, part_number: u64) -> Option<(u64, u64)> {
 	None
 }
 
-fn body_from_blocks_range(
+pub(crate) fn body_from_blocks_range(
 	garage: Arc<Garage>,
+	encryption: EncryptionParams,
+	replication: Option<BlockReplication>,
 	all_blocks: &[(VersionBlockKey, VersionBlock)],
 	begin: u64,
 	end: u64,
@@ -462,49 +701,54 @@ fn body_from_blocks_range(
 		.enumerate()
 		.map(move |(i, (block, block_offset))| {
 			let garage = garage.clone();
+			let encryption = encryption.clone();
 			async move {
-				garage
-					.block_manager
-					.rpc_get_block_streaming(&block.hash, Some(order_stream.order(i as u64)))
-					.await
-					.unwrap_or_else(|e| error_stream(i, e))
-					.scan(block_offset, move |chunk_offset, chunk| {
-						let r = match chunk {
-							Ok(chunk_bytes) => {
-								let chunk_len = chunk_bytes.len() as u64;
-								let r = if *chunk_offset >= end {
-									// The current chunk is after the part we want to read.
-									// Returning None here will stop the scan, the rest of the
-									// stream will be ignored
-									None
-								} else if *chunk_offset + chunk_len <= begin {
-									// The current chunk is before the part we want to read.
-									// We return a None that will be removed by the filter_map
-									// below.
-									Some(None)
+				get_block_stream(
+					&garage,
+					&encryption,
+					replication,
+					&block.hash,
+					order_stream.order(i as u64),
+				)
+				.await
+				.unwrap_or_else(|e| error_stream(i, e))
+				.scan(block_offset, move |chunk_offset, chunk| {
+					let r = match chunk {
+						Ok(chunk_bytes) => {
+							let chunk_len = chunk_bytes.len() as u64;
+							let r = if *chunk_offset >= end {
+								// The current chunk is after the part we want to read.
+								// Returning None here will stop the scan, the rest of the
+								// stream will be ignored
+								None
+							} else if *chunk_offset + chunk_len <= begin {
+								// The current chunk is before the part we want to read.
+								// We return a None that will be removed by the filter_map
+								// below.
+								Some(None)
+							} else {
+								// The chunk has an intersection with the requested range
+								let start_in_chunk = if *chunk_offset > begin {
+									0
+								} else {
+									begin - *chunk_offset
+								};
+								let end_in_chunk = if *chunk_offset + chunk_len < end {
+									chunk_len
 								} else {
-									// The chunk has an intersection with the requested range
-									let start_in_chunk = if *chunk_offset > begin {
-										0
-									} else {
-										begin - *chunk_offset
-									};
-									let end_in_chunk = if *chunk_offset + chunk_len < end {
-										chunk_len
-									} else {
-										end - *chunk_offset
-									};
-									Some(Some(Ok(chunk_bytes
-										.slice(start_in_chunk as usize..end_in_chunk as usize))))
+									end - *chunk_offset
 								};
-								*chunk_offset += chunk_bytes.len() as u64;
-								r
-							}
-							Err(e) => Some(Some(Err(e))),
-						};
-						futures::future::ready(r)
-					})
-					.filter_map(futures::future::ready)
+								Some(Some(Ok(chunk_bytes
+									.slice(start_in_chunk as usize..end_in_chunk as usize))))
+							};
+							*chunk_offset += chunk_bytes.len() as u64;
+							r
+						}
+						Err(e) => Some(Some(Err(e))),
+					};
+					futures::future::ready(r)
+				})
+				.filter_map(futures::future::ready)
 			}
 		})
 		.buffered(2)
@@ -513,6 +757,35 @@ fn body_from_blocks_range(
 	hyper::body::Body::wrap_stream(body_stream)
 }
 
+/// Get the data of a block as a stream. Encrypted blocks can only be decrypted
+/// once they have been entirely received, so they are not streamed from storage nodes.
+async fn get_block_stream(
+	garage: &Garage,
+	encryption: &EncryptionParams,
+	replication: Option<BlockReplication>,
+	hash: &Hash,
+	order: OrderTag,
+) -> Result<ByteStream, garage_util::error::Error> {
+	if !encryption.is_encrypted() {
+		return garage
+			.block_manager
+			.rpc_get_block_streaming(hash, Some(order), replication)
+			.await;
+	}
+
+	let block = garage
+		.block_manager
+		.rpc_get_block(hash, Some(order), replication)
+		.await?;
+	let res = encryption.decrypt_block(block).map_err(|e| {
+		std::io::Error::new(
+			std::io::ErrorKind::Other,
+			format!("Could not decrypt block: {}", e),
+		)
+	});
+	Ok(Box::pin(stream::once(future::ready(res))))
+}
+
 fn error_stream(i: usize, e: garage_util::error::Error) -> ByteStream {
 	Box::pin(futures::stream::once(async move {
 		Err(std::io::Error::new(
@@ -521,3 +794,32 @@ fn error_stream(i: usize, e: garage_util::error::Error) -> ByteStream {
 		))
 	}))
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_multipart_byteranges_delimiters() {
+		let ranges = vec![
+			http_range::HttpRange {
+				start: 0,
+				length: 10,
+			},
+			http_range::HttpRange {
+				start: 90,
+				length: 10,
+			},
+		];
+		let (part_headers, closing) =
+			multipart_byteranges_delimiters("b0und4ry", "video/mp4", &ranges, 100);
+		assert_eq!(
+			part_headers,
+			vec![
+				"--b0und4ry\r\nContent-Type: video/mp4\r\nContent-Range: bytes 0-9/100\r\n\r\n",
+				"\r\n--b0und4ry\r\nContent-Type: video/mp4\r\nContent-Range: bytes 90-99/100\r\n\r\n",
+			]
+		);
+		assert_eq!(closing, "\r\n--b0und4ry--\r\n");
+	}
+}
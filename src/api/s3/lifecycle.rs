@@ -0,0 +1,508 @@
+use quick_xml::de::from_reader;
+use std::sync::Arc;
+
+use hyper::{Body, Request, Response, StatusCode};
+
+use serde::{Deserialize, Serialize};
+
+use crate::s3::error::*;
+use crate::s3::xml::{to_xml_with_header, xmlns_tag, IntValue, Value};
+use crate::signature::verify_signed_content;
+
+use garage_model::bucket_table::{
+	Bucket, LifecycleExpiration as GarageLifecycleExpiration,
+	LifecycleFilter as GarageLifecycleFilter, LifecycleRule as GarageLifecycleRule,
+};
+use garage_model::garage::Garage;
+use garage_util::data::*;
+
+pub async fn handle_get_lifecycle(bucket: &Bucket) -> Result<Response<Body>, Error> {
+	let param = bucket
+		.params()
+		.ok_or_internal_error("Bucket should not be deleted at this point")?;
+
+	if let Some(lifecycle) = param.lifecycle_config.get() {
+		let wc = LifecycleConfiguration::from_garage_lifecycle_config(lifecycle);
+		let xml = to_xml_with_header(&wc)?;
+		Ok(Response::builder()
+			.status(StatusCode::OK)
+			.header(http::header::CONTENT_TYPE, "application/xml")
+			.body(Body::from(xml))?)
+	} else {
+		Err(Error::NoSuchLifecycleConfiguration)
+	}
+}
+
+pub async fn handle_delete_lifecycle(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+) -> Result<Response<Body>, Error> {
+	let mut bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	let param = bucket.params_mut().unwrap();
+
+	param.lifecycle_config.update(None);
+	garage.bucket_table.insert(&bucket).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::NO_CONTENT)
+		.body(Body::empty())?)
+}
+
+pub async fn handle_put_lifecycle(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	req: Request<Body>,
+	content_sha256: Option<Hash>,
+) -> Result<Response<Body>, Error> {
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	let mut bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	let param = bucket.params_mut().unwrap();
+
+	let conf: LifecycleConfiguration = from_reader(&body as &[u8])?;
+	let config = conf.validate_into_garage_lifecycle_config()?;
+
+	param.lifecycle_config.update(Some(config));
+	garage.bucket_table.insert(&bucket).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(Body::empty())?)
+}
+
+// ---- SERIALIZATION AND DESERIALIZATION TO/FROM S3 XML ----
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LifecycleConfiguration {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "Rule")]
+	pub lifecycle_rules: Vec<LifecycleRule>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LifecycleRule {
+	#[serde(rename = "ID")]
+	pub id: Option<Value>,
+	#[serde(rename = "Status")]
+	pub status: Value,
+	#[serde(rename = "Filter", default)]
+	pub filter: Option<Filter>,
+	/// Deprecated way of specifying a prefix filter, directly in the rule
+	#[serde(rename = "Prefix", default)]
+	pub prefix: Option<Value>,
+	#[serde(rename = "Expiration", default)]
+	pub expiration: Option<Expiration>,
+	#[serde(rename = "AbortIncompleteMultipartUpload", default)]
+	pub abort_incomplete_mpu: Option<AbortIncompleteMpu>,
+	#[serde(rename = "Transition", default)]
+	pub transition: Option<Unsupported>,
+	#[serde(rename = "NoncurrentVersionExpiration", default)]
+	pub noncurrent_version_expiration: Option<Unsupported>,
+	#[serde(rename = "NoncurrentVersionTransition", default)]
+	pub noncurrent_version_transition: Option<Unsupported>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Filter {
+	#[serde(rename = "And")]
+	pub and: Option<Box<Filter>>,
+	#[serde(rename = "Prefix")]
+	pub prefix: Option<Value>,
+	#[serde(rename = "ObjectSizeGreaterThan")]
+	pub size_gt: Option<IntValue>,
+	#[serde(rename = "ObjectSizeLessThan")]
+	pub size_lt: Option<IntValue>,
+	#[serde(rename = "Tag", default)]
+	pub tag: Option<Unsupported>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Expiration {
+	#[serde(rename = "Days")]
+	pub days: Option<IntValue>,
+	#[serde(rename = "Date")]
+	pub at_date: Option<Value>,
+	#[serde(rename = "ExpiredObjectDeleteMarker")]
+	pub expired_object_delete_marker: Option<Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AbortIncompleteMpu {
+	#[serde(rename = "DaysAfterInitiation")]
+	pub days: IntValue,
+}
+
+/// An element of the S3 lifecycle configuration that is not supported by Garage
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Unsupported {}
+
+impl LifecycleConfiguration {
+	pub fn validate_into_garage_lifecycle_config(self) -> Result<Vec<GarageLifecycleRule>, Error> {
+		let mut ret = vec![];
+		for rule in self.lifecycle_rules {
+			ret.push(rule.validate_into_garage_lifecycle_rule()?);
+		}
+		Ok(ret)
+	}
+
+	pub fn from_garage_lifecycle_config(config: &[GarageLifecycleRule]) -> Self {
+		Self {
+			xmlns: (),
+			lifecycle_rules: config
+				.iter()
+				.map(LifecycleRule::from_garage_lifecycle_rule)
+				.collect(),
+		}
+	}
+}
+
+impl LifecycleRule {
+	pub fn validate_into_garage_lifecycle_rule(self) -> Result<GarageLifecycleRule, Error> {
+		let enabled = match self.status.0.as_str() {
+			"Enabled" => true,
+			"Disabled" => false,
+			_ => return Err(Error::bad_request("invalid value for <Status>")),
+		};
+
+		if self.transition.is_some()
+			|| self.noncurrent_version_expiration.is_some()
+			|| self.noncurrent_version_transition.is_some()
+		{
+			return Err(Error::NotImplemented(
+				"Garage only supports the Expiration and AbortIncompleteMultipartUpload lifecycle actions".into(),
+			));
+		}
+
+		let filter = match (self.filter, self.prefix) {
+			(Some(_), Some(_)) => {
+				return Err(Error::bad_request(
+					"<Filter> and <Prefix> cannot both be set in a lifecycle rule",
+				))
+			}
+			(Some(filter), None) => filter.validate_into_garage_lifecycle_filter()?,
+			(None, prefix) => GarageLifecycleFilter {
+				prefix: prefix.map(|x| x.0),
+				..Default::default()
+			},
+		};
+
+		let abort_incomplete_mpu_days = match self.abort_incomplete_mpu {
+			Some(x) if x.days.0 <= 0 => {
+				return Err(Error::bad_request(
+					"<DaysAfterInitiation> must be a positive number",
+				))
+			}
+			Some(x) => Some(x.days.0 as usize),
+			None => None,
+		};
+
+		let expiration = self
+			.expiration
+			.map(Expiration::validate_into_garage_lifecycle_expiration)
+			.transpose()?;
+
+		if expiration.is_none() && abort_incomplete_mpu_days.is_none() {
+			return Err(Error::bad_request("lifecycle rule has no action"));
+		}
+
+		Ok(GarageLifecycleRule {
+			id: self.id.map(|x| x.0),
+			enabled,
+			filter,
+			abort_incomplete_mpu_days,
+			expiration,
+		})
+	}
+
+	pub fn from_garage_lifecycle_rule(rule: &GarageLifecycleRule) -> Self {
+		Self {
+			id: rule.id.as_deref().map(Value::from),
+			status: if rule.enabled {
+				Value::from("Enabled")
+			} else {
+				Value::from("Disabled")
+			},
+			filter: Filter::from_garage_lifecycle_filter(&rule.filter),
+			prefix: None,
+			expiration: rule
+				.expiration
+				.as_ref()
+				.map(Expiration::from_garage_lifecycle_expiration),
+			abort_incomplete_mpu: rule
+				.abort_incomplete_mpu_days
+				.map(|days| AbortIncompleteMpu {
+					days: IntValue(days as i64),
+				}),
+			transition: None,
+			noncurrent_version_expiration: None,
+			noncurrent_version_transition: None,
+		}
+	}
+}
+
+impl Filter {
+	pub fn count(&self) -> i32 {
+		fn count<T>(x: &Option<T>) -> i32 {
+			x.as_ref().map(|_| 1).unwrap_or(0)
+		}
+		count(&self.prefix) + count(&self.size_gt) + count(&self.size_lt)
+	}
+
+	pub fn validate_into_garage_lifecycle_filter(self) -> Result<GarageLifecycleFilter, Error> {
+		if self.tag.is_some() {
+			return Err(Error::NotImplemented(
+				"Garage does not support filtering lifecycle rules on object tags".into(),
+			));
+		}
+
+		if self.count() > 0 && self.and.is_some() {
+			return Err(Error::bad_request(
+				"Filter tag cannot contain both <And> and another condition",
+			));
+		} else if let Some(and) = self.and {
+			if and.and.is_some() {
+				return Err(Error::bad_request("Nested <And> tags"));
+			}
+			return and.validate_into_garage_lifecycle_filter();
+		} else if self.count() > 1 {
+			return Err(Error::bad_request(
+				"Multiple Filter conditions must be wrapped in an <And> tag",
+			));
+		}
+
+		let size_gt = match self.size_gt {
+			Some(x) if x.0 < 0 => {
+				return Err(Error::bad_request(
+					"<ObjectSizeGreaterThan> must be a non-negative number",
+				))
+			}
+			x => x.map(|x| x.0 as u64),
+		};
+		let size_lt = match self.size_lt {
+			Some(x) if x.0 <= 0 => {
+				return Err(Error::bad_request(
+					"<ObjectSizeLessThan> must be a positive number",
+				))
+			}
+			x => x.map(|x| x.0 as u64),
+		};
+
+		Ok(GarageLifecycleFilter {
+			prefix: self.prefix.map(|x| x.0),
+			size_gt,
+			size_lt,
+		})
+	}
+
+	pub fn from_garage_lifecycle_filter(rule: &GarageLifecycleFilter) -> Option<Self> {
+		let filter = Filter {
+			and: None,
+			prefix: rule.prefix.as_deref().map(Value::from),
+			size_gt: rule.size_gt.map(|x| IntValue(x as i64)),
+			size_lt: rule.size_lt.map(|x| IntValue(x as i64)),
+			tag: None,
+		};
+		match filter.count() {
+			0 => None,
+			1 => Some(filter),
+			_ => Some(Filter {
+				and: Some(Box::new(filter)),
+				..Default::default()
+			}),
+		}
+	}
+}
+
+impl Expiration {
+	pub fn validate_into_garage_lifecycle_expiration(
+		self,
+	) -> Result<GarageLifecycleExpiration, Error> {
+		if self.expired_object_delete_marker.is_some() {
+			return Err(Error::NotImplemented(
+				"Garage does not support <ExpiredObjectDeleteMarker> in lifecycle rules".into(),
+			));
+		}
+		match (self.days, self.at_date) {
+			(Some(_), Some(_)) => Err(Error::bad_request(
+				"cannot have both <Days> and <Date> in <Expiration>",
+			)),
+			(None, None) => Err(Error::bad_request(
+				"<Expiration> must contain either <Days> or <Date>",
+			)),
+			(Some(days), None) if days.0 <= 0 => {
+				Err(Error::bad_request("<Days> must be a positive number"))
+			}
+			(Some(days), None) => Ok(GarageLifecycleExpiration::AfterDays(days.0 as usize)),
+			(None, Some(date)) => {
+				let date = parse_lifecycle_date(&date.0)?;
+				Ok(GarageLifecycleExpiration::AtDate(date))
+			}
+		}
+	}
+
+	pub fn from_garage_lifecycle_expiration(exp: &GarageLifecycleExpiration) -> Self {
+		match exp {
+			GarageLifecycleExpiration::AfterDays(days) => Expiration {
+				days: Some(IntValue(*days as i64)),
+				at_date: None,
+				expired_object_delete_marker: None,
+			},
+			GarageLifecycleExpiration::AtDate(date) => Expiration {
+				days: None,
+				at_date: Some(Value(format!("{}T00:00:00Z", date))),
+				expired_object_delete_marker: None,
+			},
+		}
+	}
+}
+
+/// Parse the date of an <Expiration> element, which has to be
+/// midnight UTC, and return it in yyyy-mm-dd format
+fn parse_lifecycle_date(date: &str) -> Result<String, Error> {
+	use chrono::NaiveTime;
+
+	let date = chrono::DateTime::parse_from_rfc3339(date)
+		.ok_or_bad_request("<Date> must be a valid ISO 8601 date")?;
+	if date.time() != NaiveTime::from_hms(0, 0, 0) || date.offset().local_minus_utc() != 0 {
+		return Err(Error::bad_request("<Date> must be at midnight UTC"));
+	}
+	Ok(date.naive_utc().date().format("%Y-%m-%d").to_string())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use quick_xml::de::from_str;
+
+	#[test]
+	fn test_deserialize_lifecycle_config() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<LifecycleConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Rule>
+    <ID>id1</ID>
+    <Status>Enabled</Status>
+    <Filter>
+      <Prefix>logs/</Prefix>
+    </Filter>
+    <Expiration>
+      <Days>30</Days>
+    </Expiration>
+    <AbortIncompleteMultipartUpload>
+      <DaysAfterInitiation>7</DaysAfterInitiation>
+    </AbortIncompleteMultipartUpload>
+  </Rule>
+  <Rule>
+    <ID>id2</ID>
+    <Status>Disabled</Status>
+    <Filter>
+      <And>
+        <Prefix>tmp/</Prefix>
+        <ObjectSizeGreaterThan>1000</ObjectSizeGreaterThan>
+      </And>
+    </Filter>
+    <Expiration>
+      <Date>2023-01-01T00:00:00Z</Date>
+    </Expiration>
+  </Rule>
+</LifecycleConfiguration>"#;
+		let conf: LifecycleConfiguration = from_str(message).unwrap();
+		let ref_value = LifecycleConfiguration {
+			xmlns: (),
+			lifecycle_rules: vec![
+				LifecycleRule {
+					id: Some("id1".into()),
+					status: "Enabled".into(),
+					filter: Some(Filter {
+						prefix: Some("logs/".into()),
+						..Default::default()
+					}),
+					prefix: None,
+					expiration: Some(Expiration {
+						days: Some(IntValue(30)),
+						at_date: None,
+						expired_object_delete_marker: None,
+					}),
+					abort_incomplete_mpu: Some(AbortIncompleteMpu { days: IntValue(7) }),
+					transition: None,
+					noncurrent_version_expiration: None,
+					noncurrent_version_transition: None,
+				},
+				LifecycleRule {
+					id: Some("id2".into()),
+					status: "Disabled".into(),
+					filter: Some(Filter {
+						and: Some(Box::new(Filter {
+							prefix: Some("tmp/".into()),
+							size_gt: Some(IntValue(1000)),
+							..Default::default()
+						})),
+						..Default::default()
+					}),
+					prefix: None,
+					expiration: Some(Expiration {
+						days: None,
+						at_date: Some("2023-01-01T00:00:00Z".into()),
+						expired_object_delete_marker: None,
+					}),
+					abort_incomplete_mpu: None,
+					transition: None,
+					noncurrent_version_expiration: None,
+					noncurrent_version_transition: None,
+				},
+			],
+		};
+		assert_eq!(ref_value, conf);
+
+		let message2 = to_xml_with_header(&ref_value)?;
+
+		let cleanup = |c: &str| c.replace(char::is_whitespace, "");
+		assert_eq!(cleanup(message), cleanup(&message2));
+
+		// Check conversion to internal representation and back
+		let garage_config = conf.validate_into_garage_lifecycle_config()?;
+		assert_eq!(
+			garage_config[1].expiration,
+			Some(GarageLifecycleExpiration::AtDate("2023-01-01".into()))
+		);
+		let conf2 = LifecycleConfiguration::from_garage_lifecycle_config(&garage_config);
+		assert_eq!(ref_value, conf2);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_invalid_lifecycle_rules() {
+		let message = r#"<LifecycleConfiguration>
+  <Rule>
+    <Status>Enabled</Status>
+    <Expiration><Date>2023-01-01T12:00:00Z</Date></Expiration>
+  </Rule>
+</LifecycleConfiguration>"#;
+		let conf: LifecycleConfiguration = from_str(message).unwrap();
+		assert!(conf.validate_into_garage_lifecycle_config().is_err());
+
+		let message = r#"<LifecycleConfiguration>
+  <Rule>
+    <Status>Enabled</Status>
+    <Filter><Prefix>a/</Prefix><ObjectSizeLessThan>10</ObjectSizeLessThan></Filter>
+    <Expiration><Days>1</Days></Expiration>
+  </Rule>
+</LifecycleConfiguration>"#;
+		let conf: LifecycleConfiguration = from_str(message).unwrap();
+		assert!(conf.validate_into_garage_lifecycle_config().is_err());
+	}
+}
@@ -18,6 +18,7 @@ use garage_table::{EmptyKey, EnumerationOrder};
 use crate::encoding::*;
 use crate::helpers::key_after_prefix;
 use crate::s3::error::*;
+use crate::s3::get as s3_get;
 use crate::s3::put as s3_put;
 use crate::s3::xml as s3_xml;
 
@@ -50,6 +51,13 @@ pub struct ListMultipartUploadsQuery {
 	pub common: ListQueryCommon,
 }
 
+#[derive(Debug)]
+pub struct ListObjectVersionsQuery {
+	pub key_marker: Option<String>,
+	pub version_id_marker: Option<String>,
+	pub common: ListQueryCommon,
+}
+
 #[derive(Debug)]
 pub struct ListPartsQuery {
 	pub bucket_name: String,
@@ -265,6 +273,120 @@ pub async fn handle_list_multipart_upload(
 		.body(Body::from(xml.into_bytes()))?)
 }
 
+pub async fn handle_list_object_versions(
+	garage: Arc<Garage>,
+	query: &ListObjectVersionsQuery,
+) -> Result<Response<Body>, Error> {
+	let io = |bucket, key, count| {
+		let t = &garage.object_table;
+		async move {
+			t.get_range(
+				&bucket,
+				key,
+				Some(ObjectFilter::HasVersions),
+				count,
+				EnumerationOrder::Forward,
+			)
+			.await
+		}
+	};
+
+	debug!("ListObjectVersions {:?}", query);
+	let mut acc = query.build_accumulator();
+	let pagination = fetch_list_entries(&query.common, query.begin()?, &mut acc, &io).await?;
+
+	let mut versions = vec![];
+	let mut delete_markers = vec![];
+	for info in acc.keys.values() {
+		let key = uriencode_maybe(&info.key, query.common.urlencode_resp);
+		let version_id = s3_xml::Value(s3_get::encode_version_id(info.version, info.versioned));
+		let is_latest = s3_xml::Value(format!("{}", info.is_latest));
+		let last_modified = s3_xml::Value(msec_to_rfc3339(info.last_modified));
+		match &info.data {
+			Some((size, etag)) => versions.push(s3_xml::ListVersionsItem {
+				key,
+				version_id,
+				is_latest,
+				last_modified,
+				etag: s3_xml::Value(format!("\"{}\"", etag)),
+				size: s3_xml::IntValue(*size as i64),
+				storage_class: s3_xml::Value("STANDARD".to_string()),
+			}),
+			None => delete_markers.push(s3_xml::ListDeleteMarkerItem {
+				key,
+				version_id,
+				is_latest,
+				last_modified,
+			}),
+		}
+	}
+
+	let result = s3_xml::ListVersionsResult {
+		xmlns: (),
+
+		// Sending back some information about the request
+		name: s3_xml::Value(query.common.bucket_name.to_string()),
+		prefix: uriencode_maybe(&query.common.prefix, query.common.urlencode_resp),
+		delimiter: query
+			.common
+			.delimiter
+			.as_ref()
+			.map(|d| uriencode_maybe(d, query.common.urlencode_resp)),
+		max_keys: s3_xml::IntValue(query.common.page_size as i64),
+		key_marker: query
+			.key_marker
+			.as_ref()
+			.map(|m| uriencode_maybe(m, query.common.urlencode_resp)),
+		version_id_marker: query
+			.version_id_marker
+			.as_ref()
+			.map(|m| s3_xml::Value(m.to_string())),
+		encoding_type: match query.common.urlencode_resp {
+			true => Some(s3_xml::Value("url".to_string())),
+			false => None,
+		},
+
+		// Handling pagination
+		is_truncated: s3_xml::Value(format!("{}", pagination.is_some())),
+		// The key marker is exclusive: when the listing stopped in the middle of
+		// a key, the version id marker says after which version it continues
+		next_key_marker: match &pagination {
+			Some(RangeBegin::AfterKey { key })
+			| Some(RangeBegin::AfterUpload { key, .. })
+			| Some(RangeBegin::IncludingKey {
+				fallback_key: Some(key),
+				..
+			}) => Some(uriencode_maybe(key, query.common.urlencode_resp)),
+			_ => None,
+		},
+		next_version_id_marker: match pagination {
+			Some(RangeBegin::AfterUpload { upload, .. }) => acc
+				.keys
+				.values()
+				.find(|info| info.version == upload)
+				.map(|info| s3_xml::Value(s3_get::encode_version_id(upload, info.versioned))),
+			_ => None,
+		},
+
+		// Result body
+		versions,
+		delete_markers,
+		common_prefixes: acc
+			.common_prefixes
+			.iter()
+			.map(|c| s3_xml::CommonPrefix {
+				prefix: uriencode_maybe(c, query.common.urlencode_resp),
+			})
+			.collect(),
+	};
+
+	let xml = s3_xml::to_xml_with_header(&result)?;
+
+	Ok(Response::builder()
+		.header("Content-Type", "application/xml")
+		.body(Body::from(xml.into_bytes()))?)
+}
+
 pub async fn handle_list_parts(
 	garage: Arc<Garage>,
 	query: &ListPartsQuery,
@@ -333,6 +455,18 @@ struct UploadInfo {
 	timestamp: u64,
 }
 
+#[derive(Debug, PartialEq)]
+struct VersionInfo {
+	key: String,
+	version: Uuid,
+	/// Whether the version was written with versioning enabled
+	versioned: bool,
+	is_latest: bool,
+	last_modified: u64,
+	/// Size and etag of the version, None for delete markers
+	data: Option<(u64, String)>,
+}
+
 #[derive(Debug, PartialEq)]
 struct PartInfo {
 	etag: String,
@@ -665,6 +799,40 @@ impl ListMultipartUploadsQuery {
 	}
 }
 
+impl ListObjectVersionsQuery {
+	fn build_accumulator(&self) -> Accumulator<(String, usize), VersionInfo> {
+		Accumulator::<(String, usize), VersionInfo>::new(self.common.page_size)
+	}
+
+	fn begin(&self) -> Result<RangeBegin, Error> {
+		// Same logic as for ListMultipartUploads, with version ids taking the place
+		// of upload ids. The "null" version id marker designates the version of the
+		// key that was written while versioning was not enabled.
+		match (&self.version_id_marker, &self.key_marker) {
+			(Some(vid_marker), Some(key_marker)) => Ok(RangeBegin::AfterUpload {
+				key: key_marker.to_string(),
+				upload: match &vid_marker[..] {
+					s3_get::NULL_VERSION_ID => null_version_marker(),
+					vid => s3_put::decode_upload_id(vid).map_err(|_| Error::NoSuchVersion)?,
+				},
+			}),
+			(None, Some(key_marker)) => Ok(RangeBegin::AfterKey {
+				key: key_marker.to_string(),
+			}),
+			_ => Ok(RangeBegin::IncludingKey {
+				key: self.common.prefix.to_string(),
+				fallback_key: None,
+			}),
+		}
+	}
+}
+
+/// Placeholder for the "null" version id marker in a RangeBegin::AfterUpload
+/// cursor, as unversioned versions have no version id that clients know of
+fn null_version_marker() -> Uuid {
+	Uuid::from([0u8; 32])
+}
+
 /*
  * Accumulator logic
  */
@@ -686,6 +854,7 @@ struct Accumulator<K, V> {
 
 type ObjectAccumulator = Accumulator<String, ObjectInfo>;
 type UploadAccumulator = Accumulator<Uuid, UploadInfo>;
+type VersionAccumulator = Accumulator<(String, usize), VersionInfo>;
 
 impl<K: std::cmp::Ord, V> Accumulator<K, V> {
 	fn new(page_size: usize) -> Accumulator<K, V> {
@@ -792,7 +961,7 @@ impl ExtractAccumulator for ObjectAccumulator {
 
 		let object = objects.next().expect("This iterator can not be empty as it is checked earlier in the code. This is a logic bug, please report it.");
 
-		let version = match object.versions().iter().find(|x| x.is_data()) {
+		let version = match object.current_version().filter(|x| x.is_data()) {
 			Some(v) => v,
 			None => unreachable!(
 				"Expect to have objects having data due to earlier filtering. This is a logic bug."
@@ -911,6 +1080,81 @@ impl ExtractAccumulator for UploadAccumulator {
 	}
 }
 
+impl ExtractAccumulator for VersionAccumulator {
+	/// Observe the iterator, process a single key, and try to extract one or more versions
+	///
+	/// Versions of a key are listed from the most recent to the oldest one.
+	fn extract<'a>(
+		&mut self,
+		query: &ListQueryCommon,
+		cursor: &RangeBegin,
+		objects: &mut Peekable<impl Iterator<Item = &'a Object>>,
+	) -> ExtractionResult {
+		if let Some(e) = self.extract_common_prefix(objects, query) {
+			return e;
+		}
+
+		// Get the next object from the iterator
+		let object = objects.next().expect("This iterator can not be empty as it is checked earlier in the code. This is a logic bug, please report it.");
+
+		let current_version = object.current_version().map(|v| v.uuid);
+		let mut versions_for_key = object
+			.versions()
+			.iter()
+			.rev()
+			.filter(|x| x.is_listable_version())
+			.collect::<Vec<&ObjectVersion>>();
+
+		// Skip results if a version marker is provided for this key
+		if let RangeBegin::AfterUpload { key, upload } = cursor {
+			if *key == object.key {
+				if let Some(i) = versions_for_key.iter().position(|v| {
+					v.uuid == *upload || (*upload == null_version_marker() && !v.versioned)
+				}) {
+					versions_for_key = versions_for_key[i + 1..].to_vec();
+				}
+			}
+		}
+
+		let mut prev_uuid = None;
+		for (i, version) in versions_for_key.iter().enumerate() {
+			let data = match &version.state {
+				ObjectVersionState::Complete(ObjectVersionData::Inline(meta, _))
+				| ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _)) => {
+					Some((meta.size, meta.etag.to_string()))
+				}
+				_ => None,
+			};
+			let info = VersionInfo {
+				key: object.key.to_string(),
+				version: version.uuid,
+				versioned: version.versioned,
+				is_latest: current_version == Some(version.uuid),
+				last_modified: version.timestamp,
+				data,
+			};
+
+			// Insert data in our accumulator
+			// If it is full, return information to paginate.
+			if !self.try_insert_entry((object.key.clone(), i), info) {
+				return match prev_uuid {
+					None => ExtractionResult::Filled,
+					Some(upload) => ExtractionResult::FilledAtUpload {
+						key: object.key.clone(),
+						upload,
+					},
+				};
+			}
+			prev_uuid = Some(version.uuid);
+		}
+
+		// We successfully collected all the versions
+		ExtractionResult::Extracted {
+			key: object.key.clone(),
+		}
+	}
+}
+
 /*
  * Utility functions
  */
@@ -991,7 +1235,12 @@ mod tests {
 			state: ObjectVersionState::Uploading(ObjectVersionHeaders {
 				content_type: "text/plain".to_string(),
 				other: BTreeMap::<String, String>::new(),
+				encryption: None,
 			}),
+			versioned: false,
+			tags: Default::default(),
+			lock: Default::default(),
+			conditional_write: None,
 		}
 	}
 
@@ -1166,6 +1415,73 @@ mod tests {
 		Ok(())
 	}
 
+	fn data_version(timestamp: u64, versioned: bool) -> ObjectVersion {
+		ObjectVersion {
+			uuid: gen_uuid(),
+			timestamp,
+			state: ObjectVersionState::Complete(ObjectVersionData::Inline(
+				ObjectVersionMeta {
+					headers: ObjectVersionHeaders {
+						content_type: "text/plain".to_string(),
+						other: BTreeMap::<String, String>::new(),
+						encryption: None,
+					},
+					size: 1,
+					etag: "etag".to_string(),
+				},
+				vec![0],
+			)),
+			versioned,
+			tags: Default::default(),
+			lock: Default::default(),
+			conditional_write: None,
+		}
+	}
+
+	#[tokio::test]
+	async fn test_fetch_versions_null_marker() -> Result<(), Error> {
+		let v1 = data_version(TS, true);
+		let v2 = data_version(TS + 1, false);
+		let v3 = data_version(TS + 2, true);
+		let object = Object::new(bucket(), "a".to_string(), vec![v1.clone(), v2, v3]);
+
+		let mut query = ListObjectVersionsQuery {
+			key_marker: None,
+			version_id_marker: None,
+			common: query().common,
+		};
+		query.common.page_size = 2;
+		let fake_io = |_, _, _| {
+			let object = object.clone();
+			async move { Ok(vec![object]) }
+		};
+
+		// The first page stops after the unversioned version
+		let mut acc = query.build_accumulator();
+		let page = fetch_list_entries(&query.common, query.begin()?, &mut acc, &fake_io).await?;
+		let versions = acc.keys.values().collect::<Vec<_>>();
+		assert_eq!(versions.len(), 2);
+		assert!(!versions[1].versioned);
+		match page {
+			Some(RangeBegin::AfterUpload { key, upload }) => {
+				assert_eq!(key, "a");
+				assert_eq!(upload, versions[1].version);
+			}
+			_ => panic!("wrong pagination {:?}", page),
+		}
+
+		// The next page starts after the "null" version
+		query.key_marker = Some("a".to_string());
+		query.version_id_marker = Some("null".to_string());
+		let mut acc = query.build_accumulator();
+		let page = fetch_list_entries(&query.common, query.begin()?, &mut acc, &fake_io).await?;
+		assert_eq!(page, None);
+		let versions = acc.keys.values().map(|v| v.version).collect::<Vec<_>>();
+		assert_eq!(versions, vec![v1.uuid]);
+
+		Ok(())
+	}
+
 	fn version() -> Version {
 		let uuid = Uuid::from([0x08; 32]);
 
@@ -2,13 +2,20 @@ pub mod api_server;
 pub mod error;
 
 mod bucket;
+mod cdc;
 mod copy;
 pub mod cors;
 mod delete;
+mod encryption;
 pub mod get;
+mod lifecycle;
 mod list;
+mod object_lock;
+mod policy;
 mod post_object;
 mod put;
+mod select;
+mod tagging;
 mod website;
 
 mod router;
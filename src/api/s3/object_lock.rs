@@ -0,0 +1,612 @@
+use quick_xml::de::from_reader;
+use std::sync::Arc;
+
+use hyper::header::{HeaderMap, HeaderValue};
+use hyper::{Body, Request, Response, StatusCode};
+
+use serde::{Deserialize, Serialize};
+
+use garage_model::bucket_table::{Bucket, ObjectLockDefaultRetention, ObjectLockPeriod};
+use garage_model::garage::Garage;
+use garage_model::key_table::Key;
+use garage_model::s3::object_table::*;
+use garage_util::crdt::Lww;
+use garage_util::data::*;
+use garage_util::time::*;
+
+use crate::s3::error::*;
+use crate::s3::get::find_object_version;
+use crate::s3::xml::{to_xml_with_header, xmlns_tag, IntValue, Value};
+use crate::signature::verify_signed_content;
+
+const X_AMZ_OBJECT_LOCK_MODE: &str = "x-amz-object-lock-mode";
+const X_AMZ_OBJECT_LOCK_RETAIN_UNTIL_DATE: &str = "x-amz-object-lock-retain-until-date";
+const X_AMZ_OBJECT_LOCK_LEGAL_HOLD: &str = "x-amz-object-lock-legal-hold";
+const X_AMZ_BYPASS_GOVERNANCE_RETENTION: &str = "x-amz-bypass-governance-retention";
+
+const MISSING_LOCK_CONFIG_MESSAGE: &str = "Bucket is missing Object Lock Configuration";
+
+// ---- Bucket Object Lock configuration ----
+
+pub async fn handle_get_object_lock_config(bucket: &Bucket) -> Result<Response<Body>, Error> {
+	let param = bucket
+		.params()
+		.ok_or_internal_error("Bucket should not be deleted at this point")?;
+
+	if !param.object_lock_enabled.get() {
+		return Err(Error::ObjectLockConfigurationNotFound);
+	}
+
+	let conf = ObjectLockConfiguration {
+		xmlns: (),
+		object_lock_enabled: Some(Value("Enabled".into())),
+		rule: param
+			.object_lock_default_retention
+			.get()
+			.as_ref()
+			.map(|r| ObjectLockRule {
+				default_retention: DefaultRetention::from_garage_default_retention(r),
+			}),
+	};
+	let xml = to_xml_with_header(&conf)?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/xml")
+		.body(Body::from(xml))?)
+}
+
+pub async fn handle_put_object_lock_config(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	req: Request<Body>,
+	content_sha256: Option<Hash>,
+) -> Result<Response<Body>, Error> {
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	let conf: ObjectLockConfiguration = from_reader(&body as &[u8])?;
+	if conf.object_lock_enabled.as_ref().map(|x| x.0.as_str()) != Some("Enabled") {
+		return Err(Error::bad_request(
+			"Bad XML: ObjectLockEnabled must be set to Enabled",
+		));
+	}
+	let default_retention = conf
+		.rule
+		.map(|r| r.default_retention.validate_into_garage_default_retention())
+		.transpose()?;
+
+	let mut bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	let param = bucket.params_mut().unwrap();
+
+	if !param.object_lock_enabled.get() && !param.versioning_enabled() {
+		return Err(Error::InvalidBucketState(
+			"Versioning must be enabled on the bucket to enable Object Lock".into(),
+		));
+	}
+
+	param.object_lock_enabled.set();
+	param
+		.object_lock_default_retention
+		.update(default_retention);
+	garage.bucket_table.insert(&bucket).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(Body::empty())?)
+}
+
+// ---- Object retention and legal hold ----
+
+pub async fn handle_get_object_retention(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	key: &str,
+	version_id: Option<&str>,
+) -> Result<Response<Body>, Error> {
+	let object = get_object(&garage, bucket_id, key).await?;
+	let (object_version, _, _) = find_object_version(&object, version_id)?;
+
+	let retention = object_version
+		.lock
+		.retention
+		.get()
+		.as_ref()
+		.ok_or(Error::NoSuchObjectLockConfiguration)?;
+	let xml = to_xml_with_header(&Retention {
+		xmlns: (),
+		mode: Some(Value(mode_to_str(retention.mode).into())),
+		retain_until_date: Some(Value(msec_to_rfc3339(retention.retain_until))),
+	})?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/xml")
+		.body(Body::from(xml))?)
+}
+
+pub async fn handle_put_object_retention(
+	garage: Arc<Garage>,
+	req: Request<Body>,
+	bucket: &Bucket,
+	key: &str,
+	version_id: Option<&str>,
+	bypass_governance: bool,
+	content_sha256: Option<Hash>,
+) -> Result<Response<Body>, Error> {
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	check_object_lock_enabled(bucket)?;
+
+	let retention: Retention = from_reader(&body as &[u8])?;
+	let new_retention = retention.validate_into_garage_retention()?;
+
+	let object = get_object(&garage, bucket.id, key).await?;
+	let (object_version, _, _) = find_object_version(&object, version_id)?;
+
+	// An active retention can only be made stricter, unless governance-mode
+	// retention is bypassed by a user allowed to do so
+	if let Some(current) = object_version.lock.active_retention(now_msec()) {
+		let stricter = match &new_retention {
+			Some(new) => {
+				new.retain_until >= current.retain_until
+					&& (current.mode == ObjectLockMode::Governance
+						|| new.mode == ObjectLockMode::Compliance)
+			}
+			None => false,
+		};
+		if !stricter {
+			match current.mode {
+				ObjectLockMode::Governance if bypass_governance => (),
+				ObjectLockMode::Governance => {
+					return Err(Error::forbidden(
+						"The retention period of this object version can only be shortened \
+						by bypassing governance mode retention",
+					))
+				}
+				ObjectLockMode::Compliance => {
+					return Err(Error::forbidden(
+						"The compliance mode retention period of this object version \
+						can only be extended",
+					))
+				}
+			}
+		}
+	}
+
+	let mut object_version = object_version.clone();
+	object_version.lock.retention.update(new_retention);
+	let object = Object::new(bucket.id, key.to_string(), vec![object_version]);
+	garage.object_table.insert(&object).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(Body::empty())?)
+}
+
+pub async fn handle_get_object_legal_hold(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	key: &str,
+	version_id: Option<&str>,
+) -> Result<Response<Body>, Error> {
+	let object = get_object(&garage, bucket_id, key).await?;
+	let (object_version, _, _) = find_object_version(&object, version_id)?;
+
+	let xml = to_xml_with_header(&LegalHold {
+		xmlns: (),
+		status: Value(legal_hold_to_str(*object_version.lock.legal_hold.get()).into()),
+	})?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/xml")
+		.body(Body::from(xml))?)
+}
+
+pub async fn handle_put_object_legal_hold(
+	garage: Arc<Garage>,
+	req: Request<Body>,
+	bucket: &Bucket,
+	key: &str,
+	version_id: Option<&str>,
+	content_sha256: Option<Hash>,
+) -> Result<Response<Body>, Error> {
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	check_object_lock_enabled(bucket)?;
+
+	let legal_hold: LegalHold = from_reader(&body as &[u8])?;
+	let legal_hold = parse_legal_hold(&legal_hold.status.0)?;
+
+	let object = get_object(&garage, bucket.id, key).await?;
+	let (object_version, _, _) = find_object_version(&object, version_id)?;
+
+	let mut object_version = object_version.clone();
+	object_version.lock.legal_hold.update(legal_hold);
+	let object = Object::new(bucket.id, key.to_string(), vec![object_version]);
+	garage.object_table.insert(&object).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(Body::empty())?)
+}
+
+// ---- Helpers for other endpoints ----
+
+/// Compute the Object Lock protections of a new object version written in a bucket,
+/// from the x-amz-object-lock-* headers of the request or from the
+/// default retention of the bucket
+pub fn new_version_lock(
+	bucket: &Bucket,
+	headers: &HeaderMap<HeaderValue>,
+) -> Result<ObjectVersionLock, Error> {
+	let param = bucket
+		.params()
+		.ok_or_internal_error("Bucket should not be deleted at this point")?;
+
+	let mode = headers
+		.get(X_AMZ_OBJECT_LOCK_MODE)
+		.map(|h| h.to_str())
+		.transpose()?;
+	let retain_until = headers
+		.get(X_AMZ_OBJECT_LOCK_RETAIN_UNTIL_DATE)
+		.map(|h| h.to_str())
+		.transpose()?;
+	let legal_hold = headers
+		.get(X_AMZ_OBJECT_LOCK_LEGAL_HOLD)
+		.map(|h| h.to_str())
+		.transpose()?;
+
+	if !param.object_lock_enabled.get() {
+		if mode.is_some() || retain_until.is_some() || legal_hold.is_some() {
+			return Err(Error::bad_request(MISSING_LOCK_CONFIG_MESSAGE));
+		}
+		return Ok(ObjectVersionLock::default());
+	}
+
+	let retention = match (mode, retain_until) {
+		(Some(mode), Some(retain_until)) => Some(ObjectRetention {
+			mode: parse_mode(mode)?,
+			retain_until: parse_retain_until_date(retain_until)?,
+		}),
+		(None, None) => {
+			param
+				.object_lock_default_retention
+				.get()
+				.as_ref()
+				.map(|r| ObjectRetention {
+					mode: r.mode,
+					retain_until: now_msec() + r.period.duration_msec(),
+				})
+		}
+		_ => {
+			return Err(Error::bad_request(format!(
+				"{} and {} must be specified together",
+				X_AMZ_OBJECT_LOCK_MODE, X_AMZ_OBJECT_LOCK_RETAIN_UNTIL_DATE
+			)))
+		}
+	};
+	let legal_hold = match legal_hold {
+		Some(s) => parse_legal_hold(s)?,
+		None => false,
+	};
+
+	Ok(ObjectVersionLock {
+		retention: Lww::new(retention),
+		legal_hold: Lww::new(legal_hold),
+	})
+}
+
+/// Check that an object version can be permanently deleted
+pub fn check_version_deletable(
+	version: &ObjectVersion,
+	bypass_governance: bool,
+) -> Result<(), Error> {
+	if *version.lock.legal_hold.get() {
+		return Err(Error::forbidden(
+			"This object version is protected by a legal hold",
+		));
+	}
+	match version.lock.active_retention(now_msec()) {
+		Some(r) if r.mode == ObjectLockMode::Compliance || !bypass_governance => Err(
+			Error::forbidden("This object version is protected by a retention period"),
+		),
+		_ => Ok(()),
+	}
+}
+
+/// Whether a request asks to bypass governance mode retention and is allowed to do so.
+/// Only owners of the bucket can bypass governance mode retention.
+pub fn bypass_governance_retention(
+	headers: &HeaderMap<HeaderValue>,
+	api_key: &Key,
+	bucket_id: &Uuid,
+) -> bool {
+	headers
+		.get(X_AMZ_BYPASS_GOVERNANCE_RETENTION)
+		.map(|h| h == "true")
+		.unwrap_or(false)
+		&& api_key.allow_owner(bucket_id)
+}
+
+fn check_object_lock_enabled(bucket: &Bucket) -> Result<(), Error> {
+	let param = bucket
+		.params()
+		.ok_or_internal_error("Bucket should not be deleted at this point")?;
+	if param.object_lock_enabled.get() {
+		Ok(())
+	} else {
+		Err(Error::bad_request(MISSING_LOCK_CONFIG_MESSAGE))
+	}
+}
+
+async fn get_object(garage: &Garage, bucket_id: Uuid, key: &str) -> Result<Object, Error> {
+	garage
+		.object_table
+		.get(&bucket_id, &key.to_string())
+		.await?
+		.ok_or(Error::NoSuchKey)
+}
+
+fn parse_mode(mode: &str) -> Result<ObjectLockMode, Error> {
+	match mode {
+		"GOVERNANCE" => Ok(ObjectLockMode::Governance),
+		"COMPLIANCE" => Ok(ObjectLockMode::Compliance),
+		_ => Err(Error::bad_request(format!(
+			"Invalid Object Lock mode: {} (must be GOVERNANCE or COMPLIANCE)",
+			mode
+		))),
+	}
+}
+
+fn mode_to_str(mode: ObjectLockMode) -> &'static str {
+	match mode {
+		ObjectLockMode::Governance => "GOVERNANCE",
+		ObjectLockMode::Compliance => "COMPLIANCE",
+	}
+}
+
+fn parse_legal_hold(status: &str) -> Result<bool, Error> {
+	match status {
+		"ON" => Ok(true),
+		"OFF" => Ok(false),
+		_ => Err(Error::bad_request(format!(
+			"Invalid legal hold status: {} (must be ON or OFF)",
+			status
+		))),
+	}
+}
+
+fn legal_hold_to_str(legal_hold: bool) -> &'static str {
+	if legal_hold {
+		"ON"
+	} else {
+		"OFF"
+	}
+}
+
+fn parse_retain_until_date(date: &str) -> Result<u64, Error> {
+	let date = chrono::DateTime::parse_from_rfc3339(date)
+		.ok_or_bad_request("Retain until date must be a valid ISO 8601 date")?;
+	let retain_until = date.timestamp_millis();
+	if retain_until <= now_msec() as i64 {
+		return Err(Error::bad_request(
+			"Retain until date must be in the future",
+		));
+	}
+	Ok(retain_until as u64)
+}
+
+// ---- SERIALIZATION AND DESERIALIZATION TO/FROM S3 XML ----
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ObjectLockConfiguration {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "ObjectLockEnabled")]
+	pub object_lock_enabled: Option<Value>,
+	#[serde(rename = "Rule")]
+	pub rule: Option<ObjectLockRule>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ObjectLockRule {
+	#[serde(rename = "DefaultRetention")]
+	pub default_retention: DefaultRetention,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DefaultRetention {
+	#[serde(rename = "Mode")]
+	pub mode: Value,
+	#[serde(rename = "Days")]
+	pub days: Option<IntValue>,
+	#[serde(rename = "Years")]
+	pub years: Option<IntValue>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Retention {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "Mode")]
+	pub mode: Option<Value>,
+	#[serde(rename = "RetainUntilDate")]
+	pub retain_until_date: Option<Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LegalHold {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "Status")]
+	pub status: Value,
+}
+
+impl DefaultRetention {
+	pub fn validate_into_garage_default_retention(
+		self,
+	) -> Result<ObjectLockDefaultRetention, Error> {
+		let mode = parse_mode(&self.mode.0)?;
+		let period = match (self.days, self.years) {
+			(Some(d), None) if d.0 > 0 && d.0 <= u32::MAX as i64 => {
+				ObjectLockPeriod::Days(d.0 as u32)
+			}
+			(None, Some(y)) if y.0 > 0 && y.0 <= u32::MAX as i64 => {
+				ObjectLockPeriod::Years(y.0 as u32)
+			}
+			_ => {
+				return Err(Error::bad_request(
+					"Bad XML: exactly one of Days or Years must be given as a positive number",
+				))
+			}
+		};
+		Ok(ObjectLockDefaultRetention { mode, period })
+	}
+
+	pub fn from_garage_default_retention(r: &ObjectLockDefaultRetention) -> Self {
+		let (days, years) = match r.period {
+			ObjectLockPeriod::Days(d) => (Some(IntValue(d as i64)), None),
+			ObjectLockPeriod::Years(y) => (None, Some(IntValue(y as i64))),
+		};
+		Self {
+			mode: Value(mode_to_str(r.mode).into()),
+			days,
+			years,
+		}
+	}
+}
+
+impl Retention {
+	pub fn validate_into_garage_retention(self) -> Result<Option<ObjectRetention>, Error> {
+		match (self.mode, self.retain_until_date) {
+			(Some(mode), Some(date)) => Ok(Some(ObjectRetention {
+				mode: parse_mode(&mode.0)?,
+				retain_until: parse_retain_until_date(&date.0)?,
+			})),
+			(None, None) => Ok(None),
+			_ => Err(Error::bad_request(
+				"Bad XML: Mode and RetainUntilDate must be specified together",
+			)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use quick_xml::de::from_str;
+
+	#[test]
+	fn test_deserialize_object_lock_config() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ObjectLockConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <ObjectLockEnabled>Enabled</ObjectLockEnabled>
+  <Rule>
+    <DefaultRetention>
+      <Mode>COMPLIANCE</Mode>
+      <Days>30</Days>
+    </DefaultRetention>
+  </Rule>
+</ObjectLockConfiguration>"#;
+		let conf: ObjectLockConfiguration = from_str(message).unwrap();
+		let default_retention = conf
+			.rule
+			.unwrap()
+			.default_retention
+			.validate_into_garage_default_retention()?;
+		assert_eq!(
+			default_retention,
+			ObjectLockDefaultRetention {
+				mode: ObjectLockMode::Compliance,
+				period: ObjectLockPeriod::Days(30),
+			}
+		);
+
+		let message2 = to_xml_with_header(&ObjectLockConfiguration {
+			xmlns: (),
+			object_lock_enabled: Some(Value("Enabled".into())),
+			rule: Some(ObjectLockRule {
+				default_retention: DefaultRetention::from_garage_default_retention(
+					&default_retention,
+				),
+			}),
+		})?;
+		let conf2: ObjectLockConfiguration = from_str(&message2).unwrap();
+		assert_eq!(
+			conf2
+				.rule
+				.unwrap()
+				.default_retention
+				.validate_into_garage_default_retention()?,
+			default_retention
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_invalid_default_retention() {
+		let both = DefaultRetention {
+			mode: Value("GOVERNANCE".into()),
+			days: Some(IntValue(1)),
+			years: Some(IntValue(1)),
+		};
+		assert!(both.validate_into_garage_default_retention().is_err());
+
+		let bad_mode = DefaultRetention {
+			mode: Value("FOREVER".into()),
+			days: Some(IntValue(1)),
+			years: None,
+		};
+		assert!(bad_mode.validate_into_garage_default_retention().is_err());
+	}
+
+	#[test]
+	fn test_check_version_deletable() {
+		let mut version = ObjectVersion {
+			uuid: gen_uuid(),
+			timestamp: now_msec(),
+			state: ObjectVersionState::Complete(ObjectVersionData::DeleteMarker),
+			versioned: true,
+			tags: Default::default(),
+			lock: Default::default(),
+			conditional_write: None,
+		};
+		assert!(check_version_deletable(&version, false).is_ok());
+
+		version.lock.retention.update(Some(ObjectRetention {
+			mode: ObjectLockMode::Governance,
+			retain_until: now_msec() + 3600 * 1000,
+		}));
+		assert!(check_version_deletable(&version, false).is_err());
+		assert!(check_version_deletable(&version, true).is_ok());
+
+		version.lock.retention.update(Some(ObjectRetention {
+			mode: ObjectLockMode::Compliance,
+			retain_until: now_msec() + 3600 * 1000,
+		}));
+		assert!(check_version_deletable(&version, true).is_err());
+
+		version.lock.retention.update(None);
+		version.lock.legal_hold.update(true);
+		assert!(check_version_deletable(&version, true).is_err());
+	}
+}
@@ -0,0 +1,805 @@
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use hyper::{Body, Request, Response, StatusCode};
+
+use serde::{Deserialize, Serialize};
+
+use garage_model::bucket_table::*;
+use garage_model::garage::Garage;
+use garage_model::key_table::Key;
+use garage_util::data::*;
+use garage_util::error::Error as GarageError;
+
+use crate::helpers::Authorization;
+use crate::s3::error::*;
+use crate::s3::router::Endpoint;
+use crate::signature::verify_signed_content;
+
+const MAX_POLICY_SIZE: usize = 20 * 1024;
+
+const POLICY_VERSIONS: &[&str] = &["2012-10-17", "2008-10-17"];
+const S3_ARN_PREFIX: &str = "arn:aws:s3:::";
+const SOURCE_IP_CONDITION_KEY: &str = "aws:SourceIp";
+
+// ---- Handlers ----
+
+pub async fn handle_get_bucket_policy(
+	bucket: &Bucket,
+	bucket_name: &str,
+) -> Result<Response<Body>, Error> {
+	let param = bucket
+		.params()
+		.ok_or_internal_error("Bucket should not be deleted at this point")?;
+
+	let policy = param
+		.policy
+		.get()
+		.as_ref()
+		.ok_or(Error::NoSuchBucketPolicy)?;
+
+	let document = PolicyDocument::from_garage_policy(policy, bucket_name);
+	let json = serde_json::to_string(&document).map_err(GarageError::from)?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/json")
+		.body(Body::from(json))?)
+}
+
+pub async fn handle_put_bucket_policy(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	req: Request<Body>,
+	content_sha256: Option<Hash>,
+) -> Result<Response<Body>, Error> {
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	let mut bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	// The resources of the policy must name the bucket by one of its aliases
+	let bucket_names = bucket
+		.aliases()
+		.iter()
+		.filter(|(_, _, active)| *active)
+		.map(|(name, _, _)| name.as_str())
+		.chain(
+			bucket
+				.local_aliases()
+				.iter()
+				.filter(|(_, _, active)| *active)
+				.map(|((_, name), _, _)| name.as_str()),
+		)
+		.collect::<Vec<_>>();
+	let policy = parse_policy_json(&body, &bucket_names)?;
+
+	let param = bucket.params_mut().unwrap();
+
+	param.policy.update(Some(policy));
+	garage.bucket_table.insert(&bucket).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::NO_CONTENT)
+		.body(Body::empty())?)
+}
+
+pub async fn handle_delete_bucket_policy(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+) -> Result<Response<Body>, Error> {
+	let mut bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	let param = bucket.params_mut().unwrap();
+
+	param.policy.update(None);
+	garage.bucket_table.insert(&bucket).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::NO_CONTENT)
+		.body(Body::empty())?)
+}
+
+/// Parse and validate a bucket policy, as sent in PutBucketPolicy requests,
+/// for a bucket that has the names `bucket_names`
+pub fn parse_policy_json(body: &[u8], bucket_names: &[&str]) -> Result<BucketPolicy, Error> {
+	if body.len() > MAX_POLICY_SIZE {
+		return Err(Error::MalformedPolicy(format!(
+			"Policies must be at most {} bytes long",
+			MAX_POLICY_SIZE
+		)));
+	}
+	let document: PolicyDocument = serde_json::from_slice(body)?;
+	document.validate_into_garage_policy(bucket_names)
+}
+
+// ---- Authorization of requests ----
+
+/// Checks whether a request is allowed, by evaluating the bucket policy
+/// in addition to the permissions given to the access key on the bucket
+pub struct RequestAuthorization<'a> {
+	/// The access key used to sign the request, None for anonymous requests
+	api_key: Option<&'a Key>,
+	bucket: &'a Bucket,
+	source_ip: IpAddr,
+}
+
+impl<'a> RequestAuthorization<'a> {
+	pub fn new(api_key: &'a Key, bucket: &'a Bucket, source_ip: IpAddr) -> Self {
+		Self {
+			api_key: Some(api_key),
+			bucket,
+			source_ip,
+		}
+	}
+
+	/// Authorization of an anonymous request, which is allowed to read
+	/// objects if anonymous read access is enabled on the bucket.
+	/// The bucket policy can only deny actions to anonymous requests.
+	pub fn anonymous(bucket: &'a Bucket, source_ip: IpAddr) -> Self {
+		Self {
+			api_key: None,
+			bucket,
+			source_ip,
+		}
+	}
+
+	fn policy(&self) -> Option<&'a BucketPolicy> {
+		self.bucket.params().and_then(|p| p.policy.get().as_ref())
+	}
+
+	pub fn has_policy(&self) -> bool {
+		self.policy().is_some()
+	}
+
+	/// Check that the request to an endpoint is allowed
+	pub fn check_endpoint(&self, endpoint: &Endpoint) -> Result<(), Error> {
+		if self.api_key.is_none() && !is_anonymous_endpoint(endpoint) {
+			return Err(Error::forbidden(
+				"Anonymous access is not allowed for this request",
+			));
+		}
+
+		match endpoint {
+			// The bucket policy does not apply to the endpoints used to change it,
+			// so that owners of the bucket cannot lock themselves out
+			Endpoint::GetBucketPolicy {}
+			| Endpoint::PutBucketPolicy {}
+			| Endpoint::DeleteBucketPolicy {} => {
+				if self.key_allowed(endpoint.authorization_type()) {
+					Ok(())
+				} else {
+					Err(Error::forbidden("Operation is not allowed for this key."))
+				}
+			}
+			_ => self.check(
+				endpoint.authorization_type(),
+				&endpoint.policy_action(),
+				endpoint.get_key(),
+			),
+		}
+	}
+
+	/// Check that `action` is allowed on object `key` (or on the bucket
+	/// itself if `key` is None). Statements of the bucket policy that deny
+	/// the action have precedence, then the action is allowed if either
+	/// the access key has the required permission or the bucket policy
+	/// allows it. The bucket policy cannot give owner permissions.
+	pub fn check(
+		&self,
+		authorization: Authorization,
+		action: &str,
+		key: Option<&str>,
+	) -> Result<(), Error> {
+		let key_id = self.api_key.map(|k| k.key_id.as_str());
+		let policy_effect = self
+			.policy()
+			.and_then(|p| p.evaluate(key_id, self.source_ip, action, key));
+
+		match policy_effect {
+			Some(PolicyEffect::Deny) => Err(Error::forbidden(
+				"Operation is denied by the bucket policy.",
+			)),
+			Some(PolicyEffect::Allow)
+				if self.api_key.is_some() && !matches!(authorization, Authorization::Owner) =>
+			{
+				Ok(())
+			}
+			_ if self.key_allowed(authorization) => Ok(()),
+			_ => Err(Error::forbidden("Operation is not allowed for this key.")),
+		}
+	}
+
+	fn key_allowed(&self, authorization: Authorization) -> bool {
+		let api_key = match self.api_key {
+			Some(k) => k,
+			None => {
+				return matches!(authorization, Authorization::Read)
+					&& self
+						.bucket
+						.params()
+						.map(|p| *p.anonymous_read.get())
+						.unwrap_or(false)
+			}
+		};
+		match authorization {
+			Authorization::Read => api_key.allow_read(&self.bucket.id),
+			Authorization::Write => api_key.allow_write(&self.bucket.id),
+			Authorization::Owner => api_key.allow_owner(&self.bucket.id),
+			_ => unreachable!(),
+		}
+	}
+}
+
+/// Endpoints that can be called by anonymous requests: reading
+/// the current version of objects, and listing objects
+fn is_anonymous_endpoint(endpoint: &Endpoint) -> bool {
+	matches!(
+		endpoint,
+		Endpoint::GetObject {
+			version_id: None,
+			..
+		} | Endpoint::HeadObject {
+			version_id: None,
+			..
+		} | Endpoint::ListObjects { .. }
+			| Endpoint::ListObjectsV2 { .. }
+	)
+}
+
+// ---- SERIALIZATION AND DESERIALIZATION TO/FROM JSON POLICY DOCUMENTS ----
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum OneOrMany<T> {
+	One(T),
+	Many(Vec<T>),
+}
+
+impl<T> OneOrMany<T> {
+	fn into_vec(self) -> Vec<T> {
+		match self {
+			Self::One(x) => vec![x],
+			Self::Many(v) => v,
+		}
+	}
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct PolicyDocument {
+	#[serde(rename = "Version", skip_serializing_if = "Option::is_none")]
+	pub version: Option<String>,
+	#[serde(rename = "Id", skip_serializing_if = "Option::is_none")]
+	pub id: Option<String>,
+	#[serde(rename = "Statement")]
+	pub statement: OneOrMany<Statement>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Statement {
+	#[serde(rename = "Sid", skip_serializing_if = "Option::is_none")]
+	pub sid: Option<String>,
+	#[serde(rename = "Effect")]
+	pub effect: String,
+	#[serde(rename = "Principal")]
+	pub principal: Principal,
+	#[serde(rename = "Action")]
+	pub action: OneOrMany<String>,
+	#[serde(rename = "Resource")]
+	pub resource: OneOrMany<String>,
+	#[serde(rename = "Condition", skip_serializing_if = "Option::is_none")]
+	pub condition: Option<BTreeMap<String, BTreeMap<String, OneOrMany<String>>>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Principal {
+	Any(String),
+	Aws(AwsPrincipal),
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct AwsPrincipal {
+	#[serde(rename = "AWS")]
+	pub aws: OneOrMany<String>,
+}
+
+impl PolicyDocument {
+	pub fn validate_into_garage_policy(self, bucket_names: &[&str]) -> Result<BucketPolicy, Error> {
+		if let Some(v) = &self.version {
+			if !POLICY_VERSIONS.contains(&v.as_str()) {
+				return Err(Error::MalformedPolicy(format!(
+					"Unsupported policy version: {}",
+					v
+				)));
+			}
+		}
+
+		let statements = self
+			.statement
+			.into_vec()
+			.into_iter()
+			.map(|st| st.validate_into_garage_statement(bucket_names))
+			.collect::<Result<Vec<_>, Error>>()?;
+		if statements.is_empty() {
+			return Err(Error::MalformedPolicy(
+				"Policy must contain at least one statement".into(),
+			));
+		}
+
+		Ok(BucketPolicy {
+			id: self.id,
+			statements,
+		})
+	}
+
+	pub fn from_garage_policy(policy: &BucketPolicy, bucket_name: &str) -> Self {
+		Self {
+			version: Some(POLICY_VERSIONS[0].to_string()),
+			id: policy.id.clone(),
+			statement: OneOrMany::Many(
+				policy
+					.statements
+					.iter()
+					.map(|st| Statement::from_garage_statement(st, bucket_name))
+					.collect(),
+			),
+		}
+	}
+}
+
+impl Statement {
+	fn validate_into_garage_statement(
+		self,
+		bucket_names: &[&str],
+	) -> Result<PolicyStatement, Error> {
+		let effect = match self.effect.as_str() {
+			"Allow" => PolicyEffect::Allow,
+			"Deny" => PolicyEffect::Deny,
+			e => {
+				return Err(Error::MalformedPolicy(format!(
+					"Invalid effect: {}, expected Allow or Deny",
+					e
+				)))
+			}
+		};
+
+		let principals = match self.principal {
+			Principal::Any(p) if p == "*" => None,
+			Principal::Aws(p) => {
+				let keys = p.aws.into_vec();
+				if keys.iter().any(|k| k == "*") {
+					None
+				} else {
+					Some(keys)
+				}
+			}
+			Principal::Any(p) => {
+				return Err(Error::MalformedPolicy(format!("Invalid principal: {}", p)))
+			}
+		};
+
+		let actions = self.action.into_vec();
+		for action in actions.iter() {
+			if action != "*" && !action.to_lowercase().starts_with("s3:") {
+				return Err(Error::MalformedPolicy(format!(
+					"Invalid action: {}",
+					action
+				)));
+			}
+		}
+
+		let mut resources = vec![];
+		for res in self.resource.into_vec() {
+			if res == "*" {
+				resources.push(PolicyResource::Bucket);
+				resources.push(PolicyResource::Objects("*".into()));
+				continue;
+			}
+			let path = res
+				.strip_prefix(S3_ARN_PREFIX)
+				.ok_or_else(|| Error::MalformedPolicy(format!("Invalid resource: {}", res)))?;
+			let (bucket, resource) = match path.split_once('/') {
+				None => (path, PolicyResource::Bucket),
+				Some((bucket, pattern)) => (bucket, PolicyResource::Objects(pattern.to_string())),
+			};
+			// A policy written for another bucket, e.g. copied from it,
+			// must not apply to this one
+			if !bucket_names.contains(&bucket) {
+				return Err(Error::MalformedPolicy(format!(
+					"Resource {} does not belong to this bucket",
+					res
+				)));
+			}
+			resources.push(resource);
+		}
+
+		let mut source_ip = PolicyIpCondition::default();
+		for (operator, conditions) in self.condition.unwrap_or_default() {
+			let ranges = match operator.as_str() {
+				"IpAddress" => &mut source_ip.allow,
+				"NotIpAddress" => &mut source_ip.deny,
+				_ => {
+					return Err(Error::MalformedPolicy(format!(
+						"Unsupported condition operator: {}",
+						operator
+					)))
+				}
+			};
+			for (key, values) in conditions {
+				if !key.eq_ignore_ascii_case(SOURCE_IP_CONDITION_KEY) {
+					return Err(Error::MalformedPolicy(format!(
+						"Unsupported condition key: {}",
+						key
+					)));
+				}
+				for v in values.into_vec() {
+					ranges.push(parse_ip_range(&v)?);
+				}
+			}
+		}
+
+		Ok(PolicyStatement {
+			sid: self.sid,
+			effect,
+			principals,
+			actions,
+			resources,
+			source_ip,
+		})
+	}
+
+	fn from_garage_statement(st: &PolicyStatement, bucket_name: &str) -> Self {
+		let principal = match &st.principals {
+			None => Principal::Any("*".into()),
+			Some(keys) => Principal::Aws(AwsPrincipal {
+				aws: OneOrMany::Many(keys.clone()),
+			}),
+		};
+
+		let resource = st
+			.resources
+			.iter()
+			.map(|r| match r {
+				PolicyResource::Bucket => format!("{}{}", S3_ARN_PREFIX, bucket_name),
+				PolicyResource::Objects(pattern) => {
+					format!("{}{}/{}", S3_ARN_PREFIX, bucket_name, pattern)
+				}
+			})
+			.collect();
+
+		let mut condition = BTreeMap::new();
+		for (operator, ranges) in [
+			("IpAddress", &st.source_ip.allow),
+			("NotIpAddress", &st.source_ip.deny),
+		] {
+			if !ranges.is_empty() {
+				let values = ranges
+					.iter()
+					.map(|r| format!("{}/{}", r.addr, r.prefix_len))
+					.collect();
+				condition.insert(
+					operator.to_string(),
+					BTreeMap::from([(
+						SOURCE_IP_CONDITION_KEY.to_string(),
+						OneOrMany::Many(values),
+					)]),
+				);
+			}
+		}
+
+		Self {
+			sid: st.sid.clone(),
+			effect: match st.effect {
+				PolicyEffect::Allow => "Allow".into(),
+				PolicyEffect::Deny => "Deny".into(),
+			},
+			principal,
+			action: OneOrMany::Many(st.actions.clone()),
+			resource: OneOrMany::Many(resource),
+			condition: if condition.is_empty() {
+				None
+			} else {
+				Some(condition)
+			},
+		}
+	}
+}
+
+/// Parse an IP address range in CIDR notation, or a single IP address
+fn parse_ip_range(s: &str) -> Result<IpRange, Error> {
+	let invalid = || Error::MalformedPolicy(format!("Invalid IP address range: {}", s));
+
+	let (addr, prefix_len) = match s.split_once('/') {
+		Some((addr, len)) => (addr, Some(len)),
+		None => (s, None),
+	};
+	let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+	let max_len = match addr {
+		IpAddr::V4(_) => 32,
+		IpAddr::V6(_) => 128,
+	};
+	let prefix_len = match prefix_len {
+		Some(len) => len.parse::<u8>().map_err(|_| invalid())?,
+		None => max_len,
+	};
+	if prefix_len > max_len {
+		return Err(invalid());
+	}
+
+	Ok(IpRange { addr, prefix_len })
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_policy() -> Result<(), Error> {
+		let message = r#"{
+  "Version": "2012-10-17",
+  "Statement": [
+    {
+      "Sid": "TeamWrite",
+      "Effect": "Allow",
+      "Principal": {"AWS": "GK31c2f218a2e44f485b94239e"},
+      "Action": ["s3:PutObject", "s3:Get*"],
+      "Resource": "arn:aws:s3:::data/team-a/*",
+      "Condition": {"IpAddress": {"aws:SourceIp": ["10.0.0.0/8", "fd00::1"]}}
+    },
+    {
+      "Effect": "Deny",
+      "Principal": "*",
+      "Action": "s3:DeleteObject",
+      "Resource": ["arn:aws:s3:::data/archive/*"]
+    }
+  ]
+}"#;
+		let policy = parse_policy_json(message.as_bytes(), &["data"])?;
+		assert_eq!(
+			policy,
+			BucketPolicy {
+				id: None,
+				statements: vec![
+					PolicyStatement {
+						sid: Some("TeamWrite".into()),
+						effect: PolicyEffect::Allow,
+						principals: Some(vec!["GK31c2f218a2e44f485b94239e".into()]),
+						actions: vec!["s3:PutObject".into(), "s3:Get*".into()],
+						resources: vec![PolicyResource::Objects("team-a/*".into())],
+						source_ip: PolicyIpCondition {
+							allow: vec![
+								IpRange {
+									addr: "10.0.0.0".parse().unwrap(),
+									prefix_len: 8,
+								},
+								IpRange {
+									addr: "fd00::1".parse().unwrap(),
+									prefix_len: 128,
+								},
+							],
+							deny: vec![],
+						},
+					},
+					PolicyStatement {
+						sid: None,
+						effect: PolicyEffect::Deny,
+						principals: None,
+						actions: vec!["s3:DeleteObject".into()],
+						resources: vec![PolicyResource::Objects("archive/*".into())],
+						source_ip: PolicyIpCondition::default(),
+					},
+				],
+			}
+		);
+
+		let message2 =
+			serde_json::to_string(&PolicyDocument::from_garage_policy(&policy, "data")).unwrap();
+		assert_eq!(parse_policy_json(message2.as_bytes(), &["data"])?, policy);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_parse_invalid_policy() {
+		for message in [
+			r#"{"Statement": []}"#,
+			r#"{"Statement": {"Effect": "Maybe", "Principal": "*", "Action": "s3:*", "Resource": "*"}}"#,
+			r#"{"Statement": {"Effect": "Allow", "Principal": "*", "NotAction": "s3:*", "Resource": "*"}}"#,
+			r#"{"Statement": {"Effect": "Allow", "Principal": "*", "Action": "iam:*", "Resource": "*"}}"#,
+			r#"{"Statement": {"Effect": "Allow", "Principal": "*", "Action": "s3:*", "Resource": "bucket/*"}}"#,
+			r#"{"Statement": {"Effect": "Allow", "Principal": "*", "Action": "s3:*", "Resource": "arn:aws:s3:::otherbucket/*"}}"#,
+			r#"{"Statement": {"Effect": "Allow", "Principal": "*", "Action": "s3:*", "Resource": ["arn:aws:s3:::data", "arn:aws:s3:::other"]}}"#,
+			r#"{"Statement": {"Effect": "Allow", "Principal": "*", "Action": "s3:*", "Resource": "*",
+				"Condition": {"StringLike": {"s3:prefix": "a/*"}}}}"#,
+			r#"{"Statement": {"Effect": "Allow", "Principal": "*", "Action": "s3:*", "Resource": "*",
+				"Condition": {"IpAddress": {"aws:SourceIp": "10.0.0.0/33"}}}}"#,
+		] {
+			assert!(
+				parse_policy_json(message.as_bytes(), &["data"]).is_err(),
+				"{}",
+				message
+			);
+		}
+	}
+
+	#[test]
+	fn test_evaluate_policy() -> Result<(), Error> {
+		let policy = parse_policy_json(
+			br#"{
+  "Statement": [
+    {
+      "Effect": "Allow",
+      "Principal": {"AWS": ["GKteam"]},
+      "Action": "s3:*Object",
+      "Resource": "arn:aws:s3:::data/team-a/*",
+      "Condition": {"IpAddress": {"aws:SourceIp": "192.168.1.0/24"}}
+    },
+    {
+      "Effect": "Deny",
+      "Principal": "*",
+      "Action": "s3:DeleteObject",
+      "Resource": "arn:aws:s3:::data/team-a/keep/*"
+    }
+  ]
+}"#,
+			&["data"],
+		)?;
+		let inside: IpAddr = "192.168.1.12".parse().unwrap();
+		let inside_mapped: IpAddr = "::ffff:192.168.1.12".parse().unwrap();
+		let outside: IpAddr = "192.168.2.12".parse().unwrap();
+
+		let eval = |key_id, ip, action, key| policy.evaluate(Some(key_id), ip, action, key);
+
+		assert_eq!(
+			eval("GKteam", inside, "s3:PutObject", Some("team-a/x")),
+			Some(PolicyEffect::Allow)
+		);
+		assert_eq!(
+			eval("GKteam", inside_mapped, "s3:GetObject", Some("team-a/x")),
+			Some(PolicyEffect::Allow)
+		);
+		assert_eq!(
+			eval("GKteam", outside, "s3:PutObject", Some("team-a/x")),
+			None
+		);
+		assert_eq!(
+			eval("GKother", inside, "s3:PutObject", Some("team-a/x")),
+			None
+		);
+		assert_eq!(
+			eval("GKteam", inside, "s3:PutObject", Some("team-b/x")),
+			None
+		);
+		assert_eq!(eval("GKteam", inside, "s3:ListBucket", None), None);
+		assert_eq!(
+			eval("GKteam", inside, "s3:DeleteObject", Some("team-a/keep/x")),
+			Some(PolicyEffect::Deny)
+		);
+
+		// Anonymous requests only match statements that apply to all principals
+		assert_eq!(
+			policy.evaluate(None, inside, "s3:GetObject", Some("team-a/x")),
+			None
+		);
+		assert_eq!(
+			policy.evaluate(None, inside, "s3:DeleteObject", Some("team-a/keep/x")),
+			Some(PolicyEffect::Deny)
+		);
+
+		Ok(())
+	}
+	fn get_object(key: &str, version_id: Option<&str>) -> Endpoint {
+		Endpoint::GetObject {
+			key: key.into(),
+			part_number: None,
+			version_id: version_id.map(String::from),
+		}
+	}
+
+	fn list_objects() -> Endpoint {
+		Endpoint::ListObjectsV2 {
+			list_type: "2".into(),
+			continuation_token: None,
+			delimiter: None,
+			encoding_type: None,
+			fetch_owner: None,
+			max_keys: None,
+			prefix: None,
+			start_after: None,
+		}
+	}
+
+	#[test]
+	fn test_anonymous_authorization() -> Result<(), Error> {
+		let ip: IpAddr = "192.168.1.12".parse().unwrap();
+		let mut bucket = Bucket::new();
+
+		let read_endpoints = [
+			get_object("x", None),
+			Endpoint::HeadObject {
+				key: "x".into(),
+				part_number: None,
+				version_id: None,
+			},
+			list_objects(),
+		];
+		let other_endpoints = [
+			get_object("x", Some("0123")),
+			Endpoint::GetObjectTagging {
+				key: "x".into(),
+				version_id: None,
+			},
+			Endpoint::PutObject { key: "x".into() },
+			Endpoint::DeleteObject {
+				key: "x".into(),
+				version_id: None,
+			},
+			Endpoint::GetBucketPolicy {},
+		];
+
+		// Without anonymous read access, all requests are denied,
+		// even if the bucket policy allows them to all principals
+		bucket
+			.params_mut()
+			.unwrap()
+			.policy
+			.update(Some(parse_policy_json(
+				br#"{
+  "Statement": [
+    {
+      "Effect": "Allow",
+      "Principal": "*",
+      "Action": "s3:*",
+      "Resource": ["arn:aws:s3:::data", "arn:aws:s3:::data/*"]
+    },
+    {
+      "Effect": "Deny",
+      "Principal": "*",
+      "Action": "s3:GetObject",
+      "Resource": "arn:aws:s3:::data/private/*"
+    }
+  ]
+}"#,
+				&["data"],
+			)?));
+		for endpoint in read_endpoints.iter().chain(other_endpoints.iter()) {
+			assert!(RequestAuthorization::anonymous(&bucket, ip)
+				.check_endpoint(endpoint)
+				.is_err());
+		}
+
+		// With anonymous read access, only objects can be read and listed
+		bucket.params_mut().unwrap().anonymous_read.update(true);
+		for endpoint in read_endpoints.iter() {
+			assert!(RequestAuthorization::anonymous(&bucket, ip)
+				.check_endpoint(endpoint)
+				.is_ok());
+		}
+		for endpoint in other_endpoints.iter() {
+			assert!(RequestAuthorization::anonymous(&bucket, ip)
+				.check_endpoint(endpoint)
+				.is_err());
+		}
+
+		// The bucket policy can still deny reads
+		assert!(RequestAuthorization::anonymous(&bucket, ip)
+			.check_endpoint(&get_object("private/x", None))
+			.is_err());
+
+		Ok(())
+	}
+}
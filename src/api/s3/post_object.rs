@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 use std::convert::TryInto;
+use std::net::SocketAddr;
 use std::ops::RangeInclusive;
 use std::sync::Arc;
 use std::task::{Context, Poll};
@@ -13,9 +14,15 @@ use multer::{Constraints, Multipart, SizeLimit};
 use serde::Deserialize;
 
 use garage_model::garage::Garage;
+use garage_model::s3::object_table::ObjectTags;
 
+use crate::helpers::Authorization;
+use crate::s3::encryption::EncryptionParams;
 use crate::s3::error::*;
+use crate::s3::object_lock::new_version_lock;
+use crate::s3::policy::RequestAuthorization;
 use crate::s3::put::{get_headers, save_stream};
+use crate::s3::tagging::parse_tagging_xml;
 use crate::s3::xml as s3_xml;
 use crate::signature::payload::{parse_date, verify_v4};
 
@@ -23,6 +30,7 @@ pub async fn handle_post_object(
 	garage: Arc<Garage>,
 	req: Request<Body>,
 	bucket_name: String,
+	client_addr: SocketAddr,
 ) -> Result<Response<Body>, Error> {
 	let boundary = req
 		.headers()
@@ -129,15 +137,17 @@ pub async fn handle_post_object(
 		.resolve_bucket(&bucket_name, &api_key)
 		.await?;
 
-	if !api_key.allow_write(&bucket_id) {
-		return Err(Error::forbidden("Operation is not allowed for this key."));
-	}
-
 	let bucket = garage
 		.bucket_helper()
 		.get_existing_bucket(bucket_id)
 		.await?;
 
+	RequestAuthorization::new(&api_key, &bucket, client_addr.ip()).check(
+		Authorization::Write,
+		"s3:PutObject",
+		Some(&key),
+	)?;
+
 	let decoded_policy = base64::decode(&policy).ok_or_bad_request("Invalid policy")?;
 	let decoded_policy: Policy =
 		serde_json::from_slice(&decoded_policy).ok_or_bad_request("Invalid policy")?;
@@ -226,16 +236,26 @@ pub async fn handle_post_object(
 	}
 
 	let headers = get_headers(&params)?;
+	let encryption = EncryptionParams::new_from_headers(&params)?;
+	let tags = match params.get("tagging") {
+		Some(tagging) => parse_tagging_xml(tagging.as_bytes())?,
+		None => ObjectTags::default(),
+	};
+	let lock = new_version_lock(&bucket, &params)?;
 
 	let stream = field.map(|r| r.map_err(Into::into));
 	let (_, md5) = save_stream(
 		garage,
 		headers,
+		encryption,
+		tags,
+		lock,
 		StreamLimiter::new(stream, conditions.content_length),
 		&bucket,
 		&key,
 		None,
 		None,
+		None,
 	)
 	.await?;
 
@@ -3,7 +3,7 @@ use std::sync::Arc;
 
 use futures::prelude::*;
 use hyper::body::{Body, Bytes};
-use hyper::header::{HeaderMap, HeaderValue};
+use hyper::header::{HeaderMap, HeaderValue, IF_MATCH, IF_NONE_MATCH};
 use hyper::{Request, Response};
 use md5::{digest::generic_array::*, Digest as Md5Digest, Md5};
 use sha2::Sha256;
@@ -16,11 +16,13 @@ use opentelemetry::{
 use garage_rpc::netapp::bytes_buf::BytesBuf;
 use garage_table::*;
 use garage_util::async_hash::*;
+use garage_util::crdt::Lww;
 use garage_util::data::*;
 use garage_util::error::Error as GarageError;
 use garage_util::time::*;
 
-use garage_block::manager::INLINE_THRESHOLD;
+use garage_block::compression::Compression;
+use garage_block::manager::{BlockWriteParams, INLINE_THRESHOLD};
 use garage_model::bucket_table::Bucket;
 use garage_model::garage::Garage;
 use garage_model::index_counter::CountedItem;
@@ -28,7 +30,12 @@ use garage_model::s3::block_ref_table::*;
 use garage_model::s3::object_table::*;
 use garage_model::s3::version_table::*;
 
+use crate::s3::cdc::FastCdc;
+use crate::s3::encryption::EncryptionParams;
 use crate::s3::error::*;
+use crate::s3::get::add_version_id_header;
+use crate::s3::object_lock::new_version_lock;
+use crate::s3::tagging::parse_tagging_header;
 use crate::s3::xml as s3_xml;
 use crate::signature::verify_signed_content;
 
@@ -43,6 +50,14 @@ pub async fn handle_put(
 	let headers = get_headers(req.headers())?;
 	debug!("Object headers: {:?}", headers);
 
+	let encryption = EncryptionParams::new_from_headers(req.headers())?;
+	let tags = parse_tagging_header(req.headers())?;
+	let lock = new_version_lock(bucket, req.headers())?;
+
+	let conditional = WritePreconditions::parse(req.headers())?
+		.check(&garage, bucket.id, key)
+		.await?;
+
 	let content_md5 = match req.headers().get("content-md5") {
 		Some(x) => Some(x.to_str()?.to_string()),
 		None => None,
@@ -51,42 +66,78 @@ pub async fn handle_put(
 	let (_head, body) = req.into_parts();
 	let body = body.map_err(Error::from);
 
-	save_stream(
-		garage,
+	let (uuid, etag) = save_stream(
+		garage.clone(),
 		headers,
+		encryption.clone(),
+		tags,
+		lock,
 		body,
 		bucket,
 		key,
 		content_md5,
 		content_sha256,
+		conditional.as_ref(),
 	)
-	.await
-	.map(|(uuid, md5)| put_response(uuid, md5))
+	.await?;
+
+	let versioned = bucket
+		.params()
+		.map(|p| p.versioning_enabled())
+		.unwrap_or(false);
+
+	Ok(put_response(uuid, versioned, etag, &encryption))
 }
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 	garage: Arc<Garage>,
-	headers: ObjectVersionHeaders,
+	mut headers: ObjectVersionHeaders,
+	encryption: EncryptionParams,
+	tags: ObjectTags,
+	lock: ObjectVersionLock,
 	body: S,
 	bucket: &Bucket,
 	key: &str,
 	content_md5: Option<String>,
 	content_sha256: Option<FixedBytes32>,
+	conditional: Option<&ConditionalWrite>,
 ) -> Result<(Uuid, String), Error> {
 	// Generate identity of new version
 	let version_uuid = gen_uuid();
 	let version_timestamp = now_msec();
+	let versioned = bucket
+		.params()
+		.map(|p| p.versioning_enabled())
+		.unwrap_or(false);
+	let tags = Lww::new(tags);
+
+	headers.encryption = encryption.object_encryption();
 
-	let mut chunker = StreamChunker::new(body, garage.config.block_size);
+	let mut chunker = StreamChunker::new(
+		body,
+		garage.config.block_size,
+		content_defined_chunking(bucket, &encryption),
+	);
 	let first_block = chunker.next().await?.unwrap_or_default();
 
+	let mut object_version = ObjectVersion {
+		uuid: version_uuid,
+		timestamp: version_timestamp,
+		state: ObjectVersionState::Uploading(headers.clone()),
+		versioned,
+		tags,
+		lock,
+		conditional_write: None,
+	};
+
 	// If body is small enough, store it directly in the object table
 	// as "inline data". We can then return immediately.
 	if first_block.len() < INLINE_THRESHOLD {
 		let mut md5sum = Md5::new();
 		md5sum.update(&first_block[..]);
 		let data_md5sum = md5sum.finalize();
-		let data_md5sum_hex = hex::encode(data_md5sum);
+		let etag = encryption.etag_from_md5(&data_md5sum);
 
 		let data_sha256sum = sha256sum(&first_block[..]);
 		let size = first_block.len() as u64;
@@ -100,32 +151,29 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 
 		check_quotas(&garage, bucket, key, size).await?;
 
-		let object_version = ObjectVersion {
-			uuid: version_uuid,
-			timestamp: version_timestamp,
-			state: ObjectVersionState::Complete(ObjectVersionData::Inline(
-				ObjectVersionMeta {
-					headers,
-					size,
-					etag: data_md5sum_hex.clone(),
-				},
-				first_block.to_vec(),
-			)),
-		};
+		if let Some(conditional) = conditional {
+			conditional
+				.check_no_concurrent_write(&garage, bucket.id, key, &object_version)
+				.await?;
+		}
+
+		object_version.state = ObjectVersionState::Complete(ObjectVersionData::Inline(
+			ObjectVersionMeta {
+				headers,
+				size,
+				etag: etag.clone(),
+			},
+			encryption.encrypt_block(first_block)?.to_vec(),
+		));
 
 		let object = Object::new(bucket.id, key.into(), vec![object_version]);
 		garage.object_table.insert(&object).await?;
 
-		return Ok((version_uuid, data_md5sum_hex));
+		return Ok((version_uuid, etag));
 	}
 
 	// Write version identifier in object table so that we have a trace
 	// that we are uploading something
-	let mut object_version = ObjectVersion {
-		uuid: version_uuid,
-		timestamp: version_timestamp,
-		state: ObjectVersionState::Uploading(headers.clone()),
-	};
 	let object = Object::new(bucket.id, key.into(), vec![object_version.clone()]);
 	garage.object_table.insert(&object).await?;
 
@@ -137,15 +185,14 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 	garage.version_table.insert(&version).await?;
 
 	// Transfer data and verify checksum
-	let first_block_hash = async_blake2sum(first_block.clone()).await;
-
 	let tx_result = (|| async {
-		let (total_size, data_md5sum, data_sha256sum) = read_and_put_blocks(
+		let (total_size, data_md5sum, data_sha256sum, first_block_hash) = read_and_put_blocks(
 			&garage,
 			&version,
+			&encryption,
+			block_write_params(bucket, &encryption),
 			1,
 			first_block,
-			first_block_hash,
 			&mut chunker,
 		)
 		.await?;
@@ -159,12 +206,12 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 
 		check_quotas(&garage, bucket, key, total_size).await?;
 
-		Ok((total_size, data_md5sum))
+		Ok((total_size, data_md5sum, first_block_hash))
 	})()
 	.await;
 
 	// If something went wrong, clean up
-	let (total_size, md5sum_arr) = match tx_result {
+	let (total_size, md5sum_arr, first_block_hash) = match tx_result {
 		Ok(rv) => rv,
 		Err(e) => {
 			// Mark object as aborted, this will free the blocks further down
@@ -175,20 +222,26 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 		}
 	};
 
+	if let Some(conditional) = conditional {
+		conditional
+			.check_no_concurrent_write(&garage, bucket.id, key, &object_version)
+			.await?;
+	}
+
 	// Save final object state, marked as Complete
-	let md5sum_hex = hex::encode(md5sum_arr);
+	let etag = encryption.etag_from_md5(&md5sum_arr);
 	object_version.state = ObjectVersionState::Complete(ObjectVersionData::FirstBlock(
 		ObjectVersionMeta {
 			headers,
 			size: total_size,
-			etag: md5sum_hex.clone(),
+			etag: etag.clone(),
 		},
 		first_block_hash,
 	));
 	let object = Object::new(bucket.id, key.into(), vec![object_version]);
 	garage.object_table.insert(&object).await?;
 
-	Ok((version_uuid, md5sum_hex))
+	Ok((version_uuid, etag))
 }
 
 /// Validate MD5 sum against content-md5 header
@@ -218,6 +271,24 @@ fn ensure_checksum_matches(
 	Ok(())
 }
 
+/// Parameters for writing the data blocks of an object to a bucket: the compression
+/// and replication given by the bucket's policies, if any. Encrypted data doesn't
+/// compress, so blocks of objects encrypted with SSE-C are never compressed.
+pub(crate) fn block_write_params(
+	bucket: &Bucket,
+	encryption: &EncryptionParams,
+) -> BlockWriteParams {
+	let compression = if encryption.is_encrypted() {
+		Some(Compression::None)
+	} else {
+		bucket.params().and_then(|p| *p.compression.get())
+	};
+	BlockWriteParams {
+		compression,
+		replication: bucket.block_replication(),
+	}
+}
+
 /// Check that inserting this object with this size doesn't exceed bucket quotas
 async fn check_quotas(
 	garage: &Arc<Garage>,
@@ -276,14 +347,18 @@ async fn check_quotas(
 	Ok(())
 }
 
+/// Store the blocks of an object or of a part of a multipart upload, encrypting them
+/// if necessary. Returns the total size of the data, its MD5 and SHA256 checksums,
+/// and the hash of the first block.
 async fn read_and_put_blocks<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 	garage: &Garage,
 	version: &Version,
+	encryption: &EncryptionParams,
+	block_params: BlockWriteParams,
 	part_number: u64,
 	first_block: Bytes,
-	first_block_hash: Hash,
 	chunker: &mut StreamChunker<S>,
-) -> Result<(u64, GenericArray<u8, typenum::U16>, Hash), Error> {
+) -> Result<(u64, GenericArray<u8, typenum::U16>, Hash, Hash), Error> {
 	let tracer = opentelemetry::global::tracer("garage");
 
 	let md5hasher = AsyncHasher::<Md5>::new();
@@ -298,18 +373,24 @@ async fn read_and_put_blocks<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 	))
 	.await;
 
-	let mut next_offset = first_block.len();
+	let first_block_len = first_block.len();
+	let first_block = encryption.encrypt_block(first_block)?;
+	let first_block_hash = async_blake2sum(first_block.clone()).await;
+
+	let mut next_offset = first_block_len;
 	let mut put_curr_version_block = put_block_meta(
 		garage,
 		version,
 		part_number,
 		0,
 		first_block_hash,
-		first_block.len() as u64,
+		first_block_len as u64,
+		&block_params,
 	);
-	let mut put_curr_block = garage
-		.block_manager
-		.rpc_put_block(first_block_hash, first_block);
+	let mut put_curr_block =
+		garage
+			.block_manager
+			.rpc_put_block(first_block_hash, first_block, block_params);
 
 	loop {
 		let (_, _, next_block) = futures::try_join!(
@@ -318,16 +399,21 @@ async fn read_and_put_blocks<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 			chunker.next(),
 		)?;
 		if let Some(block) = next_block {
-			let (_, _, block_hash) = futures::future::join3(
+			let block_len = block.len();
+			let (_, _, encrypted_block) = futures::future::join3(
 				md5hasher.update(block.clone()),
 				sha256hasher.update(block.clone()),
-				async_blake2sum(block.clone()),
+				async {
+					let block = encryption.encrypt_block(block)?;
+					let block_hash = async_blake2sum(block.clone()).await;
+					Ok::<_, Error>((block, block_hash))
+				},
 			)
 			.with_context(Context::current_with_span(
 				tracer.start("Hash block (md5, sha256, blake2)"),
 			))
 			.await;
-			let block_len = block.len();
+			let (block, block_hash) = encrypted_block?;
 			put_curr_version_block = put_block_meta(
 				garage,
 				version,
@@ -335,8 +421,11 @@ async fn read_and_put_blocks<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 				next_offset as u64,
 				block_hash,
 				block_len as u64,
+				&block_params,
 			);
-			put_curr_block = garage.block_manager.rpc_put_block(block_hash, block);
+			put_curr_block = garage
+				.block_manager
+				.rpc_put_block(block_hash, block, block_params);
 			next_offset += block_len;
 		} else {
 			break;
@@ -349,7 +438,7 @@ async fn read_and_put_blocks<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 	let data_sha256sum = sha256hasher.finalize().await;
 	let data_sha256sum = Hash::try_from(&data_sha256sum[..]).unwrap();
 
-	Ok((total_size, data_md5sum, data_sha256sum))
+	Ok((total_size, data_md5sum, data_sha256sum, first_block_hash))
 }
 
 async fn put_block_meta(
@@ -359,6 +448,7 @@ async fn put_block_meta(
 	offset: u64,
 	hash: Hash,
 	size: u64,
+	block_params: &BlockWriteParams,
 ) -> Result<(), GarageError> {
 	let mut version = version.clone();
 	version.blocks.put(
@@ -373,6 +463,7 @@ async fn put_block_meta(
 		block: hash,
 		version: version.uuid,
 		deleted: false.into(),
+		replication_factor: block_params.replication.map(|r| r.replication_factor),
 	};
 
 	futures::try_join!(
@@ -387,15 +478,26 @@ struct StreamChunker<S: Stream<Item = Result<Bytes, Error>>> {
 	read_all: bool,
 	block_size: usize,
 	buf: BytesBuf,
+	/// If set, blocks are cut at content-defined boundaries
+	/// instead of being all of size block_size
+	cdc: Option<FastCdc>,
 }
 
 impl<S: Stream<Item = Result<Bytes, Error>> + Unpin> StreamChunker<S> {
-	fn new(stream: S, block_size: usize) -> Self {
+	fn new(stream: S, block_size: usize, content_defined: bool) -> Self {
+		// The first block must be larger than INLINE_THRESHOLD if there is more data,
+		// as an object is stored inline if its first block is smaller
+		let cdc = if content_defined {
+			Some(FastCdc::new(block_size, INLINE_THRESHOLD))
+		} else {
+			None
+		};
 		Self {
 			stream,
 			read_all: false,
 			block_size,
 			buf: BytesBuf::new(),
+			cdc,
 		}
 	}
 
@@ -411,17 +513,49 @@ impl<S: Stream<Item = Result<Bytes, Error>> + Unpin> StreamChunker<S> {
 		}
 
 		if self.buf.is_empty() {
-			Ok(None)
-		} else {
-			Ok(Some(self.buf.take_max(self.block_size)))
+			return Ok(None);
+		}
+
+		let block = self.buf.take_max(self.block_size);
+		match &self.cdc {
+			Some(cdc) => {
+				let len = cdc.cut_point(&block[..]);
+				if len < block.len() {
+					// Put back data after the cut point in front of the buffer
+					let rest = std::mem::replace(&mut self.buf, BytesBuf::new());
+					self.buf.extend(block.slice(len..));
+					for slice in rest.into_slices() {
+						self.buf.extend(slice);
+					}
+				}
+				Ok(Some(block.slice(..len)))
+			}
+			None => Ok(Some(block)),
 		}
 	}
 }
 
-pub fn put_response(version_uuid: Uuid, md5sum_hex: String) -> Response<Body> {
-	Response::builder()
-		.header("x-amz-version-id", hex::encode(version_uuid))
-		.header("ETag", format!("\"{}\"", md5sum_hex))
+/// Whether uploads to a bucket are cut in blocks at content-defined boundaries.
+/// This is never the case for encrypted uploads, as block boundaries would
+/// leak information about their content.
+fn content_defined_chunking(bucket: &Bucket, encryption: &EncryptionParams) -> bool {
+	!encryption.is_encrypted()
+		&& bucket
+			.params()
+			.map(|p| *p.content_defined_chunking.get())
+			.unwrap_or(false)
+}
+
+pub fn put_response(
+	version_uuid: Uuid,
+	versioned: bool,
+	etag: String,
+	encryption: &EncryptionParams,
+) -> Response<Body> {
+	let resp = Response::builder().header("ETag", format!("\"{}\"", etag));
+	let resp = add_version_id_header(resp, version_uuid, versioned);
+	encryption
+		.add_response_headers(resp)
 		.body(Body::from(vec![]))
 		.unwrap()
 }
@@ -430,26 +564,38 @@ pub async fn handle_create_multipart_upload(
 	garage: Arc<Garage>,
 	req: &Request<Body>,
 	bucket_name: &str,
-	bucket_id: Uuid,
+	bucket: &Bucket,
 	key: &str,
 ) -> Result<Response<Body>, Error> {
 	let version_uuid = gen_uuid();
-	let headers = get_headers(req.headers())?;
+	let mut headers = get_headers(req.headers())?;
+
+	// If the upload is encrypted, all parts will have to be uploaded
+	// using the same key
+	let encryption = EncryptionParams::new_from_headers(req.headers())?;
+	headers.encryption = encryption.object_encryption();
 
 	// Create object in object table
 	let object_version = ObjectVersion {
 		uuid: version_uuid,
 		timestamp: now_msec(),
 		state: ObjectVersionState::Uploading(headers),
+		versioned: bucket
+			.params()
+			.map(|p| p.versioning_enabled())
+			.unwrap_or(false),
+		tags: Lww::new(parse_tagging_header(req.headers())?),
+		lock: new_version_lock(bucket, req.headers())?,
+		conditional_write: None,
 	};
-	let object = Object::new(bucket_id, key.to_string(), vec![object_version]);
+	let object = Object::new(bucket.id, key.to_string(), vec![object_version]);
 	garage.object_table.insert(&object).await?;
 
 	// Insert empty version so that block_ref entries refer to something
 	// (they are inserted concurrently with blocks in the version table, so
 	// there is the possibility that they are inserted before the version table
 	// is created, in which case it is allowed to delete them, e.g. in repair_*)
-	let version = Version::new(version_uuid, bucket_id, key.into(), false);
+	let version = Version::new(version_uuid, bucket.id, key.into(), false);
 	garage.version_table.insert(&version).await?;
 
 	// Send success response
@@ -461,18 +607,21 @@ pub async fn handle_create_multipart_upload(
 	};
 	let xml = s3_xml::to_xml_with_header(&result)?;
 
-	Ok(Response::new(Body::from(xml.into_bytes())))
+	Ok(encryption
+		.add_response_headers(Response::builder())
+		.body(Body::from(xml.into_bytes()))?)
 }
 
 pub async fn handle_put_part(
 	garage: Arc<Garage>,
 	req: Request<Body>,
-	bucket_id: Uuid,
+	bucket: &Bucket,
 	key: &str,
 	part_number: u64,
 	upload_id: &str,
 	content_sha256: Option<Hash>,
 ) -> Result<Response<Body>, Error> {
+	let bucket_id = bucket.id;
 	let version_uuid = decode_upload_id(upload_id)?;
 
 	let content_md5 = match req.headers().get("content-md5") {
@@ -480,11 +629,17 @@ pub async fn handle_put_part(
 		None => None,
 	};
 
+	let encryption = EncryptionParams::new_from_headers(req.headers())?;
+
 	// Read first chuck, and at the same time try to get object to see if it exists
 	let key = key.to_string();
 
 	let body = req.into_body().map_err(Error::from);
-	let mut chunker = StreamChunker::new(body, garage.config.block_size);
+	let mut chunker = StreamChunker::new(
+		body,
+		garage.config.block_size,
+		content_defined_chunking(bucket, &encryption),
+	);
 
 	let (object, version, first_block) = futures::try_join!(
 		garage
@@ -502,13 +657,17 @@ pub async fn handle_put_part(
 	let first_block = first_block.ok_or_bad_request("Empty body")?;
 	let object = object.ok_or_bad_request("Object not found")?;
 
-	if !object
+	let object_version = object
 		.versions()
 		.iter()
-		.any(|v| v.uuid == version_uuid && v.is_uploading())
-	{
-		return Err(Error::NoSuchUpload);
-	}
+		.find(|v| v.uuid == version_uuid && v.is_uploading())
+		.ok_or(Error::NoSuchUpload)?;
+
+	// Check part is encrypted with the same key as the rest of the upload
+	let encryption = match &object_version.state {
+		ObjectVersionState::Uploading(headers) => encryption.check_decrypt(&headers.encryption)?,
+		_ => unreachable!(),
+	};
 
 	// Check part hasn't already been uploaded
 	if let Some(v) = version {
@@ -523,14 +682,13 @@ pub async fn handle_put_part(
 	// Copy block to store
 	let version = Version::new(version_uuid, bucket_id, key, false);
 
-	let first_block_hash = async_blake2sum(first_block.clone()).await;
-
-	let (_, data_md5sum, data_sha256sum) = read_and_put_blocks(
+	let (_, data_md5sum, data_sha256sum, _) = read_and_put_blocks(
 		&garage,
 		&version,
+		&encryption,
+		block_write_params(bucket, &encryption),
 		part_number,
 		first_block,
-		first_block_hash,
 		&mut chunker,
 	)
 	.await?;
@@ -544,15 +702,14 @@ pub async fn handle_put_part(
 	)?;
 
 	// Store part etag in version
-	let data_md5sum_hex = hex::encode(data_md5sum);
+	let etag = encryption.etag_from_md5(&data_md5sum);
 	let mut version = version;
-	version
-		.parts_etags
-		.put(part_number, data_md5sum_hex.clone());
+	version.parts_etags.put(part_number, etag.clone());
 	garage.version_table.insert(&version).await?;
 
-	let response = Response::builder()
-		.header("ETag", format!("\"{}\"", data_md5sum_hex))
+	let response = encryption
+		.add_response_headers(Response::builder())
+		.header("ETag", format!("\"{}\"", etag))
 		.body(Body::empty())
 		.unwrap();
 	Ok(response)
@@ -567,6 +724,8 @@ pub async fn handle_complete_multipart_upload(
 	upload_id: &str,
 	content_sha256: Option<Hash>,
 ) -> Result<Response<Body>, Error> {
+	let preconditions = WritePreconditions::parse(req.headers())?;
+
 	let body = hyper::body::to_bytes(req.into_body()).await?;
 
 	if let Some(content_sha256) = content_sha256 {
@@ -603,8 +762,10 @@ pub async fn handle_complete_multipart_upload(
 		return Err(Error::bad_request("No data was uploaded"));
 	}
 
-	let headers = match object_version.state {
-		ObjectVersionState::Uploading(headers) => headers,
+	let conditional = preconditions.check(&garage, bucket.id, &key).await?;
+
+	let headers = match &object_version.state {
+		ObjectVersionState::Uploading(headers) => headers.clone(),
 		_ => unreachable!(),
 	};
 
@@ -687,6 +848,12 @@ pub async fn handle_complete_multipart_upload(
 		return Err(e);
 	}
 
+	if let Some(conditional) = &conditional {
+		conditional
+			.check_no_concurrent_write(&garage, bucket.id, &key, &object_version)
+			.await?;
+	}
+
 	// Write final object version
 	object_version.state = ObjectVersionState::Complete(ObjectVersionData::FirstBlock(
 		ObjectVersionMeta {
@@ -697,6 +864,7 @@ pub async fn handle_complete_multipart_upload(
 		version.blocks.items()[0].1.hash,
 	));
 
+	let versioned = object_version.versioned;
 	let final_object = Object::new(bucket.id, key.clone(), vec![object_version]);
 	garage.object_table.insert(&final_object).await?;
 
@@ -710,7 +878,8 @@ pub async fn handle_complete_multipart_upload(
 	};
 	let xml = s3_xml::to_xml_with_header(&result)?;
 
-	Ok(Response::new(Body::from(xml.into_bytes())))
+	let resp = add_version_id_header(Response::builder(), version_uuid, versioned);
+	Ok(resp.body(Body::from(xml.into_bytes()))?)
 }
 
 pub async fn handle_abort_multipart_upload(
@@ -743,6 +912,219 @@ pub async fn handle_abort_multipart_upload(
 	Ok(Response::new(Body::from(vec![])))
 }
 
+// ---- Conditional writes ----
+
+/// Time (in milliseconds) after which the mark left on a version by a
+/// conditional write is no longer taken into account by other conditional writes
+const CONDITIONAL_WRITE_TIMEOUT: u64 = 60_000;
+
+/// Preconditions of a write request, given in the If-Match and If-None-Match
+/// headers, that are evaluated against the current version of the object.
+///
+/// As Garage has no way of atomically checking a condition and writing a new
+/// version, the preconditions are checked before the write. The new version is
+/// then first written as being uploaded, and before it is marked as complete,
+/// it is marked as being written by a conditional write and we check that no
+/// other version of the object has been completed, or started being written by
+/// another conditional write, concurrently. If that is the case, the new version is aborted
+/// and the request fails with 412 Precondition Failed: of several concurrent
+/// conditional writes to the same object, at most one succeeds, and the version
+/// they were checked against is never replaced by a version that is then aborted.
+pub(crate) struct WritePreconditions {
+	/// If Some, the current version must have one of these ETags,
+	/// or exist if the list contains `*`
+	if_match: Option<Vec<String>>,
+	/// If true, the object must not exist (`If-None-Match: *`)
+	if_none_match: bool,
+}
+
+/// A write whose preconditions have been checked against the current
+/// version of the object
+pub(crate) struct ConditionalWrite {
+	/// The version against which preconditions were checked,
+	/// None if the object did not exist
+	checked_version: Option<CheckedVersion>,
+}
+
+struct CheckedVersion {
+	uuid: Uuid,
+	timestamp: u64,
+}
+
+impl WritePreconditions {
+	pub(crate) fn parse(headers: &HeaderMap<HeaderValue>) -> Result<Self, Error> {
+		let if_match = headers
+			.get(IF_MATCH)
+			.map(|x| x.to_str())
+			.transpose()?
+			.map(|x| {
+				x.split(',')
+					.map(|m| m.trim().trim_matches('"').to_string())
+					.collect::<Vec<_>>()
+			});
+		let if_none_match = match headers.get(IF_NONE_MATCH) {
+			Some(x) if x.to_str()?.trim() == "*" => true,
+			Some(_) => {
+				return Err(Error::NotImplemented(
+					"If-None-Match is only supported with value * for write requests".into(),
+				))
+			}
+			None => false,
+		};
+		if if_match.is_some() && if_none_match {
+			return Err(Error::bad_request(
+				"Cannot specify both If-Match and If-None-Match",
+			));
+		}
+		Ok(Self {
+			if_match,
+			if_none_match,
+		})
+	}
+
+	fn is_empty(&self) -> bool {
+		self.if_match.is_none() && !self.if_none_match
+	}
+
+	/// Check the preconditions against the current version of the object.
+	/// Returns None if the write is not conditional.
+	pub(crate) async fn check(
+		&self,
+		garage: &Garage,
+		bucket_id: Uuid,
+		key: &str,
+	) -> Result<Option<ConditionalWrite>, Error> {
+		if self.is_empty() {
+			return Ok(None);
+		}
+
+		let object = garage
+			.object_table
+			.get(&bucket_id, &key.to_string())
+			.await?;
+		let current = object
+			.as_ref()
+			.and_then(|o| o.versions().iter().rev().find(|v| v.is_complete()));
+
+		let current_etag = current.and_then(|v| match &v.state {
+			ObjectVersionState::Complete(ObjectVersionData::Inline(meta, _))
+			| ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _)) => {
+				Some(meta.etag.as_str())
+			}
+			_ => None,
+		});
+
+		match (&self.if_match, current_etag) {
+			(Some(_), None) => return Err(Error::NoSuchKey),
+			(Some(im), Some(etag)) if !im.iter().any(|x| x == etag || x == "*") => {
+				return Err(Error::PreconditionFailed)
+			}
+			_ => (),
+		}
+		if self.if_none_match && current_etag.is_some() {
+			return Err(Error::PreconditionFailed);
+		}
+
+		Ok(Some(ConditionalWrite {
+			checked_version: current.map(|v| CheckedVersion {
+				uuid: v.uuid,
+				timestamp: v.timestamp,
+			}),
+		}))
+	}
+}
+
+impl ConditionalWrite {
+	/// Check that `new_version`, which is being uploaded, can be marked as complete.
+	/// If it can't, abort it and fail with 412 Precondition Failed.
+	pub(crate) async fn check_no_concurrent_write(
+		&self,
+		garage: &Garage,
+		bucket_id: Uuid,
+		key: &str,
+		new_version: &ObjectVersion,
+	) -> Result<(), Error> {
+		// Mark the new version as being written by a conditional write
+		// before reading the object, so that concurrent conditional writes see it
+		let mark = now_msec();
+		let mut marked_version = new_version.clone();
+		marked_version.conditional_write = Some(mark);
+		let object = Object::new(bucket_id, key.to_string(), vec![marked_version]);
+		garage.object_table.insert(&object).await?;
+
+		let object = garage
+			.object_table
+			.get(&bucket_id, &key.to_string())
+			.await?;
+
+		// If the check took too long, concurrent conditional writes may already
+		// consider our mark as stale and not take it into account
+		let now = now_msec();
+		if now < mark + CONDITIONAL_WRITE_TIMEOUT / 2
+			&& !self.has_concurrent_write(object.as_ref(), new_version.uuid, now)
+		{
+			return Ok(());
+		}
+
+		// The new version may already have been removed from the object,
+		// if a more recent version was completed concurrently
+		if let Some(v) = object
+			.as_ref()
+			.and_then(|o| o.find_version(&new_version.uuid))
+		{
+			let mut aborted_version = v.clone();
+			aborted_version.state = ObjectVersionState::Aborted;
+			let object = Object::new(bucket_id, key.to_string(), vec![aborted_version]);
+			garage.object_table.insert(&object).await?;
+		}
+
+		Err(Error::PreconditionFailed)
+	}
+
+	/// Whether, in `object`, another version has been completed or another
+	/// conditional write has started since the preconditions were checked,
+	/// or the new version is older than the version they were checked against
+	/// (which can happen for multipart uploads).
+	///
+	/// Versions being uploaded by other conditional writes count as concurrent
+	/// writes: when two conditional writes race, each of them first marks its new
+	/// version and then reads the object, so at least one of them sees the other
+	/// and fails. Both may fail, in which case the object is left unchanged.
+	/// Other versions being uploaded, as well as marks older than
+	/// CONDITIONAL_WRITE_TIMEOUT, e.g. left by a crashed writer, are ignored.
+	fn has_concurrent_write(&self, object: Option<&Object>, new_version: Uuid, now: u64) -> bool {
+		let object = match object {
+			Some(o) => o,
+			None => return true,
+		};
+		let new_version = match object.find_version(&new_version) {
+			Some(v) if v.is_uploading() => v,
+			// Removed by the completion of a more recent version, or already aborted
+			_ => return true,
+		};
+
+		if let Some(cv) = &self.checked_version {
+			if new_version.timestamp < cv.timestamp {
+				return true;
+			}
+		}
+
+		object.versions().iter().any(|v| {
+			let is_concurrent_write = v.is_complete()
+				|| (v.is_uploading()
+					&& v.conditional_write
+						.map(|t| t + CONDITIONAL_WRITE_TIMEOUT > now)
+						.unwrap_or(false));
+			v.uuid != new_version.uuid
+				&& is_concurrent_write
+				&& match &self.checked_version {
+					Some(cv) => v.uuid != cv.uuid && v.timestamp >= cv.timestamp,
+					None => true,
+				}
+		})
+	}
+}
+
 fn get_mime_type(headers: &HeaderMap<HeaderValue>) -> Result<String, Error> {
 	Ok(headers
 		.get(hyper::header::CONTENT_TYPE)
@@ -793,6 +1175,7 @@ pub(crate) fn get_headers(headers: &HeaderMap<HeaderValue>) -> Result<ObjectVers
 	Ok(ObjectVersionHeaders {
 		content_type,
 		other,
+		encryption: None,
 	})
 }
 
@@ -846,3 +1229,181 @@ fn parse_complete_multipart_upload_body(
 
 	Some(parts)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use garage_table::crdt::Crdt;
+
+	const NOW: u64 = 1_000_000;
+
+	fn version(uuid: u8, timestamp: u64, state: ObjectVersionState) -> ObjectVersion {
+		ObjectVersion {
+			uuid: [uuid; 32].into(),
+			timestamp,
+			state,
+			versioned: false,
+			tags: Lww::new(ObjectTags::default()),
+			lock: ObjectVersionLock::default(),
+			conditional_write: None,
+		}
+	}
+
+	fn uploading_unmarked(uuid: u8, timestamp: u64) -> ObjectVersion {
+		version(
+			uuid,
+			timestamp,
+			ObjectVersionState::Uploading(ObjectVersionHeaders {
+				content_type: "blob".into(),
+				other: BTreeMap::new(),
+				encryption: None,
+			}),
+		)
+	}
+
+	/// A version being uploaded by a conditional write that is checking
+	/// for concurrent writes
+	fn uploading(uuid: u8, timestamp: u64) -> ObjectVersion {
+		let mut v = uploading_unmarked(uuid, timestamp);
+		v.conditional_write = Some(NOW);
+		v
+	}
+
+	fn complete(uuid: u8, timestamp: u64) -> ObjectVersion {
+		version(
+			uuid,
+			timestamp,
+			ObjectVersionState::Complete(ObjectVersionData::FirstBlock(
+				ObjectVersionMeta {
+					headers: ObjectVersionHeaders {
+						content_type: "blob".into(),
+						other: BTreeMap::new(),
+						encryption: None,
+					},
+					size: 1 << 20,
+					etag: hex::encode([uuid]),
+				},
+				[uuid; 32].into(),
+			)),
+		)
+	}
+
+	fn object(versions: Vec<ObjectVersion>) -> Object {
+		let mut object = Object::new([0u8; 32].into(), "key".into(), vec![]);
+		for v in versions {
+			object.merge(&Object::new([0u8; 32].into(), "key".into(), vec![v]));
+		}
+		object
+	}
+
+	fn checked_against(uuid: u8, timestamp: u64) -> ConditionalWrite {
+		ConditionalWrite {
+			checked_version: Some(CheckedVersion {
+				uuid: [uuid; 32].into(),
+				timestamp,
+			}),
+		}
+	}
+
+	#[test]
+	fn test_conditional_write_alone() {
+		let cond = checked_against(1, 100);
+		let obj = object(vec![complete(1, 100), uploading(2, 200)]);
+		assert!(!cond.has_concurrent_write(Some(&obj), [2u8; 32].into(), NOW));
+
+		// Versions older than the checked version don't matter
+		let obj = object(vec![uploading(3, 50), complete(1, 100), uploading(2, 200)]);
+		assert!(!cond.has_concurrent_write(Some(&obj), [2u8; 32].into(), NOW));
+
+		// If-None-Match on an object that doesn't exist
+		let cond = ConditionalWrite {
+			checked_version: None,
+		};
+		let obj = object(vec![uploading(2, 200)]);
+		assert!(!cond.has_concurrent_write(Some(&obj), [2u8; 32].into(), NOW));
+	}
+
+	#[test]
+	fn test_concurrent_conditional_writers() {
+		// Two writers A (uuid 2) and B (uuid 3) check their preconditions against
+		// version 1, then write their new version as being uploaded.
+		let cond_a = checked_against(1, 100);
+		let cond_b = checked_against(1, 100);
+
+		// A reads the object before B's version is written: A can complete
+		let seen_by_a = object(vec![complete(1, 100), uploading(2, 200)]);
+		assert!(!cond_a.has_concurrent_write(Some(&seen_by_a), [2u8; 32].into(), NOW));
+
+		// B then necessarily sees A's version, whether it has been completed or not,
+		// and must fail
+		let seen_by_b = object(vec![complete(1, 100), uploading(2, 200), uploading(3, 150)]);
+		assert!(cond_b.has_concurrent_write(Some(&seen_by_b), [3u8; 32].into(), NOW));
+		let seen_by_b = object(vec![complete(1, 100), uploading(3, 150), complete(2, 200)]);
+		assert!(cond_b.has_concurrent_write(Some(&seen_by_b), [3u8; 32].into(), NOW));
+
+		// If both see each other, they both fail, and version 1 is still
+		// the current version of the object once they are aborted
+		let seen_by_both = object(vec![complete(1, 100), uploading(2, 200), uploading(3, 150)]);
+		assert!(cond_a.has_concurrent_write(Some(&seen_by_both), [2u8; 32].into(), NOW));
+		assert!(cond_b.has_concurrent_write(Some(&seen_by_both), [3u8; 32].into(), NOW));
+
+		let mut aborted_a = uploading(2, 200);
+		aborted_a.state = ObjectVersionState::Aborted;
+		let mut aborted_b = uploading(3, 150);
+		aborted_b.state = ObjectVersionState::Aborted;
+		let mut after = seen_by_both;
+		after.merge(&object(vec![aborted_a, aborted_b]));
+		assert_eq!(
+			after.current_version().map(|v| v.uuid),
+			Some([1u8; 32].into())
+		);
+	}
+
+	#[test]
+	fn test_conditional_write_after_concurrent_completion() {
+		let cond = checked_against(1, 100);
+
+		// An unconditional write completed concurrently, with a more recent timestamp:
+		// the new version has been removed from the object by the merge
+		let obj = object(vec![complete(1, 100), uploading(2, 200), complete(3, 300)]);
+		assert!(obj.find_version(&[2u8; 32].into()).is_none());
+		assert!(cond.has_concurrent_write(Some(&obj), [2u8; 32].into(), NOW));
+
+		// A multipart upload created before the version the preconditions were
+		// checked against can't be completed
+		let obj = object(vec![uploading(2, 50), complete(1, 100)]);
+		let cond = checked_against(1, 100);
+		assert!(cond.has_concurrent_write(Some(&obj), [2u8; 32].into(), NOW));
+
+		// If-None-Match on an object that was created concurrently
+		let cond = ConditionalWrite {
+			checked_version: None,
+		};
+		let obj = object(vec![complete(3, 150), uploading(2, 200)]);
+		assert!(cond.has_concurrent_write(Some(&obj), [2u8; 32].into(), NOW));
+	}
+
+	#[test]
+	fn test_conditional_write_ignores_other_uploads() {
+		// Uploads that are not conditional writes, e.g. multipart uploads that
+		// were never completed, and stale marks left by crashed conditional
+		// writes are not concurrent writes
+		let mut stale = uploading_unmarked(4, 300);
+		stale.conditional_write = Some(NOW - CONDITIONAL_WRITE_TIMEOUT);
+		let others = vec![uploading_unmarked(3, 250), stale];
+
+		let cond = checked_against(1, 100);
+		let mut versions = vec![complete(1, 100), uploading(2, 200)];
+		versions.extend(others.clone());
+		let obj = object(versions);
+		assert!(!cond.has_concurrent_write(Some(&obj), [2u8; 32].into(), NOW));
+
+		let cond = ConditionalWrite {
+			checked_version: None,
+		};
+		let mut versions = vec![uploading(2, 200)];
+		versions.extend(others);
+		let obj = object(versions);
+		assert!(!cond.has_concurrent_write(Some(&obj), [2u8; 32].into(), NOW));
+	}
+}
@@ -516,7 +516,6 @@ impl Endpoint {
 	}
 
 	/// Get the key the request target. Returns None for requests which don't use a key.
-	#[allow(dead_code)]
 	pub fn get_key(&self) -> Option<&str> {
 		router_match! {
 			@extract
@@ -550,6 +549,37 @@ impl Endpoint {
 		}
 	}
 
+	/// Get the name of the action performed by the request, as used in bucket policies.
+	/// This is the name of the endpoint, except for endpoints that are covered
+	/// by the permission of another action in S3.
+	pub fn policy_action(&self) -> String {
+		let action = match self {
+			Endpoint::HeadBucket {}
+			| Endpoint::ListObjects { .. }
+			| Endpoint::ListObjectsV2 { .. } => "ListBucket",
+			Endpoint::ListObjectVersions { .. } => "ListBucketVersions",
+			Endpoint::ListMultipartUploads { .. } => "ListBucketMultipartUploads",
+			Endpoint::ListParts { .. } => "ListMultipartUploadParts",
+			Endpoint::HeadObject { .. } | Endpoint::SelectObjectContent { .. } => "GetObject",
+			Endpoint::CopyObject { .. }
+			| Endpoint::CreateMultipartUpload { .. }
+			| Endpoint::UploadPart { .. }
+			| Endpoint::UploadPartCopy { .. }
+			| Endpoint::CompleteMultipartUpload { .. } => "PutObject",
+			Endpoint::DeleteObjects {} => "DeleteObject",
+			Endpoint::DeleteBucketCors {} => "PutBucketCors",
+			Endpoint::GetBucketLifecycleConfiguration {} => "GetLifecycleConfiguration",
+			Endpoint::PutBucketLifecycleConfiguration {} | Endpoint::DeleteBucketLifecycle {} => {
+				"PutLifecycleConfiguration"
+			}
+			Endpoint::GetObjectLockConfiguration {} => "GetBucketObjectLockConfiguration",
+			Endpoint::PutObjectLockConfiguration {} => "PutBucketObjectLockConfiguration",
+			Endpoint::ListBuckets => "ListAllMyBuckets",
+			endpoint => endpoint.name(),
+		};
+		format!("s3:{}", action)
+	}
+
 	/// Get the kind of authorization which is required to perform the operation.
 	pub fn authorization_type(&self) -> Authorization {
 		if let Endpoint::ListBuckets = self {
@@ -565,13 +595,11 @@ impl Endpoint {
 				GetBucketEncryption,
 				GetBucketIntelligentTieringConfiguration,
 				GetBucketInventoryConfiguration,
-				GetBucketLifecycleConfiguration,
 				GetBucketLocation,
 				GetBucketLogging,
 				GetBucketMetricsConfiguration,
 				GetBucketNotificationConfiguration,
 				GetBucketOwnershipControls,
-				GetBucketPolicy,
 				GetBucketPolicyStatus,
 				GetBucketReplication,
 				GetBucketRequestPayment,
@@ -610,6 +638,14 @@ impl Endpoint {
 				GetBucketCors,
 				PutBucketCors,
 				DeleteBucketCors,
+				PutBucketVersioning,
+				GetBucketLifecycleConfiguration,
+				PutBucketLifecycleConfiguration,
+				DeleteBucketLifecycle,
+				PutObjectLockConfiguration,
+				GetBucketPolicy,
+				PutBucketPolicy,
+				DeleteBucketPolicy,
 			]
 		};
 		if readonly {
@@ -0,0 +1,204 @@
+//! Encoding of the binary event stream format in which the results
+//! of SelectObjectContent requests are sent to the client.
+//!
+//! Each message is made of a prelude (total length, headers length and CRC32
+//! of these two fields), a list of headers, a payload, and a CRC32
+//! of the whole message.
+
+use bytes::Bytes;
+use serde::Serialize;
+
+use crate::s3::error::*;
+use crate::s3::xml::IntValue;
+
+const HEADER_VALUE_TYPE_STRING: u8 = 7;
+
+/// Counters sent to the client in Stats and Progress events
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SelectStats {
+	/// Number of bytes of the object that were read from storage
+	pub bytes_scanned: u64,
+	/// Number of bytes of the object that were processed, after decompression
+	pub bytes_processed: u64,
+	/// Number of bytes of records that were sent to the client
+	pub bytes_returned: u64,
+}
+
+pub fn records_message(payload: &[u8]) -> Bytes {
+	encode_message(
+		&[
+			(":event-type", "Records"),
+			(":content-type", "application/octet-stream"),
+			(":message-type", "event"),
+		],
+		payload,
+	)
+}
+
+pub fn stats_message(stats: &SelectStats) -> Result<Bytes, Error> {
+	let xml = quick_xml::se::to_string(&Stats::from(stats))?;
+	Ok(encode_message(
+		&[
+			(":event-type", "Stats"),
+			(":content-type", "text/xml"),
+			(":message-type", "event"),
+		],
+		xml.as_bytes(),
+	))
+}
+
+pub fn progress_message(stats: &SelectStats) -> Result<Bytes, Error> {
+	let xml = quick_xml::se::to_string(&Progress::from(stats))?;
+	Ok(encode_message(
+		&[
+			(":event-type", "Progress"),
+			(":content-type", "text/xml"),
+			(":message-type", "event"),
+		],
+		xml.as_bytes(),
+	))
+}
+
+/// Keep-alive message, sent when no records were returned for some time
+pub fn cont_message() -> Bytes {
+	encode_message(&[(":event-type", "Cont"), (":message-type", "event")], &[])
+}
+
+pub fn end_message() -> Bytes {
+	encode_message(&[(":event-type", "End"), (":message-type", "event")], &[])
+}
+
+pub fn error_message(error: &Error) -> Bytes {
+	let mut message = format!("{}", error);
+	if message.len() > u16::MAX as usize {
+		let mut end = u16::MAX as usize;
+		while !message.is_char_boundary(end) {
+			end -= 1;
+		}
+		message.truncate(end);
+	}
+	encode_message(
+		&[
+			(":error-code", error.aws_code()),
+			(":error-message", &message),
+			(":message-type", "error"),
+		],
+		&[],
+	)
+}
+
+fn encode_message(headers: &[(&str, &str)], payload: &[u8]) -> Bytes {
+	let headers_len: usize = headers
+		.iter()
+		.map(|(name, value)| 1 + name.len() + 1 + 2 + value.len())
+		.sum();
+	let total_len = 12 + headers_len + payload.len() + 4;
+
+	let mut msg = Vec::with_capacity(total_len);
+	msg.extend_from_slice(&(total_len as u32).to_be_bytes());
+	msg.extend_from_slice(&(headers_len as u32).to_be_bytes());
+	let prelude_crc = crc32fast::hash(&msg[..]);
+	msg.extend_from_slice(&prelude_crc.to_be_bytes());
+
+	for (name, value) in headers.iter() {
+		msg.push(name.len() as u8);
+		msg.extend_from_slice(name.as_bytes());
+		msg.push(HEADER_VALUE_TYPE_STRING);
+		msg.extend_from_slice(&(value.len() as u16).to_be_bytes());
+		msg.extend_from_slice(value.as_bytes());
+	}
+	msg.extend_from_slice(payload);
+
+	let message_crc = crc32fast::hash(&msg[..]);
+	msg.extend_from_slice(&message_crc.to_be_bytes());
+
+	Bytes::from(msg)
+}
+
+// ---- XML payloads of Stats and Progress events ----
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+struct Stats {
+	#[serde(rename = "BytesScanned")]
+	bytes_scanned: IntValue,
+	#[serde(rename = "BytesProcessed")]
+	bytes_processed: IntValue,
+	#[serde(rename = "BytesReturned")]
+	bytes_returned: IntValue,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+struct Progress {
+	#[serde(rename = "BytesScanned")]
+	bytes_scanned: IntValue,
+	#[serde(rename = "BytesProcessed")]
+	bytes_processed: IntValue,
+	#[serde(rename = "BytesReturned")]
+	bytes_returned: IntValue,
+}
+
+impl From<&SelectStats> for Stats {
+	fn from(stats: &SelectStats) -> Self {
+		Stats {
+			bytes_scanned: IntValue(stats.bytes_scanned as i64),
+			bytes_processed: IntValue(stats.bytes_processed as i64),
+			bytes_returned: IntValue(stats.bytes_returned as i64),
+		}
+	}
+}
+
+impl From<&SelectStats> for Progress {
+	fn from(stats: &SelectStats) -> Self {
+		Progress {
+			bytes_scanned: IntValue(stats.bytes_scanned as i64),
+			bytes_processed: IntValue(stats.bytes_processed as i64),
+			bytes_returned: IntValue(stats.bytes_returned as i64),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::convert::TryInto;
+
+	#[test]
+	fn test_encode_message() {
+		let msg = records_message(b"a,b\n");
+
+		let total_len = u32::from_be_bytes(msg[0..4].try_into().unwrap()) as usize;
+		let headers_len = u32::from_be_bytes(msg[4..8].try_into().unwrap()) as usize;
+		assert_eq!(total_len, msg.len());
+		assert_eq!(
+			u32::from_be_bytes(msg[8..12].try_into().unwrap()),
+			crc32fast::hash(&msg[..8])
+		);
+		assert_eq!(
+			u32::from_be_bytes(msg[total_len - 4..].try_into().unwrap()),
+			crc32fast::hash(&msg[..total_len - 4])
+		);
+
+		let headers = &msg[12..12 + headers_len];
+		assert_eq!(headers[0] as usize, ":event-type".len());
+		assert_eq!(&headers[1..12], b":event-type");
+		assert_eq!(headers[12], HEADER_VALUE_TYPE_STRING);
+		assert_eq!(&headers[13..15], &[0, 7]);
+		assert_eq!(&headers[15..22], b"Records");
+
+		assert_eq!(&msg[12 + headers_len..total_len - 4], b"a,b\n");
+	}
+
+	#[test]
+	fn test_stats_payload() {
+		let stats = SelectStats {
+			bytes_scanned: 100,
+			bytes_processed: 200,
+			bytes_returned: 10,
+		};
+		let xml = quick_xml::se::to_string(&Stats::from(&stats)).unwrap();
+		assert_eq!(
+			xml,
+			"<Stats><BytesScanned>100</BytesScanned><BytesProcessed>200</BytesProcessed><BytesReturned>10</BytesReturned></Stats>"
+		);
+	}
+}
@@ -0,0 +1,924 @@
+//! Implementation of SelectObjectContent, which runs a SQL query on the records
+//! of a CSV or JSON object and streams the selected records back to the client.
+
+mod event_stream;
+mod sql;
+
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_compression::futures::bufread::GzipDecoder;
+use bytes::Bytes;
+use futures::io::{AsyncRead, AsyncReadExt};
+use futures::stream::{StreamExt, TryStreamExt};
+use hyper::{Body, Request, Response, StatusCode};
+use tokio::sync::mpsc;
+
+use garage_table::EmptyKey;
+use garage_util::data::*;
+use garage_util::error::OkOrMessage;
+
+use garage_model::bucket_table::Bucket;
+use garage_model::garage::Garage;
+use garage_model::s3::object_table::*;
+
+use crate::helpers::read_body_limited;
+use crate::s3::encryption::EncryptionParams;
+use crate::s3::error::*;
+use crate::s3::get::{body_from_blocks_range, find_object_version};
+use crate::s3::select::event_stream::*;
+use crate::s3::select::sql::{Accumulator, Query, Record, Value};
+use crate::signature::verify_signed_content;
+
+/// Size of the chunks in which the object is read
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+/// Maximum size of a record of the object (this is the limit in AWS S3)
+const MAX_RECORD_SIZE: usize = 1024 * 1024;
+/// Selected records are sent to the client in messages of about this size
+const RECORDS_MESSAGE_SIZE: usize = 64 * 1024;
+/// If no records were returned during this time, a Cont message (or a Progress
+/// message if it was requested) is sent so that the connection is kept alive
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(5);
+/// Maximum size of the body of a SelectObjectContent request
+const MAX_REQUEST_SIZE: usize = 1024 * 1024;
+
+pub async fn handle_select_object_content(
+	garage: Arc<Garage>,
+	req: Request<Body>,
+	bucket: &Bucket,
+	key: &str,
+	content_sha256: Option<Hash>,
+) -> Result<Response<Body>, Error> {
+	let encryption = EncryptionParams::new_from_headers(req.headers())?;
+
+	let body = read_body_limited(req.into_body(), MAX_REQUEST_SIZE).await?;
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	let body_xml = roxmltree::Document::parse(std::str::from_utf8(&body)?)?;
+	let request = SelectRequest::parse(&body_xml)?;
+	let query = Query::parse(&request.expression)?;
+
+	let object = garage
+		.object_table
+		.get(&bucket.id, &key.to_string())
+		.await?
+		.ok_or(Error::NoSuchKey)?;
+	let (object_version, version_data, version_meta) = find_object_version(&object, None)?;
+	let encryption = encryption.check_decrypt(&version_meta.headers.encryption)?;
+
+	let object_body = match version_data {
+		ObjectVersionData::DeleteMarker => unreachable!(),
+		ObjectVersionData::Inline(_, bytes) => {
+			Body::from(encryption.decrypt_block(bytes.to_vec().into())?)
+		}
+		ObjectVersionData::FirstBlock(_, _) => {
+			let version = garage
+				.version_table
+				.get(&object_version.uuid, &EmptyKey)
+				.await?
+				.ok_or(Error::NoSuchKey)?;
+			body_from_blocks_range(
+				garage.clone(),
+				encryption,
+				bucket.block_replication(),
+				version.blocks.items(),
+				0,
+				version_meta.size,
+			)
+		}
+	};
+
+	let (tx, rx) = mpsc::channel(2);
+	tokio::spawn(SelectRun::new(query, request, tx).run(object_body));
+
+	let body_stream =
+		tokio_stream::wrappers::ReceiverStream::new(rx).map(Ok::<Bytes, std::io::Error>);
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(Body::wrap_stream(body_stream))?)
+}
+
+// ---- Execution of a query ----
+
+/// State of a query running on the content of an object
+struct SelectRun {
+	query: Query,
+	compression: CompressionType,
+	input: InputParser,
+	output: OutputFormat,
+	request_progress: bool,
+	tx: mpsc::Sender<Bytes>,
+
+	stats: SelectStats,
+	bytes_scanned: Arc<AtomicU64>,
+	records_returned: u64,
+	accumulators: Option<Vec<Accumulator>>,
+	/// Selected records that have not yet been sent to the client
+	records: Vec<u8>,
+	last_message: Instant,
+}
+
+impl SelectRun {
+	fn new(query: Query, request: SelectRequest, tx: mpsc::Sender<Bytes>) -> Self {
+		let accumulators = if query.is_aggregate() {
+			Some(query.new_accumulators())
+		} else {
+			None
+		};
+		Self {
+			query,
+			compression: request.compression,
+			input: InputParser::new(request.input),
+			output: request.output,
+			request_progress: request.request_progress,
+			tx,
+			stats: SelectStats::default(),
+			bytes_scanned: Arc::new(AtomicU64::new(0)),
+			records_returned: 0,
+			accumulators,
+			records: vec![],
+			last_message: Instant::now(),
+		}
+	}
+
+	async fn run(mut self, object_body: Body) {
+		let res = async {
+			self.run_query(object_body).await?;
+			self.flush_records().await?;
+			self.update_stats();
+			let stats = stats_message(&self.stats)?;
+			self.send(stats).await?;
+			self.send(end_message()).await
+		}
+		.await;
+
+		if let Err(e) = res {
+			debug!("Error in SelectObjectContent: {}", e);
+			// Records selected before the error are still returned to the client
+			let _ = self.flush_records().await;
+			let _ = self.tx.send(error_message(&e)).await;
+		}
+	}
+
+	async fn run_query(&mut self, object_body: Body) -> Result<(), Error> {
+		let bytes_scanned = self.bytes_scanned.clone();
+		let object_reader = object_body
+			.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+			.inspect_ok(move |chunk| {
+				bytes_scanned.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+			})
+			.into_async_read();
+		let mut reader: Pin<Box<dyn AsyncRead + Send>> = match self.compression {
+			CompressionType::None => Box::pin(object_reader),
+			CompressionType::Gzip => {
+				let mut decoder = GzipDecoder::new(object_reader);
+				decoder.multiple_members(true);
+				Box::pin(decoder)
+			}
+		};
+
+		let mut buf = vec![];
+		let mut chunk = vec![0u8; READ_CHUNK_SIZE];
+		loop {
+			let n = reader.read(&mut chunk[..]).await.map_err(read_error)?;
+			let eof = n == 0;
+			self.stats.bytes_processed += n as u64;
+			buf.extend_from_slice(&chunk[..n]);
+
+			let mut consumed = 0;
+			let mut done = false;
+			while !done {
+				let (record, len) = self.input.next_record(&buf[consumed..], eof)?;
+				consumed += len;
+				match record {
+					Some(record) => done = !self.process_record(record)?,
+					None => break,
+				}
+			}
+			buf.drain(..consumed);
+			if buf.len() > MAX_RECORD_SIZE {
+				return Err(Error::bad_request(format!(
+					"Records cannot be larger than {} bytes",
+					MAX_RECORD_SIZE
+				)));
+			}
+
+			if self.records.len() >= RECORDS_MESSAGE_SIZE {
+				self.flush_records().await?;
+			}
+			if done || eof {
+				break;
+			}
+			if self.last_message.elapsed() >= KEEPALIVE_INTERVAL {
+				let msg = if self.request_progress {
+					self.update_stats();
+					progress_message(&self.stats)?
+				} else {
+					cont_message()
+				};
+				self.send(msg).await?;
+			}
+		}
+
+		if let Some(accumulators) = &self.accumulators {
+			if self.query.limit() != Some(0) {
+				let row = self.query.aggregate_result(accumulators)?;
+				self.output.write_record(&row, &mut self.records)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Run the query on a record. Returns false if no more records need to be read.
+	fn process_record(&mut self, record: Record) -> Result<bool, Error> {
+		let limit = self.query.limit();
+
+		match &mut self.accumulators {
+			Some(accumulators) => {
+				if self.query.matches(&record)? {
+					self.query.accumulate(accumulators, &record)?;
+				}
+				Ok(true)
+			}
+			None => {
+				if limit.map(|l| self.records_returned >= l).unwrap_or(false) {
+					return Ok(false);
+				}
+				if self.query.matches(&record)? {
+					let row = self.query.project(&record)?;
+					self.output.write_record(&row, &mut self.records)?;
+					self.records_returned += 1;
+				}
+				Ok(limit.map(|l| self.records_returned < l).unwrap_or(true))
+			}
+		}
+	}
+
+	async fn flush_records(&mut self) -> Result<(), Error> {
+		if self.records.is_empty() {
+			return Ok(());
+		}
+		self.stats.bytes_returned += self.records.len() as u64;
+		let msg = records_message(&self.records);
+		self.records.clear();
+		self.send(msg).await
+	}
+
+	async fn send(&mut self, msg: Bytes) -> Result<(), Error> {
+		self.last_message = Instant::now();
+		self.tx.send(msg).await.ok_or_message("channel closed")?;
+		Ok(())
+	}
+
+	fn update_stats(&mut self) {
+		self.stats.bytes_scanned = self.bytes_scanned.load(Ordering::Relaxed);
+	}
+}
+
+fn read_error(e: std::io::Error) -> Error {
+	match e.kind() {
+		std::io::ErrorKind::InvalidData | std::io::ErrorKind::InvalidInput => {
+			Error::bad_request(format!("Could not decompress object: {}", e))
+		}
+		_ => Error::internal_error(format!("Could not read object: {}", e)),
+	}
+}
+
+// ---- Reading records from the object ----
+
+struct InputParser {
+	format: InputFormat,
+	/// Whether the first line of a CSV file, which contains a header,
+	/// remains to be read
+	header_pending: bool,
+	columns: Option<Arc<Vec<String>>>,
+}
+
+enum CsvParseResult {
+	Record(Vec<String>, usize),
+	Skip(usize),
+	Incomplete,
+}
+
+impl InputParser {
+	fn new(format: InputFormat) -> Self {
+		let header_pending = match &format {
+			InputFormat::Csv(csv) => csv.file_header_info != FileHeaderInfo::None,
+			InputFormat::Json => false,
+		};
+		Self {
+			format,
+			header_pending,
+			columns: None,
+		}
+	}
+
+	/// Read the next record at the beginning of `buf`. If `eof` is false, more data
+	/// might follow `buf`. Returns the record if a complete record was found,
+	/// and the number of bytes of `buf` that were consumed.
+	fn next_record(&mut self, buf: &[u8], eof: bool) -> Result<(Option<Record>, usize), Error> {
+		let csv = match &self.format {
+			InputFormat::Csv(csv) => csv,
+			InputFormat::Json => return next_json_record(buf, eof),
+		};
+
+		let mut offset = 0;
+		loop {
+			match csv.parse_record(&buf[offset..], eof)? {
+				CsvParseResult::Incomplete => return Ok((None, offset)),
+				CsvParseResult::Skip(len) => offset += len,
+				CsvParseResult::Record(fields, len) => {
+					offset += len;
+					// Skip empty lines
+					if fields.len() == 1 && fields[0].is_empty() {
+						continue;
+					}
+					if self.header_pending {
+						self.header_pending = false;
+						if csv.file_header_info == FileHeaderInfo::Use {
+							self.columns = Some(Arc::new(fields));
+						}
+						continue;
+					}
+					let record = Record::Csv {
+						columns: self.columns.clone(),
+						fields,
+					};
+					return Ok((Some(record), offset));
+				}
+			}
+		}
+	}
+}
+
+impl CsvInput {
+	fn parse_record(&self, buf: &[u8], eof: bool) -> Result<CsvParseResult, Error> {
+		if buf.is_empty() {
+			return Ok(CsvParseResult::Incomplete);
+		}
+
+		if Some(buf[0]) == self.comments {
+			return Ok(match buf.iter().position(|c| *c == self.record_delimiter) {
+				Some(i) => CsvParseResult::Skip(i + 1),
+				None if eof => CsvParseResult::Skip(buf.len()),
+				None => CsvParseResult::Incomplete,
+			});
+		}
+
+		let mut fields = vec![];
+		let mut field = vec![];
+		let mut in_quotes = false;
+		let mut was_quoted = false;
+		let mut i = 0;
+		while i < buf.len() {
+			let c = buf[i];
+			if in_quotes {
+				if c == self.quote_escape_character && c != self.quote_character {
+					match buf.get(i + 1) {
+						Some(next) => {
+							field.push(*next);
+							i += 2;
+						}
+						None if eof => {
+							field.push(c);
+							i += 1;
+						}
+						None => return Ok(CsvParseResult::Incomplete),
+					}
+				} else if c == self.quote_character {
+					// If the escape character is the quote character,
+					// a doubled quote character is an escaped quote
+					match buf.get(i + 1) {
+						Some(next)
+							if *next == self.quote_character
+								&& self.quote_escape_character == self.quote_character =>
+						{
+							field.push(c);
+							i += 2;
+						}
+						None if !eof => return Ok(CsvParseResult::Incomplete),
+						_ => {
+							in_quotes = false;
+							i += 1;
+						}
+					}
+				} else {
+					field.push(c);
+					i += 1;
+				}
+			} else if c == self.quote_character && field.is_empty() && !was_quoted {
+				in_quotes = true;
+				was_quoted = true;
+				i += 1;
+			} else if c == self.field_delimiter {
+				fields.push(csv_field_string(std::mem::take(&mut field)));
+				was_quoted = false;
+				i += 1;
+			} else if c == self.record_delimiter
+				|| (c == b'\r' && self.record_delimiter == b'\n' && buf.get(i + 1) == Some(&b'\n'))
+			{
+				// CRLF line endings are accepted when the record delimiter is LF
+				let len = if c == b'\r' { i + 2 } else { i + 1 };
+				fields.push(csv_field_string(field));
+				return Ok(CsvParseResult::Record(fields, len));
+			} else if c == b'\r' && self.record_delimiter == b'\n' && i + 1 == buf.len() && !eof {
+				return Ok(CsvParseResult::Incomplete);
+			} else {
+				field.push(c);
+				i += 1;
+			}
+		}
+
+		if !eof {
+			return Ok(CsvParseResult::Incomplete);
+		}
+		if in_quotes {
+			return Err(Error::bad_request(
+				"Unterminated quoted field in CSV record",
+			));
+		}
+		fields.push(csv_field_string(field));
+		Ok(CsvParseResult::Record(fields, buf.len()))
+	}
+}
+
+fn csv_field_string(field: Vec<u8>) -> String {
+	String::from_utf8(field).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
+}
+
+/// Read the JSON value at the beginning of `buf`. Values may be separated
+/// by any whitespace, which covers both JSON Lines and JSON documents.
+fn next_json_record(buf: &[u8], eof: bool) -> Result<(Option<Record>, usize), Error> {
+	let start = match buf.iter().position(|c| !c.is_ascii_whitespace()) {
+		Some(start) => start,
+		None => return Ok((None, buf.len())),
+	};
+
+	let mut values =
+		serde_json::Deserializer::from_slice(&buf[start..]).into_iter::<serde_json::Value>();
+	match values.next() {
+		Some(Ok(value)) => {
+			let end = start + values.byte_offset();
+			// A number at the end of the buffer might continue in the next chunk
+			if !eof && end == buf.len() && value.is_number() {
+				return Ok((None, start));
+			}
+			Ok((Some(Record::Json(value)), end))
+		}
+		Some(Err(e)) if e.is_eof() && !eof => Ok((None, start)),
+		Some(Err(e)) => Err(Error::bad_request(format!("Invalid JSON record: {}", e))),
+		None => Ok((None, buf.len())),
+	}
+}
+
+// ---- Writing selected records ----
+
+impl OutputFormat {
+	fn write_record(&self, fields: &[(String, Value)], out: &mut Vec<u8>) -> Result<(), Error> {
+		match self {
+			OutputFormat::Csv {
+				field_delimiter,
+				record_delimiter,
+				quote_character,
+				quote_escape_character,
+				always_quote,
+			} => {
+				for (i, (_, value)) in fields.iter().enumerate() {
+					if i > 0 {
+						out.extend_from_slice(field_delimiter.as_bytes());
+					}
+					let text = value.to_text();
+					let needs_quotes = *always_quote
+						|| text.contains(field_delimiter.as_str())
+						|| text.contains(record_delimiter.as_str())
+						|| text.contains(*quote_character)
+						|| text.contains('\n')
+						|| text.contains('\r');
+					if needs_quotes {
+						let mut quoted = String::with_capacity(text.len() + 2);
+						quoted.push(*quote_character);
+						for c in text.chars() {
+							if c == *quote_character || c == *quote_escape_character {
+								quoted.push(*quote_escape_character);
+							}
+							quoted.push(c);
+						}
+						quoted.push(*quote_character);
+						out.extend_from_slice(quoted.as_bytes());
+					} else {
+						out.extend_from_slice(text.as_bytes());
+					}
+				}
+				out.extend_from_slice(record_delimiter.as_bytes());
+			}
+			OutputFormat::Json { record_delimiter } => {
+				// Fields that do not exist in the record are not included in the output
+				let object = fields
+					.iter()
+					.filter(|(_, v)| !v.is_missing())
+					.map(|(k, v)| (k.clone(), v.to_json()))
+					.collect::<serde_json::Map<_, _>>();
+				serde_json::to_writer(&mut *out, &object)
+					.ok_or_internal_error("Could not serialize JSON record")?;
+				out.extend_from_slice(record_delimiter.as_bytes());
+			}
+		}
+		Ok(())
+	}
+}
+
+// ---- Parsing of the request ----
+
+struct SelectRequest {
+	expression: String,
+	compression: CompressionType,
+	input: InputFormat,
+	output: OutputFormat,
+	request_progress: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CompressionType {
+	None,
+	Gzip,
+}
+
+enum InputFormat {
+	Csv(CsvInput),
+	/// Both JSON Lines and JSON documents
+	Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileHeaderInfo {
+	/// The first line is a header, and columns can be referred to by their name
+	Use,
+	/// The first line is a header, which is ignored
+	Ignore,
+	/// There is no header
+	None,
+}
+
+struct CsvInput {
+	file_header_info: FileHeaderInfo,
+	field_delimiter: u8,
+	record_delimiter: u8,
+	quote_character: u8,
+	quote_escape_character: u8,
+	comments: Option<u8>,
+}
+
+enum OutputFormat {
+	Csv {
+		field_delimiter: String,
+		record_delimiter: String,
+		quote_character: char,
+		quote_escape_character: char,
+		always_quote: bool,
+	},
+	Json {
+		record_delimiter: String,
+	},
+}
+
+impl SelectRequest {
+	fn parse(xml: &roxmltree::Document) -> Result<Self, Error> {
+		let root = xml.root_element();
+		if !root.has_tag_name("SelectObjectContentRequest") {
+			return Err(Error::bad_request("Invalid SelectObjectContentRequest XML"));
+		}
+
+		let expression = child_text(root, "Expression")
+			.ok_or_bad_request("Missing Expression in SelectObjectContentRequest")?
+			.to_string();
+		match child_text(root, "ExpressionType").map(str::trim) {
+			Some("SQL") => (),
+			Some(t) => {
+				return Err(Error::bad_request(format!(
+					"Unsupported expression type: {}",
+					t
+				)))
+			}
+			None => {
+				return Err(Error::bad_request(
+					"Missing ExpressionType in SelectObjectContentRequest",
+				))
+			}
+		}
+
+		let request_progress = child(root, "RequestProgress")
+			.and_then(|n| child_text(n, "Enabled"))
+			.map(|v| v.trim() == "true")
+			.unwrap_or(false);
+
+		if child(root, "ScanRange").is_some() {
+			return Err(Error::NotImplemented(
+				"ScanRange in SelectObjectContent".into(),
+			));
+		}
+
+		let input = child(root, "InputSerialization")
+			.ok_or_bad_request("Missing InputSerialization in SelectObjectContentRequest")?;
+		let compression = match child_text(input, "CompressionType").map(str::trim) {
+			None | Some("NONE") => CompressionType::None,
+			Some("GZIP") => CompressionType::Gzip,
+			Some(c) => {
+				return Err(Error::NotImplemented(format!(
+					"SelectObjectContent on objects with compression type {}",
+					c
+				)))
+			}
+		};
+		let input_format = if let Some(csv) = child(input, "CSV") {
+			InputFormat::Csv(CsvInput::parse(csv)?)
+		} else if let Some(json) = child(input, "JSON") {
+			match child_text(json, "Type").map(str::trim) {
+				None | Some("LINES") | Some("DOCUMENT") => InputFormat::Json,
+				Some(t) => return Err(Error::bad_request(format!("Invalid JSON type: {}", t))),
+			}
+		} else if child(input, "Parquet").is_some() {
+			return Err(Error::NotImplemented(
+				"SelectObjectContent on Parquet objects".into(),
+			));
+		} else {
+			return Err(Error::bad_request(
+				"InputSerialization must specify the CSV or JSON format",
+			));
+		};
+
+		let output = child(root, "OutputSerialization")
+			.ok_or_bad_request("Missing OutputSerialization in SelectObjectContentRequest")?;
+		let output_format = if let Some(csv) = child(output, "CSV") {
+			let quote_character = single_char(child_text(csv, "QuoteCharacter").unwrap_or("\""))?;
+			OutputFormat::Csv {
+				field_delimiter: child_text(csv, "FieldDelimiter").unwrap_or(",").to_string(),
+				record_delimiter: child_text(csv, "RecordDelimiter")
+					.unwrap_or("\n")
+					.to_string(),
+				quote_character,
+				quote_escape_character: match child_text(csv, "QuoteEscapeCharacter") {
+					Some(c) => single_char(c)?,
+					None => quote_character,
+				},
+				always_quote: match child_text(csv, "QuoteFields").map(str::trim) {
+					None | Some("ASNEEDED") => false,
+					Some("ALWAYS") => true,
+					Some(q) => {
+						return Err(Error::bad_request(format!("Invalid QuoteFields: {}", q)))
+					}
+				},
+			}
+		} else if let Some(json) = child(output, "JSON") {
+			OutputFormat::Json {
+				record_delimiter: child_text(json, "RecordDelimiter")
+					.unwrap_or("\n")
+					.to_string(),
+			}
+		} else {
+			return Err(Error::bad_request(
+				"OutputSerialization must specify the CSV or JSON format",
+			));
+		};
+
+		Ok(Self {
+			expression,
+			compression,
+			input: input_format,
+			output: output_format,
+			request_progress,
+		})
+	}
+}
+
+impl CsvInput {
+	fn parse(csv: roxmltree::Node) -> Result<Self, Error> {
+		let file_header_info = match child_text(csv, "FileHeaderInfo").map(str::trim) {
+			None | Some("NONE") => FileHeaderInfo::None,
+			Some("USE") => FileHeaderInfo::Use,
+			Some("IGNORE") => FileHeaderInfo::Ignore,
+			Some(h) => return Err(Error::bad_request(format!("Invalid FileHeaderInfo: {}", h))),
+		};
+		let quote_character = single_byte(child_text(csv, "QuoteCharacter").unwrap_or("\""))?;
+		Ok(Self {
+			file_header_info,
+			field_delimiter: single_byte(child_text(csv, "FieldDelimiter").unwrap_or(","))?,
+			record_delimiter: match child_text(csv, "RecordDelimiter").unwrap_or("\n") {
+				"\r\n" => b'\n',
+				d => single_byte(d)?,
+			},
+			quote_character,
+			quote_escape_character: match child_text(csv, "QuoteEscapeCharacter") {
+				Some(c) => single_byte(c)?,
+				None => quote_character,
+			},
+			comments: child_text(csv, "Comments").map(single_byte).transpose()?,
+		})
+	}
+}
+
+fn child<'a, 'input>(
+	node: roxmltree::Node<'a, 'input>,
+	name: &str,
+) -> Option<roxmltree::Node<'a, 'input>> {
+	node.children().find(|c| c.has_tag_name(name))
+}
+
+fn child_text<'a, 'input>(node: roxmltree::Node<'a, 'input>, name: &str) -> Option<&'a str> {
+	child(node, name).and_then(|c| c.text())
+}
+
+fn single_char(s: &str) -> Result<char, Error> {
+	let mut chars = s.chars();
+	match (chars.next(), chars.next()) {
+		(Some(c), None) => Ok(c),
+		_ => Err(Error::bad_request(format!(
+			"Expected a single character, got {:?}",
+			s
+		))),
+	}
+}
+
+fn single_byte(s: &str) -> Result<u8, Error> {
+	match s.as_bytes() {
+		[c] => Ok(*c),
+		_ => Err(Error::NotImplemented(format!(
+			"CSV input with a delimiter or quote character that is not a single ASCII character ({:?})",
+			s
+		))),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn read_all(parser: &mut InputParser, data: &[u8], chunk_size: usize) -> Vec<Record> {
+		let mut records = vec![];
+		let mut buf = vec![];
+		let mut chunks = data.chunks(chunk_size);
+		loop {
+			let chunk = chunks.next();
+			let eof = chunk.is_none();
+			buf.extend_from_slice(chunk.unwrap_or_default());
+			let mut consumed = 0;
+			loop {
+				let (record, len) = parser.next_record(&buf[consumed..], eof).unwrap();
+				consumed += len;
+				match record {
+					Some(r) => records.push(r),
+					None => break,
+				}
+			}
+			buf.drain(..consumed);
+			if eof {
+				return records;
+			}
+		}
+	}
+
+	fn csv_fields(records: &[Record]) -> Vec<Vec<String>> {
+		records
+			.iter()
+			.map(|r| match r {
+				Record::Csv { fields, .. } => fields.clone(),
+				_ => unreachable!(),
+			})
+			.collect()
+	}
+
+	#[test]
+	fn test_parse_request() {
+		let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<SelectObjectContentRequest xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+	<Expression>SELECT s.name FROM S3Object s WHERE s.age &gt; 30</Expression>
+	<ExpressionType>SQL</ExpressionType>
+	<InputSerialization>
+		<CompressionType>GZIP</CompressionType>
+		<CSV>
+			<FileHeaderInfo>USE</FileHeaderInfo>
+			<RecordDelimiter>
+</RecordDelimiter>
+			<FieldDelimiter>;</FieldDelimiter>
+		</CSV>
+	</InputSerialization>
+	<OutputSerialization>
+		<JSON>
+			<RecordDelimiter>,</RecordDelimiter>
+		</JSON>
+	</OutputSerialization>
+	<RequestProgress><Enabled>true</Enabled></RequestProgress>
+</SelectObjectContentRequest>"#;
+		let doc = roxmltree::Document::parse(xml).unwrap();
+		let request = SelectRequest::parse(&doc).unwrap();
+
+		assert_eq!(
+			request.expression,
+			"SELECT s.name FROM S3Object s WHERE s.age > 30"
+		);
+		assert_eq!(request.compression, CompressionType::Gzip);
+		assert!(request.request_progress);
+		match request.input {
+			InputFormat::Csv(csv) => {
+				assert_eq!(csv.file_header_info, FileHeaderInfo::Use);
+				assert_eq!(csv.field_delimiter, b';');
+				assert_eq!(csv.record_delimiter, b'\n');
+				assert_eq!(csv.quote_character, b'"');
+				assert_eq!(csv.comments, None);
+			}
+			_ => panic!("expected CSV input"),
+		}
+		match request.output {
+			OutputFormat::Json { record_delimiter } => assert_eq!(record_delimiter, ","),
+			_ => panic!("expected JSON output"),
+		}
+	}
+
+	#[test]
+	fn test_read_csv() {
+		let data = b"name,comment\r\n\
+			alice,\"hello, world\"\r\n\
+			# not a record\n\
+			\n\
+			bob,\"multi\nline \"\"quoted\"\"\"\n\
+			carol,last";
+		let expected = vec![
+			vec!["alice".to_string(), "hello, world".to_string()],
+			vec!["bob".to_string(), "multi\nline \"quoted\"".to_string()],
+			vec!["carol".to_string(), "last".to_string()],
+		];
+
+		for chunk_size in [1, 2, 7, 1000] {
+			let mut parser = InputParser::new(InputFormat::Csv(CsvInput {
+				file_header_info: FileHeaderInfo::Use,
+				field_delimiter: b',',
+				record_delimiter: b'\n',
+				quote_character: b'"',
+				quote_escape_character: b'"',
+				comments: Some(b'#'),
+			}));
+			let records = read_all(&mut parser, data, chunk_size);
+			assert_eq!(csv_fields(&records), expected);
+			assert_eq!(
+				parser.columns.as_deref(),
+				Some(&vec!["name".to_string(), "comment".to_string()])
+			);
+		}
+	}
+
+	#[test]
+	fn test_read_json() {
+		let data = b"{\"a\": 1}\n{\"a\": [2, 3]}\n\n12345\n\"x\" {\"b\": {}}\n";
+		for chunk_size in [1, 3, 1000] {
+			let mut parser = InputParser::new(InputFormat::Json);
+			let records = read_all(&mut parser, data, chunk_size)
+				.into_iter()
+				.map(|r| match r {
+					Record::Json(v) => v,
+					_ => unreachable!(),
+				})
+				.collect::<Vec<_>>();
+			assert_eq!(
+				records,
+				vec![
+					serde_json::json!({"a": 1}),
+					serde_json::json!({"a": [2, 3]}),
+					serde_json::json!(12345),
+					serde_json::json!("x"),
+					serde_json::json!({"b": {}}),
+				]
+			);
+		}
+	}
+
+	#[test]
+	fn test_write_csv() {
+		let output = OutputFormat::Csv {
+			field_delimiter: ",".into(),
+			record_delimiter: "\n".into(),
+			quote_character: '"',
+			quote_escape_character: '"',
+			always_quote: false,
+		};
+		let mut out = vec![];
+		output
+			.write_record(
+				&[
+					("a".into(), Value::String("x, y".into())),
+					("b".into(), Value::Int(12)),
+					("c".into(), Value::Missing),
+					("d".into(), Value::String("say \"hi\"".into())),
+				],
+				&mut out,
+			)
+			.unwrap();
+		assert_eq!(
+			std::str::from_utf8(&out).unwrap(),
+			"\"x, y\",12,,\"say \"\"hi\"\"\"\n"
+		);
+	}
+}
@@ -0,0 +1,1757 @@
+//! Parsing and evaluation of the subset of SQL supported by SelectObjectContent:
+//!
+//! ```text
+//! SELECT (* | expr [[AS] name], ...) FROM S3Object[[*]] [[AS] alias]
+//!     [WHERE condition] [LIMIT n]
+//! ```
+//!
+//! Expressions can use field references (`s.name`, `s._1`, `s.a.b[0]`), literals,
+//! arithmetic and comparison operators, `AND`, `OR`, `NOT`, `IS [NOT] NULL`,
+//! `IS [NOT] MISSING`, `[NOT] LIKE`, `[NOT] BETWEEN`, `[NOT] IN`, `||`, `CAST`,
+//! a few scalar functions, and the aggregate functions `COUNT`, `SUM`, `AVG`,
+//! `MIN` and `MAX`.
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use crate::s3::error::*;
+
+// ---- Values and records ----
+
+/// A value manipulated by a SQL expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+	/// The value of a field that does not exist in the record
+	Missing,
+	Null,
+	Bool(bool),
+	Int(i64),
+	Float(f64),
+	String(String),
+	/// A JSON object or array
+	Json(serde_json::Value),
+}
+
+impl Value {
+	pub fn from_json(v: &serde_json::Value) -> Self {
+		match v {
+			serde_json::Value::Null => Value::Null,
+			serde_json::Value::Bool(b) => Value::Bool(*b),
+			serde_json::Value::Number(n) => match n.as_i64() {
+				Some(i) => Value::Int(i),
+				None => Value::Float(n.as_f64().unwrap_or(f64::NAN)),
+			},
+			serde_json::Value::String(s) => Value::String(s.clone()),
+			v => Value::Json(v.clone()),
+		}
+	}
+
+	pub fn to_json(&self) -> serde_json::Value {
+		match self {
+			Value::Missing | Value::Null => serde_json::Value::Null,
+			Value::Bool(b) => serde_json::Value::Bool(*b),
+			Value::Int(i) => serde_json::Value::from(*i),
+			Value::Float(f) => serde_json::Number::from_f64(*f)
+				.map(serde_json::Value::Number)
+				.unwrap_or(serde_json::Value::Null),
+			Value::String(s) => serde_json::Value::String(s.clone()),
+			Value::Json(v) => v.clone(),
+		}
+	}
+
+	/// Text representation of the value, as written in CSV output
+	pub fn to_text(&self) -> String {
+		match self {
+			Value::Missing | Value::Null => String::new(),
+			Value::Bool(b) => b.to_string(),
+			Value::Int(i) => i.to_string(),
+			Value::Float(f) => f.to_string(),
+			Value::String(s) => s.clone(),
+			Value::Json(v) => v.to_string(),
+		}
+	}
+
+	pub fn is_missing(&self) -> bool {
+		matches!(self, Value::Missing)
+	}
+
+	fn is_null(&self) -> bool {
+		matches!(self, Value::Missing | Value::Null)
+	}
+
+	/// Numeric interpretation of the value. Strings are parsed, so that fields
+	/// of CSV records can be used as numbers without an explicit CAST.
+	fn as_number(&self) -> Option<Value> {
+		match self {
+			Value::Int(_) | Value::Float(_) => Some(self.clone()),
+			Value::String(s) => parse_number(s.trim()),
+			_ => None,
+		}
+	}
+
+	fn as_f64(&self) -> Option<f64> {
+		match self {
+			Value::Int(i) => Some(*i as f64),
+			Value::Float(f) => Some(*f),
+			_ => None,
+		}
+	}
+}
+
+fn parse_number(s: &str) -> Option<Value> {
+	if let Ok(i) = s.parse::<i64>() {
+		return Some(Value::Int(i));
+	}
+	// f64::from_str also accepts "inf" and "NaN", which we don't consider as numbers
+	if s.bytes().any(|c| c.is_ascii_digit()) {
+		s.parse::<f64>().ok().map(Value::Float)
+	} else {
+		None
+	}
+}
+
+/// A record of the object on which a query is run
+#[derive(Debug, Clone)]
+pub enum Record {
+	/// A CSV record: a list of fields, and the column names
+	/// if they were given in the header of the file
+	Csv {
+		columns: Option<Arc<Vec<String>>>,
+		fields: Vec<String>,
+	},
+	/// A JSON value
+	Json(serde_json::Value),
+}
+
+impl Record {
+	/// Names and values of all the fields of the record, as selected by `SELECT *`
+	fn all_fields(&self) -> Vec<(String, Value)> {
+		match self {
+			Record::Csv { columns, fields } => fields
+				.iter()
+				.enumerate()
+				.map(|(i, f)| {
+					let name = match columns.as_ref().and_then(|c| c.get(i)) {
+						Some(c) => c.clone(),
+						None => format!("_{}", i + 1),
+					};
+					(name, Value::String(f.clone()))
+				})
+				.collect(),
+			Record::Json(serde_json::Value::Object(map)) => map
+				.iter()
+				.map(|(k, v)| (k.clone(), Value::from_json(v)))
+				.collect(),
+			Record::Json(v) => vec![("_1".to_string(), Value::from_json(v))],
+		}
+	}
+
+	fn lookup(&self, path: &[PathElem]) -> Value {
+		match self {
+			Record::Csv { columns, fields } => match path {
+				[] => Value::Json(serde_json::Value::Object(
+					self.all_fields()
+						.into_iter()
+						.map(|(k, v)| (k, v.to_json()))
+						.collect(),
+				)),
+				[PathElem::Field { name, quoted }] => {
+					let position = match name.strip_prefix('_').map(str::parse::<usize>) {
+						Some(Ok(i)) if i >= 1 => Some(i - 1),
+						_ => columns.as_ref().and_then(|c| {
+							c.iter().position(|col| col == name).or_else(|| {
+								c.iter()
+									.position(|col| !*quoted && col.eq_ignore_ascii_case(name))
+							})
+						}),
+					};
+					match position.and_then(|i| fields.get(i)) {
+						Some(f) => Value::String(f.clone()),
+						None => Value::Missing,
+					}
+				}
+				_ => Value::Missing,
+			},
+			Record::Json(value) => {
+				let mut value = value;
+				for elem in path.iter() {
+					let next = match (elem, value) {
+						(PathElem::Field { name, quoted }, serde_json::Value::Object(map)) => {
+							map.get(name).or_else(|| {
+								map.iter()
+									.find(|(k, _)| !*quoted && k.eq_ignore_ascii_case(name))
+									.map(|(_, v)| v)
+							})
+						}
+						(PathElem::Index(i), serde_json::Value::Array(array)) => array.get(*i),
+						_ => None,
+					};
+					match next {
+						Some(v) => value = v,
+						None => return Value::Missing,
+					}
+				}
+				Value::from_json(value)
+			}
+		}
+	}
+}
+
+// ---- Syntax tree ----
+
+/// A parsed SELECT query
+#[derive(Debug)]
+pub struct Query {
+	projection: Projection,
+	/// Name by which the object is referred to in the query, in lowercase
+	alias: Option<String>,
+	condition: Option<Expr>,
+	limit: Option<u64>,
+	aggregates: Vec<Aggregate>,
+}
+
+#[derive(Debug)]
+enum Projection {
+	All,
+	Items(Vec<SelectItem>),
+}
+
+#[derive(Debug)]
+struct SelectItem {
+	expr: Expr,
+	name: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum PathElem {
+	Field { name: String, quoted: bool },
+	Index(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompareOp {
+	Eq,
+	Ne,
+	Lt,
+	Le,
+	Gt,
+	Ge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArithOp {
+	Add,
+	Sub,
+	Mul,
+	Div,
+	Mod,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CastType {
+	Int,
+	Float,
+	String,
+	Bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Function {
+	Lower,
+	Upper,
+	CharLength,
+	Trim,
+	Substring,
+	Coalesce,
+	NullIf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AggregateFunction {
+	Count,
+	Sum,
+	Avg,
+	Min,
+	Max,
+}
+
+#[derive(Debug)]
+struct Aggregate {
+	function: AggregateFunction,
+	/// Argument of the function, None for `COUNT(*)`
+	arg: Option<Expr>,
+}
+
+#[derive(Debug)]
+enum Expr {
+	Literal(Value),
+	Path(Vec<PathElem>),
+	Not(Box<Expr>),
+	And(Box<Expr>, Box<Expr>),
+	Or(Box<Expr>, Box<Expr>),
+	Compare(CompareOp, Box<Expr>, Box<Expr>),
+	Arith(ArithOp, Box<Expr>, Box<Expr>),
+	Neg(Box<Expr>),
+	Concat(Box<Expr>, Box<Expr>),
+	IsNull {
+		expr: Box<Expr>,
+		missing: bool,
+		negated: bool,
+	},
+	Like {
+		expr: Box<Expr>,
+		pattern: Box<Expr>,
+		escape: Option<Box<Expr>>,
+		negated: bool,
+	},
+	Between {
+		expr: Box<Expr>,
+		low: Box<Expr>,
+		high: Box<Expr>,
+		negated: bool,
+	},
+	In {
+		expr: Box<Expr>,
+		list: Vec<Expr>,
+		negated: bool,
+	},
+	Cast(Box<Expr>, CastType),
+	Function(Function, Vec<Expr>),
+	/// Result of the aggregate function with the given index in `Query::aggregates`
+	Aggregate(usize),
+}
+
+impl Expr {
+	/// Whether the expression refers to fields of the record outside of aggregate functions
+	fn uses_record(&self) -> bool {
+		match self {
+			Expr::Literal(_) | Expr::Aggregate(_) => false,
+			Expr::Path(_) => true,
+			Expr::Not(e) | Expr::Neg(e) | Expr::Cast(e, _) | Expr::IsNull { expr: e, .. } => {
+				e.uses_record()
+			}
+			Expr::And(a, b)
+			| Expr::Or(a, b)
+			| Expr::Compare(_, a, b)
+			| Expr::Arith(_, a, b)
+			| Expr::Concat(a, b) => a.uses_record() || b.uses_record(),
+			Expr::Like {
+				expr,
+				pattern,
+				escape,
+				..
+			} => {
+				expr.uses_record()
+					|| pattern.uses_record()
+					|| escape.as_ref().map(|e| e.uses_record()).unwrap_or(false)
+			}
+			Expr::Between {
+				expr, low, high, ..
+			} => expr.uses_record() || low.uses_record() || high.uses_record(),
+			Expr::In { expr, list, .. } => {
+				expr.uses_record() || list.iter().any(|e| e.uses_record())
+			}
+			Expr::Function(_, args) => args.iter().any(|e| e.uses_record()),
+		}
+	}
+}
+
+// ---- Lexer ----
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+	Ident(String),
+	QuotedIdent(String),
+	Str(String),
+	Int(i64),
+	Float(f64),
+	Symbol(&'static str),
+}
+
+const SYMBOLS: &[&str] = &[
+	"!=", "<>", "<=", ">=", "||", "(", ")", ",", ".", "[", "]", "*", "+", "-", "/", "%", "=", "<",
+	">",
+];
+
+const RESERVED_KEYWORDS: &[&str] = &[
+	"select", "from", "where", "limit", "and", "or", "not", "as", "is", "null", "missing", "true",
+	"false", "like", "escape", "between", "in", "cast",
+];
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, Error> {
+	let chars = expr.chars().collect::<Vec<_>>();
+	let mut tokens = vec![];
+	let mut i = 0;
+
+	while i < chars.len() {
+		let c = chars[i];
+		if c.is_whitespace() {
+			i += 1;
+		} else if c.is_ascii_digit() {
+			let start = i;
+			let mut is_float = false;
+			while i < chars.len() && chars[i].is_ascii_digit() {
+				i += 1;
+			}
+			if i + 1 < chars.len() && chars[i] == '.' && chars[i + 1].is_ascii_digit() {
+				is_float = true;
+				i += 1;
+				while i < chars.len() && chars[i].is_ascii_digit() {
+					i += 1;
+				}
+			}
+			if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+				let mut j = i + 1;
+				if j < chars.len() && (chars[j] == '+' || chars[j] == '-') {
+					j += 1;
+				}
+				if j < chars.len() && chars[j].is_ascii_digit() {
+					is_float = true;
+					i = j;
+					while i < chars.len() && chars[i].is_ascii_digit() {
+						i += 1;
+					}
+				}
+			}
+			let text = chars[start..i].iter().collect::<String>();
+			match text.parse::<i64>() {
+				Ok(n) if !is_float => tokens.push(Token::Int(n)),
+				_ => tokens
+					.push(Token::Float(text.parse::<f64>().map_err(|_| {
+						syntax_error(format!("invalid number: {}", text))
+					})?)),
+			}
+		} else if c.is_alphabetic() || c == '_' {
+			let start = i;
+			while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+				i += 1;
+			}
+			tokens.push(Token::Ident(chars[start..i].iter().collect()));
+		} else if c == '\'' || c == '"' {
+			// String literals are delimited by single quotes, quoted identifiers
+			// by double quotes. In both, the delimiter is escaped by doubling it.
+			let mut text = String::new();
+			i += 1;
+			loop {
+				match chars.get(i) {
+					None => return Err(syntax_error("unterminated quoted string")),
+					Some(x) if *x == c => {
+						if chars.get(i + 1) == Some(&c) {
+							text.push(c);
+							i += 2;
+						} else {
+							i += 1;
+							break;
+						}
+					}
+					Some(x) => {
+						text.push(*x);
+						i += 1;
+					}
+				}
+			}
+			if c == '\'' {
+				tokens.push(Token::Str(text));
+			} else {
+				tokens.push(Token::QuotedIdent(text));
+			}
+		} else {
+			let symbol = SYMBOLS
+				.iter()
+				.find(|s| {
+					s.chars()
+						.enumerate()
+						.all(|(j, sc)| chars.get(i + j) == Some(&sc))
+				})
+				.ok_or_else(|| syntax_error(format!("unexpected character '{}'", c)))?;
+			i += symbol.len();
+			tokens.push(Token::Symbol(symbol));
+		}
+	}
+
+	Ok(tokens)
+}
+
+fn syntax_error<S: std::fmt::Display>(msg: S) -> Error {
+	Error::bad_request(format!("Invalid SQL expression: {}", msg))
+}
+
+fn eval_error<S: std::fmt::Display>(msg: S) -> Error {
+	Error::bad_request(format!("Error while evaluating SQL expression: {}", msg))
+}
+
+// ---- Parser ----
+
+/// Maximum nesting depth of an expression, so that parsing and evaluating
+/// it cannot overflow the stack
+const MAX_EXPRESSION_DEPTH: usize = 64;
+
+struct Parser {
+	tokens: Vec<Token>,
+	pos: usize,
+	aggregates: Vec<Aggregate>,
+	in_aggregate: bool,
+	/// Upper bound of the depth, in the expression tree, of the expression
+	/// being parsed
+	depth: usize,
+}
+
+impl Parser {
+	/// Go one level deeper in the expression tree.
+	/// Callers restore `self.depth` when they are done with the level.
+	fn enter(&mut self) -> Result<(), Error> {
+		self.depth += 1;
+		if self.depth > MAX_EXPRESSION_DEPTH {
+			return Err(syntax_error(format!(
+				"expression is nested too deeply (maximum depth: {})",
+				MAX_EXPRESSION_DEPTH
+			)));
+		}
+		Ok(())
+	}
+
+	fn peek(&self) -> Option<&Token> {
+		self.tokens.get(self.pos)
+	}
+
+	fn next(&mut self) -> Option<Token> {
+		let token = self.tokens.get(self.pos).cloned();
+		self.pos += 1;
+		token
+	}
+
+	fn peek_keyword(&self, keyword: &str) -> bool {
+		matches!(self.peek(), Some(Token::Ident(id)) if id.eq_ignore_ascii_case(keyword))
+	}
+
+	fn peek_keyword_at(&self, offset: usize, keyword: &str) -> bool {
+		matches!(self.tokens.get(self.pos + offset), Some(Token::Ident(id)) if id.eq_ignore_ascii_case(keyword))
+	}
+
+	fn eat_keyword(&mut self, keyword: &str) -> bool {
+		if self.peek_keyword(keyword) {
+			self.pos += 1;
+			true
+		} else {
+			false
+		}
+	}
+
+	fn expect_keyword(&mut self, keyword: &str) -> Result<(), Error> {
+		if self.eat_keyword(keyword) {
+			Ok(())
+		} else {
+			Err(self.unexpected(&format!("expected {}", keyword.to_uppercase())))
+		}
+	}
+
+	fn peek_symbol(&self, symbol: &str) -> bool {
+		matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol)
+	}
+
+	fn eat_symbol(&mut self, symbol: &str) -> bool {
+		if self.peek_symbol(symbol) {
+			self.pos += 1;
+			true
+		} else {
+			false
+		}
+	}
+
+	fn expect_symbol(&mut self, symbol: &str) -> Result<(), Error> {
+		if self.eat_symbol(symbol) {
+			Ok(())
+		} else {
+			Err(self.unexpected(&format!("expected '{}'", symbol)))
+		}
+	}
+
+	fn unexpected(&self, expected: &str) -> Error {
+		match self.peek() {
+			Some(token) => syntax_error(format!("{}, found {:?}", expected, token)),
+			None => syntax_error(format!("{}, found end of expression", expected)),
+		}
+	}
+
+	/// Parse an optional alias, introduced by AS or given directly
+	fn parse_alias(&mut self) -> Result<Option<(String, bool)>, Error> {
+		let has_as = self.eat_keyword("as");
+		match self.peek() {
+			Some(Token::Ident(id))
+				if !RESERVED_KEYWORDS.iter().any(|k| id.eq_ignore_ascii_case(k)) =>
+			{
+				let id = id.clone();
+				self.pos += 1;
+				Ok(Some((id, false)))
+			}
+			Some(Token::QuotedIdent(id)) => {
+				let id = id.clone();
+				self.pos += 1;
+				Ok(Some((id, true)))
+			}
+			_ if has_as => Err(self.unexpected("expected a name after AS")),
+			_ => Ok(None),
+		}
+	}
+
+	fn parse_query(&mut self) -> Result<Query, Error> {
+		self.expect_keyword("select")?;
+
+		let projection = if self.eat_symbol("*") {
+			Projection::All
+		} else {
+			let mut items = vec![];
+			loop {
+				let expr = self.parse_expr()?;
+				let name = match self.parse_alias()? {
+					Some((name, _)) => name,
+					None => match &expr {
+						Expr::Path(path) => match path.last() {
+							Some(PathElem::Field { name, .. }) => name.clone(),
+							_ => format!("_{}", items.len() + 1),
+						},
+						_ => format!("_{}", items.len() + 1),
+					},
+				};
+				items.push(SelectItem { expr, name });
+				if !self.eat_symbol(",") {
+					break;
+				}
+			}
+			Projection::Items(items)
+		};
+
+		self.expect_keyword("from")?;
+		match self.next() {
+			Some(Token::Ident(id)) if id.eq_ignore_ascii_case("s3object") => (),
+			_ => {
+				self.pos -= 1;
+				return Err(self.unexpected("expected S3Object"));
+			}
+		}
+		if self.eat_symbol("[") {
+			self.expect_symbol("*")?;
+			self.expect_symbol("]")?;
+		}
+		let alias = self.parse_alias()?.map(|(a, _)| a.to_lowercase());
+
+		let n_aggregates = self.aggregates.len();
+		let condition = if self.eat_keyword("where") {
+			Some(self.parse_expr()?)
+		} else {
+			None
+		};
+		if self.aggregates.len() != n_aggregates {
+			return Err(syntax_error(
+				"aggregate functions cannot be used in the WHERE clause",
+			));
+		}
+
+		let limit = if self.eat_keyword("limit") {
+			match self.next() {
+				Some(Token::Int(n)) if n >= 0 => Some(n as u64),
+				_ => {
+					self.pos -= 1;
+					return Err(self.unexpected("expected a non-negative integer after LIMIT"));
+				}
+			}
+		} else {
+			None
+		};
+
+		if self.peek().is_some() {
+			return Err(self.unexpected("expected end of expression"));
+		}
+
+		let aggregates = std::mem::take(&mut self.aggregates);
+		if !aggregates.is_empty() {
+			match &projection {
+				Projection::All => unreachable!(),
+				Projection::Items(items) => {
+					if items.iter().any(|i| i.expr.uses_record()) {
+						return Err(syntax_error(
+							"when using aggregate functions, all selected fields must be aggregates",
+						));
+					}
+				}
+			}
+		}
+
+		Ok(Query {
+			projection,
+			alias,
+			condition,
+			limit,
+			aggregates,
+		})
+	}
+
+	// Each operator applied in a chain of binary operators (e.g. `a + b + c`) adds
+	// a level to the expression tree, so each of them calls self.enter()
+
+	fn parse_expr(&mut self) -> Result<Expr, Error> {
+		let depth = self.depth;
+		self.enter()?;
+		let mut left = self.parse_and()?;
+		while self.eat_keyword("or") {
+			self.enter()?;
+			let right = self.parse_and()?;
+			left = Expr::Or(Box::new(left), Box::new(right));
+		}
+		self.depth = depth;
+		Ok(left)
+	}
+
+	fn parse_and(&mut self) -> Result<Expr, Error> {
+		let depth = self.depth;
+		let mut left = self.parse_not()?;
+		while self.eat_keyword("and") {
+			self.enter()?;
+			let right = self.parse_not()?;
+			left = Expr::And(Box::new(left), Box::new(right));
+		}
+		self.depth = depth;
+		Ok(left)
+	}
+
+	fn parse_not(&mut self) -> Result<Expr, Error> {
+		if self.eat_keyword("not") {
+			let depth = self.depth;
+			self.enter()?;
+			let expr = self.parse_not()?;
+			self.depth = depth;
+			Ok(Expr::Not(Box::new(expr)))
+		} else {
+			self.parse_comparison()
+		}
+	}
+
+	fn parse_comparison(&mut self) -> Result<Expr, Error> {
+		let left = self.parse_additive()?;
+
+		let op = match self.peek() {
+			Some(Token::Symbol("=")) => Some(CompareOp::Eq),
+			Some(Token::Symbol("!=")) | Some(Token::Symbol("<>")) => Some(CompareOp::Ne),
+			Some(Token::Symbol("<")) => Some(CompareOp::Lt),
+			Some(Token::Symbol("<=")) => Some(CompareOp::Le),
+			Some(Token::Symbol(">")) => Some(CompareOp::Gt),
+			Some(Token::Symbol(">=")) => Some(CompareOp::Ge),
+			_ => None,
+		};
+		if let Some(op) = op {
+			self.pos += 1;
+			let right = self.parse_additive()?;
+			return Ok(Expr::Compare(op, Box::new(left), Box::new(right)));
+		}
+
+		if self.eat_keyword("is") {
+			let negated = self.eat_keyword("not");
+			let missing = if self.eat_keyword("null") {
+				false
+			} else if self.eat_keyword("missing") {
+				true
+			} else {
+				return Err(self.unexpected("expected NULL or MISSING"));
+			};
+			return Ok(Expr::IsNull {
+				expr: Box::new(left),
+				missing,
+				negated,
+			});
+		}
+
+		let negated = if self.peek_keyword("not")
+			&& (self.peek_keyword_at(1, "like")
+				|| self.peek_keyword_at(1, "between")
+				|| self.peek_keyword_at(1, "in"))
+		{
+			self.pos += 1;
+			true
+		} else {
+			false
+		};
+
+		if self.eat_keyword("like") {
+			let pattern = self.parse_additive()?;
+			let escape = if self.eat_keyword("escape") {
+				Some(Box::new(self.parse_additive()?))
+			} else {
+				None
+			};
+			Ok(Expr::Like {
+				expr: Box::new(left),
+				pattern: Box::new(pattern),
+				escape,
+				negated,
+			})
+		} else if self.eat_keyword("between") {
+			let low = self.parse_additive()?;
+			self.expect_keyword("and")?;
+			let high = self.parse_additive()?;
+			Ok(Expr::Between {
+				expr: Box::new(left),
+				low: Box::new(low),
+				high: Box::new(high),
+				negated,
+			})
+		} else if self.eat_keyword("in") {
+			self.expect_symbol("(")?;
+			let mut list = vec![self.parse_expr()?];
+			while self.eat_symbol(",") {
+				list.push(self.parse_expr()?);
+			}
+			self.expect_symbol(")")?;
+			Ok(Expr::In {
+				expr: Box::new(left),
+				list,
+				negated,
+			})
+		} else {
+			Ok(left)
+		}
+	}
+
+	fn parse_additive(&mut self) -> Result<Expr, Error> {
+		let depth = self.depth;
+		let mut left = self.parse_multiplicative()?;
+		loop {
+			if self.eat_symbol("+") {
+				self.enter()?;
+				let right = self.parse_multiplicative()?;
+				left = Expr::Arith(ArithOp::Add, Box::new(left), Box::new(right));
+			} else if self.eat_symbol("-") {
+				self.enter()?;
+				let right = self.parse_multiplicative()?;
+				left = Expr::Arith(ArithOp::Sub, Box::new(left), Box::new(right));
+			} else if self.eat_symbol("||") {
+				self.enter()?;
+				let right = self.parse_multiplicative()?;
+				left = Expr::Concat(Box::new(left), Box::new(right));
+			} else {
+				self.depth = depth;
+				return Ok(left);
+			}
+		}
+	}
+
+	fn parse_multiplicative(&mut self) -> Result<Expr, Error> {
+		let depth = self.depth;
+		let mut left = self.parse_unary()?;
+		loop {
+			let op = if self.eat_symbol("*") {
+				ArithOp::Mul
+			} else if self.eat_symbol("/") {
+				ArithOp::Div
+			} else if self.eat_symbol("%") {
+				ArithOp::Mod
+			} else {
+				self.depth = depth;
+				return Ok(left);
+			};
+			self.enter()?;
+			let right = self.parse_unary()?;
+			left = Expr::Arith(op, Box::new(left), Box::new(right));
+		}
+	}
+
+	fn parse_unary(&mut self) -> Result<Expr, Error> {
+		if self.peek_symbol("-") || self.peek_symbol("+") {
+			let depth = self.depth;
+			self.enter()?;
+			let expr = if self.eat_symbol("-") {
+				Expr::Neg(Box::new(self.parse_unary()?))
+			} else {
+				self.pos += 1;
+				self.parse_unary()?
+			};
+			self.depth = depth;
+			Ok(expr)
+		} else {
+			self.parse_primary()
+		}
+	}
+
+	fn parse_primary(&mut self) -> Result<Expr, Error> {
+		match self.next() {
+			Some(Token::Int(i)) => Ok(Expr::Literal(Value::Int(i))),
+			Some(Token::Float(f)) => Ok(Expr::Literal(Value::Float(f))),
+			Some(Token::Str(s)) => Ok(Expr::Literal(Value::String(s))),
+			Some(Token::Symbol("(")) => {
+				let expr = self.parse_expr()?;
+				self.expect_symbol(")")?;
+				Ok(expr)
+			}
+			Some(Token::QuotedIdent(name)) => self.parse_path(name, true),
+			Some(Token::Ident(id)) => {
+				let lower = id.to_lowercase();
+				match lower.as_str() {
+					"true" => Ok(Expr::Literal(Value::Bool(true))),
+					"false" => Ok(Expr::Literal(Value::Bool(false))),
+					"null" => Ok(Expr::Literal(Value::Null)),
+					"missing" => Ok(Expr::Literal(Value::Missing)),
+					"cast" => self.parse_cast(),
+					_ if self.peek_symbol("(") => self.parse_function_call(&lower),
+					_ if RESERVED_KEYWORDS.contains(&lower.as_str()) => {
+						self.pos -= 1;
+						Err(self.unexpected("expected an expression"))
+					}
+					_ => self.parse_path(id, false),
+				}
+			}
+			_ => {
+				self.pos -= 1;
+				Err(self.unexpected("expected an expression"))
+			}
+		}
+	}
+
+	fn parse_path(&mut self, first: String, quoted: bool) -> Result<Expr, Error> {
+		let mut path = vec![PathElem::Field {
+			name: first,
+			quoted,
+		}];
+		loop {
+			if self.eat_symbol(".") {
+				match self.next() {
+					Some(Token::Ident(name)) => path.push(PathElem::Field {
+						name,
+						quoted: false,
+					}),
+					Some(Token::QuotedIdent(name)) => {
+						path.push(PathElem::Field { name, quoted: true })
+					}
+					_ => {
+						self.pos -= 1;
+						return Err(self.unexpected("expected a field name"));
+					}
+				}
+			} else if self.eat_symbol("[") {
+				match self.next() {
+					Some(Token::Int(i)) if i >= 0 => path.push(PathElem::Index(i as usize)),
+					_ => {
+						self.pos -= 1;
+						return Err(self.unexpected("expected an array index"));
+					}
+				}
+				self.expect_symbol("]")?;
+			} else {
+				return Ok(Expr::Path(path));
+			}
+		}
+	}
+
+	fn parse_cast(&mut self) -> Result<Expr, Error> {
+		self.expect_symbol("(")?;
+		let expr = self.parse_expr()?;
+		self.expect_keyword("as")?;
+		let cast_type = match self.next() {
+			Some(Token::Ident(t)) => match t.to_lowercase().as_str() {
+				"int" | "integer" | "bigint" | "smallint" => CastType::Int,
+				"float" | "double" | "real" | "decimal" | "numeric" => CastType::Float,
+				"string" | "varchar" | "char" | "text" => CastType::String,
+				"bool" | "boolean" => CastType::Bool,
+				_ => return Err(syntax_error(format!("unsupported type in CAST: {}", t))),
+			},
+			_ => {
+				self.pos -= 1;
+				return Err(self.unexpected("expected a type name"));
+			}
+		};
+		self.expect_symbol(")")?;
+		Ok(Expr::Cast(Box::new(expr), cast_type))
+	}
+
+	fn parse_function_call(&mut self, name: &str) -> Result<Expr, Error> {
+		self.expect_symbol("(")?;
+
+		let aggregate = match name {
+			"count" => Some(AggregateFunction::Count),
+			"sum" => Some(AggregateFunction::Sum),
+			"avg" => Some(AggregateFunction::Avg),
+			"min" => Some(AggregateFunction::Min),
+			"max" => Some(AggregateFunction::Max),
+			_ => None,
+		};
+		if let Some(function) = aggregate {
+			if self.in_aggregate {
+				return Err(syntax_error("aggregate functions cannot be nested"));
+			}
+			let arg = if function == AggregateFunction::Count && self.eat_symbol("*") {
+				None
+			} else {
+				self.in_aggregate = true;
+				let arg = self.parse_expr();
+				self.in_aggregate = false;
+				Some(arg?)
+			};
+			self.expect_symbol(")")?;
+			self.aggregates.push(Aggregate { function, arg });
+			return Ok(Expr::Aggregate(self.aggregates.len() - 1));
+		}
+
+		let (function, min_args, max_args) = match name {
+			"lower" => (Function::Lower, 1, 1),
+			"upper" => (Function::Upper, 1, 1),
+			"char_length" | "character_length" => (Function::CharLength, 1, 1),
+			"trim" => (Function::Trim, 1, 1),
+			"substring" => (Function::Substring, 2, 3),
+			"coalesce" => (Function::Coalesce, 1, usize::MAX),
+			"nullif" => (Function::NullIf, 2, 2),
+			_ => return Err(syntax_error(format!("unsupported function: {}", name))),
+		};
+
+		let mut args = vec![self.parse_expr()?];
+		if function == Function::Substring && self.eat_keyword("from") {
+			// SUBSTRING(string FROM start [FOR length])
+			args.push(self.parse_expr()?);
+			if self.eat_keyword("for") {
+				args.push(self.parse_expr()?);
+			}
+		} else {
+			while self.eat_symbol(",") {
+				args.push(self.parse_expr()?);
+			}
+		}
+		self.expect_symbol(")")?;
+
+		if args.len() < min_args || args.len() > max_args {
+			return Err(syntax_error(format!(
+				"invalid number of arguments for function {}",
+				name.to_uppercase()
+			)));
+		}
+		Ok(Expr::Function(function, args))
+	}
+}
+
+// ---- Evaluation ----
+
+/// Running state of an aggregate function
+#[derive(Debug, Clone)]
+pub enum Accumulator {
+	Count(u64),
+	Sum(Option<Value>),
+	Avg { sum: f64, count: u64 },
+	Min(Option<Value>),
+	Max(Option<Value>),
+}
+
+impl Query {
+	pub fn parse(expr: &str) -> Result<Self, Error> {
+		let mut parser = Parser {
+			tokens: tokenize(expr)?,
+			pos: 0,
+			aggregates: vec![],
+			in_aggregate: false,
+			depth: 0,
+		};
+		parser.parse_query()
+	}
+
+	pub fn limit(&self) -> Option<u64> {
+		self.limit
+	}
+
+	/// Whether the query computes aggregate functions over all of the records,
+	/// and returns a single row
+	pub fn is_aggregate(&self) -> bool {
+		!self.aggregates.is_empty()
+	}
+
+	/// Check whether a record satisfies the WHERE clause of the query
+	pub fn matches(&self, record: &Record) -> Result<bool, Error> {
+		match &self.condition {
+			None => Ok(true),
+			Some(cond) => Ok(self.eval(cond, Some(record), None)? == Value::Bool(true)),
+		}
+	}
+
+	/// Compute the fields selected by the query for a record
+	pub fn project(&self, record: &Record) -> Result<Vec<(String, Value)>, Error> {
+		match &self.projection {
+			Projection::All => Ok(record.all_fields()),
+			Projection::Items(items) => items
+				.iter()
+				.map(|item| {
+					Ok((
+						item.name.clone(),
+						self.eval(&item.expr, Some(record), None)?,
+					))
+				})
+				.collect(),
+		}
+	}
+
+	pub fn new_accumulators(&self) -> Vec<Accumulator> {
+		self.aggregates
+			.iter()
+			.map(|a| match a.function {
+				AggregateFunction::Count => Accumulator::Count(0),
+				AggregateFunction::Sum => Accumulator::Sum(None),
+				AggregateFunction::Avg => Accumulator::Avg { sum: 0., count: 0 },
+				AggregateFunction::Min => Accumulator::Min(None),
+				AggregateFunction::Max => Accumulator::Max(None),
+			})
+			.collect()
+	}
+
+	/// Update the state of the aggregate functions of the query with a record
+	pub fn accumulate(
+		&self,
+		accumulators: &mut [Accumulator],
+		record: &Record,
+	) -> Result<(), Error> {
+		for (aggregate, acc) in self.aggregates.iter().zip(accumulators.iter_mut()) {
+			let value = match &aggregate.arg {
+				None => Value::Bool(true),
+				Some(arg) => self.eval(arg, Some(record), None)?,
+			};
+			if value.is_null() {
+				continue;
+			}
+			match acc {
+				Accumulator::Count(n) => *n += 1,
+				Accumulator::Sum(sum) => {
+					let value = value.as_number().ok_or_else(|| {
+						eval_error(format!("SUM of a non-numeric value: {:?}", value))
+					})?;
+					*sum = Some(match sum.take() {
+						None => value,
+						Some(s) => arith(ArithOp::Add, &s, &value)?,
+					});
+				}
+				Accumulator::Avg { sum, count } => {
+					let value = value.as_number().and_then(|v| v.as_f64()).ok_or_else(|| {
+						eval_error(format!("AVG of a non-numeric value: {:?}", value))
+					})?;
+					*sum += value;
+					*count += 1;
+				}
+				Accumulator::Min(min) => {
+					if min
+						.as_ref()
+						.map(|m| compare(&value, m) == Some(Ordering::Less))
+						.unwrap_or(true)
+					{
+						*min = Some(value);
+					}
+				}
+				Accumulator::Max(max) => {
+					if max
+						.as_ref()
+						.map(|m| compare(&value, m) == Some(Ordering::Greater))
+						.unwrap_or(true)
+					{
+						*max = Some(value);
+					}
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Compute the fields selected by an aggregate query, once all records have been seen
+	pub fn aggregate_result(
+		&self,
+		accumulators: &[Accumulator],
+	) -> Result<Vec<(String, Value)>, Error> {
+		let values = accumulators
+			.iter()
+			.map(|acc| match acc {
+				Accumulator::Count(n) => Value::Int(*n as i64),
+				Accumulator::Avg { count: 0, .. } => Value::Null,
+				Accumulator::Avg { sum, count } => Value::Float(*sum / *count as f64),
+				Accumulator::Sum(v) | Accumulator::Min(v) | Accumulator::Max(v) => {
+					v.clone().unwrap_or(Value::Null)
+				}
+			})
+			.collect::<Vec<_>>();
+
+		match &self.projection {
+			Projection::All => unreachable!(),
+			Projection::Items(items) => items
+				.iter()
+				.map(|item| {
+					Ok((
+						item.name.clone(),
+						self.eval(&item.expr, None, Some(&values))?,
+					))
+				})
+				.collect(),
+		}
+	}
+
+	/// Remove the name of the object from the start of a path, if it is there
+	fn resolve_path<'a>(&self, path: &'a [PathElem]) -> &'a [PathElem] {
+		match path.first() {
+			Some(PathElem::Field {
+				name,
+				quoted: false,
+			}) if path.len() > 1 || self.alias.is_some() => {
+				let lower = name.to_lowercase();
+				if self.alias.as_deref() == Some(lower.as_str()) || lower == "s3object" {
+					&path[1..]
+				} else {
+					path
+				}
+			}
+			_ => path,
+		}
+	}
+
+	fn eval(
+		&self,
+		expr: &Expr,
+		record: Option<&Record>,
+		aggregates: Option<&[Value]>,
+	) -> Result<Value, Error> {
+		let eval = |e: &Expr| self.eval(e, record, aggregates);
+
+		match expr {
+			Expr::Literal(v) => Ok(v.clone()),
+			Expr::Path(path) => Ok(match record {
+				Some(r) => r.lookup(self.resolve_path(path)),
+				None => Value::Missing,
+			}),
+			Expr::Aggregate(i) => Ok(aggregates
+				.and_then(|a| a.get(*i))
+				.cloned()
+				.unwrap_or(Value::Null)),
+			Expr::Not(e) => Ok(match truth(&eval(e)?)? {
+				Some(b) => Value::Bool(!b),
+				None => Value::Null,
+			}),
+			Expr::And(a, b) => {
+				let a = truth(&eval(a)?)?;
+				if a == Some(false) {
+					return Ok(Value::Bool(false));
+				}
+				Ok(match (a, truth(&eval(b)?)?) {
+					(_, Some(false)) => Value::Bool(false),
+					(Some(true), Some(true)) => Value::Bool(true),
+					_ => Value::Null,
+				})
+			}
+			Expr::Or(a, b) => {
+				let a = truth(&eval(a)?)?;
+				if a == Some(true) {
+					return Ok(Value::Bool(true));
+				}
+				Ok(match (a, truth(&eval(b)?)?) {
+					(_, Some(true)) => Value::Bool(true),
+					(Some(false), Some(false)) => Value::Bool(false),
+					_ => Value::Null,
+				})
+			}
+			Expr::Compare(op, a, b) => Ok(compare_op(*op, &eval(a)?, &eval(b)?)),
+			Expr::Arith(op, a, b) => arith(*op, &eval(a)?, &eval(b)?),
+			Expr::Neg(e) => arith(ArithOp::Sub, &Value::Int(0), &eval(e)?),
+			Expr::Concat(a, b) => {
+				let (a, b) = (eval(a)?, eval(b)?);
+				if a.is_null() || b.is_null() {
+					Ok(Value::Null)
+				} else {
+					Ok(Value::String(a.to_text() + &b.to_text()))
+				}
+			}
+			Expr::IsNull {
+				expr,
+				missing,
+				negated,
+			} => {
+				let v = eval(expr)?;
+				let is = if *missing {
+					v.is_missing()
+				} else {
+					v.is_null()
+				};
+				Ok(Value::Bool(is != *negated))
+			}
+			Expr::Like {
+				expr,
+				pattern,
+				escape,
+				negated,
+			} => {
+				let (v, pattern) = (eval(expr)?, eval(pattern)?);
+				let escape = match escape {
+					Some(e) => match eval(e)? {
+						Value::String(s) if s.chars().count() == 1 => s.chars().next(),
+						_ => return Err(eval_error("LIKE escape must be a single character")),
+					},
+					None => None,
+				};
+				match (v, pattern) {
+					(Value::String(s), Value::String(p)) => {
+						Ok(Value::Bool(like_match(&s, &p, escape)? != *negated))
+					}
+					(v, p) if v.is_null() || p.is_null() => Ok(Value::Null),
+					(v, p) => Err(eval_error(format!(
+						"LIKE can only be used on strings, got {:?} and {:?}",
+						v, p
+					))),
+				}
+			}
+			Expr::Between {
+				expr,
+				low,
+				high,
+				negated,
+			} => {
+				let v = eval(expr)?;
+				let ge = compare_op(CompareOp::Ge, &v, &eval(low)?);
+				let le = compare_op(CompareOp::Le, &v, &eval(high)?);
+				Ok(match (truth(&ge)?, truth(&le)?) {
+					(Some(a), Some(b)) => Value::Bool((a && b) != *negated),
+					(Some(false), _) | (_, Some(false)) => Value::Bool(*negated),
+					_ => Value::Null,
+				})
+			}
+			Expr::In {
+				expr,
+				list,
+				negated,
+			} => {
+				let v = eval(expr)?;
+				let mut result = Value::Bool(false);
+				for item in list.iter() {
+					match compare_op(CompareOp::Eq, &v, &eval(item)?) {
+						Value::Bool(true) => {
+							result = Value::Bool(true);
+							break;
+						}
+						Value::Bool(false) => (),
+						_ => result = Value::Null,
+					}
+				}
+				Ok(match result {
+					Value::Bool(b) => Value::Bool(b != *negated),
+					r => r,
+				})
+			}
+			Expr::Cast(e, t) => cast(eval(e)?, *t),
+			Expr::Function(f, args) => {
+				let args = args.iter().map(eval).collect::<Result<Vec<_>, _>>()?;
+				call_function(*f, args)
+			}
+		}
+	}
+}
+
+/// Interpret a value as a boolean, None meaning unknown
+fn truth(v: &Value) -> Result<Option<bool>, Error> {
+	match v {
+		Value::Bool(b) => Ok(Some(*b)),
+		Value::Null | Value::Missing => Ok(None),
+		v => Err(eval_error(format!("expected a boolean value, got {:?}", v))),
+	}
+}
+
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+	match (a, b) {
+		(Value::String(x), Value::String(y)) => Some(x.cmp(y)),
+		(Value::Bool(x), Value::Bool(y)) => Some(x.cmp(y)),
+		_ => match (a.as_number()?, b.as_number()?) {
+			(Value::Int(x), Value::Int(y)) => Some(x.cmp(&y)),
+			(x, y) => x.as_f64()?.partial_cmp(&y.as_f64()?),
+		},
+	}
+}
+
+fn compare_op(op: CompareOp, a: &Value, b: &Value) -> Value {
+	if a.is_null() || b.is_null() {
+		return Value::Null;
+	}
+	if let (Value::Json(x), Value::Json(y)) = (a, b) {
+		return match op {
+			CompareOp::Eq => Value::Bool(x == y),
+			CompareOp::Ne => Value::Bool(x != y),
+			_ => Value::Null,
+		};
+	}
+	match compare(a, b) {
+		Some(ord) => Value::Bool(match op {
+			CompareOp::Eq => ord == Ordering::Equal,
+			CompareOp::Ne => ord != Ordering::Equal,
+			CompareOp::Lt => ord == Ordering::Less,
+			CompareOp::Le => ord != Ordering::Greater,
+			CompareOp::Gt => ord == Ordering::Greater,
+			CompareOp::Ge => ord != Ordering::Less,
+		}),
+		None => Value::Null,
+	}
+}
+
+fn arith(op: ArithOp, a: &Value, b: &Value) -> Result<Value, Error> {
+	if a.is_null() || b.is_null() {
+		return Ok(Value::Null);
+	}
+	let (x, y) = match (a.as_number(), b.as_number()) {
+		(Some(x), Some(y)) => (x, y),
+		_ => {
+			return Err(eval_error(format!(
+				"invalid operands for arithmetic operation: {:?} and {:?}",
+				a, b
+			)))
+		}
+	};
+	match (x, y) {
+		(Value::Int(x), Value::Int(y)) => {
+			let result = match op {
+				ArithOp::Add => x.checked_add(y),
+				ArithOp::Sub => x.checked_sub(y),
+				ArithOp::Mul => x.checked_mul(y),
+				ArithOp::Div | ArithOp::Mod if y == 0 => {
+					return Err(eval_error("division by zero"))
+				}
+				ArithOp::Div => x.checked_div(y),
+				ArithOp::Mod => x.checked_rem(y),
+			};
+			result
+				.map(Value::Int)
+				.ok_or_else(|| eval_error("integer overflow"))
+		}
+		(x, y) => {
+			let (x, y) = (x.as_f64().unwrap(), y.as_f64().unwrap());
+			Ok(Value::Float(match op {
+				ArithOp::Add => x + y,
+				ArithOp::Sub => x - y,
+				ArithOp::Mul => x * y,
+				ArithOp::Div | ArithOp::Mod if y == 0. => {
+					return Err(eval_error("division by zero"))
+				}
+				ArithOp::Div => x / y,
+				ArithOp::Mod => x % y,
+			}))
+		}
+	}
+}
+
+fn cast(v: Value, t: CastType) -> Result<Value, Error> {
+	if v.is_null() {
+		return Ok(Value::Null);
+	}
+	let invalid = |v: &Value| eval_error(format!("cannot cast {:?} to {:?}", v, t));
+	match t {
+		CastType::Int => match &v {
+			Value::Int(_) => Ok(v),
+			Value::Float(f) if f.is_finite() => Ok(Value::Int(f.trunc() as i64)),
+			Value::String(s) => match parse_number(s.trim()) {
+				Some(Value::Int(i)) => Ok(Value::Int(i)),
+				Some(Value::Float(f)) if f.is_finite() => Ok(Value::Int(f.trunc() as i64)),
+				_ => Err(invalid(&v)),
+			},
+			_ => Err(invalid(&v)),
+		},
+		CastType::Float => match v.as_number().and_then(|n| n.as_f64()) {
+			Some(f) => Ok(Value::Float(f)),
+			None => Err(invalid(&v)),
+		},
+		CastType::String => Ok(Value::String(v.to_text())),
+		CastType::Bool => match &v {
+			Value::Bool(_) => Ok(v),
+			Value::String(s) if s.trim().eq_ignore_ascii_case("true") => Ok(Value::Bool(true)),
+			Value::String(s) if s.trim().eq_ignore_ascii_case("false") => Ok(Value::Bool(false)),
+			_ => Err(invalid(&v)),
+		},
+	}
+}
+
+fn call_function(f: Function, mut args: Vec<Value>) -> Result<Value, Error> {
+	let string_arg = |v: &Value| match v {
+		Value::String(s) => Ok(s.clone()),
+		v => Err(eval_error(format!(
+			"expected a string argument, got {:?}",
+			v
+		))),
+	};
+	let int_arg = |v: &Value| match v.as_number() {
+		Some(Value::Int(i)) => Ok(i),
+		_ => Err(eval_error(format!(
+			"expected an integer argument, got {:?}",
+			v
+		))),
+	};
+
+	match f {
+		Function::Coalesce => {
+			return Ok(args
+				.into_iter()
+				.find(|v| !v.is_null())
+				.unwrap_or(Value::Null))
+		}
+		Function::NullIf => {
+			return Ok(match compare_op(CompareOp::Eq, &args[0], &args[1]) {
+				Value::Bool(true) => Value::Null,
+				_ => args.swap_remove(0),
+			})
+		}
+		_ => (),
+	}
+
+	if args.iter().any(Value::is_null) {
+		return Ok(Value::Null);
+	}
+	match f {
+		Function::Lower => Ok(Value::String(string_arg(&args[0])?.to_lowercase())),
+		Function::Upper => Ok(Value::String(string_arg(&args[0])?.to_uppercase())),
+		Function::CharLength => Ok(Value::Int(string_arg(&args[0])?.chars().count() as i64)),
+		Function::Trim => Ok(Value::String(string_arg(&args[0])?.trim().to_string())),
+		Function::Substring => {
+			// Positions start at 1, and characters before position 1 are counted
+			// in the length but do not exist
+			let s = string_arg(&args[0])?;
+			let start = int_arg(&args[1])?;
+			let end = match args.get(2) {
+				Some(len) => {
+					let len = int_arg(len)?;
+					if len < 0 {
+						return Err(eval_error("negative length in SUBSTRING"));
+					}
+					Some(start.saturating_add(len))
+				}
+				None => None,
+			};
+			let skip = std::cmp::max(start, 1) - 1;
+			let take = match end {
+				Some(end) => std::cmp::max(end.saturating_sub(1).saturating_sub(skip), 0),
+				None => i64::MAX,
+			};
+			Ok(Value::String(
+				s.chars().skip(skip as usize).take(take as usize).collect(),
+			))
+		}
+		Function::Coalesce | Function::NullIf => unreachable!(),
+	}
+}
+
+fn like_match(s: &str, pattern: &str, escape: Option<char>) -> Result<bool, Error> {
+	enum PatternElem {
+		AnyString,
+		AnyChar,
+		Char(char),
+	}
+
+	let mut elems = vec![];
+	let mut chars = pattern.chars();
+	while let Some(c) = chars.next() {
+		if Some(c) == escape {
+			match chars.next() {
+				Some(c) => elems.push(PatternElem::Char(c)),
+				None => return Err(eval_error("LIKE pattern ends with escape character")),
+			}
+		} else if c == '%' {
+			elems.push(PatternElem::AnyString);
+		} else if c == '_' {
+			elems.push(PatternElem::AnyChar);
+		} else {
+			elems.push(PatternElem::Char(c));
+		}
+	}
+
+	// Greedy matching, backtracking to the position of the last %
+	let s = s.chars().collect::<Vec<_>>();
+	let (mut si, mut pi) = (0, 0);
+	let mut backtrack: Option<(usize, usize)> = None;
+	while si < s.len() {
+		match elems.get(pi) {
+			Some(PatternElem::AnyString) => {
+				backtrack = Some((pi, si));
+				pi += 1;
+				continue;
+			}
+			Some(PatternElem::AnyChar) => {
+				si += 1;
+				pi += 1;
+				continue;
+			}
+			Some(PatternElem::Char(c)) if *c == s[si] => {
+				si += 1;
+				pi += 1;
+				continue;
+			}
+			_ => (),
+		}
+		match backtrack {
+			Some((bpi, bsi)) => {
+				backtrack = Some((bpi, bsi + 1));
+				pi = bpi + 1;
+				si = bsi + 1;
+			}
+			None => return Ok(false),
+		}
+	}
+	Ok(elems[pi..]
+		.iter()
+		.all(|e| matches!(e, PatternElem::AnyString)))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn csv_record(columns: &[&str], fields: &[&str]) -> Record {
+		Record::Csv {
+			columns: Some(Arc::new(columns.iter().map(|c| c.to_string()).collect())),
+			fields: fields.iter().map(|f| f.to_string()).collect(),
+		}
+	}
+
+	#[test]
+	fn test_parse_query() {
+		assert!(Query::parse("SELECT * FROM S3Object").is_ok());
+		assert!(Query::parse(
+			"select s.name, s._2 AS age from s3object s where s.age > 30 limit 10"
+		)
+		.is_ok());
+		assert!(
+			Query::parse("SELECT COUNT(*), AVG(CAST(s.age AS INT)) FROM S3Object[*] AS s").is_ok()
+		);
+		assert!(Query::parse("SELECT SUBSTRING(name FROM 2 FOR 3) FROM S3Object").is_ok());
+
+		assert!(Query::parse("SELECT * FROM table").is_err());
+		assert!(Query::parse("SELECT name FROM S3Object WHERE").is_err());
+		assert!(Query::parse("SELECT name, COUNT(*) FROM S3Object").is_err());
+		assert!(Query::parse("SELECT * FROM S3Object WHERE COUNT(*) > 1").is_err());
+		assert!(Query::parse("SELECT SUM(MAX(a)) FROM S3Object").is_err());
+		assert!(Query::parse("SELECT * FROM S3Object LIMIT -1").is_err());
+		assert!(Query::parse("SELECT 'abc FROM S3Object").is_err());
+	}
+
+	#[test]
+	fn test_expression_depth() {
+		let query = |condition: String| {
+			Query::parse(&format!("SELECT * FROM S3Object WHERE {}", condition))
+		};
+		let nested = |prefix: &str, n: usize, suffix: &str| {
+			format!("{}1{}", prefix.repeat(n), suffix.repeat(n))
+		};
+
+		assert!(query(nested("(", 10, ")") + " = 1").is_ok());
+		assert!(query(nested("NOT ", 10, "") + " = 1").is_ok());
+		assert!(query(nested("1 + ", 20, "") + " = 1").is_ok());
+
+		// Deeply nested expressions fail with a syntax error instead of
+		// overflowing the stack
+		for (prefix, suffix) in [
+			("(", ")"),
+			("NOT ", ""),
+			("- ", ""),
+			("1 + ", ""),
+			("a OR ", ""),
+		] {
+			assert!(query(nested(prefix, 100_000, suffix) + " = 1").is_err());
+		}
+	}
+
+	#[test]
+	fn test_csv_query() {
+		let query = Query::parse(
+			"SELECT s.name, s._2 + 1 AS next_age FROM S3Object s \
+			 WHERE s.age >= 30 AND name LIKE 'A%' AND s.city IS MISSING",
+		)
+		.unwrap();
+
+		let record = csv_record(&["name", "age"], &["Alice", "30"]);
+		assert!(query.matches(&record).unwrap());
+		assert_eq!(
+			query.project(&record).unwrap(),
+			vec![
+				("name".to_string(), Value::String("Alice".into())),
+				("next_age".to_string(), Value::Int(31)),
+			]
+		);
+
+		assert!(!query
+			.matches(&csv_record(&["name", "age"], &["Alice", "29"]))
+			.unwrap());
+		assert!(!query
+			.matches(&csv_record(&["name", "age"], &["Bob", "40"]))
+			.unwrap());
+		assert!(!query
+			.matches(&csv_record(
+				&["name", "age", "city"],
+				&["Alice", "40", "Paris"]
+			))
+			.unwrap());
+	}
+
+	#[test]
+	fn test_json_query() {
+		let query = Query::parse(
+			"SELECT s.user.name, s.tags[1] FROM S3Object[*] s \
+			 WHERE s.user.\"Level\" IN (2, 3) AND NOT s.deleted",
+		)
+		.unwrap();
+
+		let record = Record::Json(serde_json::json!({
+			"user": {"name": "alice", "Level": 3},
+			"tags": ["a", "b"],
+			"deleted": false,
+		}));
+		assert!(query.matches(&record).unwrap());
+		assert_eq!(
+			query.project(&record).unwrap(),
+			vec![
+				("name".to_string(), Value::String("alice".into())),
+				("_2".to_string(), Value::String("b".into())),
+			]
+		);
+
+		let record = Record::Json(serde_json::json!({
+			"user": {"name": "bob", "level": 3},
+			"deleted": false,
+		}));
+		assert!(!query.matches(&record).unwrap());
+	}
+
+	#[test]
+	fn test_aggregates() {
+		let query = Query::parse(
+			"SELECT COUNT(*), COUNT(s.age), SUM(s.age), AVG(s.age), MIN(s.name), MAX(CAST(s.age AS INT)) \
+			 FROM S3Object s",
+		)
+		.unwrap();
+		assert!(query.is_aggregate());
+
+		let mut acc = query.new_accumulators();
+		for (name, age) in [("b", Some("10")), ("a", Some("32")), ("c", None)] {
+			let record = match age {
+				Some(age) => csv_record(&["name", "age"], &[name, age]),
+				None => csv_record(&["name"], &[name]),
+			};
+			query.accumulate(&mut acc, &record).unwrap();
+		}
+
+		let values = query
+			.aggregate_result(&acc)
+			.unwrap()
+			.into_iter()
+			.map(|(_, v)| v)
+			.collect::<Vec<_>>();
+		assert_eq!(
+			values,
+			vec![
+				Value::Int(3),
+				Value::Int(2),
+				Value::Int(42),
+				Value::Float(21.),
+				Value::String("a".into()),
+				Value::Int(32),
+			]
+		);
+	}
+
+	#[test]
+	fn test_like() {
+		assert!(like_match("hello", "h%o", None).unwrap());
+		assert!(like_match("hello", "_ell_", None).unwrap());
+		assert!(like_match("hello", "%", None).unwrap());
+		assert!(like_match("", "%", None).unwrap());
+		assert!(like_match("a%b", "a!%b", Some('!')).unwrap());
+		assert!(like_match("abcbd", "%b_", None).unwrap());
+		assert!(!like_match("axb", "a!%b", Some('!')).unwrap());
+		assert!(!like_match("hello", "h%x", None).unwrap());
+		assert!(!like_match("hello", "hell", None).unwrap());
+	}
+}
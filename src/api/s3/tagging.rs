@@ -0,0 +1,278 @@
+use quick_xml::de::from_reader;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use hyper::header::{HeaderMap, HeaderValue};
+use hyper::{Body, Request, Response, StatusCode};
+
+use serde::{Deserialize, Serialize};
+
+use garage_model::garage::Garage;
+use garage_model::s3::object_table::*;
+use garage_util::data::*;
+
+use crate::s3::error::*;
+use crate::s3::get::{add_version_id_header, find_object_version};
+use crate::s3::xml::{to_xml_with_header, xmlns_tag, Value};
+use crate::signature::verify_signed_content;
+
+pub const X_AMZ_TAGGING: &str = "x-amz-tagging";
+pub const X_AMZ_TAGGING_COUNT: &str = "x-amz-tagging-count";
+
+const MAX_TAGS: usize = 10;
+const MAX_TAG_KEY_LENGTH: usize = 128;
+const MAX_TAG_VALUE_LENGTH: usize = 256;
+
+pub async fn handle_get_object_tagging(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	key: &str,
+	version_id: Option<&str>,
+) -> Result<Response<Body>, Error> {
+	let object = get_object(&garage, bucket_id, key).await?;
+	let (object_version, _, _) = find_object_version(&object, version_id)?;
+
+	let tagging = Tagging::from_garage_tags(object_version.tags.get());
+	let xml = to_xml_with_header(&tagging)?;
+
+	let resp = Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/xml");
+	let resp = add_version_id_header(resp, object_version.uuid, object_version.versioned);
+	Ok(resp.body(Body::from(xml))?)
+}
+
+pub async fn handle_put_object_tagging(
+	garage: Arc<Garage>,
+	req: Request<Body>,
+	bucket_id: Uuid,
+	key: &str,
+	version_id: Option<&str>,
+	content_sha256: Option<Hash>,
+) -> Result<Response<Body>, Error> {
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	let tags = parse_tagging_xml(&body)?;
+
+	let (version_uuid, versioned) =
+		update_object_tags(&garage, bucket_id, key, version_id, tags).await?;
+
+	let resp = Response::builder().status(StatusCode::OK);
+	Ok(add_version_id_header(resp, version_uuid, versioned).body(Body::empty())?)
+}
+
+pub async fn handle_delete_object_tagging(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	key: &str,
+	version_id: Option<&str>,
+) -> Result<Response<Body>, Error> {
+	let (version_uuid, versioned) =
+		update_object_tags(&garage, bucket_id, key, version_id, ObjectTags::default()).await?;
+
+	let resp = Response::builder().status(StatusCode::NO_CONTENT);
+	Ok(add_version_id_header(resp, version_uuid, versioned).body(Body::empty())?)
+}
+
+async fn get_object(garage: &Garage, bucket_id: Uuid, key: &str) -> Result<Object, Error> {
+	garage
+		.object_table
+		.get(&bucket_id, &key.to_string())
+		.await?
+		.ok_or(Error::NoSuchKey)
+}
+
+/// Set the tags of an object version, and return the uuid of that version
+/// and whether it was written with versioning enabled
+async fn update_object_tags(
+	garage: &Garage,
+	bucket_id: Uuid,
+	key: &str,
+	version_id: Option<&str>,
+	tags: ObjectTags,
+) -> Result<(Uuid, bool), Error> {
+	let object = get_object(garage, bucket_id, key).await?;
+	let (object_version, _, _) = find_object_version(&object, version_id)?;
+
+	// Write a copy of the version with only its tags changed:
+	// when merged with the stored version, the new tags will be kept
+	// as they have a more recent timestamp
+	let mut object_version = object_version.clone();
+	object_version.tags.update(tags);
+	let version_uuid = object_version.uuid;
+	let versioned = object_version.versioned;
+
+	let object = Object::new(bucket_id, key.to_string(), vec![object_version]);
+	garage.object_table.insert(&object).await?;
+
+	Ok((version_uuid, versioned))
+}
+
+/// Parse the tags given in the x-amz-tagging header of a request,
+/// which are encoded as URL query parameters
+pub fn parse_tagging_header(headers: &HeaderMap<HeaderValue>) -> Result<ObjectTags, Error> {
+	match headers.get(X_AMZ_TAGGING) {
+		Some(h) => {
+			let tags = url::form_urlencoded::parse(h.as_bytes())
+				.map(|(k, v)| (k.into_owned(), v.into_owned()))
+				.collect::<Vec<_>>();
+			validate_tags(tags)
+		}
+		None => Ok(ObjectTags::default()),
+	}
+}
+
+/// Parse and validate a Tagging XML document, as sent in PutObjectTagging
+/// requests or in the `tagging` field of POST object uploads
+pub fn parse_tagging_xml(body: &[u8]) -> Result<ObjectTags, Error> {
+	let tagging: Tagging = from_reader(body)?;
+	tagging.validate_into_garage_tags()
+}
+
+fn validate_tags(tags: Vec<(String, String)>) -> Result<ObjectTags, Error> {
+	if tags.len() > MAX_TAGS {
+		return Err(Error::bad_request(format!(
+			"Objects can have at most {} tags",
+			MAX_TAGS
+		)));
+	}
+
+	let mut keys = HashSet::new();
+	for (k, v) in tags.iter() {
+		if k.is_empty() || k.chars().count() > MAX_TAG_KEY_LENGTH {
+			return Err(Error::bad_request(format!(
+				"Tag keys must be between 1 and {} characters long",
+				MAX_TAG_KEY_LENGTH
+			)));
+		}
+		if v.chars().count() > MAX_TAG_VALUE_LENGTH {
+			return Err(Error::bad_request(format!(
+				"Tag values must be at most {} characters long",
+				MAX_TAG_VALUE_LENGTH
+			)));
+		}
+		if !keys.insert(k.as_str()) {
+			return Err(Error::bad_request(format!("Duplicate tag key: {}", k)));
+		}
+	}
+
+	Ok(ObjectTags(tags))
+}
+
+// ---- SERIALIZATION AND DESERIALIZATION TO/FROM S3 XML ----
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Tagging {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "TagSet")]
+	pub tag_set: TagSet,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TagSet {
+	#[serde(rename = "Tag", default)]
+	pub tags: Vec<Tag>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Tag {
+	#[serde(rename = "Key")]
+	pub key: Value,
+	#[serde(rename = "Value")]
+	pub value: Value,
+}
+
+impl Tagging {
+	pub fn validate_into_garage_tags(self) -> Result<ObjectTags, Error> {
+		validate_tags(
+			self.tag_set
+				.tags
+				.into_iter()
+				.map(|t| (t.key.0, t.value.0))
+				.collect(),
+		)
+	}
+
+	pub fn from_garage_tags(tags: &ObjectTags) -> Self {
+		Self {
+			xmlns: (),
+			tag_set: TagSet {
+				tags: tags
+					.0
+					.iter()
+					.map(|(k, v)| Tag {
+						key: Value(k.clone()),
+						value: Value(v.clone()),
+					})
+					.collect(),
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use quick_xml::de::from_str;
+
+	#[test]
+	fn test_deserialize_tagging() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Tagging xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <TagSet>
+    <Tag>
+      <Key>stage</Key>
+      <Value>raw</Value>
+    </Tag>
+    <Tag>
+      <Key>project</Key>
+      <Value>alpha</Value>
+    </Tag>
+  </TagSet>
+</Tagging>"#;
+		let tagging: Tagging = from_str(message).unwrap();
+		let tags = tagging.validate_into_garage_tags()?;
+		assert_eq!(
+			tags,
+			ObjectTags(vec![
+				("stage".to_string(), "raw".to_string()),
+				("project".to_string(), "alpha".to_string()),
+			])
+		);
+
+		let message2 = to_xml_with_header(&Tagging::from_garage_tags(&tags))?;
+		let tagging2: Tagging = from_str(&message2).unwrap();
+		assert_eq!(tagging2.validate_into_garage_tags()?, tags);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_parse_tagging_header() -> Result<(), Error> {
+		let mut headers = HeaderMap::new();
+		assert_eq!(parse_tagging_header(&headers)?, ObjectTags::default());
+
+		headers.insert(
+			X_AMZ_TAGGING,
+			HeaderValue::from_static("stage=raw&team=data%20eng"),
+		);
+		assert_eq!(
+			parse_tagging_header(&headers)?,
+			ObjectTags(vec![
+				("stage".to_string(), "raw".to_string()),
+				("team".to_string(), "data eng".to_string()),
+			])
+		);
+
+		headers.insert(X_AMZ_TAGGING, HeaderValue::from_static("a=1&a=2"));
+		assert!(parse_tagging_header(&headers).is_err());
+
+		Ok(())
+	}
+}
@@ -68,9 +68,11 @@ pub struct Deleted {
 	#[serde(rename = "Key")]
 	pub key: Value,
 	#[serde(rename = "VersionId")]
-	pub version_id: Value,
+	pub version_id: Option<Value>,
+	#[serde(rename = "DeleteMarker")]
+	pub delete_marker: Option<Value>,
 	#[serde(rename = "DeleteMarkerVersionId")]
-	pub delete_marker_version_id: Value,
+	pub delete_marker_version_id: Option<Value>,
 }
 
 #[derive(Debug, Serialize, PartialEq, Eq)]
@@ -282,9 +284,71 @@ pub struct ListBucketResult {
 }
 
 #[derive(Debug, Serialize, PartialEq, Eq)]
-pub struct VersioningConfiguration {
+pub struct ListVersionsItem {
+	#[serde(rename = "Key")]
+	pub key: Value,
+	#[serde(rename = "VersionId")]
+	pub version_id: Value,
+	#[serde(rename = "IsLatest")]
+	pub is_latest: Value,
+	#[serde(rename = "LastModified")]
+	pub last_modified: Value,
+	#[serde(rename = "ETag")]
+	pub etag: Value,
+	#[serde(rename = "Size")]
+	pub size: IntValue,
+	#[serde(rename = "StorageClass")]
+	pub storage_class: Value,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct ListDeleteMarkerItem {
+	#[serde(rename = "Key")]
+	pub key: Value,
+	#[serde(rename = "VersionId")]
+	pub version_id: Value,
+	#[serde(rename = "IsLatest")]
+	pub is_latest: Value,
+	#[serde(rename = "LastModified")]
+	pub last_modified: Value,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct ListVersionsResult {
 	#[serde(serialize_with = "xmlns_tag")]
 	pub xmlns: (),
+	#[serde(rename = "Name")]
+	pub name: Value,
+	#[serde(rename = "Prefix")]
+	pub prefix: Value,
+	#[serde(rename = "KeyMarker")]
+	pub key_marker: Option<Value>,
+	#[serde(rename = "VersionIdMarker")]
+	pub version_id_marker: Option<Value>,
+	#[serde(rename = "NextKeyMarker")]
+	pub next_key_marker: Option<Value>,
+	#[serde(rename = "NextVersionIdMarker")]
+	pub next_version_id_marker: Option<Value>,
+	#[serde(rename = "MaxKeys")]
+	pub max_keys: IntValue,
+	#[serde(rename = "Delimiter")]
+	pub delimiter: Option<Value>,
+	#[serde(rename = "EncodingType")]
+	pub encoding_type: Option<Value>,
+	#[serde(rename = "IsTruncated")]
+	pub is_truncated: Value,
+	#[serde(rename = "Version")]
+	pub versions: Vec<ListVersionsItem>,
+	#[serde(rename = "DeleteMarker")]
+	pub delete_markers: Vec<ListDeleteMarkerItem>,
+	#[serde(rename = "CommonPrefixes")]
+	pub common_prefixes: Vec<CommonPrefix>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct VersioningConfiguration {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
 	#[serde(rename = "Status")]
 	pub status: Option<Value>,
 }
@@ -418,13 +482,15 @@ mod tests {
 			deleted: vec![
 				Deleted {
 					key: Value("a/plop".to_string()),
-					version_id: Value("qsdfjklm".to_string()),
-					delete_marker_version_id: Value("wxcvbn".to_string()),
+					version_id: Some(Value("qsdfjklm".to_string())),
+					delete_marker: None,
+					delete_marker_version_id: Some(Value("wxcvbn".to_string())),
 				},
 				Deleted {
 					key: Value("b/plip".to_string()),
-					version_id: Value("1234".to_string()),
-					delete_marker_version_id: Value("4321".to_string()),
+					version_id: Some(Value("1234".to_string())),
+					delete_marker: Some(Value("true".to_string())),
+					delete_marker_version_id: Some(Value("4321".to_string())),
 				},
 			],
 			errors: vec![
@@ -454,6 +520,7 @@ mod tests {
     <Deleted>\
         <Key>b/plip</Key>\
         <VersionId>1234</VersionId>\
+        <DeleteMarker>true</DeleteMarker>\
         <DeleteMarkerVersionId>4321</DeleteMarkerVersionId>\
     </Deleted>\
     <Error>\
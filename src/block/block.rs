@@ -1,41 +1,94 @@
+use std::convert::TryInto;
+
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
-use zstd::stream::{decode_all as zstd_decode, Encoder};
 
 use garage_util::data::*;
 use garage_util::error::*;
 
+use crate::compression::*;
+
+/// Header of a block sent between nodes. Existing variants must not be changed,
+/// so that blocks can still be exchanged with nodes running older versions.
 #[derive(Debug, Serialize, Deserialize, Copy, Clone)]
 pub enum DataBlockHeader {
 	Plain,
+	/// Block compressed with zstd
 	Compressed,
+	Shard(DataShardHeader),
+	/// Block compressed with LZ4
+	CompressedLz4,
+}
+
+impl DataBlockHeader {
+	fn compressed(codec: CompressionCodec) -> Self {
+		match codec {
+			CompressionCodec::Zstd => DataBlockHeader::Compressed,
+			CompressionCodec::Lz4 => DataBlockHeader::CompressedLz4,
+		}
+	}
+}
+
+/// Header of an erasure-coded shard of a block
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub struct DataShardHeader {
+	/// Index of this shard, data shards coming before parity shards
+	pub index: u8,
+	/// Number of data shards the block was split in
+	pub data_shards: u8,
+	/// Number of parity shards computed for the block
+	pub parity_shards: u8,
+	/// Codec the block from which shards were computed is compressed with, if any
+	pub codec: Option<CompressionCodec>,
+	/// Length of the (possibly compressed) block from which shards were computed
+	pub block_len: u64,
+	/// Hash of the content of this shard, used to check its integrity
+	pub shard_hash: Hash,
+}
+
+/// Magic bytes at the beginning of compressed block files that have
+/// an integrity header. Compressed blocks written by older versions
+/// are bare zstd frames, which start with a different magic number.
+const BLOCK_FILE_MAGIC: [u8; 4] = *b"GRGB";
+/// Current version of the header of compressed block files
+const BLOCK_FILE_VERSION: u8 = 1;
+
+/// Header stored at the beginning of compressed block files
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub struct DataBlockFileHeader {
+	/// Codec the content of the file was compressed with
+	pub codec: CompressionCodec,
+	/// Hash of the uncompressed content, i.e. the hash of the block
+	pub content_hash: Hash,
 }
 
 /// A possibly compressed block of data
 pub enum DataBlock {
 	/// Uncompressed data
 	Plain(Bytes),
-	/// Data compressed with zstd
-	Compressed(Bytes),
+	/// Data compressed with the given codec
+	Compressed(CompressionCodec, Bytes),
+	/// Erasure-coded shard of a (possibly compressed) block
+	Shard(DataShardHeader, Bytes),
 }
 
 impl DataBlock {
 	/// Query whether this block is compressed
 	pub fn is_compressed(&self) -> bool {
-		matches!(self, DataBlock::Compressed(_))
+		matches!(self, DataBlock::Compressed(_, _))
 	}
 
 	/// Get the inner, possibly compressed buffer. You should probably use [`DataBlock::verify_get`]
 	/// instead
 	pub fn inner_buffer(&self) -> &[u8] {
 		use DataBlock::*;
-		let (Plain(ref res) | Compressed(ref res)) = self;
+		let (Plain(ref res) | Compressed(_, ref res) | Shard(_, ref res)) = self;
 		res
 	}
 
 	/// Get the buffer, possibly decompressing it, and verify it's integrity.
-	/// For Plain block, data is compared to hash, for Compressed block, zstd checksumming system
-	/// is used instead.
+	/// Data is compared to hash, after decompression for a Compressed block.
+	/// A shard cannot be decoded on its own and returns an error.
 	pub fn verify_get(self, hash: Hash) -> Result<Bytes, Error> {
 		match self {
 			DataBlock::Plain(data) => {
@@ -45,14 +98,26 @@ impl DataBlock {
 					Err(Error::CorruptData(hash))
 				}
 			}
-			DataBlock::Compressed(data) => zstd_decode(&data[..])
-				.map_err(|_| Error::CorruptData(hash))
-				.map(Bytes::from),
+			DataBlock::Compressed(codec, data) => {
+				let data = codec
+					.decompress(&data[..])
+					.map_err(|_| Error::CorruptData(hash))?;
+				if blake2sum(&data) == hash {
+					Ok(Bytes::from(data))
+				} else {
+					Err(Error::CorruptData(hash))
+				}
+			}
+			DataBlock::Shard(_, _) => Err(Error::Message(format!(
+				"Cannot get data of block {:?} from a single shard",
+				hash
+			))),
 		}
 	}
 
-	/// Verify data integrity. Allocate less than [`DataBlock::verify_get`] and don't consume self, but
-	/// does not return the buffer content.
+	/// Verify data integrity. Don't consume self, but does not return the buffer content.
+	/// Compressed blocks are decompressed and their content is compared to hash, so that
+	/// corruptions that the codec's own checksum (if any) would not catch are detected as well.
 	pub fn verify(&self, hash: Hash) -> Result<(), Error> {
 		match self {
 			DataBlock::Plain(data) => {
@@ -62,19 +127,33 @@ impl DataBlock {
 					Err(Error::CorruptData(hash))
 				}
 			}
-			DataBlock::Compressed(data) => zstd::stream::copy_decode(&data[..], std::io::sink())
-				.map_err(|_| Error::CorruptData(hash)),
+			DataBlock::Compressed(codec, data) => {
+				let data = codec
+					.decompress(&data[..])
+					.map_err(|_| Error::CorruptData(hash))?;
+				if blake2sum(&data) == hash {
+					Ok(())
+				} else {
+					Err(Error::CorruptData(hash))
+				}
+			}
+			DataBlock::Shard(header, data) => {
+				if blake2sum(data) == header.shard_hash {
+					Ok(())
+				} else {
+					Err(Error::CorruptData(hash))
+				}
+			}
 		}
 	}
 
-	pub async fn from_buffer(data: Bytes, level: Option<i32>) -> DataBlock {
-		tokio::task::spawn_blocking(move || {
-			if let Some(level) = level {
-				if let Ok(data) = zstd_encode(&data[..], level) {
-					return DataBlock::Compressed(data.into());
-				}
-			}
-			DataBlock::Plain(data)
+	pub async fn from_buffer(data: Bytes, compression: Compression) -> DataBlock {
+		if compression == Compression::None {
+			return DataBlock::Plain(data);
+		}
+		tokio::task::spawn_blocking(move || match compression.compress(&data[..]) {
+			Some((codec, compressed)) => DataBlock::Compressed(codec, compressed.into()),
+			None => DataBlock::Plain(data),
 		})
 		.await
 		.unwrap()
@@ -83,23 +162,172 @@ impl DataBlock {
 	pub fn into_parts(self) -> (DataBlockHeader, Bytes) {
 		match self {
 			DataBlock::Plain(data) => (DataBlockHeader::Plain, data),
-			DataBlock::Compressed(data) => (DataBlockHeader::Compressed, data),
+			DataBlock::Compressed(codec, data) => (DataBlockHeader::compressed(codec), data),
+			DataBlock::Shard(header, data) => (DataBlockHeader::Shard(header), data),
 		}
 	}
 
 	pub fn from_parts(h: DataBlockHeader, bytes: Bytes) -> Self {
 		match h {
 			DataBlockHeader::Plain => DataBlock::Plain(bytes),
-			DataBlockHeader::Compressed => DataBlock::Compressed(bytes),
+			DataBlockHeader::Compressed => DataBlock::Compressed(CompressionCodec::Zstd, bytes),
+			DataBlockHeader::CompressedLz4 => DataBlock::Compressed(CompressionCodec::Lz4, bytes),
+			DataBlockHeader::Shard(header) => DataBlock::Shard(header, bytes),
 		}
 	}
 }
 
-fn zstd_encode<R: std::io::Read>(mut source: R, level: i32) -> std::io::Result<Vec<u8>> {
-	let mut result = Vec::<u8>::new();
-	let mut encoder = Encoder::new(&mut result, level)?;
-	encoder.include_checksum(true)?;
-	std::io::copy(&mut source, &mut encoder)?;
-	encoder.finish()?;
-	Ok(result)
+impl DataShardHeader {
+	/// Serialize a shard with its header, to be stored in a file:
+	/// the length of the header is written on 4 bytes, followed
+	/// by the header, followed by the content of the shard
+	pub(crate) fn to_file_content(self, data: &[u8]) -> Result<Vec<u8>, Error> {
+		let header = rmp_to_vec_all_named(&self)?;
+		let mut ret = Vec::with_capacity(4 + header.len() + data.len());
+		ret.extend_from_slice(&(header.len() as u32).to_be_bytes());
+		ret.extend_from_slice(&header);
+		ret.extend_from_slice(data);
+		Ok(ret)
+	}
+
+	/// Read back a shard and its header stored in a file
+	pub(crate) fn from_file_content(content: Bytes) -> Result<(Self, Bytes), Error> {
+		if content.len() < 4 {
+			return Err(Error::Message("Shard file is too short".into()));
+		}
+		let header_len = u32::from_be_bytes(content[..4].try_into().unwrap()) as usize;
+		if content.len() < 4 + header_len {
+			return Err(Error::Message("Shard file is too short".into()));
+		}
+		let header = rmp_serde::decode::from_read_ref(&content[4..4 + header_len])?;
+		Ok((header, content.slice(4 + header_len..)))
+	}
+}
+
+impl DataBlockFileHeader {
+	/// Serialize a compressed block with its header, to be stored in a file:
+	/// magic bytes and the version of the header are written first, followed by
+	/// the length of the header on 4 bytes, the header, and the compressed data
+	pub(crate) fn to_file_content(self, data: &[u8]) -> Result<Vec<u8>, Error> {
+		let header = rmp_to_vec_all_named(&self)?;
+		let mut ret = Vec::with_capacity(9 + header.len() + data.len());
+		ret.extend_from_slice(&BLOCK_FILE_MAGIC);
+		ret.push(BLOCK_FILE_VERSION);
+		ret.extend_from_slice(&(header.len() as u32).to_be_bytes());
+		ret.extend_from_slice(&header);
+		ret.extend_from_slice(data);
+		Ok(ret)
+	}
+
+	/// Read back a compressed block stored in a file, checking that its header
+	/// is valid and matches the hash of the block. Files written before headers
+	/// were introduced have no header, in which case `None` is returned
+	/// with the whole content of the file.
+	pub(crate) fn from_file_content(
+		content: Bytes,
+		hash: &Hash,
+	) -> Result<(Option<Self>, Bytes), Error> {
+		if content.len() < 4 || content[..4] != BLOCK_FILE_MAGIC {
+			return Ok((None, content));
+		}
+		if content.len() < 9 {
+			return Err(Error::CorruptData(*hash));
+		}
+		if content[4] != BLOCK_FILE_VERSION {
+			return Err(Error::Message(format!(
+				"Block {:?} is stored with unsupported file version {}",
+				hash, content[4]
+			)));
+		}
+		let header_len = u32::from_be_bytes(content[5..9].try_into().unwrap()) as usize;
+		if content.len() < 9 + header_len {
+			return Err(Error::CorruptData(*hash));
+		}
+		let header: Self = rmp_serde::decode::from_read_ref(&content[9..9 + header_len])
+			.map_err(|_| Error::CorruptData(*hash))?;
+		if header.content_hash != *hash {
+			return Err(Error::CorruptData(*hash));
+		}
+		Ok((Some(header), content.slice(9 + header_len..)))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_block_file_content() {
+		let content = (0..10000u32).map(|x| (x % 7) as u8).collect::<Vec<_>>();
+		let hash = blake2sum(&content);
+		let compressed = Compression::Zstd(3).compress_all(&content).unwrap();
+
+		let header = DataBlockFileHeader {
+			codec: CompressionCodec::Zstd,
+			content_hash: hash,
+		};
+		let file = Bytes::from(header.to_file_content(&compressed).unwrap());
+		let (read_header, data) =
+			DataBlockFileHeader::from_file_content(file.clone(), &hash).unwrap();
+		assert_eq!(read_header, Some(header));
+		assert_eq!(data, compressed);
+		assert!(DataBlock::Compressed(CompressionCodec::Zstd, data)
+			.verify(hash)
+			.is_ok());
+
+		// A file with the header of another block is corrupted
+		let other_hash = blake2sum(b"other block");
+		assert!(matches!(
+			DataBlockFileHeader::from_file_content(file, &other_hash),
+			Err(Error::CorruptData(_))
+		));
+
+		// Files written by older versions are bare zstd frames
+		let (read_header, data) =
+			DataBlockFileHeader::from_file_content(Bytes::from(compressed.clone()), &hash).unwrap();
+		assert_eq!(read_header, None);
+		assert_eq!(data, compressed);
+
+		// A valid zstd frame with the wrong content is detected
+		let other = Compression::Zstd(3).compress_all(b"other block").unwrap();
+		assert!(DataBlock::Compressed(CompressionCodec::Zstd, other.into())
+			.verify(hash)
+			.is_err());
+	}
+
+	#[test]
+	fn test_block_header_compatibility() {
+		// Header of blocks sent by versions that only supported zstd
+		#[derive(Serialize, Deserialize, Debug, PartialEq)]
+		enum OldDataBlockHeader {
+			Plain,
+			Compressed,
+		}
+
+		for (old, new) in [
+			(OldDataBlockHeader::Plain, DataBlock::Plain(Bytes::new())),
+			(
+				OldDataBlockHeader::Compressed,
+				DataBlock::Compressed(CompressionCodec::Zstd, Bytes::new()),
+			),
+		] {
+			let (header, _) = new.into_parts();
+			let old_bytes = rmp_to_vec_all_named(&old).unwrap();
+			assert_eq!(rmp_to_vec_all_named(&header).unwrap(), old_bytes);
+
+			let decoded =
+				rmp_serde::decode::from_read_ref::<_, DataBlockHeader>(&old_bytes).unwrap();
+			assert_eq!(
+				DataBlock::from_parts(decoded, Bytes::new()).is_compressed(),
+				old == OldDataBlockHeader::Compressed
+			);
+		}
+
+		let (header, _) = DataBlock::Compressed(CompressionCodec::Lz4, Bytes::new()).into_parts();
+		assert!(matches!(header, DataBlockHeader::CompressedLz4));
+		assert!(matches!(
+			DataBlock::from_parts(header, Bytes::new()),
+			DataBlock::Compressed(CompressionCodec::Lz4, _)
+		));
+	}
 }
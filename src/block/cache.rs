@@ -0,0 +1,193 @@
+//! In-memory cache of decompressed blocks, used to serve frequently read
+//! blocks without fetching them from the storage nodes
+
+use std::collections::{BTreeMap, HashMap};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures::Stream;
+
+use garage_rpc::rpc_helper::netapp::stream::ByteStream;
+
+use garage_util::data::*;
+
+/// A bounded cache of the content of blocks, indexed by their hash.
+/// When it is full, the least recently used blocks are evicted first.
+pub struct BlockCache {
+	capacity: usize,
+	inner: Mutex<BlockCacheInner>,
+}
+
+#[derive(Default)]
+struct BlockCacheInner {
+	/// Content of cached blocks, with the tick of their last use
+	entries: HashMap<Hash, (Bytes, u64)>,
+	/// Cached blocks, ordered by the tick of their last use
+	lru: BTreeMap<u64, Hash>,
+	next_tick: u64,
+	size: usize,
+}
+
+impl BlockCache {
+	/// Create a cache holding at most `capacity` bytes of block data.
+	/// A capacity of zero disables the cache.
+	pub fn new(capacity: usize) -> Self {
+		Self {
+			capacity,
+			inner: Mutex::new(BlockCacheInner::default()),
+		}
+	}
+
+	pub fn is_enabled(&self) -> bool {
+		self.capacity > 0
+	}
+
+	/// Total size of the blocks currently in the cache
+	pub fn size(&self) -> usize {
+		self.inner.lock().unwrap().size
+	}
+
+	/// Get the content of a block if it is in the cache
+	pub fn get(&self, hash: &Hash) -> Option<Bytes> {
+		if !self.is_enabled() {
+			return None;
+		}
+		let mut inner = self.inner.lock().unwrap();
+		let tick = inner.tick();
+		let (data, last_use) = inner.entries.get_mut(hash)?;
+		let (data, prev_tick) = (data.clone(), std::mem::replace(last_use, tick));
+		inner.lru.remove(&prev_tick);
+		inner.lru.insert(tick, *hash);
+		Some(data)
+	}
+
+	/// Add the verified content of a block to the cache,
+	/// evicting least recently used blocks to make room for it
+	pub fn insert(&self, hash: Hash, data: Bytes) {
+		if !self.is_enabled() || data.len() > self.capacity {
+			return;
+		}
+		let mut inner = self.inner.lock().unwrap();
+		if inner.entries.contains_key(&hash) {
+			return;
+		}
+		while inner.size + data.len() > self.capacity {
+			let evicted = match inner.lru.values().next() {
+				Some(h) => *h,
+				None => break,
+			};
+			inner.remove(&evicted);
+		}
+		let tick = inner.tick();
+		inner.size += data.len();
+		inner.lru.insert(tick, hash);
+		inner.entries.insert(hash, (data, tick));
+	}
+}
+
+impl BlockCacheInner {
+	fn tick(&mut self) -> u64 {
+		self.next_tick += 1;
+		self.next_tick
+	}
+
+	fn remove(&mut self, hash: &Hash) {
+		if let Some((data, tick)) = self.entries.remove(hash) {
+			self.lru.remove(&tick);
+			self.size -= data.len();
+		}
+	}
+}
+
+/// A stream of the content of a block, that adds the block to the cache
+/// once it has been entirely received and its hash has been checked
+pub(crate) struct CachingStream {
+	inner: ByteStream,
+	hash: Hash,
+	cache: Arc<BlockCache>,
+	/// Parts received so far, or None if the block can't be cached
+	parts: Option<Vec<Bytes>>,
+	len: usize,
+}
+
+impl CachingStream {
+	pub(crate) fn new(inner: ByteStream, hash: Hash, cache: Arc<BlockCache>) -> Self {
+		Self {
+			inner,
+			hash,
+			cache,
+			parts: Some(vec![]),
+			len: 0,
+		}
+	}
+}
+
+impl Stream for CachingStream {
+	type Item = Result<Bytes, std::io::Error>;
+
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+		let this = &mut *self;
+		let res = this.inner.as_mut().poll_next(cx);
+		match &res {
+			Poll::Ready(Some(Ok(part))) => {
+				this.len += part.len();
+				if this.len > this.cache.capacity {
+					this.parts = None;
+				} else if let Some(parts) = &mut this.parts {
+					parts.push(part.clone());
+				}
+			}
+			Poll::Ready(Some(Err(_))) => {
+				this.parts = None;
+			}
+			Poll::Ready(None) => {
+				if let Some(parts) = this.parts.take() {
+					let data = Bytes::from(parts.concat());
+					if blake2sum(&data) == this.hash {
+						this.cache.insert(this.hash, data);
+					}
+				}
+			}
+			Poll::Pending => (),
+		}
+		res
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_lru_eviction() {
+		let cache = BlockCache::new(300);
+		let blocks = (0u8..4)
+			.map(|i| (blake2sum(&[i]), Bytes::from(vec![i; 100])))
+			.collect::<Vec<_>>();
+
+		for (hash, data) in blocks.iter().take(3) {
+			cache.insert(*hash, data.clone());
+		}
+		assert_eq!(cache.size(), 300);
+
+		// Use the first block, so that the second one is evicted
+		assert_eq!(cache.get(&blocks[0].0), Some(blocks[0].1.clone()));
+		cache.insert(blocks[3].0, blocks[3].1.clone());
+		assert_eq!(cache.size(), 300);
+		assert!(cache.get(&blocks[0].0).is_some());
+		assert!(cache.get(&blocks[1].0).is_none());
+		assert!(cache.get(&blocks[2].0).is_some());
+		assert!(cache.get(&blocks[3].0).is_some());
+
+		// Blocks larger than the cache are not cached
+		cache.insert(blake2sum(b"big"), Bytes::from(vec![0; 301]));
+		assert!(cache.get(&blake2sum(b"big")).is_none());
+		assert_eq!(cache.size(), 300);
+
+		let disabled = BlockCache::new(0);
+		disabled.insert(blocks[0].0, Bytes::new());
+		assert!(disabled.get(&blocks[0].0).is_none());
+	}
+}
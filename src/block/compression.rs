@@ -0,0 +1,171 @@
+//! Compression codecs for data blocks
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use zstd::stream::{decode_all as zstd_decode, Encoder};
+
+/// Size of the sample of a block that is compressed first,
+/// to estimate how well the whole block would compress
+const SAMPLE_SIZE: usize = 64 * 1024;
+
+/// Codec with which a block is compressed
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub enum CompressionCodec {
+	Zstd,
+	Lz4,
+}
+
+/// How data blocks are compressed before being stored
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub enum Compression {
+	/// Blocks are stored uncompressed
+	None,
+	/// Blocks are compressed with zstd, at the given level
+	Zstd(i32),
+	/// Blocks are compressed with LZ4, which is faster than zstd
+	/// but does not compress as well
+	Lz4,
+}
+
+impl Compression {
+	pub fn codec(&self) -> Option<CompressionCodec> {
+		match self {
+			Compression::None => None,
+			Compression::Zstd(_) => Some(CompressionCodec::Zstd),
+			Compression::Lz4 => Some(CompressionCodec::Lz4),
+		}
+	}
+
+	/// Compress a block, unless it doesn't compress well enough for it to be
+	/// worth it. To avoid spending CPU on the whole of a block that would not
+	/// compress well, e.g. media or data that is already compressed or encrypted,
+	/// a sample taken from the middle of large blocks is compressed first.
+	pub(crate) fn compress(&self, data: &[u8]) -> Option<(CompressionCodec, Vec<u8>)> {
+		let codec = self.codec()?;
+
+		if data.len() > 2 * SAMPLE_SIZE {
+			let start = (data.len() - SAMPLE_SIZE) / 2;
+			let sample = &data[start..start + SAMPLE_SIZE];
+			let compressed_sample = self.compress_all(sample).ok()?;
+			if !compresses_well(sample.len(), compressed_sample.len()) {
+				return None;
+			}
+		}
+
+		let compressed = self.compress_all(data).ok()?;
+		if compresses_well(data.len(), compressed.len()) {
+			Some((codec, compressed))
+		} else {
+			None
+		}
+	}
+
+	pub(crate) fn compress_all(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+		match self {
+			Compression::None => Ok(data.to_vec()),
+			Compression::Zstd(level) => zstd_encode(data, *level),
+			Compression::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+		}
+	}
+}
+
+/// Compression is worth it if it saves at least one eighth of the size of the data
+fn compresses_well(len: usize, compressed_len: usize) -> bool {
+	compressed_len * 8 <= len * 7
+}
+
+impl CompressionCodec {
+	pub(crate) fn decompress(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+		match self {
+			CompressionCodec::Zstd => zstd_decode(data),
+			CompressionCodec::Lz4 => lz4_flex::decompress_size_prepended(data)
+				.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+		}
+	}
+}
+
+impl fmt::Display for Compression {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Compression::None => write!(f, "none"),
+			Compression::Zstd(level) => write!(f, "zstd:{}", level),
+			Compression::Lz4 => write!(f, "lz4"),
+		}
+	}
+}
+
+impl FromStr for Compression {
+	type Err = String;
+
+	/// Parse a compression setting: `none`, `lz4`, `zstd` (at level 1)
+	/// or `zstd:<level>`
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_ascii_lowercase().as_str() {
+			"none" => Ok(Compression::None),
+			"lz4" => Ok(Compression::Lz4),
+			"zstd" => Ok(Compression::Zstd(1)),
+			x => match x.strip_prefix("zstd:").map(str::parse::<i32>) {
+				Some(Ok(level)) => Ok(Compression::Zstd(level)),
+				_ => Err(format!(
+					"Invalid compression: '{}', should be none, lz4, zstd or zstd:<level>",
+					s
+				)),
+			},
+		}
+	}
+}
+
+fn zstd_encode<R: std::io::Read>(mut source: R, level: i32) -> std::io::Result<Vec<u8>> {
+	let mut result = Vec::<u8>::new();
+	let mut encoder = Encoder::new(&mut result, level)?;
+	encoder.include_checksum(true)?;
+	std::io::copy(&mut source, &mut encoder)?;
+	encoder.finish()?;
+	Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_compress() {
+		let text = (0..200000u32)
+			.map(|x| b"garage"[(x % 6) as usize])
+			.collect::<Vec<_>>();
+		// Pseudo-random bytes, that don't compress
+		let mut x = 42u32;
+		let random = (0..200000)
+			.map(|_| {
+				x = x.wrapping_mul(1103515245).wrapping_add(12345);
+				(x >> 16) as u8
+			})
+			.collect::<Vec<_>>();
+
+		for compression in [Compression::Zstd(1), Compression::Lz4] {
+			let (codec, compressed) = compression.compress(&text).unwrap();
+			assert_eq!(Some(codec), compression.codec());
+			assert!(compressed.len() < text.len() / 10);
+			assert_eq!(codec.decompress(&compressed).unwrap(), text);
+
+			assert!(compression.compress(&random).is_none());
+			assert!(compression.compress(&[]).is_none());
+		}
+		assert!(Compression::None.compress(&text).is_none());
+	}
+
+	#[test]
+	fn test_parse() {
+		for compression in [Compression::None, Compression::Lz4, Compression::Zstd(-5)] {
+			assert_eq!(
+				compression.to_string().parse::<Compression>(),
+				Ok(compression)
+			);
+		}
+		assert_eq!("zstd".parse::<Compression>(), Ok(Compression::Zstd(1)));
+		assert!("gzip".parse::<Compression>().is_err());
+		assert!("zstd:high".parse::<Compression>().is_err());
+	}
+}
@@ -0,0 +1,373 @@
+//! Reed-Solomon erasure coding of data blocks over GF(2^8).
+//!
+//! A block is split in `data_shards` shards of equal size, to which
+//! `parity_shards` parity shards are added. The block can be rebuilt from
+//! any `data_shards` of these shards. The code is systematic: data shards
+//! contain the block itself, parity shards are computed using a Cauchy
+//! matrix, so that any square submatrix of the encoding matrix is invertible.
+
+use bytes::Bytes;
+
+use garage_rpc::ring::MAX_REPLICATION;
+use garage_util::data::*;
+use garage_util::error::Error;
+
+use crate::block::*;
+
+/// Erasure coding parameters, and the matrix used to compute parity shards
+#[derive(Debug, Clone)]
+pub struct ErasureCoding {
+	data_shards: usize,
+	parity_shards: usize,
+	parity_matrix: Vec<Vec<u8>>,
+}
+
+impl ErasureCoding {
+	pub fn new(data_shards: usize, parity_shards: usize) -> Result<Self, Error> {
+		if data_shards == 0 || parity_shards == 0 {
+			return Err(Error::Message(
+				"Erasure coding needs at least one data shard and one parity shard".into(),
+			));
+		}
+		if data_shards + parity_shards > MAX_REPLICATION {
+			return Err(Error::Message(format!(
+				"Erasure coding cannot use more than {} shards in total",
+				MAX_REPLICATION
+			)));
+		}
+
+		let parity_matrix = (0..parity_shards)
+			.map(|r| {
+				(0..data_shards)
+					.map(|c| gf_inv((data_shards + r) as u8 ^ c as u8))
+					.collect()
+			})
+			.collect();
+
+		Ok(Self {
+			data_shards,
+			parity_shards,
+			parity_matrix,
+		})
+	}
+
+	pub fn data_shards(&self) -> usize {
+		self.data_shards
+	}
+
+	pub fn parity_shards(&self) -> usize {
+		self.parity_shards
+	}
+
+	/// Total number of shards, i.e. number of nodes storing a block
+	pub fn total_shards(&self) -> usize {
+		self.data_shards + self.parity_shards
+	}
+
+	/// Number of shards that must be stored for a write to succeed.
+	/// A write tolerates the unavailability of half of the parity shards.
+	pub fn write_quorum(&self) -> usize {
+		self.data_shards + self.parity_shards / 2
+	}
+
+	/// Split data in data shards, and compute parity shards
+	pub fn encode(&self, data: &[u8]) -> Vec<Vec<u8>> {
+		let mut shard_len = std::cmp::max(1, data.len() / self.data_shards);
+		if shard_len * self.data_shards < data.len() {
+			shard_len += 1;
+		}
+
+		let mut shards = Vec::with_capacity(self.total_shards());
+		for i in 0..self.data_shards {
+			let start = std::cmp::min(i * shard_len, data.len());
+			let end = std::cmp::min(start + shard_len, data.len());
+			let mut shard = data[start..end].to_vec();
+			shard.resize(shard_len, 0);
+			shards.push(shard);
+		}
+		for row in self.parity_matrix.iter() {
+			let mut parity = vec![0u8; shard_len];
+			for (coef, shard) in row.iter().zip(shards.iter()) {
+				gf_mul_add(&mut parity, shard, *coef);
+			}
+			shards.push(parity);
+		}
+
+		shards
+	}
+
+	/// Rebuild the original data of length `data_len` from a list of shards
+	/// indexed by their shard number, of which at least `data_shards` must be present
+	pub fn decode<S: AsRef<[u8]>>(
+		&self,
+		shards: &[Option<S>],
+		data_len: usize,
+	) -> Result<Vec<u8>, Error> {
+		let available = shards
+			.iter()
+			.enumerate()
+			.take(self.total_shards())
+			.filter_map(|(i, s)| s.as_ref().map(|s| (i, s.as_ref())))
+			.take(self.data_shards)
+			.collect::<Vec<_>>();
+		if available.len() < self.data_shards {
+			return Err(Error::Message(format!(
+				"Not enough shards to rebuild data: {} available, {} needed",
+				available.len(),
+				self.data_shards
+			)));
+		}
+
+		let shard_len = available[0].1.len();
+		if available.iter().any(|(_, s)| s.len() != shard_len) {
+			return Err(Error::Message("Shards have different lengths".into()));
+		}
+		if data_len > shard_len * self.data_shards {
+			return Err(Error::Message("Shards are too short".into()));
+		}
+
+		// Rows of the encoding matrix that correspond to available shards
+		let matrix = available
+			.iter()
+			.map(|(i, _)| self.encoding_row(*i))
+			.collect::<Vec<_>>();
+		let inverse = gf_invert_matrix(matrix)?;
+
+		let mut data = Vec::with_capacity(shard_len * self.data_shards);
+		for row in inverse.iter() {
+			let mut shard = vec![0u8; shard_len];
+			for (coef, (_, input)) in row.iter().zip(available.iter()) {
+				gf_mul_add(&mut shard, input, *coef);
+			}
+			data.extend_from_slice(&shard);
+		}
+		data.truncate(data_len);
+
+		Ok(data)
+	}
+
+	/// Split a (possibly compressed) block in shards
+	pub(crate) fn split_block(&self, block: &DataBlock) -> Result<Vec<DataBlock>, Error> {
+		let codec = match block {
+			DataBlock::Plain(_) => None,
+			DataBlock::Compressed(codec, _) => Some(*codec),
+			DataBlock::Shard(_, _) => {
+				return Err(Error::Message("Cannot split a shard in shards".into()))
+			}
+		};
+		let data = block.inner_buffer();
+
+		let shards = self
+			.encode(data)
+			.into_iter()
+			.enumerate()
+			.map(|(index, shard)| {
+				let header = DataShardHeader {
+					index: index as u8,
+					data_shards: self.data_shards as u8,
+					parity_shards: self.parity_shards as u8,
+					codec,
+					block_len: data.len() as u64,
+					shard_hash: blake2sum(&shard),
+				};
+				DataBlock::Shard(header, Bytes::from(shard))
+			})
+			.collect();
+		Ok(shards)
+	}
+
+	fn encoding_row(&self, shard: usize) -> Vec<u8> {
+		if shard < self.data_shards {
+			let mut row = vec![0u8; self.data_shards];
+			row[shard] = 1;
+			row
+		} else {
+			self.parity_matrix[shard - self.data_shards].clone()
+		}
+	}
+}
+
+/// Rebuild a block from a set of its shards, which might have been
+/// computed with erasure coding parameters other than the current ones.
+/// Shards that were not computed from the same block as the first shard
+/// are ignored.
+pub(crate) fn rebuild_block(hash: &Hash, shards: &[DataBlock]) -> Result<DataBlock, Error> {
+	let reference = shards
+		.iter()
+		.find_map(|s| match s {
+			DataBlock::Shard(h, _) => Some(*h),
+			_ => None,
+		})
+		.ok_or_else(|| Error::Message("No shard to rebuild block from".into()))?;
+	let ec = ErasureCoding::new(
+		reference.data_shards as usize,
+		reference.parity_shards as usize,
+	)?;
+
+	let mut by_index: Vec<Option<&[u8]>> = vec![None; ec.total_shards()];
+	for shard in shards.iter() {
+		if let DataBlock::Shard(h, data) = shard {
+			let same_block = h.data_shards == reference.data_shards
+				&& h.parity_shards == reference.parity_shards
+				&& h.codec == reference.codec
+				&& h.block_len == reference.block_len;
+			if same_block && (h.index as usize) < by_index.len() {
+				by_index[h.index as usize] = Some(&data[..]);
+			}
+		}
+	}
+
+	let data = Bytes::from(ec.decode(&by_index, reference.block_len as usize)?);
+	let block = match reference.codec {
+		Some(codec) => DataBlock::Compressed(codec, data),
+		None => DataBlock::Plain(data),
+	};
+	block.verify(*hash)?;
+	Ok(block)
+}
+
+// ---- Arithmetic in GF(2^8), with polynomial x^8 + x^4 + x^3 + x^2 + 1 ----
+
+static GF_EXP: [u8; 512] = gf_tables().0;
+static GF_LOG: [u8; 256] = gf_tables().1;
+
+const fn gf_tables() -> ([u8; 512], [u8; 256]) {
+	let mut exp = [0u8; 512];
+	let mut log = [0u8; 256];
+	let mut x: u16 = 1;
+	let mut i = 0;
+	while i < 255 {
+		exp[i] = x as u8;
+		log[x as usize] = i as u8;
+		x <<= 1;
+		if x & 0x100 != 0 {
+			x ^= 0x11d;
+		}
+		i += 1;
+	}
+	while i < 512 {
+		exp[i] = exp[i - 255];
+		i += 1;
+	}
+	(exp, log)
+}
+
+fn gf_mul(a: u8, b: u8) -> u8 {
+	if a == 0 || b == 0 {
+		0
+	} else {
+		GF_EXP[GF_LOG[a as usize] as usize + GF_LOG[b as usize] as usize]
+	}
+}
+
+fn gf_inv(a: u8) -> u8 {
+	assert!(a != 0);
+	GF_EXP[255 - GF_LOG[a as usize] as usize]
+}
+
+/// out += coef * input
+fn gf_mul_add(out: &mut [u8], input: &[u8], coef: u8) {
+	if coef == 0 {
+		return;
+	}
+	let log_coef = GF_LOG[coef as usize] as usize;
+	for (o, i) in out.iter_mut().zip(input.iter()) {
+		if *i != 0 {
+			*o ^= GF_EXP[GF_LOG[*i as usize] as usize + log_coef];
+		}
+	}
+}
+
+/// Invert a square matrix using Gauss-Jordan elimination
+fn gf_invert_matrix(mut m: Vec<Vec<u8>>) -> Result<Vec<Vec<u8>>, Error> {
+	let n = m.len();
+	let mut inv = (0..n)
+		.map(|i| {
+			let mut row = vec![0u8; n];
+			row[i] = 1;
+			row
+		})
+		.collect::<Vec<_>>();
+
+	for col in 0..n {
+		let pivot = (col..n)
+			.find(|r| m[*r][col] != 0)
+			.ok_or_else(|| Error::Message("Singular erasure coding matrix".into()))?;
+		m.swap(col, pivot);
+		inv.swap(col, pivot);
+
+		let scale = gf_inv(m[col][col]);
+		for x in m[col].iter_mut().chain(inv[col].iter_mut()) {
+			*x = gf_mul(*x, scale);
+		}
+
+		let pivot_row = m[col].clone();
+		let pivot_inv_row = inv[col].clone();
+		for (r, (row, inv_row)) in m.iter_mut().zip(inv.iter_mut()).enumerate() {
+			let factor = row[col];
+			if r != col && factor != 0 {
+				for (x, p) in row.iter_mut().zip(pivot_row.iter()) {
+					*x ^= gf_mul(factor, *p);
+				}
+				for (x, p) in inv_row.iter_mut().zip(pivot_inv_row.iter()) {
+					*x ^= gf_mul(factor, *p);
+				}
+			}
+		}
+	}
+
+	Ok(inv)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_encode_decode() {
+		let ec = ErasureCoding::new(4, 2).unwrap();
+		let data = (0..10007u32)
+			.map(|x| (x * 7 + x / 13) as u8)
+			.collect::<Vec<_>>();
+		let shards = ec.encode(&data);
+		assert_eq!(shards.len(), 6);
+
+		// Rebuild data from every possible set of 4 shards out of 6
+		for missing1 in 0..6 {
+			for missing2 in missing1 + 1..6 {
+				let partial = shards
+					.iter()
+					.enumerate()
+					.map(|(i, s)| {
+						if i == missing1 || i == missing2 {
+							None
+						} else {
+							Some(s.clone())
+						}
+					})
+					.collect::<Vec<_>>();
+				assert_eq!(ec.decode(&partial, data.len()).unwrap(), data);
+			}
+		}
+
+		let too_few = [Some(shards[0].clone()), None, None, Some(shards[3].clone())];
+		assert!(ec.decode(&too_few, data.len()).is_err());
+	}
+
+	#[test]
+	fn test_small_data() {
+		let ec = ErasureCoding::new(3, 2).unwrap();
+		for len in [0, 1, 2, 3, 4] {
+			let data = vec![42u8; len];
+			let shards = ec.encode(&data);
+			let partial = [
+				None,
+				None,
+				Some(&shards[2]),
+				Some(&shards[3]),
+				Some(&shards[4]),
+			];
+			assert_eq!(ec.decode(&partial, len).unwrap(), data);
+		}
+	}
+}
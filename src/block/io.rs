@@ -0,0 +1,194 @@
+//! Reading and writing of block files, with the IO options configured on this node
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use garage_util::error::*;
+
+/// Alignment of buffers, file offsets and sizes required for direct IO
+const DIRECT_IO_ALIGNMENT: usize = 4096;
+
+/// Options for reading and writing block files
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BlockIoOptions {
+	/// Read and write block files with O_DIRECT, bypassing the page cache
+	pub direct_io: bool,
+	/// Allocate the space of block files with fallocate before writing them
+	pub preallocate: bool,
+	/// Drop block files from the page cache with posix_fadvise(DONTNEED)
+	/// once they have been written
+	pub fadvise_dontneed: bool,
+}
+
+impl BlockIoOptions {
+	/// Write a file and sync it to disk
+	pub(crate) async fn write_file(self, path: PathBuf, data: Vec<u8>) -> Result<(), Error> {
+		tokio::task::spawn_blocking(move || self.write_file_sync(&path, &data)).await?
+	}
+
+	/// Read the entire content of a file
+	pub(crate) async fn read_file(self, path: PathBuf) -> Result<Vec<u8>, Error> {
+		tokio::task::spawn_blocking(move || self.read_file_sync(&path)).await?
+	}
+
+	fn write_file_sync(&self, path: &Path, data: &[u8]) -> Result<(), Error> {
+		let mut options = OpenOptions::new();
+		options.write(true).create(true).truncate(true);
+		let (mut f, direct) = open(&options, path, self.direct_io)?;
+
+		if self.preallocate && !data.is_empty() {
+			preallocate(&f, data.len() as u64);
+		}
+
+		if direct {
+			// Direct IO can only write whole aligned blocks: write the data padded
+			// with zeroes, and truncate the file to the length of the data
+			let mut buf = AlignedBuffer::new(align_up(data.len()));
+			buf.as_mut_slice()[..data.len()].copy_from_slice(data);
+			f.write_all(buf.as_slice())?;
+			f.set_len(data.len() as u64)?;
+		} else {
+			f.write_all(data)?;
+		}
+		f.sync_all()?;
+
+		if self.fadvise_dontneed {
+			drop_from_page_cache(&f);
+		}
+
+		Ok(())
+	}
+
+	fn read_file_sync(&self, path: &Path) -> Result<Vec<u8>, Error> {
+		let mut options = OpenOptions::new();
+		options.read(true);
+		let (mut f, direct) = open(&options, path, self.direct_io)?;
+
+		if !direct {
+			let mut data = vec![];
+			f.read_to_end(&mut data)?;
+			return Ok(data);
+		}
+
+		// Direct IO can only read whole aligned blocks: a read that
+		// returns less than what was asked for means we reached the end of the file
+		let len = f.metadata()?.len() as usize;
+		let mut buf = AlignedBuffer::new(align_up(len));
+		let mut pos = 0;
+		while pos < buf.len {
+			let n = f.read(&mut buf.as_mut_slice()[pos..])?;
+			pos += n;
+			if n == 0 || pos % DIRECT_IO_ALIGNMENT != 0 {
+				break;
+			}
+		}
+		Ok(buf.as_slice()[..pos].to_vec())
+	}
+}
+
+/// A buffer whose start is aligned as required for direct IO
+struct AlignedBuffer {
+	buf: Vec<u8>,
+	offset: usize,
+	len: usize,
+}
+
+impl AlignedBuffer {
+	fn new(len: usize) -> Self {
+		let buf = vec![0u8; len + DIRECT_IO_ALIGNMENT];
+		let offset = buf.as_ptr().align_offset(DIRECT_IO_ALIGNMENT);
+		Self { buf, offset, len }
+	}
+
+	fn as_slice(&self) -> &[u8] {
+		&self.buf[self.offset..self.offset + self.len]
+	}
+
+	fn as_mut_slice(&mut self) -> &mut [u8] {
+		&mut self.buf[self.offset..self.offset + self.len]
+	}
+}
+
+fn align_up(len: usize) -> usize {
+	(len + DIRECT_IO_ALIGNMENT - 1) / DIRECT_IO_ALIGNMENT * DIRECT_IO_ALIGNMENT
+}
+
+/// Open a file, with O_DIRECT if `direct` is true and the filesystem supports it.
+/// Returns whether the file was opened with O_DIRECT.
+#[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+fn open(options: &OpenOptions, path: &Path, direct: bool) -> Result<(File, bool), Error> {
+	#[cfg(target_os = "linux")]
+	if direct {
+		use std::os::unix::fs::OpenOptionsExt;
+
+		let mut direct_options = options.clone();
+		direct_options.custom_flags(nix::fcntl::OFlag::O_DIRECT.bits());
+		match direct_options.open(path) {
+			Ok(f) => return Ok((f, true)),
+			Err(e) if e.raw_os_error() == Some(nix::libc::EINVAL) => {
+				debug!(
+					"Direct IO is not supported for {}, using buffered IO",
+					path.display()
+				);
+			}
+			Err(e) => return Err(e.into()),
+		}
+	}
+	Ok((options.open(path)?, false))
+}
+
+#[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+fn preallocate(f: &File, len: u64) {
+	#[cfg(target_os = "linux")]
+	{
+		use nix::fcntl::{fallocate, FallocateFlags};
+		use std::os::unix::io::AsRawFd;
+
+		if let Err(e) = fallocate(f.as_raw_fd(), FallocateFlags::empty(), 0, len as i64) {
+			debug!("Could not preallocate block file: {}", e);
+		}
+	}
+}
+
+#[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+fn drop_from_page_cache(f: &File) {
+	#[cfg(target_os = "linux")]
+	{
+		use nix::fcntl::{posix_fadvise, PosixFadviseAdvice};
+		use std::os::unix::io::AsRawFd;
+
+		if let Err(e) = posix_fadvise(f.as_raw_fd(), 0, 0, PosixFadviseAdvice::POSIX_FADV_DONTNEED)
+		{
+			debug!("Could not drop block file from page cache: {}", e);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_read_write_file() {
+		let dir = std::env::temp_dir().join(format!("garage-block-io-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let path = dir.join("block");
+
+		for len in [0, 1, 4095, 4096, 4097, 100000] {
+			let data = (0..len).map(|x| (x % 251) as u8).collect::<Vec<_>>();
+			for direct_io in [false, true] {
+				let opts = BlockIoOptions {
+					direct_io,
+					preallocate: true,
+					fadvise_dontneed: true,
+				};
+				opts.write_file_sync(&path, &data).unwrap();
+				assert_eq!(std::fs::metadata(&path).unwrap().len(), len as u64);
+				assert_eq!(opts.read_file_sync(&path).unwrap(), data);
+			}
+		}
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -0,0 +1,278 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use garage_util::config::DataDirEnum;
+use garage_util::data::Hash;
+use garage_util::error::{Error, OkOrMessage};
+
+type Idx = u16;
+
+/// Number of partitions in which the hash space is split
+/// to distribute blocks among data directories
+const DRIVE_NPART: usize = 1024;
+
+/// Repartition of the hash space among the data directories of this node.
+/// Each partition has a primary location, where new blocks are written,
+/// and possibly some secondary locations, where blocks of that partition
+/// might still be stored from an earlier layout, until they are moved
+/// to their primary location by the rebalance worker.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DataLayout {
+	pub(crate) data_dirs: Vec<DataDir>,
+
+	/// Primary storage location (index in data_dirs) for each partition
+	pub(crate) part_prim: Vec<Idx>,
+	/// Secondary storage locations for each partition
+	pub(crate) part_sec: Vec<Vec<Idx>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DataDir {
+	pub(crate) path: PathBuf,
+	pub(crate) state: DataDirState,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DataDirState {
+	Active { capacity: u64 },
+	ReadOnly,
+}
+
+impl DataLayout {
+	/// Build a layout from scratch, when no layout was previously persisted.
+	/// Read-only directories are considered as possible locations
+	/// of blocks from all partitions.
+	pub(crate) fn initialize(dirs: &DataDirEnum) -> Result<Self, Error> {
+		let data_dirs = make_data_dirs(dirs)?;
+
+		let read_only = data_dirs
+			.iter()
+			.enumerate()
+			.filter(|(_, dd)| dd.state == DataDirState::ReadOnly)
+			.map(|(i, _)| i as Idx)
+			.collect::<Vec<_>>();
+
+		Self::assign(
+			data_dirs,
+			vec![None; DRIVE_NPART],
+			vec![read_only; DRIVE_NPART],
+		)
+	}
+
+	/// Build a new layout for an updated list of data directories,
+	/// moving as few partitions as possible from their current location.
+	/// Partitions that are moved keep their previous primary location
+	/// as a secondary location.
+	pub(crate) fn update(&self, dirs: &DataDirEnum) -> Result<Self, Error> {
+		let data_dirs = make_data_dirs(dirs)?;
+
+		let new_idx = |i: Idx| {
+			let path = &self.data_dirs[i as usize].path;
+			data_dirs
+				.iter()
+				.position(|dd| dd.path == *path)
+				.map(|x| x as Idx)
+		};
+
+		let prev_prim = self
+			.part_prim
+			.iter()
+			.map(|i| new_idx(*i))
+			.collect::<Vec<_>>();
+		let prev_sec = self
+			.part_sec
+			.iter()
+			.map(|sec| sec.iter().filter_map(|i| new_idx(*i)).collect::<Vec<_>>())
+			.collect::<Vec<_>>();
+
+		Self::assign(data_dirs, prev_prim, prev_sec)
+	}
+
+	fn assign(
+		data_dirs: Vec<DataDir>,
+		prev_prim: Vec<Option<Idx>>,
+		mut part_sec: Vec<Vec<Idx>>,
+	) -> Result<Self, Error> {
+		let target = target_partition_counts(&data_dirs)?;
+		let mut counts = vec![0usize; data_dirs.len()];
+		let mut part_prim = vec![None; DRIVE_NPART];
+
+		// Partitions stay on their previous primary location,
+		// as long as that directory does not exceed its share
+		for (ipart, prev) in prev_prim.iter().enumerate() {
+			if let Some(idir) = prev {
+				let idir = *idir as usize;
+				if counts[idir] < target[idir] {
+					counts[idir] += 1;
+					part_prim[ipart] = Some(idir as Idx);
+				}
+			}
+		}
+
+		// Other partitions go to directories that are below their share
+		let mut idir = 0;
+		for ipart in 0..DRIVE_NPART {
+			if part_prim[ipart].is_some() {
+				continue;
+			}
+			while counts[idir] >= target[idir] {
+				idir += 1;
+			}
+			counts[idir] += 1;
+			part_prim[ipart] = Some(idir as Idx);
+
+			if let Some(prev) = prev_prim[ipart] {
+				if !part_sec[ipart].contains(&prev) {
+					part_sec[ipart].push(prev);
+				}
+			}
+		}
+
+		let part_prim = part_prim
+			.into_iter()
+			.map(|x| x.unwrap())
+			.collect::<Vec<_>>();
+		for (prim, sec) in part_prim.iter().zip(part_sec.iter_mut()) {
+			sec.retain(|x| x != prim);
+		}
+
+		Ok(Self {
+			data_dirs,
+			part_prim,
+			part_sec,
+		})
+	}
+
+	/// Layout with the same primary locations, but no secondary locations,
+	/// to be used once all blocks have been moved to their primary location
+	pub(crate) fn without_secondary_locations(&self) -> Self {
+		Self {
+			data_dirs: self.data_dirs.clone(),
+			part_prim: self.part_prim.clone(),
+			part_sec: vec![vec![]; DRIVE_NPART],
+		}
+	}
+
+	pub(crate) fn has_secondary_locations(&self) -> bool {
+		self.part_sec.iter().any(|sec| !sec.is_empty())
+	}
+
+	/// Directory in which a block should be written
+	pub(crate) fn primary_block_dir(&self, hash: &Hash) -> PathBuf {
+		let ipart = self.partition_from(hash);
+		let idir = self.part_prim[ipart] as usize;
+		block_dir(&self.data_dirs[idir].path, hash)
+	}
+
+	/// Data directory in which a block is written
+	pub(crate) fn primary_data_dir(&self, hash: &Hash) -> PathBuf {
+		let ipart = self.partition_from(hash);
+		let idir = self.part_prim[ipart] as usize;
+		self.data_dirs[idir].path.clone()
+	}
+
+	/// Other directories in which a block might be found
+	pub(crate) fn secondary_block_dirs(&self, hash: &Hash) -> Vec<PathBuf> {
+		let ipart = self.partition_from(hash);
+		self.part_sec[ipart]
+			.iter()
+			.map(|idir| block_dir(&self.data_dirs[*idir as usize].path, hash))
+			.collect()
+	}
+
+	fn partition_from(&self, hash: &Hash) -> usize {
+		u16::from_be_bytes([hash.as_slice()[0], hash.as_slice()[1]]) as usize % DRIVE_NPART
+	}
+}
+
+/// Directory in which a block is stored under the root of a data directory
+pub(crate) fn block_dir(root: &Path, hash: &Hash) -> PathBuf {
+	let mut path = root.to_path_buf();
+	path.push(hex::encode(&hash.as_slice()[0..1]));
+	path.push(hex::encode(&hash.as_slice()[1..2]));
+	path
+}
+
+fn make_data_dirs(dirs: &DataDirEnum) -> Result<Vec<DataDir>, Error> {
+	let mut data_dirs = vec![];
+	match dirs {
+		DataDirEnum::Single(path) => data_dirs.push(DataDir {
+			path: path.clone(),
+			state: DataDirState::Active { capacity: 1 },
+		}),
+		DataDirEnum::Multiple(dirs) => {
+			for dir in dirs.iter() {
+				if data_dirs.iter().any(|dd: &DataDir| dd.path == dir.path) {
+					return Err(Error::Message(format!(
+						"Data directory {} is specified twice",
+						dir.path.display()
+					)));
+				}
+				let state = if dir.read_only {
+					DataDirState::ReadOnly
+				} else {
+					let capacity = dir.capacity.as_ref().ok_or_message(format!(
+						"Data directory {} must have a capacity",
+						dir.path.display()
+					))?;
+					let capacity = capacity
+						.parse::<bytesize::ByteSize>()
+						.ok_or_message(format!(
+							"Invalid capacity for data directory {}",
+							dir.path.display()
+						))?
+						.as_u64();
+					if capacity == 0 {
+						return Err(Error::Message(format!(
+							"Data directory {} has zero capacity",
+							dir.path.display()
+						)));
+					}
+					DataDirState::Active { capacity }
+				};
+				data_dirs.push(DataDir {
+					path: dir.path.clone(),
+					state,
+				});
+			}
+		}
+	}
+	if data_dirs.len() > Idx::MAX as usize {
+		return Err(Error::Message("Too many data directories".into()));
+	}
+	Ok(data_dirs)
+}
+
+/// Number of partitions that each directory should be the primary location of,
+/// proportionnal to its capacity (largest remainder method)
+fn target_partition_counts(data_dirs: &[DataDir]) -> Result<Vec<usize>, Error> {
+	let capacities = data_dirs
+		.iter()
+		.map(|dd| match dd.state {
+			DataDirState::Active { capacity } => capacity as u128,
+			DataDirState::ReadOnly => 0,
+		})
+		.collect::<Vec<_>>();
+	let total_capacity: u128 = capacities.iter().sum();
+	if total_capacity == 0 {
+		return Err(Error::Message(
+			"At least one data directory must not be read-only".into(),
+		));
+	}
+
+	let mut counts = capacities
+		.iter()
+		.map(|c| (c * DRIVE_NPART as u128 / total_capacity) as usize)
+		.collect::<Vec<_>>();
+
+	let mut by_remainder = (0..data_dirs.len()).collect::<Vec<_>>();
+	by_remainder
+		.sort_by_key(|i| std::cmp::Reverse(capacities[*i] * DRIVE_NPART as u128 % total_capacity));
+	let missing = DRIVE_NPART - counts.iter().sum::<usize>();
+	for i in by_remainder.into_iter().take(missing) {
+		counts[i] += 1;
+	}
+
+	Ok(counts)
+}
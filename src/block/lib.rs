@@ -1,10 +1,16 @@
 #[macro_use]
 extern crate tracing;
 
+pub mod compression;
+pub mod erasure;
+pub mod io;
 pub mod manager;
 pub mod repair;
 pub mod resync;
 
 mod block;
+mod cache;
+mod layout;
 mod metrics;
 mod rc;
+mod tiering;
@@ -1,16 +1,19 @@
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::time::Duration;
 
+use arc_swap::ArcSwap;
 use async_trait::async_trait;
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 
+use futures::stream::FuturesUnordered;
 use futures::Stream;
 use futures_util::stream::StreamExt;
 use tokio::fs;
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::io::BufReader;
 use tokio::sync::{mpsc, Mutex, MutexGuard};
 
 use opentelemetry::{
@@ -22,9 +25,11 @@ use garage_rpc::rpc_helper::netapp::stream::{stream_asyncread, ByteStream};
 
 use garage_db as db;
 
+use garage_util::config::DataDirEnum;
 use garage_util::data::*;
 use garage_util::error::*;
 use garage_util::metrics::RecordDuration;
+use garage_util::persister::Persister;
 
 use garage_rpc::rpc_helper::OrderTag;
 use garage_rpc::system::System;
@@ -33,10 +38,16 @@ use garage_rpc::*;
 use garage_table::replication::{TableReplication, TableShardedReplication};
 
 use crate::block::*;
+use crate::cache::*;
+use crate::compression::*;
+use crate::erasure::*;
+use crate::io::*;
+use crate::layout::*;
 use crate::metrics::*;
 use crate::rc::*;
 use crate::repair::*;
 use crate::resync::*;
+use crate::tiering::*;
 
 /// Size under which data will be stored inlined in database instead of as files
 pub const INLINE_THRESHOLD: usize = 3072;
@@ -62,24 +73,61 @@ pub enum BlockRpc {
 	NeedBlockQuery(Hash),
 	/// Response : whether the node do require that block
 	NeedBlockReply(bool),
+	/// Ask other node which shard of this block it stores
+	ShardIndexQuery(Hash),
+	/// Response : index of the shard stored by the node, if it stores one
+	/// computed with the current erasure coding parameters
+	ShardIndexReply(Option<u8>),
 }
 
 impl Rpc for BlockRpc {
 	type Response = Result<BlockRpc, Error>;
 }
 
+/// Replication of data blocks, when it is not the default one of the cluster
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockReplication {
+	/// Number of nodes on which blocks are stored
+	pub replication_factor: usize,
+	/// Number of nodes that must have stored a block for its write to succeed
+	pub write_quorum: usize,
+}
+
+/// Parameters for writing a data block, as given by the bucket it is written to
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BlockWriteParams {
+	/// Compression of the block, if None the compression configured on this node is used
+	pub compression: Option<Compression>,
+	/// Replication of the block, if None the replication mode of the cluster is used
+	pub replication: Option<BlockReplication>,
+}
+
 /// The block manager, handling block exchange between nodes, and block storage on local node
 pub struct BlockManager {
 	/// Replication strategy, allowing to find on which node blocks should be located
 	pub replication: TableShardedReplication,
-	/// Directory in which block are stored
-	pub data_dir: PathBuf,
-
-	compression_level: Option<i32>,
+	/// Data layout, indicating in which of the data directories each block is stored
+	pub(crate) data_layout: ArcSwap<DataLayout>,
+	data_layout_persister: Persister<DataLayout>,
+	/// Directory to which blocks that are not read anymore are moved
+	pub(crate) cold_data_dir: Option<PathBuf>,
+
+	/// Compression of blocks written through this node,
+	/// unless their bucket specifies another compression
+	compression: Compression,
+	/// Erasure coding parameters, if blocks are stored as shards on the nodes
+	/// given by the replication strategy instead of being fully replicated
+	pub(crate) erasure_coding: Option<ErasureCoding>,
+
+	/// In-memory cache of blocks read through this node
+	cache: Arc<BlockCache>,
+	/// Options for reading and writing block files
+	io_options: BlockIoOptions,
 
 	mutation_lock: [Mutex<BlockManagerLocked>; 256],
 
 	pub(crate) rc: BlockRc,
+	pub(crate) access: BlockAccess,
 	pub resync: BlockResyncManager,
 
 	pub(crate) system: Arc<System>,
@@ -98,15 +146,55 @@ struct BlockManagerLocked();
 impl BlockManager {
 	pub fn new(
 		db: &db::Db,
-		data_dir: PathBuf,
-		compression_level: Option<i32>,
+		data_dir: &DataDirEnum,
+		cold_data_dir: Option<PathBuf>,
+		compression: Compression,
+		erasure_coding: Option<ErasureCoding>,
+		block_cache_size: Option<&str>,
+		io_options: BlockIoOptions,
 		replication: TableShardedReplication,
 		system: Arc<System>,
-	) -> Arc<Self> {
+	) -> Result<Arc<Self>, Error> {
+		let data_layout_persister: Persister<DataLayout> =
+			Persister::new(&system.metadata_dir, "data_layout");
+		let data_layout = match data_layout_persister.load() {
+			Ok(previous) => previous
+				.update(data_dir)
+				.ok_or_message("Invalid data_dir configuration")?,
+			Err(_) => {
+				DataLayout::initialize(data_dir).ok_or_message("Invalid data_dir configuration")?
+			}
+		};
+		data_layout_persister
+			.save(&data_layout)
+			.ok_or_message("Unable to save data layout")?;
+		let rebalance_needed = data_layout.has_secondary_locations();
+
+		if let Some(dir) = &cold_data_dir {
+			std::fs::create_dir_all(dir).ok_or_message("Unable to create cold_data_dir")?;
+		}
+
+		let cache_size = match block_cache_size {
+			Some(size) => size
+				.parse::<bytesize::ByteSize>()
+				.ok_or_message("Invalid block_cache_size")?
+				.as_u64() as usize,
+			None => 0,
+		};
+		let cache = Arc::new(BlockCache::new(cache_size));
+
 		let rc = db
 			.open_tree("block_local_rc")
 			.expect("Unable to open block_local_rc tree");
-		let rc = BlockRc::new(rc);
+		let rc_replication = db
+			.open_tree("block_local_replication")
+			.expect("Unable to open block_local_replication tree");
+		let rc = BlockRc::new(rc, rc_replication, replication.replication_factor);
+
+		let access = db
+			.open_tree("block_access")
+			.expect("Unable to open block_access tree");
+		let access = BlockAccess::new(access);
 
 		let resync = BlockResyncManager::new(db, &system);
 
@@ -114,16 +202,23 @@ impl BlockManager {
 			.netapp
 			.endpoint("garage_block/manager.rs/Rpc".to_string());
 
-		let metrics = BlockManagerMetrics::new(resync.queue.clone(), resync.errors.clone());
+		let metrics =
+			BlockManagerMetrics::new(resync.queue.clone(), resync.errors.clone(), cache.clone());
 
 		let (scrub_tx, scrub_rx) = mpsc::channel(1);
 
 		let block_manager = Arc::new(Self {
 			replication,
-			data_dir,
-			compression_level,
+			data_layout: ArcSwap::new(Arc::new(data_layout)),
+			data_layout_persister,
+			cold_data_dir,
+			compression,
+			erasure_coding,
+			cache,
+			io_options,
 			mutation_lock: [(); 256].map(|_| Mutex::new(BlockManagerLocked())),
 			rc,
+			access,
 			resync,
 			system,
 			endpoint,
@@ -142,7 +237,18 @@ impl BlockManager {
 		let scrub_worker = ScrubWorker::new(block_manager.clone(), scrub_rx);
 		block_manager.system.background.spawn_worker(scrub_worker);
 
-		block_manager
+		// Spawn rebalance worker if some blocks are not in their primary location,
+		// i.e. if data directories were added, removed or marked read-only
+		if rebalance_needed {
+			info!("Data layout has changed, launching rebalance worker");
+			let rebalance_worker = RebalanceWorker::new(block_manager.clone());
+			block_manager
+				.system
+				.background
+				.spawn_worker(rebalance_worker);
+		}
+
+		Ok(block_manager)
 	}
 
 	/// Ask nodes that might have a (possibly compressed) block for it
@@ -151,8 +257,21 @@ impl BlockManager {
 		&self,
 		hash: &Hash,
 		order_tag: Option<OrderTag>,
+		replication_factor: Option<usize>,
 	) -> Result<(DataBlockHeader, ByteStream), Error> {
-		let who = self.replication.read_nodes(hash);
+		if self.erasure_coding.is_some() {
+			// The block has to be rebuilt from its shards before being streamed
+			let (header, bytes) = self
+				.rpc_get_raw_block(hash, order_tag, None)
+				.await?
+				.into_parts();
+			let stream: ByteStream = Box::pin(futures::stream::once(async move {
+				Ok::<_, std::io::Error>(bytes)
+			}));
+			return Ok((header, stream));
+		}
+
+		let who = self.block_read_nodes(hash, replication_factor);
 		let who = self.system.rpc.request_order(&who);
 
 		for node in who.iter() {
@@ -198,8 +317,13 @@ impl BlockManager {
 		&self,
 		hash: &Hash,
 		order_tag: Option<OrderTag>,
+		replication_factor: Option<usize>,
 	) -> Result<DataBlock, Error> {
-		let who = self.replication.read_nodes(hash);
+		if self.erasure_coding.is_some() {
+			return self.rpc_get_raw_block_ec(hash, order_tag, None).await;
+		}
+
+		let who = self.block_read_nodes(hash, replication_factor);
 		let who = self.system.rpc.request_order(&who);
 
 		for node in who.iter() {
@@ -244,20 +368,221 @@ impl BlockManager {
 		)))
 	}
 
+	/// Ask nodes that store shards of a block for them, until enough
+	/// shards have been received to rebuild the block.
+	/// A shard of the block that is stored locally can be given, in which case
+	/// the local node is not queried.
+	pub(crate) async fn rpc_get_raw_block_ec(
+		&self,
+		hash: &Hash,
+		order_tag: Option<OrderTag>,
+		local_shard: Option<DataBlock>,
+	) -> Result<DataBlock, Error> {
+		let mut wanted = match &self.erasure_coding {
+			Some(ec) => ec.data_shards(),
+			None => 1,
+		};
+
+		let mut who = self.replication.read_nodes(hash);
+		let mut shards = vec![];
+		match local_shard {
+			Some(DataBlock::Shard(h, data)) => {
+				who.retain(|id| *id != self.system.id);
+				shards.push(DataBlock::Shard(h, data));
+			}
+			Some(block) => return Ok(block),
+			None => (),
+		}
+		let mut who = self.system.rpc.request_order(&who).into_iter();
+
+		let mut requests = FuturesUnordered::new();
+		loop {
+			// Keep enough requests in flight to get the number of shards we need
+			while shards.len() + requests.len() < wanted {
+				match who.next() {
+					Some(node) => requests.push(self.rpc_get_raw_block_from(node, hash, order_tag)),
+					None => break,
+				}
+			}
+
+			match requests.next().await {
+				None => break,
+				Some(Ok(DataBlock::Shard(h, data))) => {
+					shards.push(DataBlock::Shard(h, data));
+					if shards.len() >= wanted {
+						match rebuild_block(hash, &shards) {
+							Ok(block) => return Ok(block),
+							Err(e) => {
+								debug!("Could not rebuild block {:?}: {}", hash, e);
+								wanted += 1;
+							}
+						}
+					}
+				}
+				// A node that stores a full copy of the block (e.g. written before
+				// erasure coding was enabled) can serve it directly
+				Some(Ok(block)) => return Ok(block),
+				Some(Err(e)) => {
+					debug!("Could not get shard of block {:?}: {}", hash, e);
+				}
+			}
+		}
+
+		Err(Error::Message(format!(
+			"Unable to read block {:?}: could not get enough shards ({} received)",
+			hash,
+			shards.len()
+		)))
+	}
+
+	/// Ask a single node for its copy or shard of a block
+	async fn rpc_get_raw_block_from(
+		&self,
+		node: Uuid,
+		hash: &Hash,
+		order_tag: Option<OrderTag>,
+	) -> Result<DataBlock, Error> {
+		let node_id = NodeID::from(node);
+		let rpc = self.endpoint.call_streaming(
+			&node_id,
+			BlockRpc::GetBlock(*hash, order_tag),
+			PRIO_NORMAL | PRIO_SECONDARY,
+		);
+		let res = tokio::time::timeout(self.system.rpc.rpc_timeout(), rpc)
+			.await
+			.map_err(|_| Error::Timeout)??;
+		let (header, stream) = match res.into_parts() {
+			(Ok(BlockRpc::PutBlock { hash: _, header }), Some(stream)) => (header, stream),
+			(Err(e), _) => return Err(e),
+			_ => {
+				return Err(Error::Message(format!(
+					"Node {:?} returned a malformed response",
+					node
+				)))
+			}
+		};
+		let block = DataBlock::from_parts(header, read_stream_to_end(stream).await?);
+		if let DataBlock::Shard(_, _) = block {
+			block.verify(*hash)?;
+		}
+		Ok(block)
+	}
+
+	/// Split a block in shards, and send their shard to some of the nodes
+	/// that should store it, given with the index of their shard
+	async fn rpc_put_block_shards(
+		&self,
+		hash: Hash,
+		block: DataBlock,
+		to: &[(Uuid, usize)],
+		strategy: RequestStrategy,
+		quorum: usize,
+	) -> Result<(), Error> {
+		let ec = self
+			.erasure_coding
+			.clone()
+			.ok_or_message("Erasure coding is not enabled")?;
+
+		let who = self.replication.write_nodes(&hash);
+		if who.len() < ec.total_shards() {
+			return Err(Error::Message(format!(
+				"Not enough nodes to store the {} shards of block {:?}",
+				ec.total_shards(),
+				hash
+			)));
+		}
+
+		let mut shards = tokio::task::spawn_blocking(move || ec.split_block(&block))
+			.await??
+			.into_iter()
+			.map(Some)
+			.collect::<Vec<_>>();
+
+		let mut requests = FuturesUnordered::new();
+		for (node, index) in to.iter() {
+			let (header, bytes) = shards
+				.get_mut(*index)
+				.and_then(Option::take)
+				.ok_or_message("Invalid or duplicate shard index")?
+				.into_parts();
+
+			let req = Req::new(BlockRpc::PutBlock { hash, header })?.with_stream_from_buffer(bytes);
+
+			let node = *node;
+			let system = self.system.clone();
+			let endpoint = self.endpoint.clone();
+			requests.push(async move { system.rpc.call(&endpoint, node, req, strategy).await });
+		}
+
+		let total = requests.len();
+		let mut successes = 0;
+		let mut errors = vec![];
+		while successes < quorum {
+			match requests.next().await {
+				Some(Ok(_)) => successes += 1,
+				Some(Err(e)) => errors.push(format!("{}", e)),
+				None => break,
+			}
+		}
+		if successes < quorum {
+			return Err(Error::Quorum(quorum, successes, total, errors));
+		}
+
+		// Let remaining shards be written in the background
+		if !requests.is_empty() {
+			tokio::spawn(async move { while requests.next().await.is_some() {} });
+		}
+
+		Ok(())
+	}
+
 	// ---- Public interface ----
 
-	/// Ask nodes that might have a block for it,
+	/// Ask nodes that might have a block for it, given the replication of the
+	/// bucket it was written to (None for the replication mode of the cluster),
 	/// return it as a stream
 	pub async fn rpc_get_block_streaming(
 		&self,
 		hash: &Hash,
 		order_tag: Option<OrderTag>,
+		replication: Option<BlockReplication>,
 	) -> Result<
 		Pin<Box<dyn Stream<Item = Result<Bytes, std::io::Error>> + Send + Sync + 'static>>,
 		Error,
 	> {
-		let (header, stream) = self.rpc_get_raw_block_streaming(hash, order_tag).await?;
+		if self.cache.is_enabled() {
+			if let Some(data) = self.get_cached_block(hash) {
+				return Ok(Box::pin(futures::stream::once(async move {
+					Ok::<_, std::io::Error>(data)
+				})));
+			}
+			let stream = self
+				.rpc_get_block_streaming_uncached(hash, order_tag, replication)
+				.await?;
+			return Ok(Box::pin(CachingStream::new(
+				stream,
+				*hash,
+				self.cache.clone(),
+			)));
+		}
+		self.rpc_get_block_streaming_uncached(hash, order_tag, replication)
+			.await
+	}
+
+	async fn rpc_get_block_streaming_uncached(
+		&self,
+		hash: &Hash,
+		order_tag: Option<OrderTag>,
+		replication: Option<BlockReplication>,
+	) -> Result<ByteStream, Error> {
+		let (header, stream) = self
+			.rpc_get_raw_block_streaming(hash, order_tag, replication.map(|r| r.replication_factor))
+			.await?;
 		match header {
+			DataBlockHeader::Shard(_) => Err(Error::Message(format!(
+				"Node returned a shard of block {:?} instead of the full block",
+				hash
+			))),
 			DataBlockHeader::Plain => Ok(stream),
 			DataBlockHeader::Compressed => {
 				// Too many things, I hate it.
@@ -266,27 +591,116 @@ impl BlockManager {
 				let reader = async_compression::tokio::bufread::ZstdDecoder::new(reader);
 				Ok(Box::pin(tokio_util::io::ReaderStream::new(reader)))
 			}
+			DataBlockHeader::CompressedLz4 => {
+				// No streaming decoder for LZ4, but blocks are small enough
+				// to be decompressed at once
+				let data = read_stream_to_end(stream).await?;
+				let data = tokio::task::spawn_blocking(move || {
+					CompressionCodec::Lz4.decompress(&data[..])
+				})
+				.await?
+				.map_err(|_| Error::CorruptData(*hash))?;
+				Ok(Box::pin(futures::stream::once(async move {
+					Ok::<_, std::io::Error>(Bytes::from(data))
+				})))
+			}
 		}
 	}
 
-	/// Ask nodes that might have a block for it
+	/// Ask nodes that might have a block for it, given the replication of the
+	/// bucket it was written to (None for the replication mode of the cluster)
 	pub async fn rpc_get_block(
 		&self,
 		hash: &Hash,
 		order_tag: Option<OrderTag>,
+		replication: Option<BlockReplication>,
 	) -> Result<Bytes, Error> {
-		self.rpc_get_raw_block(hash, order_tag)
+		if let Some(data) = self.get_cached_block(hash) {
+			return Ok(data);
+		}
+		let data = self
+			.rpc_get_raw_block(hash, order_tag, replication.map(|r| r.replication_factor))
 			.await?
-			.verify_get(*hash)
+			.verify_get(*hash)?;
+		self.cache.insert(*hash, data.clone());
+		Ok(data)
 	}
 
-	/// Send block to nodes that should have it
-	pub async fn rpc_put_block(&self, hash: Hash, data: Bytes) -> Result<(), Error> {
-		let who = self.replication.write_nodes(&hash);
+	/// Get a block from the in-memory cache, if it is enabled
+	fn get_cached_block(&self, hash: &Hash) -> Option<Bytes> {
+		if !self.cache.is_enabled() {
+			return None;
+		}
+		let res = self.cache.get(hash);
+		if res.is_some() {
+			self.metrics.cache_hit_counter.add(1);
+		} else {
+			self.metrics.cache_miss_counter.add(1);
+		}
+		res
+	}
 
-		let (header, bytes) = DataBlock::from_buffer(data, self.compression_level)
-			.await
-			.into_parts();
+	/// Send block to nodes that should have it. The block is compressed and replicated
+	/// as specified by `params`, or as configured on this node for unspecified parameters.
+	/// Blocks are always stored as configured on this node when erasure coding is enabled.
+	pub async fn rpc_put_block(
+		&self,
+		hash: Hash,
+		data: Bytes,
+		params: BlockWriteParams,
+	) -> Result<(), Error> {
+		let (who, write_quorum) = match params.replication {
+			Some(r) if self.erasure_coding.is_none() => {
+				let ring = self.system.ring.borrow();
+				let factor = std::cmp::min(r.replication_factor, ring.replication_factor);
+				(
+					ring.get_nodes(&hash, factor),
+					std::cmp::min(r.write_quorum, factor),
+				)
+			}
+			_ => (
+				self.replication.write_nodes(&hash),
+				self.replication.write_quorum(),
+			),
+		};
+
+		// Fail early if too many of the nodes are known to refuse new blocks
+		// because they are running out of disk space
+		let low_space = who
+			.iter()
+			.filter(|n| self.system.is_low_on_disk_space(n))
+			.count();
+		if who.len() - low_space < write_quorum {
+			return Err(Error::Message(format!(
+				"Not enough free disk space: {} of the {} nodes storing block {:?} are running out of space",
+				low_space,
+				who.len(),
+				hash
+			)));
+		}
+
+		let compression = params.compression.unwrap_or(self.compression);
+		let block = DataBlock::from_buffer(data, compression).await;
+		if self.erasure_coding.is_some() {
+			// A new block is not stored on any node yet, so shards
+			// are given to nodes in the order of the write nodes
+			let to = who
+				.iter()
+				.enumerate()
+				.map(|(i, node)| (*node, i))
+				.collect::<Vec<_>>();
+			return self
+				.rpc_put_block_shards(
+					hash,
+					block,
+					&to[..],
+					RequestStrategy::with_priority(PRIO_NORMAL | PRIO_SECONDARY),
+					self.replication.write_quorum(),
+				)
+				.await;
+		}
+
+		let (header, bytes) = block.into_parts();
 		let put_block_rpc =
 			Req::new(BlockRpc::PutBlock { hash, header })?.with_stream_from_buffer(bytes);
 
@@ -297,7 +711,7 @@ impl BlockManager {
 				&who[..],
 				put_block_rpc,
 				RequestStrategy::with_priority(PRIO_NORMAL | PRIO_SECONDARY)
-					.with_quorum(self.replication.write_quorum()),
+					.with_quorum(write_quorum),
 			)
 			.await?;
 
@@ -317,13 +731,15 @@ impl BlockManager {
 	//// ----- Managing the reference counter ----
 
 	/// Increment the number of time a block is used, putting it to resynchronization if it is
-	/// required, but not known
+	/// required, but not known. The reference can require the block to be stored on
+	/// a given number of nodes, instead of the default replication factor.
 	pub fn block_incref(
 		self: &Arc<Self>,
 		tx: &mut db::Transaction,
 		hash: Hash,
+		replication_factor: Option<usize>,
 	) -> db::TxOpResult<()> {
-		if self.rc.block_incref(tx, &hash)? {
+		if self.rc.block_incref(tx, &hash, replication_factor)? {
 			// When the reference counter is incremented, there is
 			// normally a node that is responsible for sending us the
 			// data of the block. However that operation may fail,
@@ -399,6 +815,10 @@ impl BlockManager {
 
 		self.metrics.bytes_written.add(write_size);
 
+		if let Err(e) = self.access.record(hash) {
+			warn!("Could not record access to block {:?}: {}", hash, e);
+		}
+
 		Ok(())
 	}
 
@@ -408,6 +828,15 @@ impl BlockManager {
 			Err(e) => return Resp::new(Err(e)),
 		};
 
+		// Blocks are read with an order tag when they are streamed to clients
+		// of the API, but not by background tasks such as resync and scrub:
+		// only the former are accesses that keep a block out of cold storage
+		if order_tag.is_some() {
+			if let Err(e) = self.access.record(hash) {
+				warn!("Could not record access to block {:?}: {}", hash, e);
+			}
+		}
+
 		let (header, data) = block.into_parts();
 
 		let resp = Resp::new(Ok(BlockRpc::PutBlock {
@@ -438,40 +867,44 @@ impl BlockManager {
 	}
 
 	async fn read_block_internal(&self, hash: &Hash) -> Result<DataBlock, Error> {
-		let mut path = self.block_path(hash);
-		let compressed = match self.is_block_compressed(hash).await {
-			Ok(c) => c,
-			Err(e) => {
+		let block_path = match self.find_block(hash).await {
+			Some(p) => p,
+			None => {
 				// Not found but maybe we should have had it ??
 				self.resync
 					.put_to_resync(hash, 2 * self.system.rpc.rpc_timeout())?;
-				return Err(Into::into(e));
+				return Err(Error::Message(format!(
+					"block {:?} not found on node",
+					hash
+				)));
 			}
 		};
-		if compressed {
-			path.set_extension("zst");
-		}
-		let mut f = fs::File::open(&path).await?;
-
-		let mut data = vec![];
-		f.read_to_end(&mut data).await?;
-		drop(f);
-
-		let data = if compressed {
-			DataBlock::Compressed(data.into())
-		} else {
-			DataBlock::Plain(data.into())
+		let (data, legacy_format) = match read_block_file(&block_path, hash, self.io_options).await
+		{
+			Ok((data, legacy_format)) if data.verify(*hash).is_ok() => (data, legacy_format),
+			Err(e) if !matches!(e, Error::CorruptData(_)) => return Err(e),
+			_ => {
+				self.metrics.corruption_counter.add(1);
+
+				self.lock_mutate(hash)
+					.await
+					.move_block_to_corrupted(hash, self)
+					.await?;
+				self.resync.put_to_resync(hash, Duration::from_millis(0))?;
+				return Err(Error::CorruptData(*hash));
+			}
 		};
 
-		if data.verify(*hash).is_err() {
-			self.metrics.corruption_counter.add(1);
-
-			self.lock_mutate(hash)
+		if legacy_format {
+			// The block was verified, we can now rewrite it with an integrity header
+			if let Err(e) = self
+				.lock_mutate(hash)
 				.await
-				.move_block_to_corrupted(hash, self)
-				.await?;
-			self.resync.put_to_resync(hash, Duration::from_millis(0))?;
-			return Err(Error::CorruptData(*hash));
+				.upgrade_block_file(hash, self)
+				.await
+			{
+				warn!("Could not upgrade file format of block {:?}: {}", hash, e);
+			}
 		}
 
 		Ok(data)
@@ -499,31 +932,282 @@ impl BlockManager {
 			.await
 	}
 
-	/// Utility: gives the path of the directory in which a block should be found
-	fn block_dir(&self, hash: &Hash) -> PathBuf {
-		let mut path = self.data_dir.clone();
-		path.push(hex::encode(&hash.as_slice()[0..1]));
-		path.push(hex::encode(&hash.as_slice()[1..2]));
-		path
+	/// Move a block to its primary location if it is stored elsewhere.
+	/// Returns the size of the data that was moved.
+	pub(crate) async fn fix_block_location(&self, hash: &Hash) -> Result<usize, Error> {
+		self.lock_mutate(hash)
+			.await
+			.fix_block_location(hash, self)
+			.await
+	}
+
+	// ---- Cold storage ----
+
+	/// Whether a directory for cold storage is configured on this node
+	pub fn has_cold_storage(&self) -> bool {
+		self.cold_data_dir.is_some()
+	}
+
+	/// Time of the last read of a block by a client, or of its last write
+	pub fn block_access_time(&self, hash: &Hash) -> Result<u64, Error> {
+		self.access.get_or_init(hash)
+	}
+
+	/// Whether a block is stored in cold storage on this node,
+	/// or None if this node does not store the block
+	pub async fn is_block_cold(&self, hash: &Hash) -> Option<bool> {
+		self.find_block(hash)
+			.await
+			.map(|path| self.is_in_cold_storage(&path, hash))
+	}
+
+	/// Move a block to cold storage, or back to its primary location.
+	/// Returns the size of the data that was moved.
+	pub async fn move_block_to_tier(&self, hash: &Hash, cold: bool) -> Result<usize, Error> {
+		self.lock_mutate(hash)
+			.await
+			.move_block_to_tier(hash, cold, self)
+			.await
+	}
+
+	// ---- Replication of blocks ----
+
+	/// Whether data blocks are erasure coded, in which case they are always
+	/// stored as configured on the nodes and not as required by buckets
+	pub fn has_erasure_coding(&self) -> bool {
+		self.erasure_coding.is_some()
+	}
+
+	/// Maximum number of copies of a data block that a bucket can require
+	pub fn max_replication_factor(&self) -> usize {
+		self.system.ring.borrow().replication_factor
 	}
 
-	/// Utility: give the full path where a block should be found, minus extension if block is
-	/// compressed
-	fn block_path(&self, hash: &Hash) -> PathBuf {
-		let mut path = self.block_dir(hash);
-		path.push(hex::encode(hash.as_ref()));
-		path
+	/// Nodes that may have to store a block: all of the nodes of its partition in
+	/// the ring, as some buckets can require more copies than the default replication factor
+	pub(crate) fn storage_nodes(&self, hash: &Hash) -> Vec<Uuid> {
+		if self.erasure_coding.is_some() {
+			return self.replication.write_nodes(hash);
+		}
+		let ring = self.system.ring.borrow();
+		ring.get_nodes(hash, ring.replication_factor)
+	}
+
+	/// Nodes from which a block written with `replication_factor` copies (or with
+	/// the default replication factor if None) can be read. As blocks are stored on
+	/// the first nodes of their partition, these nodes also have the block if it
+	/// is referenced by other buckets that require more copies of it.
+	fn block_read_nodes(&self, hash: &Hash, replication_factor: Option<usize>) -> Vec<Uuid> {
+		match replication_factor {
+			Some(factor) if self.erasure_coding.is_none() => {
+				let ring = self.system.ring.borrow();
+				ring.get_nodes(hash, std::cmp::min(factor, ring.replication_factor))
+			}
+			_ => self.replication.read_nodes(hash),
+		}
 	}
 
-	/// Utility: check if block is stored compressed. Error if block is not stored
-	async fn is_block_compressed(&self, hash: &Hash) -> Result<bool, Error> {
-		let mut path = self.block_path(hash);
-		path.set_extension("zst");
-		if fs::metadata(&path).await.is_ok() {
+	/// Check whether this node is among the nodes that should store a block,
+	/// given the number of copies required by the buckets that reference it.
+	/// This is only known for blocks that this node has a reference counter for.
+	fn should_store_block(&self, hash: &Hash) -> Result<bool, Error> {
+		if self.erasure_coding.is_some() {
 			return Ok(true);
 		}
-		path.set_extension("");
-		fs::metadata(&path).await.map(|_| false).map_err(Into::into)
+		let factor = self.rc.get_block_replication_factor(hash)?;
+		let ring = self.system.ring.borrow();
+		if factor >= ring.replication_factor {
+			return Ok(true);
+		}
+		let nodes = ring.get_nodes(hash, factor);
+		// If the ring is not ready, keep the block until we know
+		Ok(nodes.is_empty() || nodes.contains(&self.system.id))
+	}
+
+	// ---- Erasure-coded blocks ----
+
+	/// Index of the shard of a block that is stored on this node, if it stores
+	/// a shard computed with the current erasure coding parameters
+	async fn local_shard_index(&self, hash: &Hash) -> Result<Option<usize>, Error> {
+		let ec = match &self.erasure_coding {
+			Some(ec) => ec,
+			None => return Ok(None),
+		};
+		let path = match self.find_block(hash).await {
+			Some(p @ DataBlockPath::Shard(_)) => p,
+			_ => return Ok(None),
+		};
+		match read_block_file(&path, hash, self.io_options).await?.0 {
+			DataBlock::Shard(header, _)
+				if (header.index as usize) < ec.total_shards()
+					&& header.data_shards as usize == ec.data_shards()
+					&& header.parity_shards as usize == ec.parity_shards() =>
+			{
+				Ok(Some(header.index as usize))
+			}
+			_ => Ok(None),
+		}
+	}
+
+	/// Index of the shard of a block that each of the nodes storing it should
+	/// store. Nodes keep the shard they already store, so that a change in the
+	/// cluster layout only requires the missing shards to be rebuilt.
+	/// All the nodes must answer, so that a shard is not given to two of them.
+	async fn shard_indexes(&self, hash: &Hash) -> Result<HashMap<Uuid, usize>, Error> {
+		let ec = self
+			.erasure_coding
+			.as_ref()
+			.ok_or_message("Erasure coding is not enabled")?;
+		let who = self.replication.write_nodes(hash);
+
+		let resps = self
+			.system
+			.rpc
+			.call_many(
+				&self.endpoint,
+				&who,
+				BlockRpc::ShardIndexQuery(*hash),
+				RequestStrategy::with_priority(PRIO_BACKGROUND),
+			)
+			.await?;
+		let mut stored = HashMap::new();
+		for (node, resp) in resps {
+			match resp.err_context("ShardIndexQuery RPC")? {
+				BlockRpc::ShardIndexReply(index) => {
+					stored.insert(node, index.map(usize::from));
+				}
+				m => return Err(Error::unexpected_rpc_message(m)),
+			}
+		}
+
+		Ok(assign_shard_indexes(&who, &stored, ec.total_shards()))
+	}
+
+	/// Check if the shard of a block that is stored on this node can't be
+	/// used anymore, because the erasure coding parameters have changed
+	pub(crate) async fn is_misplaced_shard(&self, hash: &Hash) -> Result<bool, Error> {
+		if self.erasure_coding.is_none()
+			|| !self.replication.write_nodes(hash).contains(&self.system.id)
+		{
+			return Ok(false);
+		}
+		match self.find_block(hash).await {
+			Some(DataBlockPath::Shard(_)) => Ok(self.local_shard_index(hash).await?.is_none()),
+			_ => Ok(false),
+		}
+	}
+
+	/// Verify and store a block fetched from other nodes: if erasure coding
+	/// is enabled, only the shard of the block that this node should store is written
+	pub(crate) async fn write_fetched_block(
+		&self,
+		hash: &Hash,
+		block: DataBlock,
+	) -> Result<(), Error> {
+		// Check the fetched block end to end before storing it,
+		// so that we don't store a copy that was corrupted on another node
+		block.verify(*hash)?;
+
+		let ec = match &self.erasure_coding {
+			Some(ec) if !matches!(block, DataBlock::Shard(_, _)) => ec.clone(),
+			_ => return self.write_block(hash, &block).await,
+		};
+		let index = match self.shard_indexes(hash).await?.get(&self.system.id) {
+			Some(index) => *index,
+			None => return self.write_block(hash, &block).await,
+		};
+		let shard = tokio::task::spawn_blocking(move || ec.split_block(&block))
+			.await??
+			.into_iter()
+			.nth(index)
+			.ok_or_message("Missing shard")?;
+		self.write_block(hash, &shard).await
+	}
+
+	/// Send their shard of a block to nodes that need it, rebuilding the block
+	/// from the copy or shard stored locally and shards stored on other nodes
+	pub(crate) async fn rpc_offload_block_shards(
+		&self,
+		hash: &Hash,
+		to: &[Uuid],
+	) -> Result<(), Error> {
+		let indexes = self.shard_indexes(hash).await?;
+		let to = to
+			.iter()
+			.filter_map(|node| indexes.get(node).map(|i| (*node, *i)))
+			.collect::<Vec<_>>();
+
+		let local = self.read_block(hash).await?;
+		let block = self.rpc_get_raw_block_ec(hash, None, Some(local)).await?;
+		self.rpc_put_block_shards(
+			*hash,
+			block,
+			&to[..],
+			RequestStrategy::with_priority(PRIO_BACKGROUND),
+			to.len(),
+		)
+		.await
+	}
+
+	/// Save a new data layout, once all blocks have been moved
+	/// to their primary location
+	pub(crate) async fn set_data_layout(&self, layout: DataLayout) -> Result<(), Error> {
+		self.data_layout_persister.save_async(&layout).await?;
+		self.data_layout.store(Arc::new(layout));
+		Ok(())
+	}
+
+	/// Utility: find where a block is stored, looking first in its
+	/// primary location and then in the secondary locations
+	/// given by the data layout
+	async fn find_block(&self, hash: &Hash) -> Option<DataBlockPath> {
+		self.find_block_copies(hash, false).await.into_iter().next()
+	}
+
+	/// Utility: find all the copies of a block in its primary and secondary
+	/// locations, and then in cold storage. If `all` is false, stop at the
+	/// first copy found.
+	async fn find_block_copies(&self, hash: &Hash, all: bool) -> Vec<DataBlockPath> {
+		let data_layout = self.data_layout.load_full();
+		let dirs = Some(data_layout.primary_block_dir(hash))
+			.into_iter()
+			.chain(data_layout.secondary_block_dirs(hash))
+			.chain(self.cold_block_dir(hash));
+		let filename = hex::encode(hash.as_ref());
+
+		let mut ret = vec![];
+		for dir in dirs {
+			let mut path = dir;
+			path.push(&filename);
+
+			path.set_extension("zst");
+			if fs::metadata(&path).await.is_ok() {
+				ret.push(DataBlockPath::Compressed(path.clone()));
+			}
+			path.set_extension("shard");
+			if fs::metadata(&path).await.is_ok() {
+				ret.push(DataBlockPath::Shard(path.clone()));
+			}
+			path.set_extension("");
+			if fs::metadata(&path).await.is_ok() {
+				ret.push(DataBlockPath::Plain(path));
+			}
+			if !all && !ret.is_empty() {
+				break;
+			}
+		}
+
+		ret
+	}
+
+	/// Directory in which a block is stored when it is in cold storage
+	fn cold_block_dir(&self, hash: &Hash) -> Option<PathBuf> {
+		self.cold_data_dir.as_ref().map(|dir| block_dir(dir, hash))
+	}
+
+	fn is_in_cold_storage(&self, path: &DataBlockPath, hash: &Hash) -> bool {
+		let cold_dir = self.cold_block_dir(hash);
+		cold_dir.is_some() && path.path().parent() == cold_dir.as_deref()
 	}
 
 	async fn lock_mutate(&self, hash: &Hash) -> MutexGuard<'_, BlockManagerLocked> {
@@ -550,11 +1234,36 @@ impl StreamingEndpointHandler<BlockRpc> for BlockManager {
 			BlockRpc::NeedBlockQuery(h) => {
 				Resp::new(self.need_block(h).await.map(BlockRpc::NeedBlockReply))
 			}
+			BlockRpc::ShardIndexQuery(h) => Resp::new(
+				self.local_shard_index(h)
+					.await
+					.map(|i| BlockRpc::ShardIndexReply(i.map(|i| i as u8))),
+			),
 			m => Resp::new(Err(Error::unexpected_rpc_message(m))),
 		}
 	}
 }
 
+/// Location of a block stored on the local node
+#[derive(Debug, Clone)]
+enum DataBlockPath {
+	/// Path to the uncompressed data block
+	Plain(PathBuf),
+	/// Path to the compressed data block. The codec is given by the header
+	/// of the file, the .zst extension is kept from when zstd was the only codec
+	Compressed(PathBuf),
+	/// Path to an erasure-coded shard of the data block
+	Shard(PathBuf),
+}
+
+impl DataBlockPath {
+	fn path(&self) -> &PathBuf {
+		match self {
+			DataBlockPath::Plain(p) | DataBlockPath::Compressed(p) | DataBlockPath::Shard(p) => p,
+		}
+	}
+}
+
 pub(crate) struct BlockStatus {
 	pub(crate) exists: bool,
 	pub(crate) needed: RcEntry,
@@ -566,8 +1275,13 @@ impl BlockManagerLocked {
 		hash: &Hash,
 		mgr: &BlockManager,
 	) -> Result<BlockStatus, Error> {
-		let exists = mgr.is_block_compressed(hash).await.is_ok();
-		let needed = mgr.rc.get_block_rc(hash)?;
+		let exists = mgr.find_block(hash).await.is_some();
+		let mut needed = mgr.rc.get_block_rc(hash)?;
+		if needed.is_nonzero() && !mgr.should_store_block(hash)? {
+			// The block is referenced, but by buckets that
+			// require less copies of it than we have nodes
+			needed = RcEntry::Absent;
+		}
 
 		Ok(BlockStatus { exists, needed })
 	}
@@ -578,37 +1292,98 @@ impl BlockManagerLocked {
 		data: &DataBlock,
 		mgr: &BlockManager,
 	) -> Result<(), Error> {
-		let compressed = data.is_compressed();
-		let data = data.inner_buffer();
+		let existing_path = mgr.find_block(hash).await;
+		self.write_block_inner(hash, data, mgr, existing_path, false)
+			.await
+	}
+
+	async fn write_block_inner(
+		&self,
+		hash: &Hash,
+		data: &DataBlock,
+		mgr: &BlockManager,
+		existing_path: Option<DataBlockPath>,
+		cold: bool,
+	) -> Result<(), Error> {
+		let (root, directory) = match (cold, &mgr.cold_data_dir) {
+			(true, Some(cold_data_dir)) => (cold_data_dir.clone(), block_dir(cold_data_dir, hash)),
+			_ => {
+				let data_layout = mgr.data_layout.load();
+				(
+					data_layout.primary_data_dir(hash),
+					data_layout.primary_block_dir(hash),
+				)
+			}
+		};
 
-		let mut path = mgr.block_dir(hash);
-		let directory = path.clone();
+		let mut path = directory.clone();
 		path.push(hex::encode(hash));
+		match data {
+			DataBlock::Plain(_) => (),
+			DataBlock::Compressed(_, _) => {
+				path.set_extension("zst");
+			}
+			DataBlock::Shard(_, _) => {
+				path.set_extension("shard");
+			}
+		}
 
-		fs::create_dir_all(&directory).await?;
+		let to_delete = match (existing_path, data) {
+			// If the block is stored in another directory than its primary location,
+			// write it at its primary location and delete the old copy
+			(Some(DataBlockPath::Plain(p)), DataBlock::Plain(_)) if p != path => Some(p),
+			(Some(DataBlockPath::Compressed(p)), DataBlock::Compressed(_, _)) if p != path => {
+				Some(p)
+			}
+			// If a shard of the block is stored, replace it with the new one,
+			// which may have another index if the cluster layout has changed
+			(Some(DataBlockPath::Shard(p)), DataBlock::Shard(_, _)) => {
+				Some(p).filter(|p| *p != path)
+			}
+			// If the block is already stored compressed, or if it is stored
+			// uncompressed and we don't have a compressed copy either,
+			// keep the stored copy, we have nothing to do
+			(Some(DataBlockPath::Compressed(_)), _) => return Ok(()),
+			(Some(DataBlockPath::Plain(_)), DataBlock::Plain(_) | DataBlock::Shard(_, _)) => {
+				return Ok(())
+			}
+			// If the block is stored uncompressed and we have a compressed copy,
+			// write the compressed copy and delete the uncompressed one
+			(Some(DataBlockPath::Plain(plain_path)), DataBlock::Compressed(_, _)) => {
+				Some(plain_path)
+			}
+			// If only a shard of the block is stored and we have the full block,
+			// write the full block and delete the shard
+			(Some(DataBlockPath::Shard(shard_path)), _) => Some(shard_path),
+			(None, _) => None,
+		};
 
-		let to_delete = match (mgr.is_block_compressed(hash).await, compressed) {
-			(Ok(true), _) => return Ok(()),
-			(Ok(false), false) => return Ok(()),
-			(Ok(false), true) => {
-				let path_to_delete = path.clone();
-				path.set_extension("zst");
-				Some(path_to_delete)
+		mgr.system.check_free_space(&root)?;
+
+		let file_content;
+		let data = match data {
+			DataBlock::Plain(bytes) => &bytes[..],
+			DataBlock::Compressed(codec, bytes) => {
+				let header = DataBlockFileHeader {
+					codec: *codec,
+					content_hash: *hash,
+				};
+				file_content = header.to_file_content(bytes)?;
+				&file_content[..]
 			}
-			(Err(_), compressed) => {
-				if compressed {
-					path.set_extension("zst");
-				}
-				None
+			DataBlock::Shard(header, bytes) => {
+				file_content = header.to_file_content(bytes)?;
+				&file_content[..]
 			}
 		};
 
+		fs::create_dir_all(&directory).await?;
+
 		let mut path2 = path.clone();
 		path2.set_extension("tmp");
-		let mut f = fs::File::create(&path2).await?;
-		f.write_all(data).await?;
-		f.sync_all().await?;
-		drop(f);
+		mgr.io_options
+			.write_file(path2.clone(), data.to_vec())
+			.await?;
 
 		fs::rename(path2, path).await?;
 		if let Some(to_delete) = to_delete {
@@ -636,33 +1411,182 @@ impl BlockManagerLocked {
 			"Block {:?} is corrupted. Renaming to .corrupted and resyncing.",
 			hash
 		);
-		let mut path = mgr.block_path(hash);
-		let mut path2 = path.clone();
-		if mgr.is_block_compressed(hash).await? {
-			path.set_extension("zst");
-			path2.set_extension("zst.corrupted");
-		} else {
-			path2.set_extension("corrupted");
-		}
+		let path = mgr
+			.find_block(hash)
+			.await
+			.ok_or_message("block to move to corrupted not found")?;
+		let (path, path2) = match path {
+			DataBlockPath::Plain(p) => {
+				let mut p2 = p.clone();
+				p2.set_extension("corrupted");
+				(p, p2)
+			}
+			DataBlockPath::Compressed(p) => {
+				let mut p2 = p.clone();
+				p2.set_extension("zst.corrupted");
+				(p, p2)
+			}
+			DataBlockPath::Shard(p) => {
+				let mut p2 = p.clone();
+				p2.set_extension("shard.corrupted");
+				(p, p2)
+			}
+		};
 		fs::rename(path, path2).await?;
 		Ok(())
 	}
 
+	async fn fix_block_location(&self, hash: &Hash, mgr: &BlockManager) -> Result<usize, Error> {
+		let mut copies = mgr.find_block_copies(hash, true).await.into_iter();
+		let path = match copies.next() {
+			Some(p) => p,
+			None => return Ok(0),
+		};
+		if mgr.is_in_cold_storage(&path, hash) {
+			// Blocks in cold storage are not in any of the data directories
+			return Ok(0);
+		}
+		let primary_dir = mgr.data_layout.load().primary_block_dir(hash);
+		if path.path().parent() == Some(&primary_dir) {
+			// Block is already at its primary location, remove leftover
+			// copies from an interrupted move, if any
+			for copy in copies {
+				fs::remove_file(copy.path()).await?;
+			}
+			return Ok(0);
+		}
+
+		let (data, _) = read_block_file(&path, hash, mgr.io_options).await?;
+		if data.verify(*hash).is_err() {
+			// Don't move corrupted data, the next read or scrub
+			// will move it to .corrupted and fetch it again
+			return Err(Error::CorruptData(*hash));
+		}
+		self.write_block_inner(hash, &data, mgr, Some(path), false)
+			.await?;
+		Ok(data.inner_buffer().len())
+	}
+
+	/// Rewrite a compressed block stored by an older version without
+	/// an integrity header, once its content has been verified
+	async fn upgrade_block_file(&self, hash: &Hash, mgr: &BlockManager) -> Result<(), Error> {
+		let path = match mgr.find_block(hash).await {
+			Some(p @ DataBlockPath::Compressed(_)) => p,
+			_ => return Ok(()),
+		};
+		let data = match read_block_file(&path, hash, mgr.io_options).await? {
+			(data, true) => data,
+			// Already upgraded concurrently
+			(_, false) => return Ok(()),
+		};
+		data.verify(*hash)?;
+
+		let cold = mgr.is_in_cold_storage(&path, hash);
+		let primary_dir = mgr.data_layout.load().primary_block_dir(hash);
+		let existing_path = if cold || path.path().parent() == Some(&primary_dir) {
+			// Overwrite the file in place
+			None
+		} else {
+			Some(path)
+		};
+		self.write_block_inner(hash, &data, mgr, existing_path, cold)
+			.await
+	}
+
+	async fn move_block_to_tier(
+		&self,
+		hash: &Hash,
+		cold: bool,
+		mgr: &BlockManager,
+	) -> Result<usize, Error> {
+		let path = match mgr.find_block(hash).await {
+			Some(p) => p,
+			None => return Ok(0),
+		};
+		if mgr.is_in_cold_storage(&path, hash) == cold {
+			return Ok(0);
+		}
+
+		let (data, _) = read_block_file(&path, hash, mgr.io_options).await?;
+		if data.verify(*hash).is_err() {
+			// Don't move corrupted data, the next read or scrub
+			// will move it to .corrupted and fetch it again
+			return Err(Error::CorruptData(*hash));
+		}
+		self.write_block_inner(hash, &data, mgr, Some(path), cold)
+			.await?;
+		Ok(data.inner_buffer().len())
+	}
+
 	async fn delete_if_unneeded(&self, hash: &Hash, mgr: &BlockManager) -> Result<(), Error> {
 		let BlockStatus { exists, needed } = self.check_block_status(hash, mgr).await?;
 
 		if exists && needed.is_deletable() {
-			let mut path = mgr.block_path(hash);
-			if mgr.is_block_compressed(hash).await? {
-				path.set_extension("zst");
+			// A copy of the block might remain in a secondary location
+			// if a move between data directories was interrupted,
+			// so delete all the copies we can find
+			for path in mgr.find_block_copies(hash, true).await {
+				fs::remove_file(path.path()).await?;
 			}
-			fs::remove_file(path).await?;
+			mgr.access.clear(hash)?;
 			mgr.metrics.delete_counter.add(1);
 		}
 		Ok(())
 	}
 }
 
+/// Give a shard index to each of the nodes `who` that store a block, given the
+/// index of the shard that they already store. A node keeps its shard, unless a node
+/// before it stores the same one. The other nodes are given the missing indexes,
+/// in order. Nodes after the first `total_shards` ones may get no index.
+fn assign_shard_indexes(
+	who: &[Uuid],
+	stored: &HashMap<Uuid, Option<usize>>,
+	total_shards: usize,
+) -> HashMap<Uuid, usize> {
+	let mut ret = HashMap::new();
+	let mut taken = vec![false; total_shards];
+	let mut need = vec![];
+	for node in who.iter() {
+		match stored.get(node).cloned().flatten() {
+			Some(i) if i < total_shards && !taken[i] => {
+				taken[i] = true;
+				ret.insert(*node, i);
+			}
+			_ => need.push(*node),
+		}
+	}
+	let missing = (0..total_shards).filter(|i| !taken[*i]);
+	for (node, i) in need.into_iter().zip(missing) {
+		ret.insert(node, i);
+	}
+	ret
+}
+
+/// Read a block stored in a file. The returned boolean is true if the block
+/// is compressed and was stored without an integrity header by an older version.
+async fn read_block_file(
+	block_path: &DataBlockPath,
+	hash: &Hash,
+	io_options: BlockIoOptions,
+) -> Result<(DataBlock, bool), Error> {
+	let data = io_options.read_file(block_path.path().clone()).await?;
+
+	match block_path {
+		DataBlockPath::Plain(_) => Ok((DataBlock::Plain(data.into()), false)),
+		DataBlockPath::Compressed(_) => {
+			match DataBlockFileHeader::from_file_content(data.into(), hash)? {
+				(Some(header), data) => Ok((DataBlock::Compressed(header.codec, data), false)),
+				(None, data) => Ok((DataBlock::Compressed(CompressionCodec::Zstd, data), true)),
+			}
+		}
+		DataBlockPath::Shard(_) => {
+			let (header, data) = DataShardHeader::from_file_content(data.into())?;
+			Ok((DataBlock::Shard(header, data), false))
+		}
+	}
+}
+
 async fn read_stream_to_end(mut stream: ByteStream) -> Result<Bytes, Error> {
 	let mut parts: Vec<Bytes> = vec![];
 	while let Some(part) = stream.next().await {
@@ -676,3 +1600,160 @@ async fn read_stream_to_end(mut stream: ByteStream) -> Result<Bytes, Error> {
 		.concat()
 		.into())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use tokio::sync::watch;
+
+	use garage_db::sled_adapter::{sled, SledDb};
+	use garage_rpc::netapp::NetworkKey;
+	use garage_util::background::BackgroundRunner;
+	use garage_util::config::read_config;
+
+	#[test]
+	fn test_assign_shard_indexes() {
+		let nodes = (0..5u8).map(|i| Uuid::from([i; 32])).collect::<Vec<_>>();
+		let stored = |v: &[Option<usize>]| {
+			nodes
+				.iter()
+				.cloned()
+				.zip(v.iter().cloned())
+				.collect::<HashMap<_, _>>()
+		};
+
+		// A new block: shards are given in the order of the nodes
+		let ret = assign_shard_indexes(&nodes[..4], &stored(&[None; 4]), 4);
+		assert_eq!(
+			(0..4).map(|i| ret[&nodes[i]]).collect::<Vec<_>>(),
+			vec![0, 1, 2, 3]
+		);
+
+		// The order of the nodes changed: they keep their shard
+		let who = vec![nodes[3], nodes[1], nodes[0], nodes[2]];
+		let ret = assign_shard_indexes(&who, &stored(&[Some(0), Some(1), Some(2), Some(3)]), 4);
+		assert_eq!(
+			(0..4).map(|i| ret[&nodes[i]]).collect::<Vec<_>>(),
+			vec![0, 1, 2, 3]
+		);
+
+		// A node was replaced: the new node gets the missing shard,
+		// a node storing a duplicate shard gets another one
+		let who = vec![nodes[4], nodes[0], nodes[1], nodes[2]];
+		let ret = assign_shard_indexes(&who, &stored(&[Some(0), Some(0), Some(3), None, None]), 4);
+		assert_eq!(ret[&nodes[0]], 0);
+		assert_eq!(ret[&nodes[2]], 3);
+		assert_eq!(ret[&nodes[4]], 1);
+		assert_eq!(ret[&nodes[1]], 2);
+
+		// Shards of other erasure coding parameters are not kept
+		let ret = assign_shard_indexes(&nodes[..2], &stored(&[Some(5), Some(0)]), 2);
+		assert_eq!(ret[&nodes[0]], 1);
+		assert_eq!(ret[&nodes[1]], 0);
+	}
+
+	#[tokio::test]
+	async fn test_cold_storage_round_trip() {
+		let dir = std::env::temp_dir().join(format!("garage-block-cold-{}", std::process::id()));
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(dir.join("meta")).unwrap();
+
+		let config_file = dir.join("config.toml");
+		std::fs::write(
+			&config_file,
+			format!(
+				r#"
+metadata_dir = "{path}/meta"
+data_dir = "{path}/data"
+cold_data_dir = "{path}/cold"
+replication_mode = "1"
+rpc_bind_addr = "127.0.0.1:3901"
+rpc_secret = "{secret}"
+
+[s3_api]
+s3_region = "garage"
+"#,
+				path = dir.display(),
+				secret = "00".repeat(32),
+			),
+		)
+		.unwrap();
+		let config = read_config(config_file).unwrap();
+
+		let (_stop_tx, stop_rx) = watch::channel(false);
+		let (background, _await_background) = BackgroundRunner::new(1, stop_rx);
+		let network_key = NetworkKey::from_slice(&[0u8; 32]).unwrap();
+		let system = System::new(network_key, background, 1, &config).unwrap();
+		let db = SledDb::init(sled::Config::default().temporary(true).open().unwrap());
+		let manager = BlockManager::new(
+			&db,
+			&config.data_dir,
+			config.cold_data_dir.clone(),
+			Compression::None,
+			None,
+			None,
+			BlockIoOptions::default(),
+			TableShardedReplication {
+				system: system.clone(),
+				replication_factor: 1,
+				write_quorum: 1,
+				read_quorum: 1,
+			},
+			system,
+		)
+		.unwrap();
+
+		let plain = Bytes::from(vec![42u8; 10000]);
+		let compressible = Bytes::from(b"cold data ".repeat(1000));
+		for (data, compression) in [
+			(plain, Compression::None),
+			(compressible, Compression::Zstd(1)),
+		] {
+			let hash = blake2sum(&data);
+			let block = DataBlock::from_buffer(data.clone(), compression).await;
+			let size = block.inner_buffer().len();
+			manager.write_block(&hash, &block).await.unwrap();
+			assert_eq!(manager.is_block_cold(&hash).await, Some(false));
+
+			// Move the block to cold storage, and read it from there
+			assert_eq!(manager.move_block_to_tier(&hash, true).await.unwrap(), size);
+			assert_eq!(manager.is_block_cold(&hash).await, Some(true));
+			let copies = manager.find_block_copies(&hash, true).await;
+			assert_eq!(copies.len(), 1);
+			assert_eq!(
+				copies[0].path().parent(),
+				Some(block_dir(&dir.join("cold"), &hash).as_path())
+			);
+			let read = manager.read_block(&hash).await.unwrap();
+			assert_eq!(read.verify_get(hash).unwrap(), data);
+
+			// Moving it to cold storage again does nothing
+			assert_eq!(manager.move_block_to_tier(&hash, true).await.unwrap(), 0);
+
+			// Move it back to its primary location
+			assert_eq!(
+				manager.move_block_to_tier(&hash, false).await.unwrap(),
+				size
+			);
+			assert_eq!(manager.is_block_cold(&hash).await, Some(false));
+			let copies = manager.find_block_copies(&hash, true).await;
+			assert_eq!(copies.len(), 1);
+			assert_eq!(
+				copies[0].path().parent(),
+				Some(
+					manager
+						.data_layout
+						.load()
+						.primary_block_dir(&hash)
+						.as_path()
+				)
+			);
+			let read = manager.read_block(&hash).await.unwrap();
+			assert_eq!(read.verify_get(hash).unwrap(), data);
+		}
+
+		drop(manager);
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
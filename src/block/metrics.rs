@@ -1,7 +1,12 @@
-use opentelemetry::{global, metrics::*};
+use opentelemetry::{global, metrics::*, KeyValue};
+
+use std::sync::Arc;
 
 use garage_db::counted_tree_hack::CountedTree;
 
+use crate::cache::BlockCache;
+use crate::io::BlockIoOptions;
+
 /// TableMetrics reference all counter used for metrics
 pub struct BlockManagerMetrics {
 	pub(crate) _resync_queue_len: ValueObserver<u64>,
@@ -20,11 +25,27 @@ pub struct BlockManagerMetrics {
 	pub(crate) delete_counter: BoundCounter<u64>,
 
 	pub(crate) corruption_counter: BoundCounter<u64>,
+
+	pub(crate) _cache_size: ValueObserver<u64>,
+	pub(crate) cache_hit_counter: BoundCounter<u64>,
+	pub(crate) cache_miss_counter: BoundCounter<u64>,
 }
 
 impl BlockManagerMetrics {
-	pub fn new(resync_queue: CountedTree, resync_errors: CountedTree) -> Self {
+	pub fn new(
+		resync_queue: CountedTree,
+		resync_errors: CountedTree,
+		cache: Arc<BlockCache>,
+		io_options: BlockIoOptions,
+	) -> Self {
 		let meter = global::meter("garage_model/block");
+		// Reads and writes are labelled with the IO options of the node,
+		// so that their throughput can be compared between nodes
+		let io_attributes = [
+			KeyValue::new("direct_io", io_options.direct_io),
+			KeyValue::new("preallocate", io_options.preallocate),
+			KeyValue::new("fadvise_dontneed", io_options.fadvise_dontneed),
+		];
 		Self {
 			_resync_queue_len: meter
 				.u64_value_observer("block.resync_queue_length", move |observer| {
@@ -70,22 +91,22 @@ impl BlockManagerMetrics {
 				.u64_counter("block.bytes_read")
 				.with_description("Number of bytes read from disk")
 				.init()
-				.bind(&[]),
+				.bind(&io_attributes),
 			block_read_duration: meter
 				.f64_value_recorder("block.read_duration")
 				.with_description("Duration of block read operations")
 				.init()
-				.bind(&[]),
+				.bind(&io_attributes),
 			bytes_written: meter
 				.u64_counter("block.bytes_written")
 				.with_description("Number of bytes written to disk")
 				.init()
-				.bind(&[]),
+				.bind(&io_attributes),
 			block_write_duration: meter
 				.f64_value_recorder("block.write_duration")
 				.with_description("Duration of block write operations")
 				.init()
-				.bind(&[]),
+				.bind(&io_attributes),
 			delete_counter: meter
 				.u64_counter("block.delete_counter")
 				.with_description("Number of blocks deleted")
@@ -97,6 +118,25 @@ impl BlockManagerMetrics {
 				.with_description("Data corruptions detected on block reads")
 				.init()
 				.bind(&[]),
+
+			_cache_size: meter
+				.u64_value_observer("block.cache_size", move |observer| {
+					observer.observe(cache.size() as u64, &[])
+				})
+				.with_description("Total size of the blocks in the in-memory block cache")
+				.init(),
+			cache_hit_counter: meter
+				.u64_counter("block.cache_hit_counter")
+				.with_description("Number of block reads served from the in-memory block cache")
+				.init()
+				.bind(&[]),
+			cache_miss_counter: meter
+				.u64_counter("block.cache_miss_counter")
+				.with_description(
+					"Number of block reads that could not be served from the in-memory block cache",
+				)
+				.init()
+				.bind(&[]),
 		}
 	}
 }
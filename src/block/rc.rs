@@ -10,26 +10,65 @@ use crate::manager::BLOCK_GC_DELAY;
 
 pub struct BlockRc {
 	pub(crate) rc: db::Tree,
+	/// Number of nodes on which blocks must be stored, for blocks that
+	/// are referenced by buckets that don't use the default replication factor
+	pub(crate) replication: db::Tree,
+	default_replication_factor: usize,
 }
 
 impl BlockRc {
-	pub(crate) fn new(rc: db::Tree) -> Self {
-		Self { rc }
+	pub(crate) fn new(
+		rc: db::Tree,
+		replication: db::Tree,
+		default_replication_factor: usize,
+	) -> Self {
+		Self {
+			rc,
+			replication,
+			default_replication_factor,
+		}
 	}
 
-	/// Increment the reference counter associated to a hash.
-	/// Returns true if the RC goes from zero to nonzero.
+	/// Increment the reference counter associated to a hash, for a reference
+	/// that requires the block to be stored on `replication_factor` nodes
+	/// (or on the default number of nodes if it is None).
+	/// Returns true if the RC goes from zero to nonzero, or if the block
+	/// must now be stored on more nodes than before.
 	pub(crate) fn block_incref(
 		&self,
 		tx: &mut db::Transaction,
 		hash: &Hash,
+		replication_factor: Option<usize>,
 	) -> db::TxOpResult<bool> {
 		let old_rc = RcEntry::parse_opt(tx.get(&self.rc, &hash)?);
 		match old_rc.increment().serialize() {
 			Some(x) => tx.insert(&self.rc, &hash, x)?,
 			None => unreachable!(),
 		};
-		Ok(old_rc.is_zero())
+
+		// A block is stored on the highest number of nodes required
+		// by the references it had since its RC was last zero
+		let stored = tx
+			.get(&self.replication, &hash)?
+			.and_then(|v| v.first().copied())
+			.map(usize::from);
+		let current = match (old_rc.is_zero(), stored) {
+			(true, _) => 0,
+			(false, s) => s.unwrap_or(self.default_replication_factor),
+		};
+		let new = std::cmp::max(
+			current,
+			replication_factor.unwrap_or(self.default_replication_factor),
+		);
+		if new == self.default_replication_factor {
+			if stored.is_some() {
+				tx.remove(&self.replication, &hash)?;
+			}
+		} else if stored != Some(new) {
+			tx.insert(&self.replication, &hash, [new as u8])?;
+		}
+
+		Ok(old_rc.is_zero() || new > current)
 	}
 
 	/// Decrement the reference counter associated to a hash.
@@ -52,6 +91,28 @@ impl BlockRc {
 		Ok(RcEntry::parse_opt(self.rc.get(hash.as_ref())?))
 	}
 
+	/// Read the number of nodes on which a block must be stored
+	pub(crate) fn get_block_replication_factor(&self, hash: &Hash) -> Result<usize, Error> {
+		Ok(self
+			.replication
+			.get(hash.as_ref())?
+			.and_then(|v| v.first().copied())
+			.map(usize::from)
+			.unwrap_or(self.default_replication_factor))
+	}
+
+	/// Check whether a block stored on this node can be deleted: it has no references,
+	/// and the delay during which it could be referenced again has passed. A block
+	/// without a reference counter but with a replication factor is kept, as this
+	/// means that references to it were recorded.
+	pub(crate) fn is_block_deletable(&self, hash: &Hash) -> Result<bool, Error> {
+		Ok(match self.get_block_rc(hash)? {
+			RcEntry::Present { .. } => false,
+			RcEntry::Deletable { at_time } => now_msec() > at_time,
+			RcEntry::Absent => self.replication.get(hash.as_ref())?.is_none(),
+		})
+	}
+
 	/// Delete an entry in the RC table if it is deletable and the
 	/// deletion time has passed
 	pub(crate) fn clear_deleted_block_rc(&self, hash: &Hash) -> Result<(), Error> {
@@ -61,6 +122,7 @@ impl BlockRc {
 			match rcval {
 				RcEntry::Deletable { at_time } if now > at_time => {
 					tx.remove(&self.rc, &hash)?;
+					tx.remove(&self.replication, &hash)?;
 				}
 				_ => (),
 			};
@@ -4,6 +4,7 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use async_trait::async_trait;
+use futures::Future;
 use serde::{Deserialize, Serialize};
 use tokio::fs;
 use tokio::select;
@@ -18,6 +19,8 @@ use garage_util::time::*;
 use garage_util::tranquilizer::Tranquilizer;
 
 use crate::manager::*;
+use crate::rc::BlockRc;
+use crate::tiering::BlockAccess;
 
 // Full scrub every 30 days
 const SCRUB_INTERVAL: Duration = Duration::from_secs(3600 * 24 * 30);
@@ -363,11 +366,285 @@ impl Worker for ScrubWorker {
 	}
 }
 
+// ---- ---- ----
+// THIRD KIND OF REPAIR: REBALANCING DATA BLOCKS
+// between multiple storage locations.
+// This is a one-shot repair operation that can be launched,
+// checks everything, and then exits.
+// It is launched automatically when the set of data directories
+// changes, but can also be launched manually.
+// ---- ---- ----
+
+pub struct RebalanceWorker {
+	manager: Arc<BlockManager>,
+	block_iter: BlockStoreIterator,
+	t_started: u64,
+	t_finished: Option<u64>,
+	moved: usize,
+	moved_bytes: u64,
+	errors: usize,
+}
+
+impl RebalanceWorker {
+	pub fn new(manager: Arc<BlockManager>) -> Self {
+		let block_iter = BlockStoreIterator::new(&manager);
+		Self {
+			manager,
+			block_iter,
+			t_started: now_msec(),
+			t_finished: None,
+			moved: 0,
+			moved_bytes: 0,
+			errors: 0,
+		}
+	}
+}
+
+#[async_trait]
+impl Worker for RebalanceWorker {
+	fn name(&self) -> String {
+		"Block rebalance worker".into()
+	}
+
+	fn info(&self) -> Option<String> {
+		let s = match self.t_finished {
+			None => format!("{:.2}% done", self.block_iter.progress() * 100.),
+			Some(t) => format!("Finished at {}", msec_to_rfc3339(t)),
+		};
+		Some(format!(
+			"{} ; started at {} ; moved {} blocks ({}) ; {} errors",
+			s,
+			msec_to_rfc3339(self.t_started),
+			self.moved,
+			bytesize::ByteSize::b(self.moved_bytes),
+			self.errors
+		))
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		if let Some(hash) = self.block_iter.next().await? {
+			match self.manager.fix_block_location(&hash).await {
+				Ok(0) => (),
+				Ok(size) => {
+					self.moved += 1;
+					self.moved_bytes += size as u64;
+				}
+				Err(e) => {
+					warn!(
+						"Could not move block {:?} to its primary location: {}",
+						hash, e
+					);
+					self.errors += 1;
+				}
+			}
+			Ok(WorkerState::Busy)
+		} else {
+			// All blocks are now in their primary location, unless there were
+			// errors, in which case we keep the secondary locations so that
+			// the remaining blocks can still be found.
+			if self.errors == 0 {
+				let new_layout = self
+					.manager
+					.data_layout
+					.load()
+					.without_secondary_locations();
+				self.manager.set_data_layout(new_layout).await?;
+			}
+			self.t_finished = Some(now_msec());
+			Ok(WorkerState::Done)
+		}
+	}
+
+	async fn wait_for_work(&mut self, _must_exit: &watch::Receiver<bool>) -> WorkerState {
+		unreachable!()
+	}
+}
+
+// ---- ---- ----
+// OFFLINE CHECK OF THE BLOCK STORE
+// This checks the files in the data directories against the
+// reference counters of the blocks. It is meant to be run while
+// the node is stopped, and reports or cleans up files that
+// are not needed anymore, as well as blocks that are missing.
+// ---- ---- ----
+
+/// Summary of an offline check of the block store
+#[derive(Debug, Default)]
+pub struct BlockStoreCheck {
+	/// Number of block files found in the data directories
+	pub blocks: usize,
+	/// Number of block files written too recently to be checked
+	pub recent_blocks: usize,
+	/// Number of block files that are not referenced anymore
+	pub orphan_blocks: usize,
+	pub orphan_bytes: u64,
+	/// Number of referenced blocks for which no file was found
+	pub missing_blocks: usize,
+	/// Number of temporary files left by interrupted writes
+	pub tmp_files: usize,
+	pub tmp_bytes: u64,
+	/// Number of blocks that were moved aside because they were corrupted
+	pub corrupted_files: usize,
+	/// Number of files in the data directories that were not written by Garage
+	pub unknown_files: usize,
+	/// Number of orphan blocks and temporary files that were deleted
+	pub deleted_files: usize,
+}
+
+/// Check all the files of the block store against the reference counters
+/// of the blocks. Orphan blocks and temporary files are deleted if `delete`
+/// is true, and missing blocks are added to the resync queue so that they
+/// are fetched from other nodes once the node is started again.
+/// Blocks written less than `BLOCK_GC_DELAY` ago are not checked, as the
+/// references to them may not have been received yet.
+pub async fn offline_check_block_store(
+	manager: &BlockManager,
+	delete: bool,
+) -> Result<BlockStoreCheck, Error> {
+	let mut check = BlockStoreCheck::default();
+
+	info!("Checking files in data directories...");
+	let roots = BlockStoreIterator::data_roots(manager);
+	check_block_files(
+		&manager.rc,
+		&manager.access,
+		roots,
+		BLOCK_GC_DELAY,
+		delete,
+		&mut check,
+	)
+	.await?;
+
+	info!("Checking that referenced blocks are stored...");
+	check.missing_blocks = check_referenced_blocks(&manager.rc, |hash| async move {
+		let BlockStatus { exists, needed } = manager.check_block_status(&hash).await?;
+		if needed.is_nonzero() && !exists {
+			info!("Missing block: {:?}", hash);
+			manager
+				.resync
+				.put_to_resync(&hash, Duration::from_secs(0))?;
+			Ok(true)
+		} else {
+			Ok(false)
+		}
+	})
+	.await?;
+
+	Ok(check)
+}
+
+/// Check the files found in the data directories `roots`, see `offline_check_block_store`.
+/// Block files modified less than `min_age` ago are skipped.
+async fn check_block_files(
+	rc: &BlockRc,
+	access: &BlockAccess,
+	roots: Vec<PathBuf>,
+	min_age: Duration,
+	delete: bool,
+	check: &mut BlockStoreCheck,
+) -> Result<(), Error> {
+	let mut iter = BlockStoreIterator::from_roots(roots);
+	while let Some((path, name)) = iter.next_file().await? {
+		let to_delete = match BlockStoreFile::from_file_name(&name) {
+			BlockStoreFile::Block(hash) => {
+				check.blocks += 1;
+				let metadata = fs::metadata(&path).await?;
+				let age = metadata
+					.modified()?
+					.elapsed()
+					.unwrap_or(Duration::from_secs(0));
+				if age < min_age {
+					info!("Recently written block, not checked: {}", path.display());
+					check.recent_blocks += 1;
+					false
+				} else if rc.is_block_deletable(&hash)? {
+					let size = metadata.len();
+					info!("Orphan block: {} ({} bytes)", path.display(), size);
+					check.orphan_blocks += 1;
+					check.orphan_bytes += size;
+					if delete {
+						access.clear(&hash)?;
+						rc.clear_deleted_block_rc(&hash)?;
+					}
+					true
+				} else {
+					false
+				}
+			}
+			BlockStoreFile::Temporary => {
+				let size = fs::metadata(&path).await?.len();
+				info!("Temporary file: {} ({} bytes)", path.display(), size);
+				check.tmp_files += 1;
+				check.tmp_bytes += size;
+				true
+			}
+			BlockStoreFile::Corrupted => {
+				info!("Corrupted block: {}", path.display());
+				check.corrupted_files += 1;
+				false
+			}
+			BlockStoreFile::Unknown => {
+				warn!("Unknown file in data directory: {}", path.display());
+				check.unknown_files += 1;
+				false
+			}
+		};
+		if delete && to_delete {
+			fs::remove_file(&path).await?;
+			check.deleted_files += 1;
+		}
+	}
+	Ok(())
+}
+
+/// Call `check_missing` on each block that has a nonzero reference counter,
+/// and return the number of blocks for which it returned true
+async fn check_referenced_blocks<F, Fut>(rc: &BlockRc, mut check_missing: F) -> Result<usize, Error>
+where
+	F: FnMut(Hash) -> Fut,
+	Fut: Future<Output = Result<bool, Error>>,
+{
+	let mut missing = 0;
+
+	// Hashes are read by batches, as no other operation
+	// can be done on the DB while iterating on it (see RepairWorker)
+	let mut next_start: Option<Hash> = None;
+	loop {
+		let start_bound = match next_start.as_ref() {
+			None => Bound::Unbounded,
+			Some(x) => Bound::Excluded(x.as_slice()),
+		};
+		let mut batch_of_hashes = vec![];
+		for entry in rc.rc.range::<&[u8], _>((start_bound, Bound::Unbounded))? {
+			let (hash, _) = entry?;
+			batch_of_hashes.push(Hash::try_from(&hash[..]).unwrap());
+			if batch_of_hashes.len() >= 1000 {
+				break;
+			}
+		}
+		if batch_of_hashes.is_empty() {
+			break;
+		}
+
+		for hash in batch_of_hashes.into_iter() {
+			if rc.get_block_rc(&hash)?.is_nonzero() && check_missing(hash).await? {
+				missing += 1;
+			}
+			next_start = Some(hash);
+		}
+	}
+
+	Ok(missing)
+}
+
 // ---- ---- ----
 // UTILITY FOR ENUMERATING THE BLOCK STORE
 // ---- ---- ----
 
 struct BlockStoreIterator {
+	/// Data directories that remain to be enumerated
+	todo_roots: Vec<PathBuf>,
+	n_roots: usize,
 	path: Vec<ReadingDir>,
 }
 
@@ -381,16 +658,37 @@ enum ReadingDir {
 
 impl BlockStoreIterator {
 	fn new(manager: &BlockManager) -> Self {
-		let root_dir = manager.data_dir.clone();
+		Self::from_roots(Self::data_roots(manager))
+	}
+
+	/// All the directories in which blocks can be stored
+	fn data_roots(manager: &BlockManager) -> Vec<PathBuf> {
+		manager
+			.data_layout
+			.load()
+			.data_dirs
+			.iter()
+			.map(|dd| dd.path.clone())
+			.chain(manager.cold_data_dir.clone())
+			.collect()
+	}
+
+	fn from_roots(mut todo_roots: Vec<PathBuf>) -> Self {
+		todo_roots.reverse();
 		Self {
-			path: vec![ReadingDir::Pending(root_dir)],
+			n_roots: todo_roots.len(),
+			todo_roots,
+			path: vec![],
 		}
 	}
 
 	/// Returns progress done, between 0 and 1
 	fn progress(&self) -> f32 {
-		if self.path.is_empty() {
+		let n_started_roots = self.n_roots - self.todo_roots.len();
+		if self.todo_roots.is_empty() && self.path.is_empty() {
 			1.0
+		} else if self.path.is_empty() {
+			n_started_roots as f32 / self.n_roots as f32
 		} else {
 			let mut ret = 0.0;
 			let mut next_div = 1;
@@ -403,16 +701,30 @@ impl BlockStoreIterator {
 					}
 				}
 			}
-			ret
+			((n_started_roots - 1) as f32 + ret) / self.n_roots as f32
 		}
 	}
 
 	async fn next(&mut self) -> Result<Option<Hash>, Error> {
+		while let Some((_, name)) = self.next_file().await? {
+			if let BlockStoreFile::Block(hash) = BlockStoreFile::from_file_name(&name) {
+				return Ok(Some(hash));
+			}
+		}
+		Ok(None)
+	}
+
+	/// Returns the path and name of the next file in the block store,
+	/// which may or may not be a data block
+	async fn next_file(&mut self) -> Result<Option<(PathBuf, String)>, Error> {
 		loop {
-			let last_path = match self.path.last_mut() {
-				None => return Ok(None),
-				Some(lp) => lp,
-			};
+			if self.path.is_empty() {
+				match self.todo_roots.pop() {
+					None => return Ok(None),
+					Some(root) => self.path.push(ReadingDir::Pending(root)),
+				}
+			}
+			let last_path = self.path.last_mut().unwrap();
 
 			if let ReadingDir::Pending(path) = last_path {
 				let mut reader = fs::read_dir(&path).await?;
@@ -450,17 +762,276 @@ impl BlockStoreIterator {
 			};
 			let ent_type = data_dir_ent.file_type().await?;
 
-			let name = name.strip_suffix(".zst").unwrap_or(&name);
-			if name.len() == 2 && hex::decode(&name).is_ok() && ent_type.is_dir() {
-				let path = data_dir_ent.path();
-				self.path.push(ReadingDir::Pending(path));
-			} else if name.len() == 64 {
-				if let Ok(h) = hex::decode(&name) {
-					let mut hash = [0u8; 32];
-					hash.copy_from_slice(&h);
-					return Ok(Some(hash.into()));
+			if ent_type.is_dir() {
+				if name.len() == 2 && hex::decode(&name).is_ok() {
+					let path = data_dir_ent.path();
+					self.path.push(ReadingDir::Pending(path));
 				}
+			} else {
+				return Ok(Some((data_dir_ent.path(), name)));
+			}
+		}
+	}
+}
+
+/// Kind of a file found in the block store
+enum BlockStoreFile {
+	/// A data block, possibly compressed, or a shard of a data block
+	Block(Hash),
+	/// A temporary file left by a write that was interrupted
+	Temporary,
+	/// A block that was found to be corrupted and replaced
+	Corrupted,
+	/// A file that was not written by Garage
+	Unknown,
+}
+
+impl BlockStoreFile {
+	fn from_file_name(name: &str) -> Self {
+		if name.ends_with(".tmp") {
+			return BlockStoreFile::Temporary;
+		}
+		if name.ends_with(".corrupted") {
+			return BlockStoreFile::Corrupted;
+		}
+		let name = name
+			.strip_suffix(".zst")
+			.or_else(|| name.strip_suffix(".shard"))
+			.unwrap_or(name);
+		match hex::decode(name) {
+			Ok(h) if h.len() == 32 => {
+				let mut hash = [0u8; 32];
+				hash.copy_from_slice(&h);
+				BlockStoreFile::Block(hash.into())
+			}
+			_ => BlockStoreFile::Unknown,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::collections::HashSet;
+
+	use garage_db as db;
+	use garage_db::sled_adapter::{sled, SledDb};
+
+	struct TestStore {
+		_db: db::Db,
+		rc: BlockRc,
+		access: BlockAccess,
+		dir: PathBuf,
+	}
+
+	impl TestStore {
+		fn new(name: &str) -> Self {
+			let db = SledDb::init(sled::Config::default().temporary(true).open().unwrap());
+			let rc = BlockRc::new(
+				db.open_tree("block_local_rc").unwrap(),
+				db.open_tree("block_local_replication").unwrap(),
+				3,
+			);
+			let access = BlockAccess::new(db.open_tree("block_access").unwrap());
+			let dir = std::env::temp_dir().join(format!(
+				"garage-block-check-{}-{}",
+				name,
+				std::process::id()
+			));
+			let _ = std::fs::remove_dir_all(&dir);
+			std::fs::create_dir_all(dir.join("ab")).unwrap();
+			Self {
+				_db: db,
+				rc,
+				access,
+				dir,
 			}
 		}
+
+		fn incref(&self, hash: &Hash, replication_factor: Option<usize>) {
+			self.rc
+				.rc
+				.db()
+				.transaction::<_, (), _>(|mut tx| {
+					self.rc.block_incref(&mut tx, hash, replication_factor)?;
+					tx.commit(())
+				})
+				.unwrap();
+		}
+
+		fn decref(&self, hash: &Hash) {
+			self.rc
+				.rc
+				.db()
+				.transaction::<_, (), _>(|mut tx| {
+					self.rc.block_decref(&mut tx, hash)?;
+					tx.commit(())
+				})
+				.unwrap();
+		}
+
+		fn write(&self, name: &str) -> PathBuf {
+			let path = self.dir.join("ab").join(name);
+			std::fs::write(&path, b"data").unwrap();
+			path
+		}
+
+		async fn check(&self, delete: bool) -> BlockStoreCheck {
+			self.check_min_age(Duration::from_secs(0), delete).await
+		}
+
+		async fn check_min_age(&self, min_age: Duration, delete: bool) -> BlockStoreCheck {
+			let mut check = BlockStoreCheck::default();
+			check_block_files(
+				&self.rc,
+				&self.access,
+				vec![self.dir.clone()],
+				min_age,
+				delete,
+				&mut check,
+			)
+			.await
+			.unwrap();
+			check
+		}
+	}
+
+	impl Drop for TestStore {
+		fn drop(&mut self) {
+			let _ = std::fs::remove_dir_all(&self.dir);
+		}
+	}
+
+	#[tokio::test]
+	async fn test_check_block_files() {
+		let store = TestStore::new("files");
+
+		let referenced = blake2sum(b"referenced");
+		store.incref(&referenced, None);
+		let referenced_path = store.write(&hex::encode(referenced));
+
+		// A block whose last reference was just removed is kept
+		// during the delay in which it can be referenced again
+		let recently_unreferenced = blake2sum(b"recently unreferenced");
+		store.incref(&recently_unreferenced, None);
+		store.decref(&recently_unreferenced);
+		let recently_unreferenced_path = store.write(&hex::encode(recently_unreferenced));
+
+		// A block for which a replication factor is recorded
+		// without a reference counter is kept
+		let with_replication = blake2sum(b"with replication");
+		store
+			.rc
+			.replication
+			.insert(with_replication.as_slice(), [1u8])
+			.unwrap();
+		let with_replication_path = store.write(&hex::encode(with_replication));
+
+		// Blocks that are not referenced anymore
+		let unreferenced = blake2sum(b"unreferenced");
+		store.incref(&unreferenced, Some(1));
+		store
+			.rc
+			.rc
+			.insert(
+				unreferenced.as_slice(),
+				[u64::to_be_bytes(0), u64::to_be_bytes(1)].concat(),
+			)
+			.unwrap();
+		let unreferenced_path = store.write(&format!("{}.zst", hex::encode(unreferenced)));
+		let orphan = blake2sum(b"orphan");
+		let orphan_path = store.write(&hex::encode(orphan));
+
+		let tmp_path = store.write(&format!("{}.tmp", hex::encode(referenced)));
+		let corrupted_path = store.write(&format!("{}.corrupted", hex::encode(referenced)));
+		let unknown_path = store.write("README");
+
+		let check = store.check(false).await;
+		assert_eq!(check.blocks, 5);
+		assert_eq!(check.orphan_blocks, 2);
+		assert_eq!(check.orphan_bytes, 8);
+		assert_eq!(check.tmp_files, 1);
+		assert_eq!(check.tmp_bytes, 4);
+		assert_eq!(check.corrupted_files, 1);
+		assert_eq!(check.unknown_files, 1);
+		assert_eq!(check.deleted_files, 0);
+		assert!(orphan_path.exists());
+		assert!(tmp_path.exists());
+
+		let check = store.check(true).await;
+		assert_eq!(check.orphan_blocks, 2);
+		assert_eq!(check.deleted_files, 3);
+		for path in [&unreferenced_path, &orphan_path, &tmp_path] {
+			assert!(!path.exists());
+		}
+		for path in [
+			&referenced_path,
+			&recently_unreferenced_path,
+			&with_replication_path,
+			&corrupted_path,
+			&unknown_path,
+		] {
+			assert!(path.exists());
+		}
+		// The counters of deleted blocks are removed with them
+		assert!(store.rc.rc.get(unreferenced.as_slice()).unwrap().is_none());
+		assert!(store
+			.rc
+			.replication
+			.get(unreferenced.as_slice())
+			.unwrap()
+			.is_none());
+
+		let check = store.check(true).await;
+		assert_eq!(check.blocks, 3);
+		assert_eq!(check.orphan_blocks, 0);
+		assert_eq!(check.deleted_files, 0);
+	}
+
+	#[tokio::test]
+	async fn test_check_block_files_recent() {
+		let store = TestStore::new("recent");
+
+		// The reference to a block written just before the node
+		// was stopped may not have been received yet
+		let orphan = blake2sum(b"orphan");
+		let orphan_path = store.write(&hex::encode(orphan));
+
+		let check = store.check_min_age(BLOCK_GC_DELAY, true).await;
+		assert_eq!(check.blocks, 1);
+		assert_eq!(check.recent_blocks, 1);
+		assert_eq!(check.orphan_blocks, 0);
+		assert_eq!(check.deleted_files, 0);
+		assert!(orphan_path.exists());
+
+		let check = store.check_min_age(Duration::from_secs(0), true).await;
+		assert_eq!(check.recent_blocks, 0);
+		assert_eq!(check.orphan_blocks, 1);
+		assert!(!orphan_path.exists());
+	}
+
+	#[tokio::test]
+	async fn test_check_referenced_blocks() {
+		let store = TestStore::new("referenced");
+
+		let stored = blake2sum(b"stored");
+		let missing = blake2sum(b"missing");
+		let unreferenced = blake2sum(b"unreferenced");
+		store.incref(&stored, None);
+		store.incref(&missing, None);
+		store.incref(&unreferenced, None);
+		store.decref(&unreferenced);
+
+		let mut checked = HashSet::new();
+		let n_missing = check_referenced_blocks(&store.rc, |hash| {
+			checked.insert(hash);
+			async move { Ok(hash != stored) }
+		})
+		.await
+		.unwrap();
+
+		assert_eq!(n_missing, 1);
+		assert_eq!(checked, vec![stored, missing].into_iter().collect());
 	}
 }
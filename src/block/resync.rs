@@ -325,7 +325,7 @@ impl BlockResyncManager {
 		if exists && needed.is_deletable() {
 			info!("Resync block {:?}: offloading and deleting", hash);
 
-			let mut who = manager.replication.write_nodes(hash);
+			let mut who = manager.storage_nodes(hash);
 			if who.len() < manager.replication.write_quorum() {
 				return Err(Error::Message("Not trying to offload block because we don't have a quorum of nodes to write to".to_string()));
 			}
@@ -370,25 +370,33 @@ impl BlockResyncManager {
 						.add(1, &[KeyValue::new("to", format!("{:?}", node))]);
 				}
 
-				let block = manager.read_block(hash).await?;
-				let (header, bytes) = block.into_parts();
-				let put_block_message = Req::new(BlockRpc::PutBlock {
-					hash: *hash,
-					header,
-				})?
-				.with_stream_from_buffer(bytes);
-				manager
-					.system
-					.rpc
-					.try_call_many(
-						&manager.endpoint,
-						&need_nodes[..],
-						put_block_message,
-						RequestStrategy::with_priority(PRIO_BACKGROUND)
-							.with_quorum(need_nodes.len()),
-					)
-					.await
-					.err_context("PutBlock RPC")?;
+				if manager.erasure_coding.is_some() {
+					// Each node needs its own shard of the block, which we have to rebuild
+					manager
+						.rpc_offload_block_shards(hash, &need_nodes[..])
+						.await
+						.err_context("PutBlock RPC")?;
+				} else {
+					let block = manager.read_block(hash).await?;
+					let (header, bytes) = block.into_parts();
+					let put_block_message = Req::new(BlockRpc::PutBlock {
+						hash: *hash,
+						header,
+					})?
+					.with_stream_from_buffer(bytes);
+					manager
+						.system
+						.rpc
+						.try_call_many(
+							&manager.endpoint,
+							&need_nodes[..],
+							put_block_message,
+							RequestStrategy::with_priority(PRIO_BACKGROUND)
+								.with_quorum(need_nodes.len()),
+						)
+						.await
+						.err_context("PutBlock RPC")?;
+				}
 			}
 			info!(
 				"Deleting unneeded block {:?}, offload finished ({} / {})",
@@ -408,11 +416,25 @@ impl BlockResyncManager {
 				hash
 			);
 
-			let block_data = manager.rpc_get_raw_block(hash, None).await?;
+			let replication_factor = manager.rc.get_block_replication_factor(hash)?;
+			let block_data = manager
+				.rpc_get_raw_block(hash, None, Some(replication_factor))
+				.await?;
+
+			manager.metrics.resync_recv_counter.add(1);
+
+			manager.write_fetched_block(hash, block_data).await?;
+		} else if needed.is_nonzero() && manager.is_misplaced_shard(hash).await? {
+			info!(
+				"Resync block {:?}: rebuilding shard stored with other erasure coding parameters",
+				hash
+			);
+
+			let block_data = manager.rpc_get_raw_block(hash, None, None).await?;
 
 			manager.metrics.resync_recv_counter.add(1);
 
-			manager.write_block(hash, &block_data).await?;
+			manager.write_fetched_block(hash, block_data).await?;
 		}
 
 		Ok(())
@@ -0,0 +1,61 @@
+//! Tracking of block accesses, used to move blocks that are not read anymore
+//! from the data directories to the cold storage directory
+
+use std::convert::TryInto;
+
+use garage_db as db;
+
+use garage_util::data::*;
+use garage_util::error::*;
+use garage_util::time::*;
+
+/// The access time of a block is updated at most once in this interval,
+/// to avoid writing to the metadata db on each read of hot blocks
+const ACCESS_TIME_RESOLUTION: u64 = 3600 * 1000;
+
+pub struct BlockAccess {
+	pub(crate) access: db::Tree,
+}
+
+impl BlockAccess {
+	pub(crate) fn new(access: db::Tree) -> Self {
+		Self { access }
+	}
+
+	/// Record that a block has been read or written now
+	pub(crate) fn record(&self, hash: &Hash) -> Result<(), Error> {
+		let now = now_msec();
+		match self.get(hash)? {
+			Some(t) if t + ACCESS_TIME_RESOLUTION > now => (),
+			_ => self.access.insert(hash, u64::to_be_bytes(now))?,
+		}
+		Ok(())
+	}
+
+	/// Time of the last access to a block. Blocks that were stored before
+	/// their accesses were tracked are considered to be accessed now.
+	pub(crate) fn get_or_init(&self, hash: &Hash) -> Result<u64, Error> {
+		match self.get(hash)? {
+			Some(t) => Ok(t),
+			None => {
+				let now = now_msec();
+				self.access.insert(hash, u64::to_be_bytes(now))?;
+				Ok(now)
+			}
+		}
+	}
+
+	/// Forget about a block that was deleted
+	pub(crate) fn clear(&self, hash: &Hash) -> Result<(), Error> {
+		self.access.remove(hash)?;
+		Ok(())
+	}
+
+	fn get(&self, hash: &Hash) -> Result<Option<u64>, Error> {
+		Ok(self
+			.access
+			.get(hash)?
+			.and_then(|v| v[..].try_into().ok())
+			.map(u64::from_be_bytes))
+	}
+}
@@ -21,6 +21,7 @@ use core::ops::{Bound, RangeBounds};
 
 use std::borrow::Cow;
 use std::cell::Cell;
+use std::path::Path;
 use std::sync::Arc;
 
 use err_derive::Error;
@@ -121,6 +122,14 @@ impl Db {
 		}
 	}
 
+	/// Write a copy of the whole database at path `to`, in the format used by
+	/// its engine (a directory for sled and LMDB, a file for sqlite), without
+	/// stopping other operations on the database. The copy is a consistent
+	/// point-in-time snapshot. This is not supported with sled.
+	pub fn snapshot(&self, to: &Path) -> Result<()> {
+		self.0.snapshot(to)
+	}
+
 	pub fn import(&self, other: &Db) -> Result<()> {
 		let existing_trees = self.list_trees()?;
 		if !existing_trees.is_empty() {
@@ -345,6 +354,8 @@ pub(crate) trait IDb: Send + Sync {
 	) -> Result<ValueIter<'_>>;
 
 	fn transaction(&self, f: &dyn ITxFn) -> TxResult<(), ()>;
+
+	fn snapshot(&self, to: &Path) -> Result<()>;
 }
 
 pub(crate) trait ITx {
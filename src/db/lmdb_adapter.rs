@@ -3,6 +3,7 @@ use core::ptr::NonNull;
 
 use std::collections::HashMap;
 use std::convert::TryInto;
+use std::path::Path;
 use std::sync::{Arc, RwLock};
 
 use heed::types::ByteSlice;
@@ -211,6 +212,16 @@ impl IDb for LmdbDb {
 			}
 		}
 	}
+
+	fn snapshot(&self, to: &Path) -> Result<()> {
+		std::fs::create_dir_all(to).map_err(|e| {
+			Error(format!("Unable to create LMDB snapshot directory: {}", e).into())
+		})?;
+		// The copy is made from a read transaction, which does not block writers
+		self.db
+			.copy_to_path(to.join("data.mdb"), heed::CompactionOption::Enabled)?;
+		Ok(())
+	}
 }
 
 // ----
@@ -2,6 +2,7 @@ use core::ops::Bound;
 
 use std::cell::Cell;
 use std::collections::HashMap;
+use std::path::Path;
 use std::sync::{Arc, RwLock};
 
 use sled::transaction::{
@@ -186,6 +187,14 @@ impl IDb for SledDb {
 			Err(TransactionError::Storage(s)) => Err(TxError::Db(s.into())),
 		}
 	}
+
+	fn snapshot(&self, _to: &Path) -> Result<()> {
+		// Sled can't read several trees at a single point in time, and blocking
+		// writes for the whole copy would stop the node from serving requests
+		Err(Error(
+			"online snapshots are not supported with sled, stop the node and copy the database instead".into(),
+		))
+	}
 }
 
 // ----
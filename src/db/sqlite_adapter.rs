@@ -2,11 +2,14 @@ use core::ops::Bound;
 
 use std::borrow::BorrowMut;
 use std::marker::PhantomPinned;
+use std::os::raw::{c_char, c_int};
+use std::path::Path;
 use std::pin::Pin;
 use std::ptr::NonNull;
 use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
 
-use rusqlite::{params, Connection, Rows, Statement, Transaction};
+use rusqlite::{ffi, params, Connection, Rows, Statement, Transaction};
 
 use crate::{
 	Db, Error, IDb, ITx, ITxFn, Result, TxError, TxFnResult, TxOpError, TxOpResult, TxResult,
@@ -31,6 +34,11 @@ impl From<rusqlite::Error> for TxOpError {
 
 // -- db
 
+// Number of pages copied at each step of a snapshot, while the database is locked
+const SNAPSHOT_STEP_PAGES: c_int = 256;
+// Delay before retrying a step of a snapshot if the database is busy
+const SNAPSHOT_RETRY_DELAY: Duration = Duration::from_millis(10);
+
 pub struct SqliteDb(Mutex<SqliteDbInner>);
 
 struct SqliteDbInner {
@@ -292,6 +300,49 @@ impl IDb for SqliteDb {
 		trace!("transaction done");
 		res
 	}
+
+	fn snapshot(&self, to: &Path) -> Result<()> {
+		// The database is copied a few pages at a time using the online backup API,
+		// and the lock on the connection is released between steps so that
+		// other operations can go on during the copy. Sqlite applies the changes
+		// made through the source connection to the copy as the backup proceeds,
+		// so the copy is a consistent snapshot of the database when it is done.
+		let dst = Connection::open(to)?;
+		let main = b"main\0".as_ptr() as *const c_char;
+
+		let backup = {
+			trace!("snapshot: lock db");
+			let this = self.0.lock().unwrap();
+			trace!("snapshot: lock acquired");
+			unsafe { ffi::sqlite3_backup_init(dst.handle(), main, this.db.handle(), main) }
+		};
+		if backup.is_null() {
+			let rc = unsafe { ffi::sqlite3_errcode(dst.handle()) };
+			return Err(sqlite_error(rc));
+		}
+
+		let res = loop {
+			let rc = {
+				let _this = self.0.lock().unwrap();
+				unsafe { ffi::sqlite3_backup_step(backup, SNAPSHOT_STEP_PAGES) }
+			};
+			match rc {
+				ffi::SQLITE_DONE => break Ok(()),
+				ffi::SQLITE_OK => std::thread::yield_now(),
+				ffi::SQLITE_BUSY | ffi::SQLITE_LOCKED => std::thread::sleep(SNAPSHOT_RETRY_DELAY),
+				rc => break Err(sqlite_error(rc)),
+			}
+		};
+
+		let _this = self.0.lock().unwrap();
+		unsafe { ffi::sqlite3_backup_finish(backup) };
+		trace!("snapshot done");
+		res
+	}
+}
+
+fn sqlite_error(rc: c_int) -> Error {
+	rusqlite::Error::SqliteFailure(ffi::Error::new(rc), None).into()
 }
 
 // ----
@@ -75,6 +75,39 @@ fn test_suite(db: Db) {
 	drop(iter);
 }
 
+fn test_snapshot(db: Db, open_snapshot: impl Fn(&std::path::Path) -> Db) {
+	let tree_a = db.open_tree("tree_a").unwrap();
+	let tree_b = db.open_tree("tree_b").unwrap();
+	for i in 0u8..100 {
+		tree_a.insert([i], [i, 1]).unwrap();
+		tree_b.insert([i], [i, 2]).unwrap();
+	}
+
+	let dir = mktemp::Temp::new_dir().unwrap();
+	let path = dir.join("snapshot");
+	db.snapshot(&path).unwrap();
+
+	// Writes made after the snapshot are not in it
+	tree_a.insert([200], [0]).unwrap();
+	tree_b.remove([0]).unwrap();
+
+	let snap = open_snapshot(&path);
+	let mut trees = snap.list_trees().unwrap();
+	trees.sort();
+	assert_eq!(trees, vec!["tree_a".to_string(), "tree_b".to_string()]);
+
+	let snap_a = snap.open_tree("tree_a").unwrap();
+	let snap_b = snap.open_tree("tree_b").unwrap();
+	assert_eq!(snap_a.len().unwrap(), 100);
+	assert_eq!(snap_b.len().unwrap(), 100);
+	assert_eq!(snap_a.get([42]).unwrap().unwrap(), vec![42, 1]);
+	assert_eq!(snap_b.get([0]).unwrap().unwrap(), vec![0, 2]);
+	assert!(snap_a.get([200]).unwrap().is_none());
+
+	drop(snap);
+	drop(dir);
+}
+
 #[test]
 #[cfg(feature = "lmdb")]
 fn test_lmdb_db() {
@@ -109,3 +142,40 @@ fn test_sqlite_db() {
 	let db = SqliteDb::init(rusqlite::Connection::open_in_memory().unwrap());
 	test_suite(db);
 }
+
+#[test]
+#[cfg(feature = "lmdb")]
+fn test_lmdb_snapshot() {
+	use crate::lmdb_adapter::LmdbDb;
+
+	let open = |path: &std::path::Path| {
+		let db = heed::EnvOpenOptions::new().max_dbs(100).open(path).unwrap();
+		LmdbDb::init(db)
+	};
+	let path = mktemp::Temp::new_dir().unwrap();
+	test_snapshot(open(&path), open);
+	drop(path);
+}
+
+#[test]
+#[cfg(feature = "sled")]
+fn test_sled_snapshot() {
+	use crate::sled_adapter::SledDb;
+
+	// Online snapshots are refused, as they would block writes
+	let path = mktemp::Temp::new_dir().unwrap();
+	let db = SledDb::init(sled::open(&path).unwrap());
+	assert!(db.snapshot(&path.join("snapshot")).is_err());
+	drop(path);
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_sqlite_snapshot() {
+	use crate::sqlite_adapter::SqliteDb;
+
+	let db = SqliteDb::init(rusqlite::Connection::open_in_memory().unwrap());
+	test_snapshot(db, |path| {
+		SqliteDb::init(rusqlite::Connection::open(path).unwrap())
+	});
+}
@@ -15,6 +15,7 @@ use garage_table::*;
 
 use garage_rpc::*;
 
+use garage_block::compression::Compression;
 use garage_block::repair::ScrubWorkerCommand;
 
 use garage_model::bucket_alias_table::*;
@@ -24,6 +25,7 @@ use garage_model::helper::error::{Error, OkOrBadRequest};
 use garage_model::key_table::*;
 use garage_model::migrate::Migrate;
 use garage_model::permission::*;
+use garage_model::snapshot::snapshot_metadata;
 
 use crate::cli::*;
 use crate::repair::online::launch_online_repair;
@@ -37,6 +39,7 @@ pub enum AdminRpc {
 	KeyOperation(KeyOperation),
 	LaunchRepair(RepairOpt),
 	Migrate(MigrateOpt),
+	MetaOperation(MetaOperation),
 	Stats(StatsOpt),
 	Worker(WorkerOpt),
 
@@ -84,7 +87,18 @@ impl AdminRpcHandler {
 			BucketOperation::Allow(query) => self.handle_bucket_allow(query).await,
 			BucketOperation::Deny(query) => self.handle_bucket_deny(query).await,
 			BucketOperation::Website(query) => self.handle_bucket_website(query).await,
+			BucketOperation::AnonymousRead(query) => self.handle_bucket_anonymous_read(query).await,
 			BucketOperation::SetQuotas(query) => self.handle_bucket_set_quotas(query).await,
+			BucketOperation::SetCompression(query) => {
+				self.handle_bucket_set_compression(query).await
+			}
+			BucketOperation::Chunking(query) => self.handle_bucket_chunking(query).await,
+			BucketOperation::SetColdStorage(query) => {
+				self.handle_bucket_set_cold_storage(query).await
+			}
+			BucketOperation::SetReplication(query) => {
+				self.handle_bucket_set_replication(query).await
+			}
 			BucketOperation::CleanupIncompleteUploads(query) => {
 				self.handle_bucket_cleanup_incomplete_uploads(query).await
 			}
@@ -461,6 +475,42 @@ impl AdminRpcHandler {
 		Ok(AdminRpc::Ok(msg))
 	}
 
+	async fn handle_bucket_anonymous_read(
+		&self,
+		query: &AnonymousReadOpt,
+	) -> Result<AdminRpc, Error> {
+		let bucket_id = self
+			.garage
+			.bucket_helper()
+			.resolve_global_bucket_name(&query.bucket)
+			.await?
+			.ok_or_bad_request("Bucket not found")?;
+
+		let mut bucket = self
+			.garage
+			.bucket_helper()
+			.get_existing_bucket(bucket_id)
+			.await?;
+		let bucket_state = bucket.state.as_option_mut().unwrap();
+
+		if !(query.allow ^ query.deny) {
+			return Err(Error::BadRequest(
+				"You must specify exactly one flag, either --allow or --deny".to_string(),
+			));
+		}
+
+		bucket_state.anonymous_read.update(query.allow);
+		self.garage.bucket_table.insert(&bucket).await?;
+
+		let msg = if query.allow {
+			format!("Anonymous read access allowed for {}", &query.bucket)
+		} else {
+			format!("Anonymous read access denied for {}", &query.bucket)
+		};
+
+		Ok(AdminRpc::Ok(msg))
+	}
+
 	async fn handle_bucket_set_quotas(&self, query: &SetQuotasOpt) -> Result<AdminRpc, Error> {
 		let bucket_id = self
 			.garage
@@ -515,6 +565,153 @@ impl AdminRpcHandler {
 		)))
 	}
 
+	async fn handle_bucket_set_compression(
+		&self,
+		query: &SetCompressionOpt,
+	) -> Result<AdminRpc, Error> {
+		let bucket_id = self
+			.garage
+			.bucket_helper()
+			.resolve_global_bucket_name(&query.bucket)
+			.await?
+			.ok_or_bad_request("Bucket not found")?;
+
+		let mut bucket = self
+			.garage
+			.bucket_helper()
+			.get_existing_bucket(bucket_id)
+			.await?;
+		let bucket_state = bucket.state.as_option_mut().unwrap();
+
+		let compression = if query.compression == "default" {
+			None
+		} else {
+			Some(
+				query
+					.compression
+					.parse::<Compression>()
+					.map_err(Error::BadRequest)?,
+			)
+		};
+
+		bucket_state.compression.update(compression);
+		self.garage.bucket_table.insert(&bucket).await?;
+
+		Ok(AdminRpc::Ok(format!(
+			"Compression updated for {}",
+			&query.bucket
+		)))
+	}
+
+	async fn handle_bucket_chunking(&self, query: &ChunkingOpt) -> Result<AdminRpc, Error> {
+		let bucket_id = self
+			.garage
+			.bucket_helper()
+			.resolve_global_bucket_name(&query.bucket)
+			.await?
+			.ok_or_bad_request("Bucket not found")?;
+
+		let mut bucket = self
+			.garage
+			.bucket_helper()
+			.get_existing_bucket(bucket_id)
+			.await?;
+		let bucket_state = bucket.state.as_option_mut().unwrap();
+
+		if !(query.content_defined ^ query.fixed_size) {
+			return Err(Error::BadRequest(
+				"You must specify exactly one flag, either --content-defined or --fixed-size"
+					.to_string(),
+			));
+		}
+
+		bucket_state
+			.content_defined_chunking
+			.update(query.content_defined);
+		self.garage.bucket_table.insert(&bucket).await?;
+
+		let msg = if query.content_defined {
+			format!("Content-defined chunking enabled for {}", &query.bucket)
+		} else {
+			format!("Fixed-size chunking enabled for {}", &query.bucket)
+		};
+
+		Ok(AdminRpc::Ok(msg))
+	}
+
+	async fn handle_bucket_set_cold_storage(
+		&self,
+		query: &SetColdStorageOpt,
+	) -> Result<AdminRpc, Error> {
+		let bucket_id = self
+			.garage
+			.bucket_helper()
+			.resolve_global_bucket_name(&query.bucket)
+			.await?
+			.ok_or_bad_request("Bucket not found")?;
+
+		let mut bucket = self
+			.garage
+			.bucket_helper()
+			.get_existing_bucket(bucket_id)
+			.await?;
+		let bucket_state = bucket.state.as_option_mut().unwrap();
+
+		let after_days = if query.after_days == "never" {
+			None
+		} else {
+			Some(query.after_days.parse::<u32>().ok_or_bad_request(format!(
+				"Invalid number of days specified: {}",
+				query.after_days
+			))?)
+		};
+
+		bucket_state.cold_storage_after.update(after_days);
+		self.garage.bucket_table.insert(&bucket).await?;
+
+		Ok(AdminRpc::Ok(format!(
+			"Cold storage updated for {}",
+			&query.bucket
+		)))
+	}
+
+	async fn handle_bucket_set_replication(
+		&self,
+		query: &SetReplicationOpt,
+	) -> Result<AdminRpc, Error> {
+		let bucket_id = self
+			.garage
+			.bucket_helper()
+			.resolve_global_bucket_name(&query.bucket)
+			.await?
+			.ok_or_bad_request("Bucket not found")?;
+
+		let mut bucket = self
+			.garage
+			.bucket_helper()
+			.get_existing_bucket(bucket_id)
+			.await?;
+		let bucket_state = bucket.state.as_option_mut().unwrap();
+
+		let replication = if query.replication == "default" {
+			None
+		} else {
+			Some(
+				self.garage
+					.bucket_helper()
+					.parse_block_replication(&query.replication)?,
+			)
+		};
+
+		bucket_state.replication.update(replication);
+		self.garage.bucket_table.insert(&bucket).await?;
+
+		Ok(AdminRpc::Ok(format!(
+			"Replication updated for {}",
+			&query.bucket
+		)))
+	}
+
 	async fn handle_bucket_cleanup_incomplete_uploads(
 		&self,
 		query: &CleanupIncompleteUploadsOpt,
@@ -747,6 +944,44 @@ impl AdminRpcHandler {
 		}
 	}
 
+	async fn handle_meta_cmd(&self, mo: &MetaOperation) -> Result<AdminRpc, Error> {
+		match mo {
+			MetaOperation::Snapshot { all_nodes: true } => {
+				let mut ret = String::new();
+				let ring = self.garage.system.ring.borrow().clone();
+
+				for node in ring.layout.node_ids().iter() {
+					let node_id = (*node).into();
+					let res = match self
+						.endpoint
+						.call(
+							&node_id,
+							AdminRpc::MetaOperation(MetaOperation::Snapshot { all_nodes: false }),
+							PRIO_NORMAL,
+						)
+						.await?
+					{
+						Ok(AdminRpc::Ok(s)) => s,
+						Ok(x) => format!("Bad answer: {:?}", x),
+						Err(e) => format!("Error: {}", e),
+					};
+					writeln!(&mut ret, "{:?}: {}", node, res).unwrap();
+				}
+				Ok(AdminRpc::Ok(ret))
+			}
+			MetaOperation::Snapshot { all_nodes: false } => {
+				let path = snapshot_metadata(&self.garage).await?;
+				Ok(AdminRpc::Ok(format!(
+					"Snapshot of metadata database written to {}",
+					path.display()
+				)))
+			}
+			MetaOperation::Restore(_) => Err(Error::BadRequest(
+				"Snapshots must be restored offline, directly on the server node".to_string(),
+			)),
+		}
+	}
+
 	async fn handle_stats(&self, opt: StatsOpt) -> Result<AdminRpc, Error> {
 		if opt.all_nodes {
 			let mut ret = String::new();
@@ -869,6 +1104,35 @@ impl AdminRpcHandler {
 		.unwrap();
 		writeln!(to, "  GC todo queue length: {}", t.data.gc_todo_len()?).unwrap();
 
+		let sync_status = t.syncer.sync_status();
+		let n_synced = sync_status.iter().filter(|p| p.last_sync.is_some()).count();
+		let oldest_sync = match sync_status.iter().map(|p| p.last_sync).min() {
+			Some(Some(t)) => msec_to_rfc3339(t),
+			Some(None) => "never".to_string(),
+			None => "-".to_string(),
+		};
+		writeln!(
+			to,
+			"  partitions synced since startup: {}/{} (oldest sync: {})",
+			n_synced,
+			sync_status.len(),
+			oldest_sync
+		)
+		.unwrap();
+		if opt.detailed {
+			let fmt_time = |t: Option<u64>| t.map(msec_to_rfc3339).unwrap_or_else(|| "-".into());
+			for p in sync_status.iter() {
+				writeln!(
+					to,
+					"    partition {:>3}: last sync {}, last change {}",
+					p.partition,
+					fmt_time(p.last_sync),
+					fmt_time(p.last_change)
+				)
+				.unwrap();
+			}
+		}
+
 		Ok(())
 	}
 
@@ -922,6 +1186,7 @@ impl EndpointHandler<AdminRpc> for AdminRpcHandler {
 			AdminRpc::KeyOperation(ko) => self.handle_key_cmd(ko).await,
 			AdminRpc::Migrate(opt) => self.handle_migrate(opt.clone()).await,
 			AdminRpc::LaunchRepair(opt) => self.handle_launch_repair(opt.clone()).await,
+			AdminRpc::MetaOperation(mo) => self.handle_meta_cmd(mo).await,
 			AdminRpc::Stats(opt) => self.handle_stats(opt.clone()).await,
 			AdminRpc::Worker(opt) => self.handle_worker_cmd(opt.clone()).await,
 			m => Err(GarageError::unexpected_rpc_message(m).into()),
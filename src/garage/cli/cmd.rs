@@ -1,6 +1,8 @@
 use std::collections::HashSet;
 use std::time::Duration;
 
+use bytesize::ByteSize;
+
 use garage_util::error::*;
 use garage_util::formater::format_table;
 
@@ -39,6 +41,9 @@ pub async fn cli_command_dispatch(
 		Command::Repair(ro) => {
 			cmd_admin(admin_rpc_endpoint, rpc_host, AdminRpc::LaunchRepair(ro)).await
 		}
+		Command::Meta(mo) => {
+			cmd_admin(admin_rpc_endpoint, rpc_host, AdminRpc::MetaOperation(mo)).await
+		}
 		Command::Stats(so) => cmd_admin(admin_rpc_endpoint, rpc_host, AdminRpc::Stats(so)).await,
 		Command::Worker(wo) => cmd_admin(admin_rpc_endpoint, rpc_host, AdminRpc::Worker(wo)).await,
 		_ => unreachable!(),
@@ -56,18 +61,26 @@ pub async fn cmd_status(rpc_cli: &Endpoint<SystemRpc, ()>, rpc_host: NodeID) ->
 	let layout = fetch_layout(rpc_cli, rpc_host).await?;
 
 	println!("==== HEALTHY NODES ====");
-	let mut healthy_nodes = vec!["ID\tHostname\tAddress\tTags\tZone\tCapacity".to_string()];
+	let mut healthy_nodes =
+		vec!["ID\tHostname\tAddress\tTags\tZone\tCapacity\tDataAvail\tMetaAvail".to_string()];
 	for adv in status.iter().filter(|adv| adv.is_up) {
 		match layout.roles.get(&adv.id) {
 			Some(NodeRoleV(Some(cfg))) => {
 				healthy_nodes.push(format!(
-					"{id:?}\t{host}\t{addr}\t[{tags}]\t{zone}\t{capacity}",
+					"{id:?}\t{host}\t{addr}\t[{tags}]\t{zone}\t{capacity}\t{data_avail}\t{meta_avail}{low}",
 					id = adv.id,
 					host = adv.status.hostname,
 					addr = adv.addr,
 					tags = cfg.tags.join(","),
 					zone = cfg.zone,
 					capacity = cfg.capacity_string(),
+					data_avail = disk_avail_string(adv.status.data_disk_avail),
+					meta_avail = disk_avail_string(adv.status.meta_disk_avail),
+					low = if adv.status.low_disk_space {
+						"\t(low disk space, refusing writes)"
+					} else {
+						""
+					},
 				));
 			}
 			_ => {
@@ -194,3 +207,15 @@ pub async fn cmd_admin(
 	}
 	Ok(())
 }
+
+fn disk_avail_string(avail: Option<(u64, u64)>) -> String {
+	match avail {
+		Some((avail, total)) if total > 0 => format!(
+			"{}/{} ({:.1}%)",
+			ByteSize::b(avail),
+			ByteSize::b(total),
+			avail as f64 * 100.0 / total as f64
+		),
+		_ => "?".to_string(),
+	}
+}
@@ -0,0 +1,34 @@
+use std::path::PathBuf;
+
+use garage_util::error::*;
+
+use garage_model::snapshot::restore_metadata_snapshot;
+
+use crate::cli::structs::MetaRestoreOpt;
+
+pub fn meta_restore_command(config_file: PathBuf, opt: MetaRestoreOpt) -> Result<(), Error> {
+	if !opt.yes {
+		return Err(Error::Message(
+			"Please make sure that the Garage node is stopped, and add the --yes flag to restore the snapshot".into(),
+		));
+	}
+
+	let config = garage_util::config::read_config(config_file.clone()).err_context(format!(
+		"Unable to read configuration file {}",
+		config_file.to_string_lossy(),
+	))?;
+
+	let old = restore_metadata_snapshot(&config, &opt.snapshot)?;
+
+	println!("Metadata database restored from snapshot {}.", opt.snapshot);
+	if let Some(old) = old {
+		println!(
+			"The previous metadata database has been moved to {}, you can delete it once the node is running again.",
+			old.display()
+		);
+	}
+	println!("When the node is restarted, it will fetch from other nodes the changes made since the snapshot was taken.");
+	println!("To speed this up, you can then run: garage repair --yes tables");
+
+	Ok(())
+}
@@ -1,11 +1,13 @@
 pub(crate) mod cmd;
 pub(crate) mod init;
 pub(crate) mod layout;
+pub(crate) mod meta;
 pub(crate) mod structs;
 pub(crate) mod util;
 
 pub(crate) use cmd::*;
 pub(crate) use init::*;
 pub(crate) use layout::*;
+pub(crate) use meta::*;
 pub(crate) use structs::*;
 pub(crate) use util::*;
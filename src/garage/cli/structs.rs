@@ -43,6 +43,10 @@ pub enum Command {
 	#[structopt(name = "offline-repair", version = garage_version())]
 	OfflineRepair(OfflineRepairOpt),
 
+	/// Operations on the metadata database of nodes
+	#[structopt(name = "meta", version = garage_version())]
+	Meta(MetaOperation),
+
 	/// Gather node statistics
 	#[structopt(name = "stats", version = garage_version())]
 	Stats(StatsOpt),
@@ -186,10 +190,31 @@ pub enum BucketOperation {
 	#[structopt(name = "website", version = garage_version())]
 	Website(WebsiteOpt),
 
+	/// Allow or deny anonymous read access to objects through the S3 API
+	#[structopt(name = "anonymous-read", version = garage_version())]
+	AnonymousRead(AnonymousReadOpt),
+
 	/// Set the quotas for this bucket
 	#[structopt(name = "set-quotas", version = garage_version())]
 	SetQuotas(SetQuotasOpt),
 
+	/// Set how data blocks of objects written to this bucket are compressed
+	#[structopt(name = "set-compression", version = garage_version())]
+	SetCompression(SetCompressionOpt),
+
+	/// Set how objects written to this bucket are split in data blocks
+	#[structopt(name = "chunking", version = garage_version())]
+	Chunking(ChunkingOpt),
+
+	/// Set after how long data blocks of objects of this bucket that are
+	/// not read anymore are moved to cold storage
+	#[structopt(name = "set-cold-storage", version = garage_version())]
+	SetColdStorage(SetColdStorageOpt),
+
+	/// Set on how many nodes data blocks of objects written to this bucket are stored
+	#[structopt(name = "set-replication", version = garage_version())]
+	SetReplication(SetReplicationOpt),
+
 	/// Clean up (abort) old incomplete multipart uploads
 	#[structopt(name = "cleanup-incomplete-uploads", version = garage_version())]
 	CleanupIncompleteUploads(CleanupIncompleteUploadsOpt),
@@ -217,6 +242,65 @@ pub struct WebsiteOpt {
 	pub error_document: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct AnonymousReadOpt {
+	/// Allow anonymous requests to get and list objects
+	#[structopt(long = "allow")]
+	pub allow: bool,
+
+	/// Deny anonymous requests
+	#[structopt(long = "deny")]
+	pub deny: bool,
+
+	/// Bucket name
+	pub bucket: String,
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct SetCompressionOpt {
+	/// Bucket name
+	pub bucket: String,
+
+	/// Compression of data blocks: none, lz4, zstd or zstd:<level>,
+	/// or default to use the compression configured on each node
+	pub compression: String,
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct ChunkingOpt {
+	/// Cut blocks at content-defined boundaries, so that identical data
+	/// is deduplicated between objects even when it is not at the same offset
+	#[structopt(long = "content-defined")]
+	pub content_defined: bool,
+
+	/// Cut blocks at fixed offsets (default)
+	#[structopt(long = "fixed-size")]
+	pub fixed_size: bool,
+
+	/// Bucket name
+	pub bucket: String,
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct SetColdStorageOpt {
+	/// Bucket name
+	pub bucket: String,
+
+	/// Number of days without being read after which data blocks are moved
+	/// to cold storage, or never to keep them in the data directories
+	pub after_days: String,
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct SetReplicationOpt {
+	/// Bucket name
+	pub bucket: String,
+
+	/// Replication of data blocks, with the same syntax as replication_mode
+	/// (e.g. 2, 3 or 4-degraded), or default to use the replication mode of the cluster
+	pub replication: String,
+}
+
 #[derive(Serialize, Deserialize, StructOpt, Debug)]
 pub struct BucketOpt {
 	/// Bucket name
@@ -446,6 +530,9 @@ pub enum RepairWhat {
 		#[structopt(subcommand)]
 		cmd: ScrubCmd,
 	},
+	/// Rebalance data blocks among storage locations
+	#[structopt(name = "rebalance", version = garage_version())]
+	Rebalance,
 }
 
 #[derive(Serialize, Deserialize, StructOpt, Debug, Eq, PartialEq, Clone)]
@@ -489,6 +576,40 @@ pub enum OfflineRepairWhat {
 	/// Repair object counters
 	#[structopt(name = "object_counters", version = garage_version())]
 	ObjectCounters,
+	/// Check the files of the block store against block reference counters,
+	/// reporting orphan blocks, missing blocks and leftover temporary files
+	#[structopt(name = "block_store", version = garage_version())]
+	BlockStore {
+		/// Delete orphan blocks and temporary files
+		#[structopt(long = "delete")]
+		delete: bool,
+	},
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug, Clone)]
+pub enum MetaOperation {
+	/// Save a snapshot of the metadata database, while the node keeps running
+	#[structopt(name = "snapshot", version = garage_version())]
+	Snapshot {
+		/// Take a snapshot on all nodes instead of only the node we are connected to
+		#[structopt(short = "a", long = "all-nodes")]
+		all_nodes: bool,
+	},
+
+	/// Replace the metadata database of this node by a snapshot
+	/// (this must be run offline, directly on the server node)
+	#[structopt(name = "restore", version = garage_version())]
+	Restore(MetaRestoreOpt),
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug, Clone)]
+pub struct MetaRestoreOpt {
+	/// Confirm the restoration of the snapshot
+	#[structopt(long = "yes")]
+	pub yes: bool,
+
+	/// Name of the snapshot in the snapshot directory, or its full path
+	pub snapshot: String,
 }
 
 #[derive(Serialize, Deserialize, StructOpt, Debug, Clone)]
@@ -164,6 +164,26 @@ pub fn print_bucket_info(
 			);
 
 			println!("\nWebsite access: {}", p.website_config.get().is_some());
+			println!("Anonymous read access: {}", p.anonymous_read.get());
+			match p.compression.get() {
+				Some(c) => println!("Compression: {}", c),
+				None => println!("Compression: node default"),
+			}
+			println!(
+				"Content-defined chunking: {}",
+				p.content_defined_chunking.get()
+			);
+			match p.cold_storage_after.get() {
+				Some(d) => println!("Cold storage: after {} days without reads", d),
+				None => println!("Cold storage: never"),
+			}
+			match p.replication.get() {
+				Some(r) => println!(
+					"Replication of data blocks: {} copies, write quorum {}",
+					r.replication_factor, r.write_quorum
+				),
+				None => println!("Replication of data blocks: cluster default"),
+			}
 
 			let quotas = p.quotas.get();
 			if quotas.max_size.is_some() || quotas.max_objects.is_some() {
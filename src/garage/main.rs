@@ -148,6 +148,9 @@ async fn main() {
 		Command::Node(NodeOperation::NodeId(node_id_opt)) => {
 			node_id_command(opt.config_file, node_id_opt.quiet)
 		}
+		Command::Meta(MetaOperation::Restore(restore_opt)) => {
+			meta_restore_command(opt.config_file, restore_opt)
+		}
 		_ => cli_command(opt).await,
 	};
 
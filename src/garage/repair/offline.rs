@@ -6,6 +6,8 @@ use garage_util::background::*;
 use garage_util::config::*;
 use garage_util::error::*;
 
+use garage_block::repair::offline_check_block_store;
+
 use garage_model::garage::Garage;
 
 use crate::cli::structs::*;
@@ -41,6 +43,32 @@ pub async fn offline_repair(config_file: PathBuf, opt: OfflineRepairOpt) -> Resu
 				.object_counter_table
 				.offline_recount_all(&garage.object_table)?;
 		}
+		OfflineRepairWhat::BlockStore { delete } => {
+			let check = offline_check_block_store(&garage.block_manager, delete).await?;
+			println!("Block files:              {}", check.blocks);
+			println!("Recently written blocks:  {}", check.recent_blocks);
+			println!(
+				"Orphan blocks:            {} ({})",
+				check.orphan_blocks,
+				bytesize::ByteSize::b(check.orphan_bytes)
+			);
+			println!("Missing blocks:           {}", check.missing_blocks);
+			println!(
+				"Temporary files:          {} ({})",
+				check.tmp_files,
+				bytesize::ByteSize::b(check.tmp_bytes)
+			);
+			println!("Corrupted blocks:         {}", check.corrupted_files);
+			println!("Unknown files:            {}", check.unknown_files);
+			if delete {
+				println!("Deleted files:            {}", check.deleted_files);
+			} else if check.orphan_blocks + check.tmp_files > 0 {
+				println!("Run again with --delete to delete orphan blocks and temporary files.");
+			}
+			if check.missing_blocks > 0 {
+				println!("Missing blocks will be fetched from other nodes when Garage is started.");
+			}
+		}
 	}
 
 	info!("Repair operation finished, shutting down Garage internals...");
@@ -45,6 +45,14 @@ pub async fn launch_online_repair(garage: Arc<Garage>, opt: RepairOpt) {
 					garage.block_manager.clone(),
 				));
 		}
+		RepairWhat::Rebalance => {
+			info!("Rebalancing the stored blocks among storage locations");
+			garage
+				.background
+				.spawn_worker(garage_block::repair::RebalanceWorker::new(
+					garage.block_manager.clone(),
+				));
+		}
 		RepairWhat::Scrub { cmd } => {
 			let cmd = match cmd {
 				ScrubCmd::Start => ScrubWorkerCommand::Start,
@@ -201,6 +209,7 @@ impl Worker for RepairBlockrefsWorker {
 						block: block_ref.block,
 						version: block_ref.version,
 						deleted: true.into(),
+						replication_factor: None,
 					})
 					.await?;
 			}
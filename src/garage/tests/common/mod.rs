@@ -27,7 +27,7 @@ pub struct K2VContext {
 impl Context {
 	fn new() -> Self {
 		let garage = garage::instance();
-		let client = client::build_client(garage);
+		let client = client::build_client(garage, &garage.key);
 		let custom_request = CustomRequester::new_s3(garage);
 		let k2v_request = CustomRequester::new_k2v(garage);
 
@@ -41,6 +41,11 @@ impl Context {
 		}
 	}
 
+	/// Build a client that signs its requests with another key
+	pub fn client_for_key(&self, key: &garage::Key) -> Client {
+		client::build_client(self.garage, key)
+	}
+
 	/// Create an unique bucket with a random suffix.
 	///
 	/// Return the created bucket full name.
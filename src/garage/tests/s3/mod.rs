@@ -1,6 +1,9 @@
 mod list;
 mod multipart;
+mod object_lock;
 mod objects;
+mod policy;
 mod simple;
 mod streaming_signature;
+mod versioning;
 mod website;
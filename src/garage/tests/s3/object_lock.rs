@@ -0,0 +1,249 @@
+use crate::common;
+use aws_sdk_s3::model::{
+	BucketVersioningStatus, DefaultRetention, ObjectLockConfiguration, ObjectLockEnabled,
+	ObjectLockLegalHold, ObjectLockLegalHoldStatus, ObjectLockRetentionMode, ObjectLockRule,
+	VersioningConfiguration,
+};
+use aws_sdk_s3::types::ByteStream;
+
+const STD_KEY: &str = "hello world";
+const BODY: &[u8; 62] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+fn lock_configuration() -> ObjectLockConfiguration {
+	ObjectLockConfiguration::builder()
+		.object_lock_enabled(ObjectLockEnabled::Enabled)
+		.rule(
+			ObjectLockRule::builder()
+				.default_retention(
+					DefaultRetention::builder()
+						.mode(ObjectLockRetentionMode::Governance)
+						.days(1)
+						.build(),
+				)
+				.build(),
+		)
+		.build()
+}
+
+fn versioning_configuration(status: BucketVersioningStatus) -> VersioningConfiguration {
+	VersioningConfiguration::builder().status(status).build()
+}
+
+#[tokio::test]
+async fn test_object_lock_configuration() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("objectlockconfig");
+
+	// Object Lock requires versioning to be enabled
+	assert!(ctx
+		.client
+		.put_object_lock_configuration()
+		.bucket(&bucket)
+		.object_lock_configuration(lock_configuration())
+		.send()
+		.await
+		.is_err());
+	assert!(ctx
+		.client
+		.get_object_lock_configuration()
+		.bucket(&bucket)
+		.send()
+		.await
+		.is_err());
+
+	ctx.client
+		.put_bucket_versioning()
+		.bucket(&bucket)
+		.versioning_configuration(versioning_configuration(BucketVersioningStatus::Enabled))
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.put_object_lock_configuration()
+		.bucket(&bucket)
+		.object_lock_configuration(lock_configuration())
+		.send()
+		.await
+		.unwrap();
+
+	let conf = ctx
+		.client
+		.get_object_lock_configuration()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap()
+		.object_lock_configuration
+		.unwrap();
+	assert_eq!(conf.object_lock_enabled, Some(ObjectLockEnabled::Enabled));
+	let retention = conf.rule.unwrap().default_retention.unwrap();
+	assert_eq!(retention.mode, Some(ObjectLockRetentionMode::Governance));
+	assert_eq!(retention.days, 1);
+
+	// Versioning cannot be suspended anymore
+	assert!(ctx
+		.client
+		.put_bucket_versioning()
+		.bucket(&bucket)
+		.versioning_configuration(versioning_configuration(BucketVersioningStatus::Suspended))
+		.send()
+		.await
+		.is_err());
+}
+
+#[tokio::test]
+async fn test_object_lock_protections() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("objectlockprotections");
+
+	ctx.client
+		.put_bucket_versioning()
+		.bucket(&bucket)
+		.versioning_configuration(versioning_configuration(BucketVersioningStatus::Enabled))
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.put_object_lock_configuration()
+		.bucket(&bucket)
+		.object_lock_configuration(lock_configuration())
+		.send()
+		.await
+		.unwrap();
+
+	{
+		// New versions get the default retention of the bucket
+		let v1 = ctx
+			.client
+			.put_object()
+			.bucket(&bucket)
+			.key(STD_KEY)
+			.body(ByteStream::from_static(BODY))
+			.send()
+			.await
+			.unwrap()
+			.version_id
+			.unwrap();
+
+		let r = ctx
+			.client
+			.get_object_retention()
+			.bucket(&bucket)
+			.key(STD_KEY)
+			.version_id(&v1)
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(
+			r.retention.unwrap().mode,
+			Some(ObjectLockRetentionMode::Governance)
+		);
+
+		// The version cannot be deleted while it is retained,
+		// unless governance mode retention is bypassed by an owner of the bucket
+		assert!(ctx
+			.client
+			.delete_object()
+			.bucket(&bucket)
+			.key(STD_KEY)
+			.version_id(&v1)
+			.send()
+			.await
+			.is_err());
+
+		// Adding a delete marker is allowed
+		let dm = ctx
+			.client
+			.delete_object()
+			.bucket(&bucket)
+			.key(STD_KEY)
+			.send()
+			.await
+			.unwrap();
+		assert!(dm.delete_marker);
+
+		ctx.client
+			.delete_object()
+			.bucket(&bucket)
+			.key(STD_KEY)
+			.version_id(&v1)
+			.bypass_governance_retention(true)
+			.send()
+			.await
+			.unwrap();
+		assert!(ctx
+			.client
+			.get_object()
+			.bucket(&bucket)
+			.key(STD_KEY)
+			.version_id(&v1)
+			.send()
+			.await
+			.is_err());
+	}
+
+	{
+		// A legal hold protects a version even when retention is bypassed
+		let v2 = ctx
+			.client
+			.put_object()
+			.bucket(&bucket)
+			.key(STD_KEY)
+			.body(ByteStream::from_static(BODY))
+			.object_lock_legal_hold_status(ObjectLockLegalHoldStatus::On)
+			.send()
+			.await
+			.unwrap()
+			.version_id
+			.unwrap();
+
+		let r = ctx
+			.client
+			.get_object_legal_hold()
+			.bucket(&bucket)
+			.key(STD_KEY)
+			.version_id(&v2)
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(
+			r.legal_hold.unwrap().status,
+			Some(ObjectLockLegalHoldStatus::On)
+		);
+
+		assert!(ctx
+			.client
+			.delete_object()
+			.bucket(&bucket)
+			.key(STD_KEY)
+			.version_id(&v2)
+			.bypass_governance_retention(true)
+			.send()
+			.await
+			.is_err());
+
+		ctx.client
+			.put_object_legal_hold()
+			.bucket(&bucket)
+			.key(STD_KEY)
+			.version_id(&v2)
+			.legal_hold(
+				ObjectLockLegalHold::builder()
+					.status(ObjectLockLegalHoldStatus::Off)
+					.build(),
+			)
+			.send()
+			.await
+			.unwrap();
+
+		ctx.client
+			.delete_object()
+			.bucket(&bucket)
+			.key(STD_KEY)
+			.version_id(&v2)
+			.bypass_governance_retention(true)
+			.send()
+			.await
+			.unwrap();
+	}
+}
@@ -1,8 +1,10 @@
 use crate::common;
 use aws_sdk_s3::model::{Delete, ObjectIdentifier};
 use aws_sdk_s3::types::ByteStream;
+use hyper::{Method, StatusCode};
 
 const STD_KEY: &str = "hello world";
+const COND_KEY: &str = "conditional";
 const CTRL_KEY: &str = "\x00\x01\x02\x00";
 const UTF8_KEY: &str = "\u{211D}\u{1F923}\u{1F44B}";
 const BODY: &[u8; 62] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
@@ -187,6 +189,101 @@ async fn test_getobject() {
 	}
 }
 
+#[tokio::test]
+async fn test_getobject_multirange() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("getobject-multirange");
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.content_type("text/plain")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.range("bytes=0-1,10-12")
+		.send()
+		.await
+		.unwrap();
+
+	// Each range is sent in a separate part of a multipart/byteranges body
+	let content_type = o.content_type.unwrap();
+	let boundary = content_type
+		.strip_prefix("multipart/byteranges; boundary=")
+		.unwrap();
+	let expected = format!(
+		"--{b}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/62\r\n\r\n01\
+		\r\n--{b}\r\nContent-Type: text/plain\r\nContent-Range: bytes 10-12/62\r\n\r\nABC\
+		\r\n--{b}--\r\n",
+		b = boundary
+	);
+	assert_eq!(o.content_length, expected.len() as i64);
+	assert_bytes_eq!(o.body, expected.as_bytes());
+}
+
+#[tokio::test]
+async fn test_putobject_conditional() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-conditional");
+
+	// The AWS SDK does not send If-Match and If-None-Match headers on PutObject
+	let put = |header: &'static str, value: String| {
+		let mut req = ctx.custom_request.builder(bucket.clone());
+		req.method(Method::PUT)
+			.path(COND_KEY)
+			.signed_header(header, value)
+			.body(BODY.to_vec());
+		async move { req.send().await.unwrap().status() }
+	};
+
+	// If-None-Match: * only writes the object if it does not exist yet
+	assert_eq!(put("if-none-match", "*".into()).await, StatusCode::OK);
+	assert_eq!(
+		put("if-none-match", "*".into()).await,
+		StatusCode::PRECONDITION_FAILED
+	);
+
+	let etag = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key(COND_KEY)
+		.send()
+		.await
+		.unwrap()
+		.e_tag
+		.unwrap();
+
+	// If-Match only overwrites the object if its ETag matches
+	assert_eq!(
+		put("if-match", "\"00000000000000000000000000000000\"".into()).await,
+		StatusCode::PRECONDITION_FAILED
+	);
+	assert_eq!(put("if-match", etag).await, StatusCode::OK);
+
+	// If-Match fails on an object that does not exist
+	let status = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("missing")
+		.signed_header("if-match", "*")
+		.body(BODY.to_vec())
+		.send()
+		.await
+		.unwrap()
+		.status();
+	assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
 #[tokio::test]
 async fn test_deleteobject() {
 	let ctx = common::context();
@@ -0,0 +1,201 @@
+use crate::common;
+use crate::common::ext::*;
+use aws_sdk_s3::types::ByteStream;
+use http::{Request, StatusCode};
+use hyper::{
+	body::{to_bytes, Body},
+	Client,
+};
+
+const BCKT_NAME: &str = "policy";
+const BODY: &[u8; 62] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+#[tokio::test]
+async fn test_bucket_policy() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket(BCKT_NAME);
+
+	for key in ["public/a", "private/b", "shared/c"] {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key(key)
+			.body(ByteStream::from_static(BODY))
+			.send()
+			.await
+			.unwrap();
+	}
+
+	let client = Client::new();
+	let anonymous = |method: &str, key: &str| {
+		Request::builder()
+			.method(method)
+			.uri(format!(
+				"http://127.0.0.1:{}/{}/{}",
+				ctx.garage.s3_port, BCKT_NAME, key
+			))
+			.body(Body::empty())
+			.unwrap()
+	};
+
+	{
+		// Anonymous requests are refused until anonymous read access is enabled
+		let resp = client.request(anonymous("GET", "public/a")).await.unwrap();
+		assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+		ctx.garage
+			.command()
+			.args(["bucket", "anonymous-read", "--allow", BCKT_NAME])
+			.quiet()
+			.expect_success_status("Could not allow anonymous read on bucket");
+
+		let mut resp = client.request(anonymous("GET", "public/a")).await.unwrap();
+		assert_eq!(resp.status(), StatusCode::OK);
+		assert_eq!(
+			to_bytes(resp.body_mut()).await.unwrap().as_ref(),
+			BODY.as_ref()
+		);
+
+		let resp = client.request(anonymous("GET", "")).await.unwrap();
+		assert_eq!(resp.status(), StatusCode::OK);
+
+		// Anonymous requests can only read objects
+		let resp = client.request(anonymous("PUT", "public/d")).await.unwrap();
+		assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+		let resp = client
+			.request(anonymous("DELETE", "public/a"))
+			.await
+			.unwrap();
+		assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+	}
+
+	// The resources of a policy must belong to the bucket
+	assert!(ctx
+		.client
+		.put_bucket_policy()
+		.bucket(&bucket)
+		.policy(
+			r#"{"Statement": {"Effect": "Allow", "Principal": "*", "Action": "s3:GetObject",
+				"Resource": "arn:aws:s3:::otherbucket/*"}}"#
+		)
+		.send()
+		.await
+		.is_err());
+
+	let other_key = ctx.garage.new_key("policy-other");
+	let other_client = ctx.client_for_key(&other_key);
+
+	let policy = format!(
+		r#"{{
+  "Version": "2012-10-17",
+  "Statement": [
+    {{
+      "Effect": "Deny",
+      "Principal": "*",
+      "Action": "s3:GetObject",
+      "Resource": "arn:aws:s3:::{bucket}/private/*"
+    }},
+    {{
+      "Effect": "Allow",
+      "Principal": {{"AWS": "{key}"}},
+      "Action": "s3:GetObject",
+      "Resource": "arn:aws:s3:::{bucket}/shared/*"
+    }}
+  ]
+}}"#,
+		bucket = BCKT_NAME,
+		key = other_key.id
+	);
+	ctx.client
+		.put_bucket_policy()
+		.bucket(&bucket)
+		.policy(policy)
+		.send()
+		.await
+		.unwrap();
+
+	{
+		let r = ctx
+			.client
+			.get_bucket_policy()
+			.bucket(&bucket)
+			.send()
+			.await
+			.unwrap();
+		assert!(r.policy.unwrap().contains(&other_key.id));
+	}
+
+	{
+		// Statements that deny an action apply to all requests,
+		// including those signed with a key that has the permission
+		let resp = client.request(anonymous("GET", "private/b")).await.unwrap();
+		assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+		assert!(ctx
+			.client
+			.get_object()
+			.bucket(&bucket)
+			.key("private/b")
+			.send()
+			.await
+			.is_err());
+
+		let resp = client.request(anonymous("GET", "public/a")).await.unwrap();
+		assert_eq!(resp.status(), StatusCode::OK);
+	}
+
+	{
+		// Statements that allow an action give access to keys
+		// that have no permission on the bucket
+		let o = other_client
+			.get_object()
+			.bucket(&bucket)
+			.key("shared/c")
+			.send()
+			.await
+			.unwrap();
+		assert_bytes_eq!(o.body, BODY);
+
+		assert!(other_client
+			.get_object()
+			.bucket(&bucket)
+			.key("public/a")
+			.send()
+			.await
+			.is_err());
+		assert!(other_client
+			.put_object()
+			.bucket(&bucket)
+			.key("shared/d")
+			.body(ByteStream::from_static(BODY))
+			.send()
+			.await
+			.is_err());
+
+		// Keys that are not owners of the bucket cannot change its policy
+		assert!(other_client
+			.delete_bucket_policy()
+			.bucket(&bucket)
+			.send()
+			.await
+			.is_err());
+	}
+
+	ctx.client
+		.delete_bucket_policy()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+
+	{
+		let resp = client.request(anonymous("GET", "private/b")).await.unwrap();
+		assert_eq!(resp.status(), StatusCode::OK);
+		assert!(other_client
+			.get_object()
+			.bucket(&bucket)
+			.key("shared/c")
+			.send()
+			.await
+			.is_err());
+	}
+}
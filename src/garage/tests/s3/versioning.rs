@@ -0,0 +1,262 @@
+use crate::common;
+use aws_sdk_s3::model::{BucketVersioningStatus, VersioningConfiguration};
+use aws_sdk_s3::types::ByteStream;
+
+const STD_KEY: &str = "hello world";
+const BODY1: &[u8; 5] = b"first";
+const BODY2: &[u8; 6] = b"second";
+
+#[tokio::test]
+async fn test_versioning() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("versioning");
+
+	{
+		// Versioning is not enabled on new buckets
+		let r = ctx
+			.client
+			.get_bucket_versioning()
+			.bucket(&bucket)
+			.send()
+			.await
+			.unwrap();
+		assert!(r.status.is_none());
+	}
+
+	// An object written before versioning is enabled is the "null" version
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.body(ByteStream::from_static(BODY1))
+		.send()
+		.await
+		.unwrap();
+
+	ctx.client
+		.put_bucket_versioning()
+		.bucket(&bucket)
+		.versioning_configuration(
+			VersioningConfiguration::builder()
+				.status(BucketVersioningStatus::Enabled)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+
+	{
+		let r = ctx
+			.client
+			.get_bucket_versioning()
+			.bucket(&bucket)
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(r.status, Some(BucketVersioningStatus::Enabled));
+	}
+
+	let v2 = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.body(ByteStream::from_static(BODY2))
+		.send()
+		.await
+		.unwrap()
+		.version_id
+		.unwrap();
+	assert_ne!(v2, "null");
+
+	{
+		// The latest version is returned by default,
+		// older versions can be read with their version id
+		let o = ctx
+			.client
+			.get_object()
+			.bucket(&bucket)
+			.key(STD_KEY)
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(o.version_id.as_deref(), Some(v2.as_str()));
+		assert_bytes_eq!(o.body, BODY2);
+
+		let o = ctx
+			.client
+			.get_object()
+			.bucket(&bucket)
+			.key(STD_KEY)
+			.version_id("null")
+			.send()
+			.await
+			.unwrap();
+		assert_bytes_eq!(o.body, BODY1);
+	}
+
+	{
+		let r = ctx
+			.client
+			.list_object_versions()
+			.bucket(&bucket)
+			.send()
+			.await
+			.unwrap();
+		let versions = r.versions.unwrap();
+		assert_eq!(versions.len(), 2);
+		assert_eq!(versions[0].version_id.as_deref(), Some(v2.as_str()));
+		assert!(versions[0].is_latest);
+		assert_eq!(versions[1].version_id.as_deref(), Some("null"));
+		assert!(!versions[1].is_latest);
+		assert!(r.delete_markers.is_none());
+	}
+
+	// Deleting the object without a version id adds a delete marker
+	let dm = ctx
+		.client
+		.delete_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.send()
+		.await
+		.unwrap();
+	assert!(dm.delete_marker);
+	let dm = dm.version_id.unwrap();
+
+	{
+		assert!(ctx
+			.client
+			.get_object()
+			.bucket(&bucket)
+			.key(STD_KEY)
+			.send()
+			.await
+			.is_err());
+
+		let o = ctx
+			.client
+			.get_object()
+			.bucket(&bucket)
+			.key(STD_KEY)
+			.version_id(&v2)
+			.send()
+			.await
+			.unwrap();
+		assert_bytes_eq!(o.body, BODY2);
+
+		let r = ctx
+			.client
+			.list_object_versions()
+			.bucket(&bucket)
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(r.versions.unwrap().len(), 2);
+		let delete_markers = r.delete_markers.unwrap();
+		assert_eq!(delete_markers.len(), 1);
+		assert_eq!(delete_markers[0].version_id.as_deref(), Some(dm.as_str()));
+		assert!(delete_markers[0].is_latest);
+
+		let r = ctx
+			.client
+			.list_objects_v2()
+			.bucket(&bucket)
+			.send()
+			.await
+			.unwrap();
+		assert!(r.contents.is_none());
+	}
+
+	// Deleting the delete marker makes the previous version current again
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.version_id(&dm)
+		.send()
+		.await
+		.unwrap();
+
+	{
+		let o = ctx
+			.client
+			.get_object()
+			.bucket(&bucket)
+			.key(STD_KEY)
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(o.version_id.as_deref(), Some(v2.as_str()));
+		assert_bytes_eq!(o.body, BODY2);
+	}
+
+	// Deleting a version permanently removes it
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.version_id(&v2)
+		.send()
+		.await
+		.unwrap();
+
+	{
+		let o = ctx
+			.client
+			.get_object()
+			.bucket(&bucket)
+			.key(STD_KEY)
+			.send()
+			.await
+			.unwrap();
+		assert_bytes_eq!(o.body, BODY1);
+
+		assert!(ctx
+			.client
+			.get_object()
+			.bucket(&bucket)
+			.key(STD_KEY)
+			.version_id(&v2)
+			.send()
+			.await
+			.is_err());
+	}
+
+	{
+		// When versioning is suspended, new writes replace the "null" version
+		ctx.client
+			.put_bucket_versioning()
+			.bucket(&bucket)
+			.versioning_configuration(
+				VersioningConfiguration::builder()
+					.status(BucketVersioningStatus::Suspended)
+					.build(),
+			)
+			.send()
+			.await
+			.unwrap();
+
+		let r = ctx
+			.client
+			.put_object()
+			.bucket(&bucket)
+			.key(STD_KEY)
+			.body(ByteStream::from_static(BODY2))
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(r.version_id.as_deref(), Some("null"));
+
+		let r = ctx
+			.client
+			.list_object_versions()
+			.bucket(&bucket)
+			.send()
+			.await
+			.unwrap();
+		let versions = r.versions.unwrap();
+		assert_eq!(versions.len(), 1);
+		assert_eq!(versions[0].version_id.as_deref(), Some("null"));
+	}
+}
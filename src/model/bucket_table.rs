@@ -1,11 +1,16 @@
+use std::net::IpAddr;
+
 use serde::{Deserialize, Serialize};
 
+use garage_block::compression::Compression;
+use garage_block::manager::BlockReplication;
 use garage_table::crdt::*;
 use garage_table::*;
 use garage_util::data::*;
 use garage_util::time::*;
 
 use crate::permission::BucketKeyPerm;
+use crate::s3::object_table::ObjectLockMode;
 
 /// A bucket is a collection of objects
 ///
@@ -47,6 +52,47 @@ pub struct BucketParams {
 	/// Bucket quotas
 	#[serde(default)]
 	pub quotas: crdt::Lww<BucketQuotas>,
+	/// Versioning state of the bucket
+	#[serde(default)]
+	pub versioning: crdt::Lww<BucketVersioning>,
+	/// Lifecycle configuration (object expiration and
+	/// cleanup of incomplete multipart uploads)
+	#[serde(default)]
+	pub lifecycle_config: crdt::Lww<Option<Vec<LifecycleRule>>>,
+	/// Whether Object Lock is enabled on this bucket. Once it has been enabled,
+	/// it cannot be disabled and versioning cannot be suspended.
+	#[serde(default)]
+	pub object_lock_enabled: crdt::Bool,
+	/// Retention that is applied by default to new object versions
+	/// when Object Lock is enabled
+	#[serde(default)]
+	pub object_lock_default_retention: crdt::Lww<Option<ObjectLockDefaultRetention>>,
+	/// Bucket policy, evaluated in addition to the permissions
+	/// given to access keys in `authorized_keys`
+	#[serde(default)]
+	pub policy: crdt::Lww<Option<BucketPolicy>>,
+	/// Whether objects of this bucket can be read and listed through
+	/// the S3 API by anonymous requests (that are not signed with an access key)
+	#[serde(default)]
+	pub anonymous_read: crdt::Lww<bool>,
+	/// Compression of the data blocks of objects written to this bucket,
+	/// if None the compression configured on the node receiving them is used
+	#[serde(default)]
+	pub compression: crdt::Lww<Option<Compression>>,
+	/// Whether objects written to this bucket are split in blocks at
+	/// content-defined boundaries, so that identical data in different
+	/// objects is deduplicated even if it is not at the same offset
+	#[serde(default)]
+	pub content_defined_chunking: crdt::Lww<bool>,
+	/// Number of days after which data blocks of objects of this bucket that
+	/// have not been read are moved to cold storage, on nodes that have a
+	/// cold_data_dir. If None, they are never moved to cold storage.
+	#[serde(default)]
+	pub cold_storage_after: crdt::Lww<Option<u32>>,
+	/// Replication of the data blocks of objects written to this bucket,
+	/// if None the replication mode of the cluster is used
+	#[serde(default)]
+	pub replication: crdt::Lww<Option<BlockReplication>>,
 }
 
 #[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
@@ -65,6 +111,270 @@ pub struct CorsRule {
 	pub expose_headers: Vec<String>,
 }
 
+/// Lifecycle configuration rule
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+pub struct LifecycleRule {
+	/// The ID of the rule
+	pub id: Option<String>,
+	/// Whether the rule is active
+	pub enabled: bool,
+	/// The filter to check whether rule applies to a given object
+	pub filter: LifecycleFilter,
+	/// Number of days after which incomplete multipart uploads are aborted
+	pub abort_incomplete_mpu_days: Option<usize>,
+	/// Expiration policy for stored objects
+	pub expiration: Option<LifecycleExpiration>,
+}
+
+/// A lifecycle filter is a set of conditions that must all be true.
+/// For each condition, if it is None, it is not verified (always true),
+/// and if it is Some(x), then it is verified for value x
+#[derive(Default, PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+pub struct LifecycleFilter {
+	/// If Some(x), object key has to start with prefix x
+	pub prefix: Option<String>,
+	/// If Some(x), object size has to be more than x
+	pub size_gt: Option<u64>,
+	/// If Some(x), object size has to be less than x
+	pub size_lt: Option<u64>,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+pub enum LifecycleExpiration {
+	/// Objects expire x days after they were created
+	AfterDays(usize),
+	/// Objects expire at date x (must be in yyyy-mm-dd format)
+	AtDate(String),
+}
+
+impl LifecycleFilter {
+	/// Check whether the filter matches an object with the given key and size.
+	/// If the size is not known (e.g. for incomplete uploads), only the key is checked.
+	pub fn matches(&self, key: &str, size: Option<u64>) -> bool {
+		if let Some(prefix) = &self.prefix {
+			if !key.starts_with(prefix) {
+				return false;
+			}
+		}
+		if let Some(size) = size {
+			if let Some(size_gt) = self.size_gt {
+				if size <= size_gt {
+					return false;
+				}
+			}
+			if let Some(size_lt) = self.size_lt {
+				if size >= size_lt {
+					return false;
+				}
+			}
+		}
+		true
+	}
+}
+
+/// Default Object Lock retention of a bucket
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+pub struct ObjectLockDefaultRetention {
+	/// Retention mode applied to new object versions
+	pub mode: ObjectLockMode,
+	/// Retention period of new object versions
+	pub period: ObjectLockPeriod,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum ObjectLockPeriod {
+	Days(u32),
+	Years(u32),
+}
+
+impl ObjectLockPeriod {
+	/// Duration of the retention period in milliseconds.
+	/// As in S3, a year is counted as 365 days.
+	pub fn duration_msec(&self) -> u64 {
+		const DAY_MSEC: u64 = 24 * 3600 * 1000;
+		match self {
+			Self::Days(d) => *d as u64 * DAY_MSEC,
+			Self::Years(y) => *y as u64 * 365 * DAY_MSEC,
+		}
+	}
+}
+
+/// Bucket policy, made of statements that allow or deny S3 actions
+/// to access keys, on the bucket or on some of its objects
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+pub struct BucketPolicy {
+	/// The ID of the policy
+	pub id: Option<String>,
+	pub statements: Vec<PolicyStatement>,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+pub struct PolicyStatement {
+	/// The ID of the statement
+	pub sid: Option<String>,
+	/// Whether matching requests are allowed or denied
+	pub effect: PolicyEffect,
+	/// Access key IDs the statement applies to, None for all keys
+	pub principals: Option<Vec<String>>,
+	/// Action names (e.g. `s3:GetObject`), that can contain `*` and `?` wildcards
+	pub actions: Vec<String>,
+	/// Resources the statement applies to
+	pub resources: Vec<PolicyResource>,
+	/// Conditions on the IP address of the client
+	pub source_ip: PolicyIpCondition,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum PolicyEffect {
+	Allow,
+	Deny,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+pub enum PolicyResource {
+	/// The bucket itself, for bucket-level actions such as ListBucket
+	Bucket,
+	/// Objects whose key matches a pattern, that can contain `*` and `?` wildcards
+	Objects(String),
+}
+
+/// Condition on the source IP address of a request.
+/// If `allow` is non-empty, the address has to be in one of its ranges,
+/// and it must not be in any of the ranges in `deny`.
+#[derive(Default, PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+pub struct PolicyIpCondition {
+	pub allow: Vec<IpRange>,
+	pub deny: Vec<IpRange>,
+}
+
+/// An IP address range in CIDR notation
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct IpRange {
+	pub addr: IpAddr,
+	pub prefix_len: u8,
+}
+
+impl BucketPolicy {
+	/// Evaluate the policy for a request made by access key `key_id` from
+	/// address `source_ip`, to perform `action` on object `key`
+	/// (or on the bucket itself if `key` is None).
+	/// Anonymous requests (with no access key) only match statements
+	/// that apply to all principals.
+	/// Returns the effect of the matching statements, Deny having precedence
+	/// over Allow, or None if no statement matches the request.
+	pub fn evaluate(
+		&self,
+		key_id: Option<&str>,
+		source_ip: IpAddr,
+		action: &str,
+		key: Option<&str>,
+	) -> Option<PolicyEffect> {
+		self.statements
+			.iter()
+			.filter(|st| st.matches(key_id, source_ip, action, key))
+			.map(|st| st.effect)
+			.max()
+	}
+}
+
+impl PolicyStatement {
+	pub fn matches(
+		&self,
+		key_id: Option<&str>,
+		source_ip: IpAddr,
+		action: &str,
+		key: Option<&str>,
+	) -> bool {
+		if let Some(principals) = &self.principals {
+			if !principals.iter().any(|p| Some(p.as_str()) == key_id) {
+				return false;
+			}
+		}
+		if !self
+			.actions
+			.iter()
+			.any(|a| wildcard_match(&a.to_lowercase(), &action.to_lowercase()))
+		{
+			return false;
+		}
+		let resource_matches = self.resources.iter().any(|r| match (r, key) {
+			(PolicyResource::Bucket, None) => true,
+			(PolicyResource::Objects(pattern), Some(key)) => wildcard_match(pattern, key),
+			_ => false,
+		});
+		resource_matches && self.source_ip.matches(source_ip)
+	}
+}
+
+impl PolicyIpCondition {
+	pub fn matches(&self, ip: IpAddr) -> bool {
+		(self.allow.is_empty() || self.allow.iter().any(|r| r.contains(ip)))
+			&& !self.deny.iter().any(|r| r.contains(ip))
+	}
+}
+
+impl IpRange {
+	pub fn contains(&self, ip: IpAddr) -> bool {
+		let ip = match (self.addr, ip) {
+			// IPv4 clients of a dual-stack socket appear as IPv4-mapped IPv6 addresses
+			(IpAddr::V4(_), IpAddr::V6(v6)) => match v6.to_ipv4_mapped() {
+				Some(v4) => IpAddr::V4(v4),
+				None => return false,
+			},
+			_ => ip,
+		};
+		match (self.addr, ip) {
+			(IpAddr::V4(range), IpAddr::V4(ip)) => {
+				prefix_match(&range.octets(), &ip.octets(), self.prefix_len)
+			}
+			(IpAddr::V6(range), IpAddr::V6(ip)) => {
+				prefix_match(&range.octets(), &ip.octets(), self.prefix_len)
+			}
+			_ => false,
+		}
+	}
+}
+
+fn prefix_match(a: &[u8], b: &[u8], prefix_len: u8) -> bool {
+	let full_bytes = prefix_len as usize / 8;
+	let rem_bits = prefix_len % 8;
+	if a[..full_bytes] != b[..full_bytes] {
+		return false;
+	}
+	if rem_bits > 0 {
+		let mask = 0xffu8 << (8 - rem_bits);
+		a[full_bytes] & mask == b[full_bytes] & mask
+	} else {
+		true
+	}
+}
+
+/// Match a string against a pattern where `*` matches any sequence of
+/// characters and `?` matches exactly one character
+pub fn wildcard_match(pattern: &str, s: &str) -> bool {
+	let p = pattern.chars().collect::<Vec<_>>();
+	let s = s.chars().collect::<Vec<_>>();
+
+	let (mut pi, mut si) = (0, 0);
+	let mut backtrack = None;
+	while si < s.len() {
+		if pi < p.len() && (p[pi] == '?' || p[pi] == s[si]) {
+			pi += 1;
+			si += 1;
+		} else if pi < p.len() && p[pi] == '*' {
+			backtrack = Some((pi, si));
+			pi += 1;
+		} else if let Some((bp, bs)) = backtrack {
+			pi = bp + 1;
+			si = bs + 1;
+			backtrack = Some((bp, bs + 1));
+		} else {
+			return false;
+		}
+	}
+	p[pi..].iter().all(|c| *c == '*')
+}
+
 #[derive(Default, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
 pub struct BucketQuotas {
 	/// Maximum size in bytes (bucket size = sum of sizes of objects in the bucket)
@@ -77,6 +387,28 @@ impl AutoCrdt for BucketQuotas {
 	const WARN_IF_DIFFERENT: bool = true;
 }
 
+/// Versioning state of a bucket, as set by PutBucketVersioning
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum BucketVersioning {
+	/// Versioning has never been enabled on this bucket
+	Unversioned,
+	/// New versions are kept alongside previous versions
+	Enabled,
+	/// Versioning has been enabled previously, but new versions replace
+	/// the previous unversioned version
+	Suspended,
+}
+
+impl Default for BucketVersioning {
+	fn default() -> Self {
+		Self::Unversioned
+	}
+}
+
+impl AutoCrdt for BucketVersioning {
+	const WARN_IF_DIFFERENT: bool = true;
+}
+
 impl BucketParams {
 	/// Create an empty BucketParams with no authorized keys and no website accesss
 	pub fn new() -> Self {
@@ -88,8 +420,24 @@ impl BucketParams {
 			website_config: crdt::Lww::new(None),
 			cors_config: crdt::Lww::new(None),
 			quotas: crdt::Lww::new(BucketQuotas::default()),
+			versioning: crdt::Lww::new(BucketVersioning::default()),
+			lifecycle_config: crdt::Lww::new(None),
+			object_lock_enabled: crdt::Bool::new(false),
+			object_lock_default_retention: crdt::Lww::new(None),
+			policy: crdt::Lww::new(None),
+			anonymous_read: crdt::Lww::new(false),
+			compression: crdt::Lww::new(None),
+			content_defined_chunking: crdt::Lww::new(false),
+			cold_storage_after: crdt::Lww::new(None),
+			replication: crdt::Lww::new(None),
 		}
 	}
+
+	/// Whether new object versions written in this bucket should be kept
+	/// when even newer versions are written
+	pub fn versioning_enabled(&self) -> bool {
+		*self.versioning.get() == BucketVersioning::Enabled
+	}
 }
 
 impl Crdt for BucketParams {
@@ -103,6 +451,18 @@ impl Crdt for BucketParams {
 		self.website_config.merge(&o.website_config);
 		self.cors_config.merge(&o.cors_config);
 		self.quotas.merge(&o.quotas);
+		self.versioning.merge(&o.versioning);
+		self.lifecycle_config.merge(&o.lifecycle_config);
+		self.object_lock_enabled.merge(&o.object_lock_enabled);
+		self.object_lock_default_retention
+			.merge(&o.object_lock_default_retention);
+		self.policy.merge(&o.policy);
+		self.anonymous_read.merge(&o.anonymous_read);
+		self.compression.merge(&o.compression);
+		self.content_defined_chunking
+			.merge(&o.content_defined_chunking);
+		self.cold_storage_after.merge(&o.cold_storage_after);
+		self.replication.merge(&o.replication);
 	}
 }
 
@@ -159,6 +519,12 @@ impl Bucket {
 			.map(|s| s.local_aliases.items())
 			.unwrap_or(&[])
 	}
+
+	/// Replication of the data blocks of this bucket,
+	/// None if it uses the replication mode of the cluster
+	pub fn block_replication(&self) -> Option<BlockReplication> {
+		self.params().and_then(|p| *p.replication.get())
+	}
 }
 
 impl Entry<EmptyKey, Uuid> for Bucket {
@@ -1,4 +1,5 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use netapp::NetworkKey;
 
@@ -8,15 +9,22 @@ use garage_util::background::*;
 use garage_util::config::*;
 use garage_util::error::*;
 
+use garage_rpc::ring::MAX_REPLICATION;
 use garage_rpc::system::System;
 
+use garage_block::compression::Compression;
+use garage_block::erasure::ErasureCoding;
+use garage_block::io::BlockIoOptions;
 use garage_block::manager::*;
+use garage_table::change_log::*;
 use garage_table::replication::ReplicationMode;
 use garage_table::replication::TableFullReplication;
+use garage_table::replication::TableReplication;
 use garage_table::replication::TableShardedReplication;
 use garage_table::*;
 
 use crate::s3::block_ref_table::*;
+use crate::s3::lifecycle_worker;
 use crate::s3::object_table::*;
 use crate::s3::version_table::*;
 
@@ -61,6 +69,9 @@ pub struct Garage {
 
 	#[cfg(feature = "k2v")]
 	pub k2v: GarageK2V,
+
+	/// Log of the changes made to the tables of this node
+	pub change_log: Arc<ChangeLog>,
 }
 
 #[cfg(feature = "k2v")]
@@ -79,81 +90,20 @@ impl Garage {
 		// Create meta dir and data dir if they don't exist already
 		std::fs::create_dir_all(&config.metadata_dir)
 			.ok_or_message("Unable to create Garage metadata directory")?;
-		std::fs::create_dir_all(&config.data_dir)
-			.ok_or_message("Unable to create Garage data directory")?;
-
-		info!("Opening database...");
-		let mut db_path = config.metadata_dir.clone();
-		let db = match config.db_engine.as_str() {
-			// ---- Sled DB ----
-			#[cfg(feature = "sled")]
-			"sled" => {
-				db_path.push("db");
-				info!("Opening Sled database at: {}", db_path.display());
-				let db = db::sled_adapter::sled::Config::default()
-					.path(&db_path)
-					.cache_capacity(config.sled_cache_capacity)
-					.flush_every_ms(Some(config.sled_flush_every_ms))
-					.open()
-					.expect("Unable to open sled DB");
-				db::sled_adapter::SledDb::init(db)
-			}
-			#[cfg(not(feature = "sled"))]
-			"sled" => return Err(Error::Message("sled db not available in this build".into())),
-			// ---- Sqlite DB ----
-			#[cfg(feature = "sqlite")]
-			"sqlite" | "sqlite3" | "rusqlite" => {
-				db_path.push("db.sqlite");
-				info!("Opening Sqlite database at: {}", db_path.display());
-				let db = db::sqlite_adapter::rusqlite::Connection::open(db_path)
-					.expect("Unable to open sqlite DB");
-				db::sqlite_adapter::SqliteDb::init(db)
-			}
-			#[cfg(not(feature = "sqlite"))]
-			"sqlite" | "sqlite3" | "rusqlite" => {
-				return Err(Error::Message(
-					"sqlite db not available in this build".into(),
-				))
+		match &config.data_dir {
+			DataDirEnum::Single(data_dir) => {
+				std::fs::create_dir_all(data_dir)
+					.ok_or_message("Unable to create Garage data directory")?;
 			}
-			// ---- LMDB DB ----
-			#[cfg(feature = "lmdb")]
-			"lmdb" | "heed" => {
-				db_path.push("db.lmdb");
-				info!("Opening LMDB database at: {}", db_path.display());
-				std::fs::create_dir_all(&db_path).expect("Unable to create LMDB data directory");
-				let map_size = garage_db::lmdb_adapter::recommended_map_size();
-
-				use db::lmdb_adapter::heed;
-				let mut env_builder = heed::EnvOpenOptions::new();
-				env_builder.max_dbs(100);
-				env_builder.max_readers(500);
-				env_builder.map_size(map_size);
-				unsafe {
-					env_builder.flag(heed::flags::Flags::MdbNoSync);
-					env_builder.flag(heed::flags::Flags::MdbNoMetaSync);
+			DataDirEnum::Multiple(data_dirs) => {
+				for dir in data_dirs.iter().filter(|dir| !dir.read_only) {
+					std::fs::create_dir_all(&dir.path)
+						.ok_or_message("Unable to create Garage data directory")?;
 				}
-				let db = env_builder.open(&db_path).expect("Unable to open LMDB DB");
-				db::lmdb_adapter::LmdbDb::init(db)
 			}
-			#[cfg(not(feature = "lmdb"))]
-			"lmdb" | "heed" => return Err(Error::Message("lmdb db not available in this build".into())),
-			// ---- Unavailable DB engine ----
-			e => {
-				return Err(Error::Message(format!(
-					"Unsupported DB engine: {} (options: {})",
-					e,
-					vec![
-						#[cfg(feature = "sled")]
-						"sled",
-						#[cfg(feature = "sqlite")]
-						"sqlite",
-						#[cfg(feature = "lmdb")]
-						"lmdb",
-					]
-					.join(", ")
-				)));
-			}
-		};
+		}
+
+		let db = open_db(&config)?;
 
 		let network_key = NetworkKey::from_slice(
 			&hex::decode(&config.rpc_secret).expect("Invalid RPC secret key")[..],
@@ -163,19 +113,52 @@ impl Garage {
 		let replication_mode = ReplicationMode::parse(&config.replication_mode)
 			.expect("Invalid replication_mode in config file.");
 
+		let erasure_coding = match &config.erasure_coding {
+			Some(ec) => Some(
+				ErasureCoding::new(ec.data_shards, ec.parity_shards)
+					.ok_or_message("Invalid erasure_coding in config file")?,
+			),
+			None => None,
+		};
+		// With erasure coding, each partition of the ring must have enough nodes
+		// to store all shards of a block, and without it, enough nodes to store
+		// all copies required by buckets. Metadata is stored on the first
+		// nodes of each partition.
+		let ring_replication_factor = match &erasure_coding {
+			Some(ec) => std::cmp::max(ec.total_shards(), replication_mode.replication_factor()),
+			None => std::cmp::max(
+				config.max_replication_factor.unwrap_or(0),
+				replication_mode.replication_factor(),
+			),
+		};
+		if ring_replication_factor > MAX_REPLICATION {
+			return Err(Error::Message(format!(
+				"Invalid max_replication_factor in config file: at most {} copies are supported",
+				MAX_REPLICATION
+			)));
+		}
+
 		info!("Initialize membership management system...");
 		let system = System::new(
 			network_key,
 			background.clone(),
-			replication_mode.replication_factor(),
+			ring_replication_factor,
 			&config,
 		)?;
 
-		let data_rep_param = TableShardedReplication {
-			system: system.clone(),
-			replication_factor: replication_mode.replication_factor(),
-			write_quorum: replication_mode.write_quorum(),
-			read_quorum: 1,
+		let data_rep_param = match &erasure_coding {
+			Some(ec) => TableShardedReplication {
+				system: system.clone(),
+				replication_factor: ec.total_shards(),
+				write_quorum: ec.write_quorum(),
+				read_quorum: ec.data_shards(),
+			},
+			None => TableShardedReplication {
+				system: system.clone(),
+				replication_factor: replication_mode.replication_factor(),
+				write_quorum: replication_mode.write_quorum(),
+				read_quorum: 1,
+			},
 		};
 
 		let meta_rep_param = TableShardedReplication {
@@ -185,19 +168,53 @@ impl Garage {
 			read_quorum: replication_mode.read_quorum(),
 		};
 
+		// Block references must be stored on all nodes that store a shard or
+		// a copy of the block, so that these nodes know that they need to keep it
+		let block_ref_rep_param = match &erasure_coding {
+			Some(ec) => TableShardedReplication {
+				system: system.clone(),
+				replication_factor: ec.total_shards(),
+				write_quorum: ec.write_quorum(),
+				read_quorum: ec.total_shards() - ec.write_quorum() + 1,
+			},
+			None if ring_replication_factor > replication_mode.replication_factor() => {
+				TableShardedReplication {
+					system: system.clone(),
+					replication_factor: ring_replication_factor,
+					write_quorum: replication_mode.write_quorum(),
+					read_quorum: ring_replication_factor - replication_mode.write_quorum() + 1,
+				}
+			}
+			None => meta_rep_param.clone(),
+		};
+
 		let control_rep_param = TableFullReplication {
 			system: system.clone(),
 			max_faults: replication_mode.control_write_max_faults(),
 		};
 
+		let compression = match (config.compression_codec, config.compression_level) {
+			(_, None) => Compression::None,
+			(CompressionCodecConfig::Zstd, Some(level)) => Compression::Zstd(level),
+			(CompressionCodecConfig::Lz4, Some(_)) => Compression::Lz4,
+		};
+
 		info!("Initialize block manager...");
 		let block_manager = BlockManager::new(
 			&db,
-			config.data_dir.clone(),
-			config.compression_level,
+			&config.data_dir,
+			config.cold_data_dir.clone(),
+			compression,
+			erasure_coding,
+			config.block_cache_size.as_deref(),
+			BlockIoOptions {
+				direct_io: config.block_direct_io,
+				preallocate: config.block_preallocate,
+				fadvise_dontneed: config.block_fadvise_dontneed,
+			},
 			data_rep_param,
 			system.clone(),
-		);
+		)?;
 
 		// ---- admin tables ----
 		info!("Initialize bucket_table...");
@@ -219,7 +236,7 @@ impl Garage {
 			BlockRefTable {
 				block_manager: block_manager.clone(),
 			},
-			meta_rep_param.clone(),
+			block_ref_rep_param,
 			system.clone(),
 			&db,
 		);
@@ -255,6 +272,45 @@ impl Garage {
 		#[cfg(feature = "k2v")]
 		let k2v = GarageK2V::new(system.clone(), &db, meta_rep_param);
 
+		// -- anti-entropy parameters and change log --
+		let change_log_retention = config
+			.change_log
+			.retention_secs
+			.map(Duration::from_secs)
+			.unwrap_or(DEFAULT_CHANGE_LOG_RETENTION);
+		let change_log = ChangeLog::new(&db, change_log_retention, &background);
+
+		#[allow(unused_mut)]
+		let mut table_names = vec![
+			configure_table(&bucket_table, &config, &change_log),
+			configure_table(&bucket_alias_table, &config, &change_log),
+			configure_table(&key_table, &config, &change_log),
+			configure_table(&block_ref_table, &config, &change_log),
+			configure_table(&version_table, &config, &change_log),
+			configure_table(&object_counter_table.table, &config, &change_log),
+			configure_table(&object_table, &config, &change_log),
+		];
+		#[cfg(feature = "k2v")]
+		{
+			table_names.push(configure_table(
+				&k2v.counter_table.table,
+				&config,
+				&change_log,
+			));
+			table_names.push(configure_table(&k2v.item_table, &config, &change_log));
+		}
+		if let Some(t) = config
+			.change_log
+			.tables
+			.iter()
+			.find(|t| !table_names.contains(&t.as_str()))
+		{
+			return Err(Error::Message(format!(
+				"Invalid table in change_log.tables in config file: {}",
+				t
+			)));
+		}
+
 		// -- done --
 		Ok(Arc::new(Self {
 			config,
@@ -271,9 +327,20 @@ impl Garage {
 			block_ref_table,
 			#[cfg(feature = "k2v")]
 			k2v,
+			change_log,
 		}))
 	}
 
+	/// Launch the background workers that need a reference to the full Garage struct
+	pub fn spawn_workers(self: &Arc<Self>) {
+		self.background
+			.spawn_worker(lifecycle_worker::LifecycleWorker::new(self.clone()));
+		if self.block_manager.has_cold_storage() {
+			self.background
+				.spawn_worker(cold_storage_worker::ColdStorageWorker::new(self.clone()));
+		}
+	}
+
 	pub fn bucket_helper(&self) -> helper::bucket::BucketHelper {
 		helper::bucket::BucketHelper(self)
 	}
@@ -283,6 +350,99 @@ impl Garage {
 	}
 }
 
+/// Open the metadata database of this node, with the engine given in the configuration
+pub fn open_db(config: &Config) -> Result<db::Db, Error> {
+	info!("Opening database...");
+	let mut db_path = config.metadata_dir.clone();
+	let db = match config.db_engine.as_str() {
+		// ---- Sled DB ----
+		#[cfg(feature = "sled")]
+		"sled" => {
+			db_path.push("db");
+			info!("Opening Sled database at: {}", db_path.display());
+			let db = db::sled_adapter::sled::Config::default()
+				.path(&db_path)
+				.cache_capacity(config.sled_cache_capacity)
+				.flush_every_ms(Some(config.sled_flush_every_ms))
+				.open()
+				.expect("Unable to open sled DB");
+			db::sled_adapter::SledDb::init(db)
+		}
+		#[cfg(not(feature = "sled"))]
+		"sled" => return Err(Error::Message("sled db not available in this build".into())),
+		// ---- Sqlite DB ----
+		#[cfg(feature = "sqlite")]
+		"sqlite" | "sqlite3" | "rusqlite" => {
+			db_path.push("db.sqlite");
+			info!("Opening Sqlite database at: {}", db_path.display());
+			let db = db::sqlite_adapter::rusqlite::Connection::open(db_path)
+				.expect("Unable to open sqlite DB");
+			db::sqlite_adapter::SqliteDb::init(db)
+		}
+		#[cfg(not(feature = "sqlite"))]
+		"sqlite" | "sqlite3" | "rusqlite" => {
+			return Err(Error::Message(
+				"sqlite db not available in this build".into(),
+			))
+		}
+		// ---- LMDB DB ----
+		#[cfg(feature = "lmdb")]
+		"lmdb" | "heed" => {
+			db_path.push("db.lmdb");
+			info!("Opening LMDB database at: {}", db_path.display());
+			std::fs::create_dir_all(&db_path).expect("Unable to create LMDB data directory");
+			let map_size = garage_db::lmdb_adapter::recommended_map_size();
+
+			use db::lmdb_adapter::heed;
+			let mut env_builder = heed::EnvOpenOptions::new();
+			env_builder.max_dbs(100);
+			env_builder.max_readers(500);
+			env_builder.map_size(map_size);
+			unsafe {
+				env_builder.flag(heed::flags::Flags::MdbNoSync);
+				env_builder.flag(heed::flags::Flags::MdbNoMetaSync);
+			}
+			let db = env_builder.open(&db_path).expect("Unable to open LMDB DB");
+			db::lmdb_adapter::LmdbDb::init(db)
+		}
+		#[cfg(not(feature = "lmdb"))]
+		"lmdb" | "heed" => return Err(Error::Message("lmdb db not available in this build".into())),
+		// ---- Unavailable DB engine ----
+		e => {
+			return Err(Error::Message(format!(
+				"Unsupported DB engine: {} (options: {})",
+				e,
+				vec![
+					#[cfg(feature = "sled")]
+					"sled",
+					#[cfg(feature = "sqlite")]
+					"sqlite",
+					#[cfg(feature = "lmdb")]
+					"lmdb",
+				]
+				.join(", ")
+			)));
+		}
+	};
+	Ok(db)
+}
+
+/// Apply the anti-entropy parameters and the change log settings
+/// of the config file to a table, and return its name
+fn configure_table<F: TableSchema + 'static, R: TableReplication + 'static>(
+	table: &Table<F, R>,
+	config: &Config,
+	change_log: &Arc<ChangeLog>,
+) -> &'static str {
+	table
+		.syncer
+		.set_params(SyncParams::from_config(&config.table_sync, F::TABLE_NAME));
+	if config.change_log.tables.iter().any(|t| t == F::TABLE_NAME) {
+		table.data.set_change_log(change_log.clone());
+	}
+	F::TABLE_NAME
+}
+
 #[cfg(feature = "k2v")]
 impl GarageK2V {
 	fn new(system: Arc<System>, db: &db::Db, meta_rep_param: TableShardedReplication) -> Self {
@@ -5,8 +5,11 @@ use garage_util::data::*;
 use garage_util::error::{Error as GarageError, OkOrMessage};
 use garage_util::time::*;
 
+use garage_table::replication::ReplicationMode;
 use garage_table::util::*;
 
+use garage_block::manager::BlockReplication;
+
 use crate::bucket_alias_table::*;
 use crate::bucket_table::*;
 use crate::garage::Garage;
@@ -71,6 +74,30 @@ impl<'a> BucketHelper<'a> {
 		}
 	}
 
+	/// Parse the replication of the data blocks of a bucket, given with the
+	/// same syntax as replication_mode in the configuration file,
+	/// and check that it can be used in this cluster
+	pub fn parse_block_replication(&self, mode: &str) -> Result<BlockReplication, Error> {
+		let mode = ReplicationMode::parse(mode)
+			.ok_or_bad_request(format!("Invalid replication mode: {}", mode))?;
+		let block_manager = &self.0.block_manager;
+		if block_manager.has_erasure_coding() {
+			return Err(Error::BadRequest(
+				"Replication of buckets cannot be set when erasure coding is enabled".to_string(),
+			));
+		}
+		if mode.replication_factor() > block_manager.max_replication_factor() {
+			return Err(Error::BadRequest(format!(
+				"Buckets cannot require more than {} copies of their data, see max_replication_factor",
+				block_manager.max_replication_factor()
+			)));
+		}
+		Ok(BlockReplication {
+			replication_factor: mode.replication_factor(),
+			write_quorum: mode.write_quorum(),
+		})
+	}
+
 	/// Returns a Bucket if it is present in bucket table,
 	/// even if it is in deleted state. Querying a non-existing
 	/// bucket ID returns an internal error.
@@ -439,7 +466,7 @@ impl<'a> BucketHelper<'a> {
 			.get_range(
 				&bucket_id,
 				None,
-				Some(ObjectFilter::IsData),
+				Some(ObjectFilter::HasVersions),
 				10,
 				EnumerationOrder::Forward,
 			)
@@ -513,6 +540,10 @@ impl<'a> BucketHelper<'a> {
 							state: ObjectVersionState::Aborted,
 							uuid: v.uuid,
 							timestamp: v.timestamp,
+							versioned: v.versioned,
+							tags: v.tags.clone(),
+							lock: v.lock.clone(),
+							conditional_write: v.conditional_write,
 						})
 						.collect::<Vec<_>>();
 					if !aborted_versions.is_empty() {
@@ -5,7 +5,7 @@ use garage_util::error::OkOrMessage;
 use crate::garage::Garage;
 use crate::helper::bucket::BucketHelper;
 use crate::helper::error::*;
-use crate::key_table::{Key, KeyFilter};
+use crate::key_table::{Key, KeyFilter, KEY_NAME_INDEX};
 use crate::permission::BucketKeyPerm;
 
 pub struct KeyHelper<'a>(pub(crate) &'a Garage);
@@ -43,19 +43,47 @@ impl<'a> KeyHelper<'a> {
 	/// Querying a non-existing key ID or a deleted key
 	/// returns a bad request error.
 	pub async fn get_existing_matching_key(&self, pattern: &str) -> Result<Key, Error> {
-		let candidates = self
+		let mut candidates = self
 			.0
 			.key_table
-			.get_range(
-				&EmptyKey,
+			.get_index_range(
+				KEY_NAME_INDEX,
+				pattern.to_lowercase().as_bytes(),
 				None,
 				Some(KeyFilter::MatchesAndNotDeleted(pattern.to_string())),
 				10,
+			)
+			.await?;
+
+		// Keys whose ID starts with the pattern are next to each other in the
+		// table, starting at the pattern. Key IDs are GK followed by lowercase
+		// hexadecimal characters, the pattern is normalized in the same way.
+		let id_prefix = match pattern.get(..2) {
+			Some(gk) if gk.eq_ignore_ascii_case("gk") => {
+				format!("GK{}", pattern[2..].to_lowercase())
+			}
+			_ => pattern.to_string(),
+		};
+		let by_id = self
+			.0
+			.key_table
+			.get_range(
+				&EmptyKey,
+				Some(id_prefix.clone()),
+				Some(KeyFilter::Deleted(DeletedFilter::NotDeleted)),
+				10,
 				EnumerationOrder::Forward,
 			)
-			.await?
+			.await?;
+		for key in by_id
 			.into_iter()
-			.collect::<Vec<_>>();
+			.take_while(|k| k.key_id.starts_with(&id_prefix))
+		{
+			if !candidates.iter().any(|k| k.key_id == key.key_id) {
+				candidates.push(key);
+			}
+		}
+
 		if candidates.len() != 1 {
 			Err(Error::BadRequest(format!(
 				"{} matching keys",
@@ -144,6 +144,9 @@ impl Crdt for Key {
 
 pub struct KeyTable;
 
+/// Index of the keys by their name, in lower case
+pub const KEY_NAME_INDEX: &str = "name";
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum KeyFilter {
 	Deleted(DeletedFilter),
@@ -158,6 +161,15 @@ impl TableSchema for KeyTable {
 	type E = Key;
 	type Filter = KeyFilter;
 
+	const INDEXES: &'static [&'static str] = &[KEY_NAME_INDEX];
+
+	fn index_keys(index: &str, entry: &Self::E) -> Vec<Vec<u8>> {
+		match (index, entry.params()) {
+			(KEY_NAME_INDEX, Some(p)) => vec![p.name.get().to_lowercase().into_bytes()],
+			_ => vec![],
+		}
+	}
+
 	fn matches_filter(entry: &Self::E, filter: &Self::Filter) -> bool {
 		match filter {
 			KeyFilter::Deleted(df) => df.apply(entry.state.is_deleted()),
@@ -198,3 +210,20 @@ impl TableSchema for KeyTable {
 		})
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_key_name_index() {
+		let mut key = Key::new("My Key");
+		assert_eq!(
+			KeyTable::index_keys(KEY_NAME_INDEX, &key),
+			vec![b"my key".to_vec()]
+		);
+
+		key.state = crdt::Deletable::Deleted;
+		assert!(KeyTable::index_keys(KEY_NAME_INDEX, &key).is_empty());
+	}
+}
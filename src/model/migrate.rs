@@ -78,6 +78,16 @@ impl Migrate {
 					website_config: Lww::new(website),
 					cors_config: Lww::new(None),
 					quotas: Lww::new(Default::default()),
+					versioning: Lww::new(Default::default()),
+					lifecycle_config: Lww::new(None),
+					object_lock_enabled: Bool::new(false),
+					object_lock_default_retention: Lww::new(None),
+					policy: Lww::new(None),
+					anonymous_read: Lww::new(false),
+					compression: Lww::new(None),
+					content_defined_chunking: Lww::new(false),
+					cold_storage_after: Lww::new(None),
+					replication: Lww::new(None),
 				}),
 			})
 			.await?;
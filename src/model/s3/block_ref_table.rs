@@ -21,6 +21,11 @@ pub struct BlockRef {
 	// Keep track of deleted status
 	/// Is the Version that contains this block deleted
 	pub deleted: crdt::Bool,
+
+	/// Number of nodes on which the block must be stored, if the bucket of
+	/// the Version does not use the default replication factor
+	#[serde(default)]
+	pub replication_factor: Option<usize>,
 }
 
 impl Entry<Hash, Uuid> for BlockRef {
@@ -38,6 +43,7 @@ impl Entry<Hash, Uuid> for BlockRef {
 impl Crdt for BlockRef {
 	fn merge(&mut self, other: &Self) {
 		self.deleted.merge(&other.deleted);
+		self.replication_factor = std::cmp::max(self.replication_factor, other.replication_factor);
 	}
 }
 
@@ -63,7 +69,9 @@ impl TableSchema for BlockRefTable {
 		let was_before = old.map(|x| !x.deleted.get()).unwrap_or(false);
 		let is_after = new.map(|x| !x.deleted.get()).unwrap_or(false);
 		if is_after && !was_before {
-			self.block_manager.block_incref(tx, block)?;
+			let replication_factor = new.and_then(|x| x.replication_factor);
+			self.block_manager
+				.block_incref(tx, block, replication_factor)?;
 		}
 		if was_before && !is_after {
 			self.block_manager.block_decref(tx, block)?;
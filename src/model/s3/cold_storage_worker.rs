@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use garage_util::background::*;
+use garage_util::data::*;
+use garage_util::error::Error;
+use garage_util::persister::Persister;
+use garage_util::time::*;
+use garage_util::tranquilizer::Tranquilizer;
+
+use garage_table::*;
+
+use crate::s3::block_ref_table::*;
+
+use crate::garage::Garage;
+
+/// Interval between the start of two passes over the blocks stored on this node
+const PASS_INTERVAL: Duration = Duration::from_secs(24 * 3600);
+/// Tranquility of the worker when it moves blocks between directories
+const COLD_STORAGE_TRANQUILITY: u32 = 2;
+
+/// Number of references to a block that are read at once
+const BLOCK_REFS_BATCH_SIZE: usize = 1000;
+/// Number of versions whose bucket is fetched at the same time
+const VERSION_FETCH_CONCURRENCY: usize = 16;
+/// Maximum number of versions whose bucket is kept in memory during a pass
+const VERSION_CACHE_SIZE: usize = 100_000;
+
+const DAY_MSEC: u64 = 24 * 3600 * 1000;
+
+/// Background worker that moves the data blocks stored on this node to
+/// the cold storage directory when they have not been read for the number
+/// of days configured on their buckets, and back to the data directories
+/// when they are read again. A pass over the block references stored
+/// locally is made once a day.
+///
+/// As a block can be shared by objects of several buckets, it is moved
+/// to cold storage only once the delays of all of these buckets are expired.
+pub struct ColdStorageWorker {
+	garage: Arc<Garage>,
+
+	state: State,
+	tranquilizer: Tranquilizer,
+
+	persister: Persister<ColdStorageWorkerPersisted>,
+}
+
+enum State {
+	Completed(u64),
+	Running {
+		started: u64,
+		pos: Vec<u8>,
+		/// Delay after which the blocks of each bucket are moved to cold storage
+		bucket_delays: HashMap<Uuid, Option<u64>>,
+		/// Shortest of these delays
+		min_delay: Option<u64>,
+		/// Bucket of the versions that reference the blocks, as the blocks
+		/// of an object are usually scanned one after the other
+		version_buckets: HashMap<Uuid, Uuid>,
+		counter: usize,
+		moved_cold: usize,
+		moved_hot: usize,
+		errors: usize,
+	},
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct ColdStorageWorkerPersisted {
+	last_completed: u64,
+}
+
+impl ColdStorageWorker {
+	pub fn new(garage: Arc<Garage>) -> Self {
+		let persister = Persister::new(&garage.config.metadata_dir, "cold_storage_worker_state");
+		let last_completed = persister
+			.load()
+			.map(|x: ColdStorageWorkerPersisted| x.last_completed)
+			.unwrap_or(0);
+
+		Self {
+			garage,
+			state: State::Completed(last_completed),
+			tranquilizer: Tranquilizer::new(30),
+			persister,
+		}
+	}
+
+	fn start(&mut self) -> Result<(), Error> {
+		let mut bucket_delays = HashMap::new();
+		for item in self.garage.bucket_table.data.store.iter()? {
+			let (_, bytes) = item?;
+			let bucket = self.garage.bucket_table.data.decode_entry(&bytes)?;
+			if let Some(params) = bucket.params() {
+				let delay = params.cold_storage_after.get().map(|d| d as u64 * DAY_MSEC);
+				bucket_delays.insert(bucket.id, delay);
+			}
+		}
+		let min_delay = bucket_delays.values().flatten().min().cloned();
+
+		info!("Starting cold storage worker");
+		self.state = State::Running {
+			started: now_msec(),
+			pos: vec![],
+			bucket_delays,
+			min_delay,
+			version_buckets: HashMap::new(),
+			counter: 0,
+			moved_cold: 0,
+			moved_hot: 0,
+			errors: 0,
+		};
+		Ok(())
+	}
+}
+
+#[async_trait]
+impl Worker for ColdStorageWorker {
+	fn name(&self) -> String {
+		"Block cold storage worker".into()
+	}
+
+	fn info(&self) -> Option<String> {
+		match &self.state {
+			State::Completed(t) => Some(format!("Last completed: {}", msec_to_rfc3339(*t))),
+			State::Running {
+				started,
+				counter,
+				moved_cold,
+				moved_hot,
+				errors,
+				..
+			} => Some(format!(
+				"Started: {}, blocks scanned: {}, moved to cold storage: {}, moved back: {}, errors: {}",
+				msec_to_rfc3339(*started),
+				counter,
+				moved_cold,
+				moved_hot,
+				errors
+			)),
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		match &mut self.state {
+			State::Completed(_) => Ok(WorkerState::Idle),
+			State::Running {
+				started,
+				pos,
+				bucket_delays,
+				min_delay,
+				version_buckets,
+				counter,
+				moved_cold,
+				moved_hot,
+				errors,
+			} => {
+				self.tranquilizer.reset();
+
+				let hash = match self.garage.block_ref_table.data.store.get_gt(&pos[..])? {
+					Some((key, _)) => Hash::try_from(&key[..32]).unwrap(),
+					None => {
+						info!(
+							"Cold storage worker finished: {} blocks moved to cold storage, {} moved back, {} errors",
+							moved_cold, moved_hot, errors
+						);
+						let started = *started;
+						self.persister
+							.save_async(&ColdStorageWorkerPersisted {
+								last_completed: started,
+							})
+							.await?;
+						self.state = State::Completed(started);
+						self.tranquilizer.clear();
+						return Ok(WorkerState::Idle);
+					}
+				};
+				// Skip all other references to this block
+				*pos = [hash.as_slice(), &[0xffu8; 32][..]].concat();
+				*counter += 1;
+
+				if version_buckets.len() > VERSION_CACHE_SIZE {
+					version_buckets.clear();
+				}
+
+				match process_block(
+					&self.garage,
+					&hash,
+					bucket_delays,
+					*min_delay,
+					version_buckets,
+				)
+				.await
+				{
+					Ok(None) => Ok(WorkerState::Busy),
+					Ok(Some(cold)) => {
+						if cold {
+							*moved_cold += 1;
+						} else {
+							*moved_hot += 1;
+						}
+						Ok(self
+							.tranquilizer
+							.tranquilize_worker(COLD_STORAGE_TRANQUILITY))
+					}
+					Err(e) => {
+						warn!("Could not move block {:?}: {}", hash, e);
+						*errors += 1;
+						Ok(WorkerState::Busy)
+					}
+				}
+			}
+		}
+	}
+
+	async fn wait_for_work(&mut self, _must_exit: &watch::Receiver<bool>) -> WorkerState {
+		if let State::Completed(t) = &self.state {
+			let now = now_msec();
+			let next_start = *t + PASS_INTERVAL.as_millis() as u64;
+			if now < next_start {
+				tokio::time::sleep(Duration::from_millis(next_start - now)).await;
+			}
+			if let Err(e) = self.start() {
+				error!("Could not start cold storage worker: {}", e);
+				return WorkerState::Idle;
+			}
+		}
+		WorkerState::Busy
+	}
+}
+
+/// Move a block to or from cold storage if needed.
+/// Returns whether the block was moved to cold storage or back,
+/// or None if it was not moved.
+async fn process_block(
+	garage: &Garage,
+	hash: &Hash,
+	bucket_delays: &HashMap<Uuid, Option<u64>>,
+	min_delay: Option<u64>,
+	version_buckets: &mut HashMap<Uuid, Uuid>,
+) -> Result<Option<bool>, Error> {
+	let block_manager = &garage.block_manager;
+
+	let is_cold = match block_manager.is_block_cold(hash).await {
+		Some(c) => c,
+		None => return Ok(None),
+	};
+	let age = now_msec().saturating_sub(block_manager.block_access_time(hash)?);
+
+	// The references of the block are only read if its age is above
+	// the delay of at least one bucket
+	let to_cold = match min_delay {
+		Some(min) if age >= min => {
+			match block_delay(garage, hash, bucket_delays, version_buckets).await? {
+				Some(delay) => age >= delay,
+				None => false,
+			}
+		}
+		_ => false,
+	};
+	if to_cold == is_cold {
+		return Ok(None);
+	}
+
+	// A cold block is moved back if it has been read since it was
+	// moved to cold storage, or if its buckets don't want it in cold
+	// storage anymore
+	block_manager.move_block_to_tier(hash, to_cold).await?;
+	Ok(Some(to_cold))
+}
+
+/// Delay after which a block can be moved to cold storage: the longest
+/// delay of the buckets of the objects that contain it, or None if one
+/// of them does not move its blocks to cold storage
+async fn block_delay(
+	garage: &Garage,
+	hash: &Hash,
+	bucket_delays: &HashMap<Uuid, Option<u64>>,
+	version_buckets: &mut HashMap<Uuid, Uuid>,
+) -> Result<Option<u64>, Error> {
+	let mut delay = None;
+	let mut start = None;
+	loop {
+		let refs = garage.block_ref_table.data.read_range(
+			hash,
+			&start,
+			&Some(DeletedFilter::NotDeleted),
+			BLOCK_REFS_BATCH_SIZE,
+			EnumerationOrder::Forward,
+		)?;
+		let last_batch = refs.len() < BLOCK_REFS_BATCH_SIZE;
+
+		let mut versions = vec![];
+		for bytes in refs.iter() {
+			let block_ref: BlockRef = garage.block_ref_table.data.decode_entry(bytes)?;
+			// The first reference of a batch is the last one of the previous batch
+			if start != Some(block_ref.version) {
+				versions.push(block_ref.version);
+			}
+		}
+
+		// Fetch the bucket of the versions that were not seen yet
+		let to_fetch = versions
+			.iter()
+			.filter(|v| !version_buckets.contains_key(v))
+			.cloned()
+			.collect::<Vec<_>>();
+		for chunk in to_fetch.chunks(VERSION_FETCH_CONCURRENCY) {
+			let fetched = futures::future::try_join_all(
+				chunk.iter().map(|v| garage.version_table.get(v, &EmptyKey)),
+			)
+			.await?;
+			for (version_id, version) in chunk.iter().zip(fetched) {
+				match version {
+					Some(v) => {
+						version_buckets.insert(*version_id, v.bucket_id);
+					}
+					None => return Ok(None),
+				}
+			}
+		}
+
+		for version_id in versions.iter() {
+			let bucket_id = version_buckets[version_id];
+			match bucket_delays.get(&bucket_id).cloned().flatten() {
+				Some(d) => delay = Some(std::cmp::max(d, delay.unwrap_or(0))),
+				None => return Ok(None),
+			}
+		}
+
+		match versions.last() {
+			Some(v) if !last_batch => start = Some(*v),
+			_ => return Ok(delay),
+		}
+	}
+}
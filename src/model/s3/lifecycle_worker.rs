@@ -0,0 +1,361 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use garage_util::background::*;
+use garage_util::data::*;
+use garage_util::error::Error;
+use garage_util::persister::Persister;
+use garage_util::time::*;
+
+use garage_table::EmptyKey;
+
+use crate::bucket_table::*;
+use crate::s3::object_table::*;
+
+use crate::garage::Garage;
+
+/// Background worker that applies the lifecycle rules of buckets
+/// to the objects stored on this node. A full pass over the local
+/// object table is made once a day (days are counted in UTC).
+///
+/// The worker also removes the versions that have been replaced by a newer
+/// version and were only kept because of an Object Lock protection that has
+/// since ended. This is not done when merging objects, as the result of a
+/// merge must not depend on the clock of the node that does it.
+///
+/// All nodes storing a copy of an object process it independently:
+/// the delete markers and abortions they write are computed
+/// deterministically from the object's state, so that they are
+/// merged into a single version when written by several nodes.
+pub struct LifecycleWorker {
+	garage: Arc<Garage>,
+
+	state: State,
+
+	persister: Persister<LifecycleWorkerPersisted>,
+}
+
+enum State {
+	Completed(NaiveDate),
+	Running {
+		date: NaiveDate,
+		pos: Vec<u8>,
+		counter: usize,
+		objects_expired: usize,
+		mpu_aborted: usize,
+		locked_removed: usize,
+		last_bucket: Option<Bucket>,
+	},
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct LifecycleWorkerPersisted {
+	last_completed: Option<String>,
+}
+
+impl LifecycleWorker {
+	pub fn new(garage: Arc<Garage>) -> Self {
+		let persister = Persister::new(&garage.config.metadata_dir, "lifecycle_worker_state");
+
+		let last_completed = persister
+			.load()
+			.ok()
+			.and_then(|x: LifecycleWorkerPersisted| x.last_completed)
+			.and_then(|x| NaiveDate::parse_from_str(&x, "%Y-%m-%d").ok());
+		let state = match last_completed {
+			Some(d) => State::Completed(d),
+			None => State::start(today()),
+		};
+
+		Self {
+			garage,
+			state,
+			persister,
+		}
+	}
+}
+
+impl State {
+	fn start(date: NaiveDate) -> Self {
+		info!("Starting lifecycle worker for {}", date);
+		State::Running {
+			date,
+			pos: vec![],
+			counter: 0,
+			objects_expired: 0,
+			mpu_aborted: 0,
+			locked_removed: 0,
+			last_bucket: None,
+		}
+	}
+}
+
+#[async_trait]
+impl Worker for LifecycleWorker {
+	fn name(&self) -> String {
+		"object lifecycle worker".to_string()
+	}
+
+	fn info(&self) -> Option<String> {
+		match &self.state {
+			State::Completed(d) => Some(format!("Last completed: {}", d)),
+			State::Running {
+				date,
+				counter,
+				objects_expired,
+				mpu_aborted,
+				locked_removed,
+				..
+			} => Some(format!(
+				"Started: {}, objects scanned: {}, objects expired: {}, multipart uploads aborted: {}, unlocked versions removed: {}",
+				date, counter, objects_expired, mpu_aborted, locked_removed
+			)),
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		match &mut self.state {
+			State::Completed(_) => Ok(WorkerState::Idle),
+			State::Running {
+				date,
+				pos,
+				counter,
+				objects_expired,
+				mpu_aborted,
+				locked_removed,
+				last_bucket,
+			} => {
+				let (next_pos, object_bytes) =
+					match self.garage.object_table.data.store.get_gt(&pos[..])? {
+						Some(kv) => kv,
+						None => {
+							let date = *date;
+							info!(
+								"Lifecycle worker finished for {}: {} objects expired, {} uploads aborted",
+								date, objects_expired, mpu_aborted
+							);
+							self.persister
+								.save_async(&LifecycleWorkerPersisted {
+									last_completed: Some(date.to_string()),
+								})
+								.await?;
+							self.state = State::Completed(date);
+							return Ok(WorkerState::Idle);
+						}
+					};
+
+				let object = self.garage.object_table.data.decode_entry(&object_bytes)?;
+				process_object(
+					&self.garage,
+					*date,
+					&object,
+					objects_expired,
+					mpu_aborted,
+					locked_removed,
+					last_bucket,
+				)
+				.await?;
+
+				*counter += 1;
+				*pos = next_pos;
+
+				Ok(WorkerState::Busy)
+			}
+		}
+	}
+
+	async fn wait_for_work(&mut self, _must_exit: &watch::Receiver<bool>) -> WorkerState {
+		match &self.state {
+			State::Completed(d) => {
+				let now = now_msec();
+				let next_start = midnight_ts(d.succ());
+				if now < next_start {
+					tokio::time::sleep(Duration::from_millis(next_start - now)).await;
+				}
+				self.state = State::start(std::cmp::max(d.succ(), today()));
+			}
+			State::Running { .. } => (),
+		}
+		WorkerState::Busy
+	}
+}
+
+async fn process_object(
+	garage: &Arc<Garage>,
+	now_date: NaiveDate,
+	object: &Object,
+	objects_expired: &mut usize,
+	mpu_aborted: &mut usize,
+	locked_removed: &mut usize,
+	last_bucket: &mut Option<Bucket>,
+) -> Result<(), Error> {
+	if !object
+		.versions()
+		.iter()
+		.any(|x| x.is_data() || x.is_uploading())
+	{
+		return Ok(());
+	}
+
+	// Remove replaced versions whose Object Lock protection has ended
+	let now = now_msec();
+	let unlocked_versions = object
+		.replaced_locked_versions()
+		.filter(|v| !v.lock.is_protected(now))
+		.map(|v| ObjectVersion {
+			state: ObjectVersionState::Aborted,
+			uuid: v.uuid,
+			timestamp: v.timestamp,
+			versioned: v.versioned,
+			tags: v.tags.clone(),
+			lock: v.lock.clone(),
+			conditional_write: v.conditional_write,
+		})
+		.collect::<Vec<_>>();
+	if !unlocked_versions.is_empty() {
+		*locked_removed += unlocked_versions.len();
+		let unlocked_object = Object::new(object.bucket_id, object.key.clone(), unlocked_versions);
+		garage.object_table.insert(&unlocked_object).await?;
+	}
+
+	let bucket = match last_bucket.take() {
+		Some(b) if b.id == object.bucket_id => b,
+		_ => match garage
+			.bucket_table
+			.get(&EmptyKey, &object.bucket_id)
+			.await?
+		{
+			Some(b) => b,
+			None => {
+				warn!(
+					"Lifecycle worker: object in non-existent bucket {:?}",
+					object.bucket_id
+				);
+				return Ok(());
+			}
+		},
+	};
+
+	let params = match last_bucket.insert(bucket).params() {
+		Some(p) => p,
+		None => return Ok(()),
+	};
+
+	let rules = match params.lifecycle_config.get() {
+		Some(rules) => rules,
+		None => return Ok(()),
+	};
+
+	// Expire the current version of the object if a rule says so
+	if let Some(current_version) = object.current_version() {
+		if let ObjectVersionState::Complete(
+			ObjectVersionData::Inline(meta, _) | ObjectVersionData::FirstBlock(meta, _),
+		) = &current_version.state
+		{
+			let expires_on = rules
+				.iter()
+				.filter(|rule| rule.enabled && rule.filter.matches(&object.key, Some(meta.size)))
+				.filter_map(|rule| rule.expiration.as_ref())
+				.filter_map(|exp| expiration_date(exp, current_version.timestamp))
+				.filter(|d| *d <= now_date)
+				.min();
+
+			if let Some(exp_date) = expires_on {
+				// Delete marker is derived from the expired version, so that if
+				// several nodes write it, they all write the exact same version
+				let marker = ObjectVersion {
+					uuid: lifecycle_uuid(&current_version.uuid, b"expiration"),
+					timestamp: std::cmp::max(midnight_ts(exp_date), current_version.timestamp + 1),
+					state: ObjectVersionState::Complete(ObjectVersionData::DeleteMarker),
+					versioned: params.versioning_enabled(),
+					tags: Default::default(),
+					lock: Default::default(),
+					conditional_write: None,
+				};
+				let deleted_object =
+					Object::new(object.bucket_id, object.key.clone(), vec![marker]);
+				garage.object_table.insert(&deleted_object).await?;
+				*objects_expired += 1;
+			}
+		}
+	}
+
+	// Abort incomplete multipart uploads
+	let abort_days = rules
+		.iter()
+		.filter(|rule| rule.enabled && rule.filter.matches(&object.key, None))
+		.filter_map(|rule| rule.abort_incomplete_mpu_days)
+		.min();
+	if let Some(abort_days) = abort_days {
+		let aborted_versions = object
+			.versions()
+			.iter()
+			.filter(|v| {
+				v.is_uploading()
+					&& date_of(v.timestamp)
+						.map(|d| d + chrono::Duration::days(abort_days as i64) < now_date)
+						.unwrap_or(false)
+			})
+			.map(|v| ObjectVersion {
+				state: ObjectVersionState::Aborted,
+				uuid: v.uuid,
+				timestamp: v.timestamp,
+				versioned: v.versioned,
+				tags: v.tags.clone(),
+				lock: v.lock.clone(),
+				conditional_write: v.conditional_write,
+			})
+			.collect::<Vec<_>>();
+		if !aborted_versions.is_empty() {
+			*mpu_aborted += aborted_versions.len();
+			let aborted_object =
+				Object::new(object.bucket_id, object.key.clone(), aborted_versions);
+			garage.object_table.insert(&aborted_object).await?;
+		}
+	}
+
+	Ok(())
+}
+
+/// Compute the date at which an object created at the given timestamp expires.
+/// As in S3, objects that expire after a number of days are expired at
+/// the first midnight UTC after creation date + that number of days.
+fn expiration_date(exp: &LifecycleExpiration, created_ts: u64) -> Option<NaiveDate> {
+	match exp {
+		LifecycleExpiration::AfterDays(n) => {
+			date_of(created_ts).map(|d| d + chrono::Duration::days(*n as i64 + 1))
+		}
+		LifecycleExpiration::AtDate(d) => match NaiveDate::parse_from_str(d, "%Y-%m-%d") {
+			Ok(d) => Some(d),
+			Err(e) => {
+				warn!("Invalid expiration date in lifecycle rule: {} ({})", d, e);
+				None
+			}
+		},
+	}
+}
+
+fn lifecycle_uuid(version_uuid: &Uuid, what: &[u8]) -> Uuid {
+	blake2sum(&[version_uuid.as_slice(), b"lifecycle-", what].concat())
+}
+
+fn today() -> NaiveDate {
+	Utc::today().naive_utc()
+}
+
+fn date_of(ts_msec: u64) -> Option<NaiveDate> {
+	NaiveDateTime::from_timestamp_opt(
+		(ts_msec / 1000) as i64,
+		((ts_msec % 1000) * 1_000_000) as u32,
+	)
+	.map(|dt| dt.date())
+}
+
+fn midnight_ts(date: NaiveDate) -> u64 {
+	date.and_hms(0, 0, 0).timestamp_millis() as u64
+}
@@ -1,3 +1,6 @@
 pub mod block_ref_table;
 pub mod object_table;
 pub mod version_table;
+
+pub mod cold_storage_worker;
+pub mod lifecycle_worker;
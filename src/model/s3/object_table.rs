@@ -67,6 +67,31 @@ impl Object {
 	pub fn versions(&self) -> &[ObjectVersion] {
 		&self.versions[..]
 	}
+
+	/// Get the current version of the object, i.e. the most recent complete version,
+	/// which can be a delete marker
+	pub fn current_version(&self) -> Option<&ObjectVersion> {
+		self.versions.iter().rev().find(|v| v.is_complete())
+	}
+
+	/// Get a stored version of the object by its version id
+	pub fn find_version(&self, uuid: &Uuid) -> Option<&ObjectVersion> {
+		self.versions.iter().find(|v| v.uuid == *uuid)
+	}
+
+	/// Get the unversioned versions that have been replaced by a newer
+	/// unversioned version, and that are only kept because an Object Lock
+	/// protection is set on them
+	pub fn replaced_locked_versions(&self) -> impl Iterator<Item = &ObjectVersion> {
+		let last_unversioned_complete = self
+			.versions
+			.iter()
+			.rposition(|v| v.is_complete() && !v.versioned)
+			.unwrap_or(0);
+		self.versions[..last_unversioned_complete]
+			.iter()
+			.filter(|v| !v.versioned && v.state != ObjectVersionState::Aborted)
+	}
 }
 
 /// Informations about a version of an object
@@ -78,6 +103,23 @@ pub struct ObjectVersion {
 	pub timestamp: u64,
 	/// State of the version
 	pub state: ObjectVersionState,
+	/// Whether this version was created while versioning was enabled on the bucket.
+	/// Such versions are kept as non-current versions when a newer version is written,
+	/// whereas other versions are replaced by any newer complete unversioned version.
+	#[serde(default)]
+	pub versioned: bool,
+	/// Tags of the object version. Unlike the rest of the version's metadata,
+	/// they can be changed after the version has been written.
+	#[serde(default)]
+	pub tags: Lww<ObjectTags>,
+	/// Object Lock protections of the object version
+	#[serde(default)]
+	pub lock: ObjectVersionLock,
+	/// For a version written by a conditional write, the time at which the writer
+	/// started checking that no other version was written concurrently. Conditional
+	/// writes only take into account the versions being uploaded that have this mark.
+	#[serde(default)]
+	pub conditional_write: Option<u64>,
 }
 
 /// State of an object version
@@ -146,6 +188,81 @@ pub struct ObjectVersionHeaders {
 	pub content_type: String,
 	/// Any other http headers to send
 	pub other: BTreeMap<String, String>,
+	/// If the object is encrypted with a customer-provided key (SSE-C),
+	/// the information required to check the key given by clients
+	#[serde(default)]
+	pub encryption: Option<ObjectVersionEncryption>,
+}
+
+/// Encryption parameters of an object version encrypted with a customer-provided key.
+/// The key itself is never stored in Garage.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+pub struct ObjectVersionEncryption {
+	/// Fingerprint of the encryption key, used to check that
+	/// the key given by a client is the one the object was encrypted with
+	pub key_fingerprint: Hash,
+}
+
+/// Tags of an object version, as a list of unique (key, value) pairs
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ObjectTags(pub Vec<(String, String)>);
+
+impl AutoCrdt for ObjectTags {
+	const WARN_IF_DIFFERENT: bool = false;
+}
+
+/// Object Lock protections of an object version. An object version
+/// that is protected cannot be deleted, nor replaced by a newer version.
+#[derive(PartialEq, Eq, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ObjectVersionLock {
+	/// Retention period: the version is protected until the given date
+	pub retention: Lww<Option<ObjectRetention>>,
+	/// Legal hold: the version is protected until the hold is removed
+	pub legal_hold: Lww<bool>,
+}
+
+/// Retention period of an object version
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+pub struct ObjectRetention {
+	pub mode: ObjectLockMode,
+	/// Timestamp (in milliseconds) until which the version is protected
+	pub retain_until: u64,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum ObjectLockMode {
+	/// The retention can be shortened or removed by users with special permissions
+	Governance,
+	/// The retention can only be extended, by anyone
+	Compliance,
+}
+
+impl ObjectVersionLock {
+	/// Get the retention period of the version, if it is still active at time `now`
+	pub fn active_retention(&self, now: u64) -> Option<&ObjectRetention> {
+		self.retention
+			.get()
+			.as_ref()
+			.filter(|r| r.retain_until > now)
+	}
+
+	/// Whether the version is protected against deletion at time `now`
+	pub fn is_protected(&self, now: u64) -> bool {
+		*self.legal_hold.get() || self.active_retention(now).is_some()
+	}
+
+	/// Whether a retention period or a legal hold is set on the version,
+	/// regardless of whether it is still in effect
+	pub fn is_set(&self) -> bool {
+		*self.legal_hold.get() || self.retention.get().is_some()
+	}
+}
+
+impl Crdt for ObjectVersionLock {
+	fn merge(&mut self, other: &Self) {
+		self.retention.merge(&other.retention);
+		self.legal_hold.merge(&other.legal_hold);
+	}
 }
 
 impl ObjectVersion {
@@ -171,6 +288,20 @@ impl ObjectVersion {
 			_ => false,
 		}
 	}
+
+	/// Is the object version a delete marker
+	pub fn is_delete_marker(&self) -> bool {
+		matches!(
+			self.state,
+			ObjectVersionState::Complete(ObjectVersionData::DeleteMarker)
+		)
+	}
+
+	/// Is the object version one that should be listed by ListObjectVersions,
+	/// i.e. data or a delete marker written with versioning enabled
+	pub fn is_listable_version(&self) -> bool {
+		self.is_data() || (self.versioned && self.is_delete_marker())
+	}
 }
 
 impl Entry<Uuid, String> for Object {
@@ -181,9 +312,12 @@ impl Entry<Uuid, String> for Object {
 		&self.key
 	}
 	fn is_tombstone(&self) -> bool {
-		self.versions.len() == 1
-			&& self.versions[0].state
-				== ObjectVersionState::Complete(ObjectVersionData::DeleteMarker)
+		// Delete markers written with versioning enabled are part of the
+		// history of the object and must not be garbage collected
+		!self.versions.is_empty()
+			&& self.versions.iter().all(|v| {
+				v.state == ObjectVersionState::Aborted || (v.is_delete_marker() && !v.versioned)
+			})
 	}
 }
 
@@ -197,6 +331,12 @@ impl Crdt for Object {
 			{
 				Ok(i) => {
 					self.versions[i].state.merge(&other_v.state);
+					self.versions[i].tags.merge(&other_v.tags);
+					self.versions[i].lock.merge(&other_v.lock);
+					self.versions[i].conditional_write = std::cmp::max(
+						self.versions[i].conditional_write,
+						other_v.conditional_write,
+					);
 				}
 				Err(i) => {
 					self.versions.insert(i, other_v.clone());
@@ -204,18 +344,34 @@ impl Crdt for Object {
 			}
 		}
 
-		// Remove versions which are obsolete, i.e. those that come
-		// before the last version which .is_complete().
-		let last_complete = self
+		// Remove versions which are obsolete, i.e. unversioned versions that come
+		// before the last unversioned version which .is_complete().
+		// Versions written while versioning was enabled, as well as versions on which
+		// an Object Lock protection is set, are kept as non-current versions; they can
+		// only be removed explicitly by marking them as aborted. Aborted versions are
+		// removed once a newer complete version exists.
+		let last_complete = self.versions.iter().rposition(|v| v.is_complete());
+		let last_unversioned_complete = self
 			.versions
 			.iter()
-			.enumerate()
-			.rev()
-			.find(|(_, v)| v.is_complete())
-			.map(|(vi, _)| vi);
+			.rposition(|v| v.is_complete() && !v.versioned);
 
 		if let Some(last_vi) = last_complete {
-			self.versions = self.versions.drain(last_vi..).collect::<Vec<_>>();
+			self.versions = self
+				.versions
+				.drain(..)
+				.enumerate()
+				.filter(|(vi, v)| match v.state {
+					ObjectVersionState::Aborted => *vi > last_vi,
+					_ => {
+						last_unversioned_complete
+							.map(|lu| *vi >= lu)
+							.unwrap_or(true) || v.versioned
+							|| v.lock.is_set()
+					}
+				})
+				.map(|(_, v)| v)
+				.collect::<Vec<_>>();
 		}
 	}
 }
@@ -230,6 +386,7 @@ pub struct ObjectTable {
 pub enum ObjectFilter {
 	IsData,
 	IsUploading,
+	HasVersions,
 }
 
 impl TableSchema for ObjectTable {
@@ -288,8 +445,12 @@ impl TableSchema for ObjectTable {
 
 	fn matches_filter(entry: &Self::E, filter: &Self::Filter) -> bool {
 		match filter {
-			ObjectFilter::IsData => entry.versions.iter().any(|v| v.is_data()),
+			ObjectFilter::IsData => entry
+				.current_version()
+				.map(|v| v.is_data())
+				.unwrap_or(false),
 			ObjectFilter::IsUploading => entry.versions.iter().any(|v| v.is_uploading()),
+			ObjectFilter::HasVersions => entry.versions.iter().any(|v| v.is_listable_version()),
 		}
 	}
 
@@ -316,7 +477,7 @@ impl CountedItem for Object {
 
 	fn counts(&self) -> Vec<(&'static str, i64)> {
 		let versions = self.versions();
-		let n_objects = if versions.iter().any(|v| v.is_data()) {
+		let n_objects = if self.current_version().map(|v| v.is_data()).unwrap_or(false) {
 			1
 		} else {
 			0
@@ -372,6 +533,10 @@ fn migrate_object_version(v: old::ObjectVersion) -> ObjectVersion {
 			}
 			old::ObjectVersionState::Aborted => ObjectVersionState::Aborted,
 		},
+		versioned: false,
+		tags: Lww::default(),
+		lock: ObjectVersionLock::default(),
+		conditional_write: None,
 	}
 }
 
@@ -379,6 +544,7 @@ fn migrate_object_version_headers(h: old::ObjectVersionHeaders) -> ObjectVersion
 	ObjectVersionHeaders {
 		content_type: h.content_type,
 		other: h.other,
+		encryption: None,
 	}
 }
 
@@ -402,3 +568,189 @@ fn migrate_object_version_meta(m: old::ObjectVersionMeta) -> ObjectVersionMeta {
 		etag: m.etag,
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use garage_util::time::now_msec;
+
+	fn version(timestamp: u64, state: ObjectVersionState, versioned: bool) -> ObjectVersion {
+		ObjectVersion {
+			uuid: gen_uuid(),
+			timestamp,
+			state,
+			versioned,
+			tags: Lww::new(ObjectTags::default()),
+			lock: ObjectVersionLock::default(),
+			conditional_write: None,
+		}
+	}
+
+	fn data() -> ObjectVersionState {
+		ObjectVersionState::Complete(ObjectVersionData::Inline(
+			ObjectVersionMeta {
+				headers: ObjectVersionHeaders {
+					content_type: "text/plain".into(),
+					other: BTreeMap::new(),
+					encryption: None,
+				},
+				size: 1,
+				etag: "etag".into(),
+			},
+			vec![0],
+		))
+	}
+
+	fn delete_marker() -> ObjectVersionState {
+		ObjectVersionState::Complete(ObjectVersionData::DeleteMarker)
+	}
+
+	fn uploading() -> ObjectVersionState {
+		ObjectVersionState::Uploading(ObjectVersionHeaders {
+			content_type: "text/plain".into(),
+			other: BTreeMap::new(),
+			encryption: None,
+		})
+	}
+
+	fn merge_all(versions: &[&ObjectVersion]) -> Object {
+		let mut object = Object::new(Uuid::from([0u8; 32]), "key".into(), vec![]);
+		for v in versions {
+			object.merge(&Object::new(
+				object.bucket_id,
+				object.key.clone(),
+				vec![(*v).clone()],
+			));
+		}
+		object
+	}
+
+	fn uuids(object: &Object) -> Vec<Uuid> {
+		object.versions().iter().map(|v| v.uuid).collect()
+	}
+
+	#[test]
+	fn test_merge_versioned_and_unversioned() {
+		let v1 = version(1, data(), false);
+		let v2 = version(2, data(), true);
+		let v3 = version(3, data(), false);
+		let v4 = version(4, data(), false);
+
+		// Versioned versions are kept, older unversioned versions are replaced
+		let object = merge_all(&[&v1, &v2, &v3, &v4]);
+		assert_eq!(uuids(&object), vec![v2.uuid, v4.uuid]);
+		assert_eq!(object.current_version().unwrap().uuid, v4.uuid);
+
+		// The result does not depend on the order in which versions are merged
+		let object = merge_all(&[&v4, &v3, &v2, &v1]);
+		assert_eq!(uuids(&object), vec![v2.uuid, v4.uuid]);
+	}
+
+	#[test]
+	fn test_merge_suspended_versioning() {
+		// Versions written while versioning was enabled, then suspended
+		let v1 = version(1, data(), true);
+		let v2 = version(2, data(), true);
+		let v3 = version(3, data(), false);
+		let v4 = version(4, uploading(), false);
+
+		let object = merge_all(&[&v1, &v2, &v3, &v4]);
+		assert_eq!(uuids(&object), vec![v1.uuid, v2.uuid, v3.uuid, v4.uuid]);
+		assert_eq!(object.current_version().unwrap().uuid, v3.uuid);
+
+		// A newer complete unversioned version replaces the older unversioned
+		// versions, including unfinished uploads, but not the versioned ones
+		let v5 = version(5, delete_marker(), false);
+		let v6 = version(6, uploading(), false);
+		let object = merge_all(&[&v1, &v2, &v3, &v4, &v5, &v6]);
+		assert_eq!(uuids(&object), vec![v1.uuid, v2.uuid, v5.uuid, v6.uuid]);
+		assert!(object.current_version().unwrap().is_delete_marker());
+	}
+
+	#[test]
+	fn test_merge_locked_versions() {
+		let now = now_msec();
+		let retention = |retain_until| {
+			Lww::new(Some(ObjectRetention {
+				mode: ObjectLockMode::Governance,
+				retain_until,
+			}))
+		};
+
+		let mut retained = version(1, data(), false);
+		retained.lock.retention = retention(now + 3600 * 1000);
+		let mut expired = version(2, data(), false);
+		expired.lock.retention = retention(now - 1);
+		let mut held = version(3, data(), false);
+		held.lock.legal_hold = Lww::new(true);
+		let v4 = version(4, data(), false);
+
+		// Unversioned versions on which a protection is set are kept by a newer
+		// version, whether or not the protection is still in effect: the result
+		// of a merge must not depend on the time at which it is done
+		let mut object = merge_all(&[&retained, &expired, &held, &v4]);
+		assert_eq!(
+			uuids(&object),
+			vec![retained.uuid, expired.uuid, held.uuid, v4.uuid]
+		);
+		assert_eq!(
+			object
+				.replaced_locked_versions()
+				.map(|v| v.uuid)
+				.collect::<Vec<_>>(),
+			vec![retained.uuid, expired.uuid, held.uuid]
+		);
+
+		// They are removed once they are explicitly aborted
+		let mut expired_aborted = expired.clone();
+		expired_aborted.state = ObjectVersionState::Aborted;
+		object.merge(&Object::new(
+			object.bucket_id,
+			object.key.clone(),
+			vec![expired_aborted],
+		));
+		assert_eq!(uuids(&object), vec![retained.uuid, held.uuid, v4.uuid]);
+	}
+
+	#[test]
+	fn test_merge_aborted_versions() {
+		let v1 = version(1, data(), true);
+		let v2 = version(2, ObjectVersionState::Aborted, true);
+		let v3 = version(3, data(), true);
+		let v4 = version(4, ObjectVersionState::Aborted, true);
+
+		// Aborted versions are removed once a newer complete version exists,
+		// including in a versioned bucket
+		let object = merge_all(&[&v1, &v2, &v3, &v4]);
+		assert_eq!(uuids(&object), vec![v1.uuid, v3.uuid, v4.uuid]);
+
+		// A deleted version is removed
+		let mut v1_deleted = v1.clone();
+		v1_deleted.state = ObjectVersionState::Aborted;
+		let object = merge_all(&[&v1, &v2, &v3, &v4, &v1_deleted]);
+		assert_eq!(uuids(&object), vec![v3.uuid, v4.uuid]);
+
+		// Aborted versions are kept when there is no complete version
+		let object = merge_all(&[&v2, &v4]);
+		assert_eq!(uuids(&object), vec![v2.uuid, v4.uuid]);
+		assert!(object.is_tombstone());
+	}
+
+	#[test]
+	fn test_is_tombstone() {
+		let empty = merge_all(&[]);
+		assert!(!empty.is_tombstone());
+
+		let aborted = version(1, ObjectVersionState::Aborted, true);
+		let unversioned_dm = version(2, delete_marker(), false);
+		let versioned_dm = version(3, delete_marker(), true);
+		let data_v = version(4, data(), false);
+
+		assert!(merge_all(&[&aborted]).is_tombstone());
+		assert!(merge_all(&[&aborted, &unversioned_dm]).is_tombstone());
+		assert!(!merge_all(&[&versioned_dm]).is_tombstone());
+		assert!(!merge_all(&[&aborted, &versioned_dm]).is_tombstone());
+		assert!(!merge_all(&[&aborted, &data_v]).is_tombstone());
+		assert!(!merge_all(&[&version(5, uploading(), false)]).is_tombstone());
+	}
+}
@@ -161,6 +161,7 @@ impl TableSchema for VersionTable {
 							block: vb.hash,
 							version: old_v.uuid,
 							deleted: true.into(),
+							replication_factor: None,
 						})
 						.collect::<Vec<_>>();
 					block_ref_table.insert_many(&deleted_block_refs[..]).await?;
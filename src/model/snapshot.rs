@@ -0,0 +1,186 @@
+//! Snapshots of the metadata database of a node, taken while the node is running,
+//! and restoration of the metadata database from such a snapshot
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use garage_util::config::Config;
+use garage_util::error::*;
+use garage_util::time::*;
+
+use garage_table::change_log;
+
+use crate::garage::{open_db, Garage};
+
+/// Prevents several snapshots from being taken at the same time
+static SNAPSHOT_LOCK: Mutex<()> = Mutex::new(());
+
+/// Name of the file or directory of the metadata database in the
+/// metadata directory, and in snapshots, for a given database engine
+pub fn db_file_name(db_engine: &str) -> Option<&'static str> {
+	match db_engine {
+		"sled" => Some("db"),
+		"sqlite" | "sqlite3" | "rusqlite" => Some("db.sqlite"),
+		"lmdb" | "heed" => Some("db.lmdb"),
+		_ => None,
+	}
+}
+
+/// Directory in which snapshots of the metadata database are stored
+pub fn snapshots_dir(config: &Config) -> PathBuf {
+	config
+		.metadata_snapshots_dir
+		.clone()
+		.unwrap_or_else(|| config.metadata_dir.join("snapshots"))
+}
+
+/// Take a snapshot of the metadata database of this node, and delete the oldest
+/// snapshots so that only `metadata_snapshots_keep` of them remain.
+/// Returns the path of the new snapshot.
+pub async fn snapshot_metadata(garage: &Arc<Garage>) -> Result<PathBuf, Error> {
+	let garage = garage.clone();
+	tokio::task::spawn_blocking(move || snapshot_metadata_sync(&garage)).await?
+}
+
+fn snapshot_metadata_sync(garage: &Garage) -> Result<PathBuf, Error> {
+	let _lock = SNAPSHOT_LOCK
+		.try_lock()
+		.ok()
+		.ok_or_message("A snapshot of the metadata database is already in progress")?;
+
+	let db_file = db_file_name(&garage.config.db_engine)
+		.ok_or_message("Unsupported DB engine for snapshots")?;
+	let dir = snapshots_dir(&garage.config);
+	std::fs::create_dir_all(&dir).ok_or_message("Unable to create snapshot directory")?;
+
+	// Snapshots are named after the time at which they are taken, so that
+	// they are sorted chronologically. They are first written to a temporary
+	// directory, so that incomplete snapshots are never mistaken for valid ones.
+	let name = msec_to_rfc3339(now_msec());
+	let tmp_path = dir.join(format!("{}.tmp", name));
+	let path = dir.join(&name);
+
+	info!("Taking snapshot of metadata database to {}", path.display());
+	std::fs::create_dir_all(&tmp_path).ok_or_message("Unable to create snapshot directory")?;
+	if let Err(e) = garage.db.snapshot(&tmp_path.join(db_file)) {
+		let _ = std::fs::remove_dir_all(&tmp_path);
+		return Err(Error::Message(format!(
+			"Unable to take snapshot of metadata database: {}",
+			e
+		)));
+	}
+	std::fs::rename(&tmp_path, &path)?;
+	info!(
+		"Snapshot of metadata database written to {}",
+		path.display()
+	);
+
+	let keep = garage.config.metadata_snapshots_keep;
+	let mut snapshots = list_snapshots(&dir)?;
+	for (old_name, complete) in snapshots.iter() {
+		if !complete {
+			info!("Removing incomplete snapshot {}", old_name);
+			std::fs::remove_dir_all(dir.join(old_name))?;
+		}
+	}
+	snapshots.retain(|(_, complete)| *complete);
+	if keep > 0 && snapshots.len() > keep {
+		for (old_name, _) in snapshots[..snapshots.len() - keep].iter() {
+			info!("Removing old snapshot {}", old_name);
+			std::fs::remove_dir_all(dir.join(old_name))?;
+		}
+	}
+
+	Ok(path)
+}
+
+/// List the snapshots in a directory, from oldest to newest,
+/// with a flag indicating whether they are complete
+fn list_snapshots(dir: &Path) -> Result<Vec<(String, bool)>, Error> {
+	let mut snapshots = vec![];
+	for ent in std::fs::read_dir(dir)? {
+		let ent = ent?;
+		if !ent.file_type()?.is_dir() {
+			continue;
+		}
+		if let Ok(name) = ent.file_name().into_string() {
+			match name.strip_suffix(".tmp") {
+				Some(base) => snapshots.push((base.to_string(), name, false)),
+				None => snapshots.push((name.clone(), name, true)),
+			}
+		}
+	}
+	snapshots.sort();
+	Ok(snapshots
+		.into_iter()
+		.map(|(_, name, complete)| (name, complete))
+		.collect())
+}
+
+/// Replace the metadata database of this node by the content of a snapshot.
+/// The snapshot is given either by its path, or by its name in the snapshot directory.
+/// The current database is not deleted but moved aside, and its new path is returned.
+/// The sequence numbers of the change log continue after the last one given
+/// by the current database. This must be done while the node is stopped.
+pub fn restore_metadata_snapshot(
+	config: &Config,
+	snapshot: &str,
+) -> Result<Option<PathBuf>, Error> {
+	let db_file =
+		db_file_name(&config.db_engine).ok_or_message("Unsupported DB engine for snapshots")?;
+
+	let snapshot_path = if Path::new(snapshot).is_absolute() {
+		PathBuf::from(snapshot)
+	} else {
+		snapshots_dir(config).join(snapshot)
+	};
+	let src = snapshot_path.join(db_file);
+	if !src.exists() {
+		return Err(Error::Message(format!(
+			"{} does not contain a database for engine {}",
+			snapshot_path.display(),
+			config.db_engine
+		)));
+	}
+
+	let dst = config.metadata_dir.join(db_file);
+	let old = if dst.exists() {
+		// Readers of the change log identify changes by their sequence number,
+		// remember the last one given so that it is not given again
+		// to other changes after the restoration
+		let last_seq = change_log::last_seq(&open_db(config)?)?;
+
+		let old = config
+			.metadata_dir
+			.join(format!("{}.before-restore-{}", db_file, now_msec()));
+		info!("Moving current metadata database to {}", old.display());
+		std::fs::rename(&dst, &old)?;
+		Some((old, last_seq))
+	} else {
+		None
+	};
+
+	info!("Restoring metadata database from {}", src.display());
+	copy_recursive(&src, &dst)?;
+
+	match old {
+		Some((old, last_seq)) => {
+			change_log::advance_last_seq(&open_db(config)?, last_seq)?;
+			Ok(Some(old))
+		}
+		None => Ok(None),
+	}
+}
+
+fn copy_recursive(src: &Path, dst: &Path) -> Result<(), Error> {
+	if src.is_dir() {
+		std::fs::create_dir_all(dst)?;
+		for ent in std::fs::read_dir(src)? {
+			let ent = ent?;
+			copy_recursive(&ent.path(), &dst.join(ent.file_name()))?;
+		}
+	} else {
+		std::fs::copy(src, dst)?;
+	}
+	Ok(())
+}
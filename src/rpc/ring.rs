@@ -41,11 +41,12 @@ pub struct Ring {
 // Change this to u16 the day we want to have more than 256 nodes in a cluster
 pub type CompactNodeType = u8;
 
-// The maximum number of times an object might get replicated
-// This must be at least 3 because Garage supports 3-way replication
-// Here we use 6 so that the size of a ring entry is 8 bytes
-// (2 bytes partition id, 6 bytes node numbers as u8s)
-const MAX_REPLICATION: usize = 6;
+/// The maximum number of times an object might get replicated
+/// (or the maximum number of shards of an erasure-coded block).
+/// This must be at least 3 because Garage supports 3-way replication
+/// Here we use 6 so that the size of a ring entry is 8 bytes
+/// (2 bytes partition id, 6 bytes node numbers as u8s)
+pub const MAX_REPLICATION: usize = 6;
 
 /// An entry in the ring
 #[derive(Clone, Debug)]
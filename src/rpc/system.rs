@@ -10,6 +10,7 @@ use arc_swap::ArcSwap;
 use async_trait::async_trait;
 use futures::{join, select};
 use futures_util::future::*;
+use nix::sys::statvfs::{statvfs, Statvfs};
 use serde::{Deserialize, Serialize};
 use sodiumoxide::crypto::sign::ed25519;
 use tokio::sync::watch;
@@ -22,9 +23,9 @@ use netapp::util::parse_and_resolve_peer_addr_async;
 use netapp::{NetApp, NetworkKey, NodeID, NodeKey};
 
 use garage_util::background::BackgroundRunner;
-use garage_util::config::Config;
 #[cfg(feature = "kubernetes-discovery")]
 use garage_util::config::KubernetesDiscoveryConfig;
+use garage_util::config::{Config, DataDirEnum};
 use garage_util::data::*;
 use garage_util::error::*;
 use garage_util::persister::Persister;
@@ -113,6 +114,10 @@ pub struct System {
 
 	/// Path to metadata directory
 	pub metadata_dir: PathBuf,
+	/// Data directories in which new blocks can be written
+	data_dirs: Vec<PathBuf>,
+	/// Free space under which this node refuses to store new blocks
+	min_free_space: Option<MinFreeSpace>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -125,6 +130,25 @@ pub struct NodeStatus {
 	pub cluster_layout_version: u64,
 	/// Hash of cluster layout staging data
 	pub cluster_layout_staging_hash: Hash,
+	/// Disk usage on partition containing metadata directory (tuple: `(avail, total)`)
+	#[serde(default)]
+	pub meta_disk_avail: Option<(u64, u64)>,
+	/// Disk usage on partition(s) containing data directory(ies) (tuple: `(avail, total)`)
+	#[serde(default)]
+	pub data_disk_avail: Option<(u64, u64)>,
+	/// Whether free space on the metadata directory or on one of the data directories
+	/// of the node is below its configured `min_free_space`, in which case it refuses
+	/// to store new blocks
+	#[serde(default)]
+	pub low_disk_space: bool,
+}
+
+/// Minimum free space on a filesystem, either an absolute value
+/// or a percentage of its total size
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MinFreeSpace {
+	Bytes(u64),
+	Percent(f64),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -195,6 +219,51 @@ pub fn gen_node_key(metadata_dir: &Path) -> Result<NodeKey, Error> {
 	}
 }
 
+impl MinFreeSpace {
+	/// Whether `avail` bytes free out of `total` is enough
+	pub fn is_satisfied(&self, avail: u64, total: u64) -> bool {
+		match *self {
+			MinFreeSpace::Bytes(min) => avail >= min,
+			MinFreeSpace::Percent(pct) => avail as f64 >= total as f64 * pct / 100.0,
+		}
+	}
+}
+
+impl std::str::FromStr for MinFreeSpace {
+	type Err = String;
+
+	/// Parse a minimum free space: either a size (e.g. `10G`)
+	/// or a percentage (e.g. `5%`)
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let s = s.trim();
+		if let Some(pct) = s.strip_suffix('%') {
+			match pct.trim().parse::<f64>() {
+				Ok(pct) if (0.0..=100.0).contains(&pct) => Ok(MinFreeSpace::Percent(pct)),
+				_ => Err(format!("Invalid percentage of free space: '{}'", s)),
+			}
+		} else {
+			s.parse::<bytesize::ByteSize>()
+				.map(|size| MinFreeSpace::Bytes(size.as_u64()))
+				.map_err(|e| format!("Invalid amount of free space '{}': {}", s, e))
+		}
+	}
+}
+
+/// Available and total space on the filesystem containing a path
+fn disk_usage(path: &Path) -> Result<(u64, u64), Error> {
+	let st = statvfs(path).map_err(std::io::Error::from)?;
+	Ok(statvfs_usage(&st))
+}
+
+// The integer types of statvfs fields depend on the platform
+#[allow(clippy::unnecessary_cast)]
+fn statvfs_usage(st: &Statvfs) -> (u64, u64) {
+	(
+		st.blocks_available() as u64 * st.fragment_size() as u64,
+		st.blocks() as u64 * st.fragment_size() as u64,
+	)
+}
+
 impl System {
 	/// Create this node's membership manager
 	pub fn new(
@@ -203,6 +272,21 @@ impl System {
 		replication_factor: usize,
 		config: &Config,
 	) -> Result<Arc<Self>, Error> {
+		let min_free_space = config
+			.min_free_space
+			.as_deref()
+			.map(str::parse::<MinFreeSpace>)
+			.transpose()
+			.map_err(Error::Message)?;
+		let data_dirs = match &config.data_dir {
+			DataDirEnum::Single(path) => vec![path.clone()],
+			DataDirEnum::Multiple(dirs) => dirs
+				.iter()
+				.filter(|dir| !dir.read_only)
+				.map(|dir| dir.path.clone())
+				.collect(),
+		};
+
 		let node_key =
 			gen_node_key(&config.metadata_dir).expect("Unable to read or generate node ID");
 		info!(
@@ -241,6 +325,9 @@ impl System {
 			replication_factor,
 			cluster_layout_version: cluster_layout.version,
 			cluster_layout_staging_hash: cluster_layout.staging_hash,
+			meta_disk_avail: None,
+			data_disk_avail: None,
+			low_disk_space: false,
 		};
 
 		let ring = Ring::new(cluster_layout, replication_factor);
@@ -338,6 +425,8 @@ impl System {
 			update_ring: Mutex::new(update_ring),
 			background,
 			metadata_dir: config.metadata_dir.clone(),
+			data_dirs,
+			min_free_space,
 		});
 		sys.system_endpoint.set_handler(sys.clone());
 		Ok(sys)
@@ -380,12 +469,51 @@ impl System {
 						replication_factor: 0,
 						cluster_layout_version: 0,
 						cluster_layout_staging_hash: Hash::from([0u8; 32]),
+						meta_disk_avail: None,
+						data_disk_avail: None,
+						low_disk_space: false,
 					}),
 			})
 			.collect::<Vec<_>>();
 		known_nodes
 	}
 
+	/// Check that there is enough free space to write new data in the given
+	/// data directory, as well as in the metadata directory
+	pub fn check_free_space(&self, data_dir: &Path) -> Result<(), Error> {
+		let min_free_space = match self.min_free_space {
+			Some(x) => x,
+			None => return Ok(()),
+		};
+		for dir in [data_dir, self.metadata_dir.as_path()] {
+			let (avail, total) = disk_usage(dir)?;
+			if !min_free_space.is_satisfied(avail, total) {
+				return Err(Error::Message(format!(
+					"Not enough free space in {}: {} available out of {}",
+					dir.display(),
+					bytesize::ByteSize::b(avail),
+					bytesize::ByteSize::b(total),
+				)));
+			}
+		}
+		Ok(())
+	}
+
+	/// Whether a node has advertised that it is low on disk space,
+	/// and will therefore refuse to store new blocks
+	pub fn is_low_on_disk_space(&self, node: &Uuid) -> bool {
+		if *node == self.id {
+			self.local_status.load().low_disk_space
+		} else {
+			self.node_status
+				.read()
+				.unwrap()
+				.get(node)
+				.map(|(_, st)| st.low_disk_space)
+				.unwrap_or(false)
+		}
+	}
+
 	pub fn get_cluster_layout(&self) -> ClusterLayout {
 		self.ring.borrow().layout.clone()
 	}
@@ -496,9 +624,59 @@ impl System {
 		let ring = self.ring.borrow();
 		new_si.cluster_layout_version = ring.layout.version;
 		new_si.cluster_layout_staging_hash = ring.layout.staging_hash;
+
+		self.update_disk_usage(&mut new_si);
+
 		self.local_status.swap(Arc::new(new_si));
 	}
 
+	fn update_disk_usage(&self, status: &mut NodeStatus) {
+		status.meta_disk_avail = match disk_usage(&self.metadata_dir) {
+			Ok(x) => Some(x),
+			Err(e) => {
+				warn!("Could not get disk usage of metadata directory: {}", e);
+				None
+			}
+		};
+
+		// Several data directories may be on the same filesystem,
+		// only count each filesystem once
+		let mut filesystems = HashMap::new();
+		for dir in self.data_dirs.iter() {
+			match statvfs(dir) {
+				Ok(st) => {
+					filesystems.insert(st.filesystem_id(), statvfs_usage(&st));
+				}
+				Err(e) => warn!(
+					"Could not get disk usage of data directory {}: {}",
+					dir.display(),
+					e
+				),
+			}
+		}
+		status.data_disk_avail = if filesystems.is_empty() {
+			None
+		} else {
+			Some(
+				filesystems
+					.values()
+					.fold((0, 0), |(a, t), (a2, t2)| (a + a2, t + t2)),
+			)
+		};
+
+		// The threshold applies to each filesystem separately: the sum of the
+		// free space of all data directories may be above it while one of them,
+		// to which some blocks are written, is full
+		status.low_disk_space = match self.min_free_space {
+			Some(min_free_space) => status
+				.meta_disk_avail
+				.iter()
+				.chain(filesystems.values())
+				.any(|(avail, total)| !min_free_space.is_satisfied(*avail, *total)),
+			None => false,
+		};
+	}
+
 	// --- RPC HANDLERS ---
 
 	async fn handle_connect(&self, node: &str) -> Result<SystemRpc, Error> {
@@ -784,3 +962,24 @@ async fn resolve_peers(peers: &[String]) -> Vec<(NodeID, SocketAddr)> {
 
 	ret
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_min_free_space() {
+		let pct = "5%".parse::<MinFreeSpace>().unwrap();
+		assert_eq!(pct, MinFreeSpace::Percent(5.0));
+		assert!(pct.is_satisfied(50, 1000));
+		assert!(!pct.is_satisfied(49, 1000));
+
+		let bytes = "1K".parse::<MinFreeSpace>().unwrap();
+		assert_eq!(bytes, MinFreeSpace::Bytes(1000));
+		assert!(bytes.is_satisfied(1000, 1000));
+		assert!(!bytes.is_satisfied(999, 1_000_000));
+
+		assert!("150%".parse::<MinFreeSpace>().is_err());
+		assert!("lots".parse::<MinFreeSpace>().is_err());
+	}
+}
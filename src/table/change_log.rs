@@ -0,0 +1,369 @@
+//! Durable log of the changes made to the entries of tables on this node,
+//! that external systems can read to react to these changes
+
+use std::convert::TryInto;
+use std::ops::Bound;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use tokio::sync::watch;
+
+use garage_db as db;
+
+use garage_util::background::*;
+use garage_util::data::*;
+use garage_util::error::*;
+use garage_util::time::now_msec;
+
+/// Duration for which changes are kept in the log, if not configured otherwise
+pub const DEFAULT_CHANGE_LOG_RETENTION: Duration = Duration::from_secs(7 * 24 * 3600);
+
+// Remove old changes from the log every hour
+const CHANGE_LOG_TRIM_INTERVAL: Duration = Duration::from_secs(3600);
+const CHANGE_LOG_TRIM_BATCH_SIZE: usize = 1000;
+
+/// The change log of this node. Changes are identified by a sequence number,
+/// that increases with each change recorded on this node.
+pub struct ChangeLog {
+	tree: db::Tree,
+	// Holds the sequence number of the last change recorded, under the key
+	// LAST_SEQ_KEY. It is read and written in the transaction that records
+	// a change, so that changes are committed in the order of their
+	// sequence numbers and that sequence numbers are never reused.
+	seq_tree: db::Tree,
+	last_seq: watch::Sender<u64>,
+	last_seq_lock: Mutex<()>,
+}
+
+const LAST_SEQ_KEY: &[u8] = b"last_seq";
+
+/// A change of an entry of a table
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Change {
+	/// Time at which the change was made on this node
+	pub timestamp: u64,
+	/// Name of the table
+	pub table: String,
+	/// Hash of the partition key of the entry
+	pub partition: Hash,
+	/// Sort key of the entry
+	pub sort_key: ByteBuf,
+	/// Whether the entry was a tombstone before the change,
+	/// None if the entry did not exist
+	pub old_tombstone: Option<bool>,
+	/// Whether the entry is a tombstone after the change,
+	/// None if the entry was removed from this node
+	pub new_tombstone: Option<bool>,
+}
+
+impl ChangeLog {
+	/// Open the change log of this node. Changes older than `retention`
+	/// are regularly removed from the log.
+	pub fn new(db: &db::Db, retention: Duration, background: &BackgroundRunner) -> Arc<Self> {
+		let change_log = Arc::new(Self::open(db).expect("Unable to open change log"));
+
+		background.spawn_worker(ChangeLogTrimWorker {
+			change_log: change_log.clone(),
+			retention,
+		});
+
+		change_log
+	}
+
+	fn open(db: &db::Db) -> Result<Self, Error> {
+		let tree = db.open_tree("table_change_log")?;
+		let seq_tree = db.open_tree("table_change_log_seq")?;
+
+		let last_seq = last_seq(db)?;
+
+		Ok(Self {
+			tree,
+			seq_tree,
+			last_seq: watch::channel(last_seq).0,
+			last_seq_lock: Mutex::new(()),
+		})
+	}
+
+	/// Read at most `limit` changes, starting after the change with sequence
+	/// number `after` (or from the oldest change in the log if `after` is None).
+	/// Returns the changes with their sequence numbers.
+	pub fn read(&self, after: Option<u64>, limit: usize) -> Result<Vec<(u64, Change)>, Error> {
+		let low_bound = match after {
+			Some(seq) => Bound::Excluded(seq.to_be_bytes()),
+			None => Bound::Unbounded,
+		};
+		let mut ret = vec![];
+		for item in self.tree.range((low_bound, Bound::Unbounded))? {
+			let (k, v) = item?;
+			let change = rmp_serde::decode::from_read_ref::<_, Change>(&v)?;
+			ret.push((seq_of_key(&k), change));
+			if ret.len() >= limit {
+				break;
+			}
+		}
+		Ok(ret)
+	}
+
+	/// Get a receiver that is notified of the sequence number
+	/// of the last change recorded in the log
+	pub fn watch_last_seq(&self) -> watch::Receiver<u64> {
+		self.last_seq.subscribe()
+	}
+
+	/// Record a change as part of the transaction that modifies the entry,
+	/// returns its sequence number. `notify` must be called with this
+	/// sequence number once the transaction is committed.
+	pub(crate) fn record(
+		&self,
+		tx: &mut db::Transaction,
+		change: &Change,
+	) -> db::TxResult<u64, Error> {
+		let seq = match tx.get(&self.seq_tree, LAST_SEQ_KEY)? {
+			Some(v) => seq_of_key(&v) + 1,
+			None => 1,
+		};
+		let bytes = rmp_to_vec_all_named(change)
+			.map_err(Error::RmpEncode)
+			.map_err(db::TxError::Abort)?;
+		tx.insert(&self.seq_tree, LAST_SEQ_KEY, seq.to_be_bytes())?;
+		tx.insert(&self.tree, seq.to_be_bytes(), bytes)?;
+		Ok(seq)
+	}
+
+	/// Notify the readers of the log that the change with sequence number
+	/// `seq` has been committed
+	pub(crate) fn notify(&self, seq: u64) {
+		// Transactions may finish in any order once committed,
+		// make sure the last sequence number never goes back
+		let _lock = self.last_seq_lock.lock().unwrap();
+		if seq > *self.last_seq.borrow() {
+			self.last_seq.send_replace(seq);
+		}
+	}
+
+	// Remove a batch of changes older than `retention`,
+	// returns the number of changes removed
+	fn trim(&self, retention: Duration) -> Result<usize, Error> {
+		self.trim_before(now_msec().saturating_sub(retention.as_millis() as u64))
+	}
+
+	fn trim_before(&self, limit: u64) -> Result<usize, Error> {
+		let mut to_remove = vec![];
+		for item in self.tree.iter()? {
+			let (k, v) = item?;
+			let change = rmp_serde::decode::from_read_ref::<_, Change>(&v)?;
+			if change.timestamp >= limit || to_remove.len() >= CHANGE_LOG_TRIM_BATCH_SIZE {
+				break;
+			}
+			to_remove.push(k);
+		}
+
+		for k in to_remove.iter() {
+			self.tree.remove(k)?;
+		}
+		Ok(to_remove.len())
+	}
+}
+
+/// Get the sequence number of the last change recorded in the change log
+/// of a metadata database, without opening the change log
+pub fn last_seq(db: &db::Db) -> Result<u64, Error> {
+	let seq_tree = db.open_tree("table_change_log_seq")?;
+	Ok(match seq_tree.get(LAST_SEQ_KEY)? {
+		Some(v) => seq_of_key(&v),
+		None => 0,
+	})
+}
+
+/// Make sure that the changes recorded in the change log of a metadata database
+/// get sequence numbers higher than `seq`. This is used when the database is
+/// replaced by an older snapshot, so that sequence numbers that readers of the
+/// log have already seen are not given to other changes.
+pub fn advance_last_seq(db: &db::Db, seq: u64) -> Result<(), Error> {
+	let seq_tree = db.open_tree("table_change_log_seq")?;
+	db.transaction::<_, Error, _>(|mut tx| {
+		let current = match tx.get(&seq_tree, LAST_SEQ_KEY)? {
+			Some(v) => seq_of_key(&v),
+			None => 0,
+		};
+		if current < seq {
+			tx.insert(&seq_tree, LAST_SEQ_KEY, seq.to_be_bytes())?;
+		}
+		tx.commit(())
+	})?;
+	Ok(())
+}
+
+fn seq_of_key(k: &[u8]) -> u64 {
+	u64::from_be_bytes(k.try_into().unwrap())
+}
+
+struct ChangeLogTrimWorker {
+	change_log: Arc<ChangeLog>,
+	retention: Duration,
+}
+
+#[async_trait]
+impl Worker for ChangeLogTrimWorker {
+	fn name(&self) -> String {
+		"Change log trimmer".into()
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		let change_log = self.change_log.clone();
+		let retention = self.retention;
+		let removed = tokio::task::spawn_blocking(move || change_log.trim(retention)).await??;
+		if removed > 0 {
+			debug!("Removed {} changes from the change log", removed);
+			Ok(WorkerState::Busy)
+		} else {
+			Ok(WorkerState::Idle)
+		}
+	}
+
+	async fn wait_for_work(&mut self, must_exit: &watch::Receiver<bool>) -> WorkerState {
+		if *must_exit.borrow() {
+			return WorkerState::Done;
+		}
+		tokio::time::sleep(CHANGE_LOG_TRIM_INTERVAL).await;
+		WorkerState::Busy
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use garage_db::sled_adapter::{sled, SledDb};
+
+	fn test_change(timestamp: u64, sort_key: &str) -> Change {
+		Change {
+			timestamp,
+			table: "object".into(),
+			partition: blake2sum(b"bucket"),
+			sort_key: ByteBuf::from(sort_key.as_bytes().to_vec()),
+			old_tombstone: None,
+			new_tombstone: Some(false),
+		}
+	}
+
+	fn record(db: &db::Db, change_log: &ChangeLog, change: &Change) -> u64 {
+		let seq = db
+			.transaction(|mut tx| {
+				let seq = change_log.record(&mut tx, change)?;
+				tx.commit(seq)
+			})
+			.map_err(Error::from)
+			.unwrap();
+		change_log.notify(seq);
+		seq
+	}
+
+	fn read_keys(change_log: &ChangeLog, after: Option<u64>, limit: usize) -> Vec<(u64, String)> {
+		change_log
+			.read(after, limit)
+			.unwrap()
+			.into_iter()
+			.map(|(seq, c)| (seq, String::from_utf8(c.sort_key.into_vec()).unwrap()))
+			.collect()
+	}
+
+	#[test]
+	fn test_change_log_record_and_read() {
+		let db = SledDb::init(sled::Config::default().temporary(true).open().unwrap());
+		let change_log = ChangeLog::open(&db).unwrap();
+		let last_seq = change_log.watch_last_seq();
+
+		assert!(change_log.read(None, 10).unwrap().is_empty());
+
+		assert_eq!(record(&db, &change_log, &test_change(100, "a")), 1);
+		assert_eq!(record(&db, &change_log, &test_change(200, "b")), 2);
+		assert_eq!(record(&db, &change_log, &test_change(300, "c")), 3);
+		assert_eq!(*last_seq.borrow(), 3);
+
+		let changes = change_log.read(None, 10).unwrap();
+		assert_eq!(changes.len(), 3);
+		assert_eq!(changes[1].0, 2);
+		assert_eq!(changes[1].1.timestamp, 200);
+		assert_eq!(changes[1].1.new_tombstone, Some(false));
+
+		// Read with a cursor
+		assert_eq!(
+			read_keys(&change_log, Some(1), 1),
+			vec![(2, "b".to_string())]
+		);
+		assert_eq!(
+			read_keys(&change_log, Some(2), 10),
+			vec![(3, "c".to_string())]
+		);
+		assert!(read_keys(&change_log, Some(3), 10).is_empty());
+
+		// Sequence numbers continue when the log is reopened
+		drop(change_log);
+		let change_log = ChangeLog::open(&db).unwrap();
+		assert_eq!(*change_log.watch_last_seq().borrow(), 3);
+		assert_eq!(record(&db, &change_log, &test_change(400, "d")), 4);
+	}
+
+	#[test]
+	fn test_change_log_aborted_transaction() {
+		let db = SledDb::init(sled::Config::default().temporary(true).open().unwrap());
+		let change_log = ChangeLog::open(&db).unwrap();
+
+		let res = db.transaction::<(), _, _>(|mut tx| {
+			change_log.record(&mut tx, &test_change(100, "a"))?;
+			tx.abort(Error::Message("aborted".into()))
+		});
+		assert!(res.is_err());
+		assert!(change_log.read(None, 10).unwrap().is_empty());
+
+		assert_eq!(record(&db, &change_log, &test_change(200, "b")), 1);
+	}
+
+	#[test]
+	fn test_change_log_trim() {
+		let db = SledDb::init(sled::Config::default().temporary(true).open().unwrap());
+		let change_log = ChangeLog::open(&db).unwrap();
+
+		record(&db, &change_log, &test_change(100, "a"));
+		record(&db, &change_log, &test_change(200, "b"));
+		record(&db, &change_log, &test_change(300, "c"));
+
+		assert_eq!(change_log.trim_before(250).unwrap(), 2);
+		assert_eq!(read_keys(&change_log, None, 10), vec![(3, "c".to_string())]);
+		assert_eq!(change_log.trim_before(250).unwrap(), 0);
+
+		// Sequence numbers are not reused once all changes are removed
+		assert_eq!(change_log.trim_before(1000).unwrap(), 1);
+		assert!(change_log.read(None, 10).unwrap().is_empty());
+		assert_eq!(record(&db, &change_log, &test_change(400, "d")), 4);
+	}
+
+	#[test]
+	fn test_change_log_advance_last_seq() {
+		let db = SledDb::init(sled::Config::default().temporary(true).open().unwrap());
+		{
+			let change_log = ChangeLog::open(&db).unwrap();
+			record(&db, &change_log, &test_change(100, "a"));
+		}
+		assert_eq!(last_seq(&db).unwrap(), 1);
+
+		// E.g. the database was restored from a snapshot taken before
+		// changes 2 to 10 were recorded
+		advance_last_seq(&db, 10).unwrap();
+		assert_eq!(last_seq(&db).unwrap(), 10);
+		advance_last_seq(&db, 5).unwrap();
+		assert_eq!(last_seq(&db).unwrap(), 10);
+
+		let change_log = ChangeLog::open(&db).unwrap();
+		assert_eq!(*change_log.watch_last_seq().borrow(), 10);
+		assert_eq!(record(&db, &change_log, &test_change(200, "b")), 11);
+		assert_eq!(
+			read_keys(&change_log, None, 10),
+			vec![(1, "a".to_string()), (11, "b".to_string())]
+		);
+	}
+}
@@ -1,7 +1,9 @@
 use core::borrow::Borrow;
+use std::collections::HashSet;
 use std::convert::TryInto;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
+use arc_swap::ArcSwapOption;
 use serde_bytes::ByteBuf;
 use tokio::sync::Notify;
 
@@ -10,11 +12,14 @@ use garage_db::counted_tree_hack::CountedTree;
 
 use garage_util::data::*;
 use garage_util::error::*;
+use garage_util::time::now_msec;
 
 use garage_rpc::system::System;
 
+use crate::change_log::*;
 use crate::crdt::Crdt;
 use crate::gc::GcTodoEntry;
+use crate::index::*;
 use crate::metrics::*;
 use crate::replication::*;
 use crate::schema::*;
@@ -28,11 +33,23 @@ pub struct TableData<F: TableSchema, R: TableReplication> {
 
 	pub store: db::Tree,
 
+	// Secondary indexes declared in F::INDEXES
+	pub(crate) indexes: TableIndexes<F>,
+
 	pub(crate) merkle_tree: db::Tree,
 	pub(crate) merkle_todo: db::Tree,
 	pub(crate) merkle_todo_notify: Notify,
 	pub(crate) gc_todo: CountedTree,
 
+	// Keys of the entries that were last modified by the sync of their partition
+	// with other nodes, and whose Merkle tree entry has not been updated yet.
+	// The Merkle updater does not count these modifications as changes of the
+	// partition, so that the incremental sync does not sync it again.
+	sync_updates: Mutex<HashSet<Vec<u8>>>,
+
+	// Change log in which updates of entries are recorded, if enabled for this table
+	change_log: ArcSwapOption<ChangeLog>,
+
 	pub(crate) metrics: TableMetrics,
 }
 
@@ -46,6 +63,8 @@ where
 			.open_tree(&format!("{}:table", F::TABLE_NAME))
 			.expect("Unable to open DB tree");
 
+		let indexes = TableIndexes::open(db).expect("Unable to open DB index trees");
+
 		let merkle_tree = db
 			.open_tree(&format!("{}:merkle_tree", F::TABLE_NAME))
 			.expect("Unable to open DB Merkle tree tree");
@@ -60,17 +79,37 @@ where
 
 		let metrics = TableMetrics::new(F::TABLE_NAME, merkle_todo.clone(), gc_todo.clone());
 
-		Arc::new(Self {
+		let data = Arc::new(Self {
 			system,
 			instance,
 			replication,
 			store,
+			indexes,
 			merkle_tree,
 			merkle_todo,
 			merkle_todo_notify: Notify::new(),
 			gc_todo,
+			sync_updates: Mutex::new(HashSet::new()),
+			change_log: ArcSwapOption::new(None),
 			metrics,
-		})
+		});
+
+		data.indexes
+			.build(&data.store, |bytes| data.decode_entry(bytes))
+			.expect("Unable to build DB index trees");
+
+		data
+	}
+
+	/// Whether the last modification of an entry was made by the sync of its
+	/// partition, called by the Merkle updater when it processes the entry
+	pub(crate) fn take_sync_update(&self, tree_key: &[u8]) -> bool {
+		self.sync_updates.lock().unwrap().remove(tree_key)
+	}
+
+	/// Record the changes made to the entries of this table in a change log
+	pub fn set_change_log(&self, change_log: Arc<ChangeLog>) {
+		self.change_log.store(Some(change_log));
 	}
 
 	// Read functions
@@ -149,6 +188,39 @@ where
 		Ok(ret)
 	}
 
+	pub fn read_index_range(
+		&self,
+		index: &str,
+		index_key: &[u8],
+		begin: &Option<(F::P, F::S)>,
+		filter: &Option<F::Filter>,
+		limit: usize,
+	) -> Result<Vec<Arc<ByteBuf>>, Error> {
+		let begin = begin.as_ref().map(|(pk, sk)| self.tree_key(pk, sk));
+
+		let mut ret = vec![];
+		for tree_key in self.indexes.read(index, index_key, begin.as_deref())? {
+			let value = match self.store.get(&tree_key?)? {
+				Some(v) => v,
+				None => continue,
+			};
+			let keep = match filter {
+				None => true,
+				Some(f) => {
+					let entry = self.decode_entry(value.as_ref())?;
+					F::matches_filter(&entry, f)
+				}
+			};
+			if keep {
+				ret.push(Arc::new(ByteBuf::from(value)));
+			}
+			if ret.len() >= limit {
+				break;
+			}
+		}
+		Ok(ret)
+	}
+
 	// Mutation functions
 	// When changing this code, take care of propagating modifications correctly:
 	// - When an entry is modified or deleted, call the updated() function
@@ -157,19 +229,35 @@ where
 	//   This has to be done atomically with the modification for the merkle updater
 	//   to maintain consistency. The merkle updater must then be notified with todo_notify.
 	// - When an entry is updated to be a tombstone, add it to the gc_todo tree
+	// - When an entry is modified or deleted, update the secondary indexes
+	//   of the table atomically with the modification, with indexes.update()
+	// - When the value of an entry is modified or the entry is deleted, record
+	//   the change in the change log if there is one, atomically with the modification
 
 	pub(crate) fn update_many<T: Borrow<ByteBuf>>(&self, entries: &[T]) -> Result<(), Error> {
 		for update_bytes in entries.iter() {
-			self.update_entry(update_bytes.borrow().as_slice())?;
+			self.update_entry(update_bytes.borrow().as_slice(), false)?;
+		}
+		Ok(())
+	}
+
+	/// Same as `update_many`, for entries received from another node
+	/// while syncing their partition
+	pub(crate) fn update_many_from_sync<T: Borrow<ByteBuf>>(
+		&self,
+		entries: &[T],
+	) -> Result<(), Error> {
+		for update_bytes in entries.iter() {
+			self.update_entry(update_bytes.borrow().as_slice(), true)?;
 		}
 		Ok(())
 	}
 
-	pub(crate) fn update_entry(&self, update_bytes: &[u8]) -> Result<(), Error> {
+	pub(crate) fn update_entry(&self, update_bytes: &[u8], from_sync: bool) -> Result<(), Error> {
 		let update = self.decode_entry(update_bytes)?;
 		let tree_key = self.tree_key(update.partition_key(), update.sort_key());
 
-		self.update_entry_with(&tree_key[..], |ent| match ent {
+		self.update_entry_with_origin(&tree_key[..], from_sync, |ent| match ent {
 			Some(mut ent) => {
 				ent.merge(&update);
 				ent
@@ -184,6 +272,28 @@ where
 		tree_key: &[u8],
 		f: impl Fn(Option<F::E>) -> F::E,
 	) -> Result<Option<F::E>, Error> {
+		self.update_entry_with_origin(tree_key, false, f)
+	}
+
+	fn update_entry_with_origin(
+		&self,
+		tree_key: &[u8],
+		from_sync: bool,
+		f: impl Fn(Option<F::E>) -> F::E,
+	) -> Result<Option<F::E>, Error> {
+		let change_log = self.change_log.load_full();
+
+		// Mark the entry before it is added to the Merkle todo list,
+		// as the Merkle updater may process it right after
+		{
+			let mut sync_updates = self.sync_updates.lock().unwrap();
+			if from_sync {
+				sync_updates.insert(tree_key.to_vec());
+			} else {
+				sync_updates.remove(tree_key);
+			}
+		}
+
 		let changed = self.store.db().transaction(|mut tx| {
 			let (old_entry, old_bytes, new_entry) = match tx.get(&self.store, tree_key)? {
 				Some(old_bytes) => {
@@ -212,18 +322,35 @@ where
 				tx.insert(&self.merkle_todo, tree_key, new_bytes_hash.as_slice())?;
 				tx.insert(&self.store, tree_key, new_bytes)?;
 
+				self.indexes
+					.update(&mut tx, tree_key, old_entry.as_ref(), Some(&new_entry))?;
 				self.instance
 					.updated(&mut tx, old_entry.as_ref(), Some(&new_entry))?;
 
-				Ok(Some((new_entry, new_bytes_hash)))
+				let change_seq = match (&change_log, value_changed) {
+					(Some(change_log), true) => Some(self.record_change(
+						change_log,
+						&mut tx,
+						tree_key,
+						old_entry.as_ref(),
+						Some(&new_entry),
+					)?),
+					_ => None,
+				};
+
+				Ok(Some((new_entry, new_bytes_hash, change_seq)))
 			} else {
 				Ok(None)
 			}
 		})?;
 
-		if let Some((new_entry, new_bytes_hash)) = changed {
+		if let Some((new_entry, new_bytes_hash, change_seq)) = changed {
 			self.metrics.internal_update_counter.add(1);
 
+			if let (Some(change_log), Some(seq)) = (&change_log, change_seq) {
+				change_log.notify(seq);
+			}
+
 			let is_tombstone = new_entry.is_tombstone();
 			self.merkle_todo_notify.notify_one();
 			if is_tombstone {
@@ -242,11 +369,16 @@ where
 
 			Ok(Some(new_entry))
 		} else {
+			if from_sync {
+				self.sync_updates.lock().unwrap().remove(tree_key);
+			}
 			Ok(None)
 		}
 	}
 
 	pub(crate) fn delete_if_equal(self: &Arc<Self>, k: &[u8], v: &[u8]) -> Result<bool, Error> {
+		let change_log = self.change_log.load_full();
+
 		let removed = self
 			.store
 			.db()
@@ -256,17 +388,32 @@ where
 					tx.insert(&self.merkle_todo, k, vec![])?;
 
 					let old_entry = self.decode_entry(v).map_err(db::TxError::Abort)?;
+					self.indexes.update(&mut tx, k, Some(&old_entry), None)?;
 					self.instance.updated(&mut tx, Some(&old_entry), None)?;
-					Ok(true)
+
+					let change_seq = match &change_log {
+						Some(change_log) => Some(self.record_change(
+							change_log,
+							&mut tx,
+							k,
+							Some(&old_entry),
+							None,
+						)?),
+						None => None,
+					};
+					Ok(Some(change_seq))
 				}
-				_ => Ok(false),
+				_ => Ok(None),
 			})?;
 
-		if removed {
+		if let Some(change_seq) = removed {
 			self.metrics.internal_delete_counter.add(1);
 			self.merkle_todo_notify.notify_one();
+			if let (Some(change_log), Some(seq)) = (&change_log, change_seq) {
+				change_log.notify(seq);
+			}
 		}
-		Ok(removed)
+		Ok(removed.is_some())
 	}
 
 	pub(crate) fn delete_if_equal_hash(
@@ -274,6 +421,8 @@ where
 		k: &[u8],
 		vhash: Hash,
 	) -> Result<bool, Error> {
+		let change_log = self.change_log.load_full();
+
 		let removed = self
 			.store
 			.db()
@@ -283,25 +432,57 @@ where
 					tx.insert(&self.merkle_todo, k, vec![])?;
 
 					let old_entry = self.decode_entry(&cur_v[..]).map_err(db::TxError::Abort)?;
+					self.indexes.update(&mut tx, k, Some(&old_entry), None)?;
 					self.instance.updated(&mut tx, Some(&old_entry), None)?;
-					Ok(true)
+
+					let change_seq = match &change_log {
+						Some(change_log) => Some(self.record_change(
+							change_log,
+							&mut tx,
+							k,
+							Some(&old_entry),
+							None,
+						)?),
+						None => None,
+					};
+					Ok(Some(change_seq))
 				}
-				_ => Ok(false),
+				_ => Ok(None),
 			})?;
 
-		if removed {
+		if let Some(change_seq) = removed {
 			self.metrics.internal_delete_counter.add(1);
 			self.merkle_todo_notify.notify_one();
+			if let (Some(change_log), Some(seq)) = (&change_log, change_seq) {
+				change_log.notify(seq);
+			}
 		}
-		Ok(removed)
+		Ok(removed.is_some())
+	}
+
+	fn record_change(
+		&self,
+		change_log: &ChangeLog,
+		tx: &mut db::Transaction,
+		tree_key: &[u8],
+		old: Option<&F::E>,
+		new: Option<&F::E>,
+	) -> db::TxResult<u64, Error> {
+		let change = Change {
+			timestamp: now_msec(),
+			table: F::TABLE_NAME.to_string(),
+			partition: Hash::try_from(&tree_key[..32]).unwrap(),
+			sort_key: ByteBuf::from(tree_key[32..].to_vec()),
+			old_tombstone: old.map(|e| e.is_tombstone()),
+			new_tombstone: new.map(|e| e.is_tombstone()),
+		};
+		change_log.record(tx, &change)
 	}
 
 	// ---- Utility functions ----
 
 	pub fn tree_key(&self, p: &F::P, s: &F::S) -> Vec<u8> {
-		let mut ret = p.hash().to_vec();
-		ret.extend(s.sort_key());
-		ret
+		entry_tree_key::<F>(p, s)
 	}
 
 	pub fn decode_entry(&self, bytes: &[u8]) -> Result<F::E, Error> {
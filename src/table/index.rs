@@ -0,0 +1,485 @@
+//! Secondary indexes of the entries of a table, declared by its schema
+
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::ops::Bound;
+
+use garage_db as db;
+
+use garage_util::data::*;
+use garage_util::error::*;
+
+use crate::crdt::Crdt;
+use crate::schema::*;
+
+/// The secondary indexes of a table. Each index is stored in a tree
+/// whose keys are the hash of the index key followed by the key
+/// of the entry in the store of the table.
+pub(crate) struct TableIndexes<F: TableSchema> {
+	trees: Vec<(&'static str, db::Tree)>,
+	// Names of the indexes that contain all the entries of the store,
+	// the other indexes are built when the table is opened
+	built: db::Tree,
+	_phantom: PhantomData<F>,
+}
+
+impl<F: TableSchema> TableIndexes<F> {
+	pub(crate) fn open(db: &db::Db) -> Result<Self, Error> {
+		let trees = F::INDEXES
+			.iter()
+			.map(|index| {
+				let tree = db.open_tree(&format!("{}:index:{}", F::TABLE_NAME, index))?;
+				Ok((*index, tree))
+			})
+			.collect::<Result<Vec<_>, Error>>()?;
+		let built = db.open_tree(&format!("{}:index_built", F::TABLE_NAME))?;
+
+		Ok(Self {
+			trees,
+			built,
+			_phantom: PhantomData,
+		})
+	}
+
+	/// Index the entries of the store in the indexes that were added
+	/// to the schema since the table was last opened
+	pub(crate) fn build(
+		&self,
+		store: &db::Tree,
+		decode_entry: impl Fn(&[u8]) -> Result<F::E, Error>,
+	) -> Result<(), Error> {
+		// Indexes removed from the schema are not maintained anymore,
+		// they have to be built again if they are added back
+		let removed = self
+			.built
+			.iter()?
+			.map(|item| item.map(|(k, _)| k))
+			.collect::<Result<Vec<_>, _>>()?
+			.into_iter()
+			.filter(|k| !F::INDEXES.iter().any(|index| index.as_bytes() == &k[..]))
+			.collect::<Vec<_>>();
+		for k in removed {
+			self.built.remove(k)?;
+		}
+
+		for (index, tree) in self.trees.iter() {
+			if self.built.get(index)?.is_some() {
+				continue;
+			}
+
+			if store.first()?.is_some() {
+				info!("({}) Building index {}...", F::TABLE_NAME, index);
+				// Remove what was indexed by an interrupted build,
+				// or before the index was removed from the schema
+				tree.clear()?;
+				self.build_index(store, index, tree, &decode_entry)?;
+			}
+			self.built.insert(index, vec![])?;
+		}
+		Ok(())
+	}
+
+	fn build_index(
+		&self,
+		store: &db::Tree,
+		index: &str,
+		tree: &db::Tree,
+		decode_entry: impl Fn(&[u8]) -> Result<F::E, Error>,
+	) -> Result<(), Error> {
+		let mut last_key: Option<Vec<u8>> = None;
+		loop {
+			// Entries are read in batches, so that we don't write in the index
+			// while iterating on the store
+			let batch = match &last_key {
+				None => store.iter()?,
+				Some(k) => store.range::<&[u8], _>((Bound::Excluded(&k[..]), Bound::Unbounded))?,
+			}
+			.take(1000)
+			.collect::<Result<Vec<_>, _>>()?;
+
+			for (tree_key, value) in batch.iter() {
+				let entry = decode_entry(value)?;
+				for k in entry_index_keys::<F>(index, &entry) {
+					tree.insert(index_tree_key(&blake2sum(&k), tree_key), vec![])?;
+				}
+			}
+
+			match batch.into_iter().last() {
+				Some((k, _)) => last_key = Some(k),
+				None => break,
+			}
+		}
+		Ok(())
+	}
+
+	/// Update the indexes when an entry changes from `old` to `new`,
+	/// as part of the transaction that modifies the entry
+	pub(crate) fn update(
+		&self,
+		tx: &mut db::Transaction,
+		tree_key: &[u8],
+		old: Option<&F::E>,
+		new: Option<&F::E>,
+	) -> db::TxOpResult<()> {
+		for (index, tree) in self.trees.iter() {
+			let old_keys = old
+				.map(|e| entry_index_keys::<F>(index, e))
+				.unwrap_or_default();
+			let new_keys = new
+				.map(|e| entry_index_keys::<F>(index, e))
+				.unwrap_or_default();
+			for k in old_keys.iter().filter(|k| !new_keys.contains(k)) {
+				tx.remove(tree, index_tree_key(&blake2sum(k), tree_key))?;
+			}
+			for k in new_keys.iter().filter(|k| !old_keys.contains(k)) {
+				tx.insert(tree, index_tree_key(&blake2sum(k), tree_key), vec![])?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Iterate on the keys in the store of the entries that have key
+	/// `index_key` in index `index`, in order, starting at key `begin`
+	pub(crate) fn read<'a>(
+		&'a self,
+		index: &str,
+		index_key: &[u8],
+		begin: Option<&[u8]>,
+	) -> Result<impl Iterator<Item = Result<Vec<u8>, Error>> + 'a, Error> {
+		let tree = self
+			.trees
+			.iter()
+			.find(|(name, _)| *name == index)
+			.map(|(_, tree)| tree)
+			.ok_or_else(|| {
+				Error::Message(format!("Table {} has no index {}", F::TABLE_NAME, index))
+			})?;
+
+		let prefix = blake2sum(index_key);
+		let first_key = match begin {
+			None => prefix.to_vec(),
+			Some(k) => index_tree_key(&prefix, k),
+		};
+
+		let iter = tree
+			.range(first_key..)?
+			.map(|item| item.map_err(Error::from))
+			.take_while(move |item| match item {
+				Ok((k, _)) => &k[..32] == prefix.as_slice(),
+				Err(_) => true,
+			})
+			.map(|item| item.map(|(k, _)| k[32..].to_vec()));
+		Ok(iter)
+	}
+}
+
+pub(crate) fn entry_index_keys<F: TableSchema>(index: &str, entry: &F::E) -> Vec<Vec<u8>> {
+	if entry.is_tombstone() {
+		vec![]
+	} else {
+		F::index_keys(index, entry)
+	}
+}
+
+fn index_tree_key(index_key_hash: &Hash, tree_key: &[u8]) -> Vec<u8> {
+	let mut ret = index_key_hash.to_vec();
+	ret.extend(tree_key);
+	ret
+}
+
+pub(crate) fn entry_tree_key<F: TableSchema>(p: &F::P, s: &F::S) -> Vec<u8> {
+	let mut ret = p.hash().to_vec();
+	ret.extend(s.sort_key());
+	ret
+}
+
+/// Merge the entries of a range of an index returned by several nodes,
+/// each of which returned at most `limit` entries ordered by their key.
+/// If some nodes returned `limit` entries, they might have more entries
+/// after their last one: only the entries up to the smallest of these
+/// last keys are returned, with this key, from which the range continues.
+pub(crate) fn merge_index_range<F: TableSchema>(
+	responses: Vec<Vec<F::E>>,
+	limit: usize,
+) -> (BTreeMap<Vec<u8>, F::E>, Option<Vec<u8>>) {
+	let mut ret: BTreeMap<Vec<u8>, F::E> = BTreeMap::new();
+	let mut end: Option<Vec<u8>> = None;
+
+	for entries in responses {
+		if entries.len() >= limit {
+			if let Some(last) = entries.last() {
+				let last_key = entry_tree_key::<F>(last.partition_key(), last.sort_key());
+				if end.as_ref().map(|e| last_key < *e).unwrap_or(true) {
+					end = Some(last_key);
+				}
+			}
+		}
+		for entry in entries {
+			let entry_key = entry_tree_key::<F>(entry.partition_key(), entry.sort_key());
+			match ret.get_mut(&entry_key) {
+				Some(e) => e.merge(&entry),
+				None => {
+					ret.insert(entry_key, entry);
+				}
+			}
+		}
+	}
+
+	if let Some(end) = &end {
+		ret.retain(|k, _| k <= end);
+	}
+	(ret, end)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use serde::{Deserialize, Serialize};
+
+	use garage_db::sled_adapter::{sled, SledDb};
+
+	#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+	struct TestEntry {
+		pk: String,
+		sk: String,
+		tags: Vec<String>,
+		deleted: bool,
+	}
+
+	impl Crdt for TestEntry {
+		fn merge(&mut self, other: &Self) {
+			self.deleted = self.deleted || other.deleted;
+			for t in other.tags.iter() {
+				if !self.tags.contains(t) {
+					self.tags.push(t.clone());
+				}
+			}
+		}
+	}
+
+	impl Entry<String, String> for TestEntry {
+		fn partition_key(&self) -> &String {
+			&self.pk
+		}
+		fn sort_key(&self) -> &String {
+			&self.sk
+		}
+		fn is_tombstone(&self) -> bool {
+			self.deleted
+		}
+	}
+
+	struct TestTable;
+
+	impl TableSchema for TestTable {
+		const TABLE_NAME: &'static str = "test";
+
+		type P = String;
+		type S = String;
+		type E = TestEntry;
+		type Filter = ();
+
+		const INDEXES: &'static [&'static str] = &["tag"];
+
+		fn index_keys(index: &str, entry: &TestEntry) -> Vec<Vec<u8>> {
+			match index {
+				"tag" => entry.tags.iter().map(|t| t.as_bytes().to_vec()).collect(),
+				_ => vec![],
+			}
+		}
+
+		fn matches_filter(_entry: &TestEntry, _filter: &()) -> bool {
+			true
+		}
+	}
+
+	fn entry(pk: &str, sk: &str, tags: &[&str]) -> TestEntry {
+		TestEntry {
+			pk: pk.to_string(),
+			sk: sk.to_string(),
+			tags: tags.iter().map(|t| t.to_string()).collect(),
+			deleted: false,
+		}
+	}
+
+	fn tree_key(e: &TestEntry) -> Vec<u8> {
+		entry_tree_key::<TestTable>(&e.pk, &e.sk)
+	}
+
+	fn decode(bytes: &[u8]) -> Result<TestEntry, Error> {
+		Ok(rmp_serde::decode::from_read_ref(bytes)?)
+	}
+
+	// Write an entry in the store and update the indexes, as TableData does
+	fn write(
+		db: &db::Db,
+		store: &db::Tree,
+		indexes: &TableIndexes<TestTable>,
+		tree_key: &[u8],
+		new: Option<&TestEntry>,
+	) {
+		db.transaction::<_, Error, _>(|mut tx| {
+			let old = tx.get(store, tree_key)?.map(|v| decode(&v).unwrap());
+			match new {
+				Some(e) => tx.insert(store, tree_key, rmp_to_vec_all_named(e).unwrap())?,
+				None => tx.remove(store, tree_key)?,
+			};
+			indexes.update(&mut tx, tree_key, old.as_ref(), new)?;
+			tx.commit(())
+		})
+		.map_err(Error::from)
+		.unwrap();
+	}
+
+	fn lookup(
+		indexes: &TableIndexes<TestTable>,
+		index_key: &str,
+		begin: Option<&TestEntry>,
+	) -> Vec<Vec<u8>> {
+		let begin = begin.map(tree_key);
+		indexes
+			.read("tag", index_key.as_bytes(), begin.as_deref())
+			.unwrap()
+			.collect::<Result<Vec<_>, _>>()
+			.unwrap()
+	}
+
+	fn open() -> (db::Db, db::Tree, TableIndexes<TestTable>) {
+		let db = SledDb::init(sled::Config::default().temporary(true).open().unwrap());
+		let store = db.open_tree("test:table").unwrap();
+		let indexes = TableIndexes::<TestTable>::open(&db).unwrap();
+		indexes.build(&store, decode).unwrap();
+		(db, store, indexes)
+	}
+
+	#[test]
+	fn test_index_maintenance() {
+		let (db, store, indexes) = open();
+
+		let a = entry("p1", "a", &["red", "blue"]);
+		let b = entry("p2", "b", &["red"]);
+
+		// Insert
+		write(&db, &store, &indexes, &tree_key(&a), Some(&a));
+		write(&db, &store, &indexes, &tree_key(&b), Some(&b));
+		let mut red = vec![tree_key(&a), tree_key(&b)];
+		red.sort();
+		assert_eq!(lookup(&indexes, "red", None), red);
+		assert_eq!(lookup(&indexes, "blue", None), vec![tree_key(&a)]);
+		assert!(lookup(&indexes, "green", None).is_empty());
+
+		// Update
+		let a2 = entry("p1", "a", &["blue", "green"]);
+		write(&db, &store, &indexes, &tree_key(&a), Some(&a2));
+		assert_eq!(lookup(&indexes, "red", None), vec![tree_key(&b)]);
+		assert_eq!(lookup(&indexes, "blue", None), vec![tree_key(&a)]);
+		assert_eq!(lookup(&indexes, "green", None), vec![tree_key(&a)]);
+
+		// Tombstone
+		let mut b2 = b.clone();
+		b2.deleted = true;
+		write(&db, &store, &indexes, &tree_key(&b), Some(&b2));
+		assert!(lookup(&indexes, "red", None).is_empty());
+
+		// Deletion
+		write(&db, &store, &indexes, &tree_key(&a), None);
+		assert!(lookup(&indexes, "blue", None).is_empty());
+		assert!(lookup(&indexes, "green", None).is_empty());
+	}
+
+	#[test]
+	fn test_index_read_from() {
+		let (db, store, indexes) = open();
+
+		let mut entries = (0..5)
+			.map(|i| entry(&format!("p{}", i), "x", &["red"]))
+			.collect::<Vec<_>>();
+		for e in entries.iter() {
+			write(&db, &store, &indexes, &tree_key(e), Some(e));
+		}
+		entries.sort_by_key(tree_key);
+		let keys = entries.iter().map(tree_key).collect::<Vec<_>>();
+
+		assert_eq!(lookup(&indexes, "red", None), keys);
+		assert_eq!(
+			lookup(&indexes, "red", Some(&entries[2])),
+			keys[2..].to_vec()
+		);
+	}
+
+	#[test]
+	fn test_index_build() {
+		let db = SledDb::init(sled::Config::default().temporary(true).open().unwrap());
+		let store = db.open_tree("test:table").unwrap();
+
+		let a = entry("p1", "a", &["red"]);
+		let b = entry("p1", "b", &["blue"]);
+		store
+			.insert(tree_key(&a), rmp_to_vec_all_named(&a).unwrap())
+			.unwrap();
+
+		// Existing entries are indexed when the index is added
+		let indexes = TableIndexes::<TestTable>::open(&db).unwrap();
+		indexes.build(&store, decode).unwrap();
+		assert_eq!(lookup(&indexes, "red", None), vec![tree_key(&a)]);
+
+		// The index is not built again when the table is opened again
+		store
+			.insert(tree_key(&b), rmp_to_vec_all_named(&b).unwrap())
+			.unwrap();
+		let indexes = TableIndexes::<TestTable>::open(&db).unwrap();
+		indexes
+			.build(&store, |_| panic!("index built again"))
+			.unwrap();
+		assert!(lookup(&indexes, "blue", None).is_empty());
+
+		// An index that was removed from the schema is built again
+		indexes.built.insert("other", vec![]).unwrap();
+		indexes.built.remove("tag").unwrap();
+		indexes.build(&store, decode).unwrap();
+		assert_eq!(lookup(&indexes, "red", None), vec![tree_key(&a)]);
+		assert_eq!(lookup(&indexes, "blue", None), vec![tree_key(&b)]);
+		assert!(indexes.built.get("other").unwrap().is_none());
+	}
+
+	#[test]
+	fn test_merge_index_range() {
+		let e = (0..6)
+			.map(|i| entry("p", &format!("{}", i), &["red"]))
+			.collect::<Vec<_>>();
+		let keys =
+			|m: &BTreeMap<Vec<u8>, TestEntry>| m.values().map(|e| e.sk.clone()).collect::<Vec<_>>();
+
+		// No node returned `limit` entries: the range is complete
+		let (ret, end) = merge_index_range::<TestTable>(
+			vec![vec![e[0].clone(), e[2].clone()], vec![e[1].clone()]],
+			3,
+		);
+		assert_eq!(keys(&ret), vec!["0", "1", "2"]);
+		assert_eq!(end, None);
+
+		// Entries after the last entry of a node that returned `limit`
+		// entries are not returned, as that node might have more entries
+		// before them
+		let (ret, end) = merge_index_range::<TestTable>(
+			vec![
+				vec![e[0].clone(), e[2].clone()],
+				vec![e[1].clone(), e[3].clone(), e[5].clone()],
+			],
+			2,
+		);
+		assert_eq!(keys(&ret), vec!["0", "1", "2"]);
+		assert_eq!(end, Some(tree_key(&e[2])));
+
+		// Entries returned by several nodes are merged
+		let mut e1_blue = e[1].clone();
+		e1_blue.tags = vec!["blue".into()];
+		let (ret, _) = merge_index_range::<TestTable>(vec![vec![e[1].clone()], vec![e1_blue]], 3);
+		assert_eq!(ret.len(), 1);
+		assert_eq!(
+			ret.values().next().unwrap().tags,
+			vec!["red".to_string(), "blue".to_string()]
+		);
+	}
+}
@@ -8,14 +8,17 @@ mod metrics;
 pub mod schema;
 pub mod util;
 
+pub mod change_log;
 pub mod data;
 mod gc;
+mod index;
 mod merkle;
 pub mod replication;
 mod sync;
 pub mod table;
 
 pub use schema::*;
+pub use sync::{PartitionSyncStatus, SyncParams};
 pub use table::*;
 pub use util::*;
 
@@ -1,4 +1,5 @@
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use async_trait::async_trait;
@@ -10,6 +11,7 @@ use garage_db as db;
 use garage_util::background::*;
 use garage_util::data::*;
 use garage_util::error::Error;
+use garage_util::time::now_msec;
 
 use garage_rpc::ring::*;
 
@@ -38,6 +40,12 @@ pub struct MerkleUpdater<F: TableSchema, R: TableReplication> {
 	// Field in data:
 	//		pub(crate) merkle_tree: sled::Tree,
 	empty_node_hash: Hash,
+
+	// Time at which the Merkle tree of each partition last changed because
+	// of a write made on this node, since this node was started. Used by the
+	// syncer to prioritize partitions that changed recently. Changes caused
+	// by the sync itself are not counted.
+	partition_changes: Mutex<HashMap<Partition, u64>>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -75,6 +83,7 @@ where
 		let ret = Arc::new(Self {
 			data,
 			empty_node_hash,
+			partition_changes: Mutex::new(HashMap::new()),
 		});
 
 		background.spawn_worker(MerkleWorker(ret.clone()));
@@ -107,10 +116,18 @@ where
 				.partition_of(&Hash::try_from(&k[0..32]).unwrap()),
 			prefix: vec![],
 		};
-		self.data
+		let changed = self
+			.data
 			.merkle_tree
 			.db()
 			.transaction(|mut tx| self.update_item_rec(&mut tx, k, &khash, &key, new_vhash))?;
+		let from_sync = self.data.take_sync_update(k);
+		if changed.is_some() && !from_sync {
+			self.partition_changes
+				.lock()
+				.unwrap()
+				.insert(key.partition, now_msec());
+		}
 
 		let deleted = self.data.merkle_todo.db().transaction(|mut tx| {
 			let remove = matches!(tx.get(&self.data.merkle_todo, k)?, Some(ov) if ov == vhash_by);
@@ -296,6 +313,16 @@ where
 	pub fn todo_len(&self) -> Result<usize, Error> {
 		Ok(self.data.merkle_todo.len()?)
 	}
+
+	/// Time at which the Merkle tree of a partition last changed because of
+	/// a write made on this node, if it changed since this node was started
+	pub(crate) fn partition_last_change(&self, partition: Partition) -> Option<u64> {
+		self.partition_changes
+			.lock()
+			.unwrap()
+			.get(&partition)
+			.copied()
+	}
 }
 
 struct MerkleWorker<F, R>(Arc<MerkleUpdater<F, R>>)
@@ -1,56 +1,101 @@
-pub enum ReplicationMode {
-	None,
-	TwoWay,
-	TwoWayDangerous,
-	ThreeWay,
-	ThreeWayDegraded,
-	ThreeWayDangerous,
+use garage_rpc::ring::MAX_REPLICATION;
+
+/// Replication mode: the number of copies of each piece of data,
+/// and how many of them are required for reads and writes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReplicationMode {
+	replication_factor: usize,
+	consistency: ConsistencyMode,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ConsistencyMode {
+	/// Reads and writes are done on a majority of nodes,
+	/// so that reads always see the result of previous writes
+	Consistent,
+	/// Reads are done on a single node
+	Degraded,
+	/// Reads and writes are done on a single node
+	Dangerous,
 }
 
 impl ReplicationMode {
+	/// Parse a replication mode, given as a number of copies (e.g. `3`),
+	/// optionally followed by `-degraded` or `-dangerous`
 	pub fn parse(v: &str) -> Option<Self> {
-		match v {
-			"none" | "1" => Some(Self::None),
-			"2" => Some(Self::TwoWay),
-			"2-dangerous" => Some(Self::TwoWayDangerous),
-			"3" => Some(Self::ThreeWay),
-			"3-degraded" => Some(Self::ThreeWayDegraded),
-			"3-dangerous" => Some(Self::ThreeWayDangerous),
-			_ => None,
+		let (factor, consistency) = if let Some(f) = v.strip_suffix("-degraded") {
+			(f, ConsistencyMode::Degraded)
+		} else if let Some(f) = v.strip_suffix("-dangerous") {
+			(f, ConsistencyMode::Dangerous)
+		} else {
+			(v, ConsistencyMode::Consistent)
+		};
+		let replication_factor = match factor {
+			"none" if consistency == ConsistencyMode::Consistent => 1,
+			f => f.parse::<usize>().ok()?,
+		};
+		if !(1..=MAX_REPLICATION).contains(&replication_factor) {
+			return None;
 		}
+		Some(Self {
+			replication_factor,
+			consistency,
+		})
 	}
 
 	pub fn control_write_max_faults(&self) -> usize {
-		match self {
-			Self::None => 0,
+		match self.replication_factor {
+			1 => 0,
 			_ => 1,
 		}
 	}
 
 	pub fn replication_factor(&self) -> usize {
-		match self {
-			Self::None => 1,
-			Self::TwoWay | Self::TwoWayDangerous => 2,
-			Self::ThreeWay | Self::ThreeWayDegraded | Self::ThreeWayDangerous => 3,
-		}
+		self.replication_factor
 	}
 
 	pub fn read_quorum(&self) -> usize {
-		match self {
-			Self::None => 1,
-			Self::TwoWay | Self::TwoWayDangerous => 1,
-			Self::ThreeWay => 2,
-			Self::ThreeWayDegraded | Self::ThreeWayDangerous => 1,
+		match self.consistency {
+			ConsistencyMode::Consistent => self.replication_factor - self.write_quorum() + 1,
+			ConsistencyMode::Degraded | ConsistencyMode::Dangerous => 1,
 		}
 	}
 
 	pub fn write_quorum(&self) -> usize {
-		match self {
-			Self::None => 1,
-			Self::TwoWay => 2,
-			Self::TwoWayDangerous => 1,
-			Self::ThreeWay | Self::ThreeWayDegraded => 2,
-			Self::ThreeWayDangerous => 1,
+		match self.consistency {
+			ConsistencyMode::Consistent | ConsistencyMode::Degraded => {
+				self.replication_factor / 2 + 1
+			}
+			ConsistencyMode::Dangerous => 1,
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn quorums(v: &str) -> Option<(usize, usize, usize)> {
+		ReplicationMode::parse(v)
+			.map(|m| (m.replication_factor(), m.read_quorum(), m.write_quorum()))
+	}
+
+	#[test]
+	fn test_parse_replication_mode() {
+		assert_eq!(quorums("none"), Some((1, 1, 1)));
+		assert_eq!(quorums("1"), Some((1, 1, 1)));
+		assert_eq!(quorums("2"), Some((2, 1, 2)));
+		assert_eq!(quorums("2-dangerous"), Some((2, 1, 1)));
+		assert_eq!(quorums("3"), Some((3, 2, 2)));
+		assert_eq!(quorums("3-degraded"), Some((3, 1, 2)));
+		assert_eq!(quorums("3-dangerous"), Some((3, 1, 1)));
+		assert_eq!(quorums("4"), Some((4, 2, 3)));
+		assert_eq!(quorums("5"), Some((5, 3, 3)));
+		assert_eq!(quorums("6-degraded"), Some((6, 1, 4)));
+
+		assert_eq!(quorums("0"), None);
+		assert_eq!(quorums("7"), None);
+		assert_eq!(quorums("none-dangerous"), None);
+		assert_eq!(quorums("3-fast"), None);
+	}
+}
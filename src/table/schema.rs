@@ -97,5 +97,17 @@ pub trait TableSchema: Send + Sync + 'static {
 		Ok(())
 	}
 
+	/// Names of the secondary indexes of this table. For each of them,
+	/// entries can be looked up by the keys returned by `index_keys`
+	/// using `Table::get_index_range`.
+	const INDEXES: &'static [&'static str] = &[];
+
+	/// Keys under which an entry appears in one of the secondary indexes
+	/// declared in `INDEXES`. Tombstones are never indexed.
+	/// Default implementation returns no keys.
+	fn index_keys(_index: &str, _entry: &Self::E) -> Vec<Vec<u8>> {
+		vec![]
+	}
+
 	fn matches_filter(entry: &Self::E, filter: &Self::Filter) -> bool;
 }
@@ -1,8 +1,9 @@
-use std::collections::VecDeque;
-use std::sync::Arc;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
+use futures_util::future::join_all;
 use futures_util::stream::*;
 use opentelemetry::KeyValue;
 use rand::Rng;
@@ -12,8 +13,10 @@ use tokio::select;
 use tokio::sync::{mpsc, watch};
 
 use garage_util::background::*;
+use garage_util::config::TableSyncConfig;
 use garage_util::data::*;
 use garage_util::error::Error;
+use garage_util::time::now_msec;
 
 use garage_rpc::ring::*;
 use garage_rpc::system::System;
@@ -24,16 +27,79 @@ use crate::merkle::*;
 use crate::replication::*;
 use crate::*;
 
-// Do anti-entropy every 10 minutes
+// Do anti-entropy every 10 minutes by default
 const ANTI_ENTROPY_INTERVAL: Duration = Duration::from_secs(10 * 60);
 
+/// Parameters of the anti-entropy synchronization of a table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncParams {
+	/// Interval between two full syncs of all partitions
+	pub interval: Duration,
+	/// Interval between two syncs of the partitions that changed
+	/// since they were last synced, if enabled
+	pub incremental_interval: Option<Duration>,
+	/// Number of partitions that are synced at the same time
+	pub concurrency: usize,
+}
+
+impl Default for SyncParams {
+	fn default() -> Self {
+		Self {
+			interval: ANTI_ENTROPY_INTERVAL,
+			incremental_interval: None,
+			concurrency: 1,
+		}
+	}
+}
+
+impl SyncParams {
+	/// Sync parameters of a table, as given in the configuration file:
+	/// settings specific to the table take precedence over global settings.
+	/// An interval of 0 for full syncs is ignored, and disables incremental syncs.
+	pub fn from_config(config: &TableSyncConfig, table_name: &str) -> Self {
+		let table = config.tables.get(table_name);
+		let interval_secs = table.and_then(|t| t.interval_secs).or(config.interval_secs);
+		let incremental_interval_secs = table
+			.and_then(|t| t.incremental_interval_secs)
+			.or(config.incremental_interval_secs);
+		let concurrency = table.and_then(|t| t.concurrency).or(config.concurrency);
+
+		let default = Self::default();
+		Self {
+			interval: interval_secs
+				.filter(|s| *s > 0)
+				.map(Duration::from_secs)
+				.unwrap_or(default.interval),
+			incremental_interval: incremental_interval_secs
+				.filter(|s| *s > 0)
+				.map(Duration::from_secs),
+			concurrency: concurrency.unwrap_or(default.concurrency).max(1),
+		}
+	}
+}
+
+/// Synchronization status of a partition stored by this node
+#[derive(Debug, Clone)]
+pub struct PartitionSyncStatus {
+	pub partition: Partition,
+	/// Time at which the last successful sync of the partition started
+	pub last_sync: Option<u64>,
+	/// Time at which the Merkle tree of the partition last changed
+	pub last_change: Option<u64>,
+}
+
 pub struct TableSyncer<F: TableSchema + 'static, R: TableReplication + 'static> {
 	system: Arc<System>,
 	data: Arc<TableData<F, R>>,
 	merkle: Arc<MerkleUpdater<F, R>>,
 
 	add_full_sync_tx: mpsc::UnboundedSender<()>,
+	params_tx: watch::Sender<SyncParams>,
 	endpoint: Arc<Endpoint<SyncRpc, Self>>,
+
+	// Time at which the last successful sync of each partition started,
+	// since this node was started
+	last_sync: Mutex<HashMap<Partition, u64>>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -75,13 +141,16 @@ where
 			.endpoint(format!("garage_table/sync.rs/Rpc:{}", F::TABLE_NAME));
 
 		let (add_full_sync_tx, add_full_sync_rx) = mpsc::unbounded_channel();
+		let (params_tx, params_rx) = watch::channel(SyncParams::default());
 
 		let syncer = Arc::new(Self {
 			system: system.clone(),
 			data,
 			merkle,
 			add_full_sync_tx,
+			params_tx,
 			endpoint,
+			last_sync: Mutex::new(HashMap::new()),
 		});
 
 		syncer.endpoint.set_handler(syncer.clone());
@@ -91,8 +160,11 @@ where
 			ring_recv: system.ring.clone(),
 			ring: system.ring.borrow().clone(),
 			add_full_sync_rx,
+			params: *params_rx.borrow(),
+			params_rx,
 			todo: vec![],
 			next_full_sync: Instant::now() + Duration::from_secs(20),
+			next_incremental_sync: None,
 		});
 
 		syncer
@@ -104,6 +176,39 @@ where
 		}
 	}
 
+	/// Change the interval and concurrency of the anti-entropy of this table
+	pub fn set_params(&self, params: SyncParams) {
+		if self.params_tx.send(params).is_err() {
+			error!("({}) Could not set sync parameters", F::TABLE_NAME);
+		}
+	}
+
+	/// Synchronization status of the partitions stored by this node
+	pub fn sync_status(&self) -> Vec<PartitionSyncStatus> {
+		let my_id = self.system.id;
+		let last_sync = self.last_sync.lock().unwrap();
+		self.data
+			.replication
+			.partitions()
+			.into_iter()
+			.filter(|(_, begin)| self.data.replication.write_nodes(begin).contains(&my_id))
+			.map(|(partition, _)| PartitionSyncStatus {
+				partition,
+				last_sync: last_sync.get(&partition).copied(),
+				last_change: self.merkle.partition_last_change(partition),
+			})
+			.collect()
+	}
+
+	/// Time at which a partition changed, if it changed since it was last synced
+	fn partition_changed_since_sync(&self, partition: Partition) -> Option<u64> {
+		let last_change = self.merkle.partition_last_change(partition)?;
+		match self.last_sync.lock().unwrap().get(&partition) {
+			Some(last_sync) if *last_sync > last_change => None,
+			_ => Some(last_change),
+		}
+	}
+
 	// ----
 
 	async fn sync_partition(
@@ -113,6 +218,7 @@ where
 	) -> Result<(), Error> {
 		if partition.retain {
 			let my_id = self.system.id;
+			let start = now_msec();
 
 			let nodes = self
 				.data
@@ -149,6 +255,11 @@ where
 					nodes
 				)));
 			}
+
+			self.last_sync
+				.lock()
+				.unwrap()
+				.insert(partition.partition, start);
 		} else {
 			self.offload_partition(&partition.begin, &partition.end, must_exit)
 				.await?;
@@ -480,7 +591,7 @@ where
 					],
 				);
 
-				self.data.update_many(items)?;
+				self.data.update_many_from_sync(items)?;
 				Ok(SyncRpc::Ok)
 			}
 			m => Err(Error::unexpected_rpc_message(m)),
@@ -495,36 +606,51 @@ struct SyncWorker<F: TableSchema + 'static, R: TableReplication + 'static> {
 	ring_recv: watch::Receiver<Arc<Ring>>,
 	ring: Arc<Ring>,
 	add_full_sync_rx: mpsc::UnboundedReceiver<()>,
+	params_rx: watch::Receiver<SyncParams>,
+	params: SyncParams,
 	todo: Vec<TodoPartition>,
 	next_full_sync: Instant,
+	next_incremental_sync: Option<Instant>,
 }
 
 impl<F: TableSchema + 'static, R: TableReplication + 'static> SyncWorker<F, R> {
-	fn add_full_sync(&mut self) {
-		let system = &self.syncer.system;
+	fn all_partitions(&self) -> Vec<TodoPartition> {
 		let data = &self.syncer.data;
+		let my_id = self.syncer.system.id;
 
-		let my_id = system.id;
+		let partitions = data.replication.partitions();
 
-		self.todo.clear();
+		(0..partitions.len())
+			.map(|i| {
+				let begin = partitions[i].1;
 
-		let partitions = data.replication.partitions();
+				let end = if i + 1 < partitions.len() {
+					partitions[i + 1].1
+				} else {
+					[0xFFu8; 32].into()
+				};
 
-		for i in 0..partitions.len() {
-			let begin = partitions[i].1;
+				let nodes = data.replication.write_nodes(&begin);
 
-			let end = if i + 1 < partitions.len() {
-				partitions[i + 1].1
-			} else {
-				[0xFFu8; 32].into()
-			};
+				TodoPartition {
+					partition: partitions[i].0,
+					begin,
+					end,
+					retain: nodes.contains(&my_id),
+				}
+			})
+			.collect()
+	}
 
-			let nodes = data.replication.write_nodes(&begin);
+	fn add_full_sync(&mut self) {
+		let data = &self.syncer.data;
+
+		self.todo.clear();
 
-			let retain = nodes.contains(&my_id);
-			if !retain {
+		for todo in self.all_partitions() {
+			if !todo.retain {
 				// Check if we have some data to send, otherwise skip
-				match data.store.range(begin..end) {
+				match data.store.range(todo.begin..todo.end) {
 					Ok(mut iter) => {
 						if iter.next().is_none() {
 							continue;
@@ -537,15 +663,35 @@ impl<F: TableSchema + 'static, R: TableReplication + 'static> SyncWorker<F, R> {
 				}
 			}
 
-			self.todo.push(TodoPartition {
-				partition: partitions[i].0,
-				begin,
-				end,
-				retain,
-			});
+			self.todo.push(todo);
 		}
 
-		self.next_full_sync = Instant::now() + ANTI_ENTROPY_INTERVAL;
+		self.next_full_sync = Instant::now() + self.params.interval;
+		self.reset_incremental_sync();
+	}
+
+	// Add to the todo list the partitions we store that changed
+	// since they were last synced
+	fn add_incremental_sync(&mut self) {
+		for todo in self.all_partitions() {
+			let changed = self.syncer.partition_changed_since_sync(todo.partition);
+			let queued = self.todo.iter().any(|t| t.partition == todo.partition);
+			if todo.retain && changed.is_some() && !queued {
+				self.todo.push(todo);
+			}
+		}
+
+		self.reset_incremental_sync();
+	}
+
+	fn reset_incremental_sync(&mut self) {
+		self.next_incremental_sync = self.params.incremental_interval.map(|i| Instant::now() + i);
+	}
+
+	fn set_params(&mut self, params: SyncParams) {
+		self.params = params;
+		self.next_full_sync = std::cmp::min(self.next_full_sync, Instant::now() + params.interval);
+		self.reset_incremental_sync();
 	}
 
 	fn pop_task(&mut self) -> Option<TodoPartition> {
@@ -553,7 +699,23 @@ impl<F: TableSchema + 'static, R: TableReplication + 'static> SyncWorker<F, R> {
 			return None;
 		}
 
-		let i = rand::thread_rng().gen_range(0..self.todo.len());
+		// Sync first the partition that changed most recently,
+		// or a random one if none of them changed since their last sync
+		let most_recent_change = self
+			.todo
+			.iter()
+			.enumerate()
+			.filter(|(_, t)| t.retain)
+			.filter_map(|(i, t)| {
+				self.syncer
+					.partition_changed_since_sync(t.partition)
+					.map(|c| (c, i))
+			})
+			.max();
+		let i = match most_recent_change {
+			Some((_, i)) => i,
+			None => rand::thread_rng().gen_range(0..self.todo.len()),
+		};
 		if i == self.todo.len() - 1 {
 			self.todo.pop()
 		} else {
@@ -580,12 +742,27 @@ impl<F: TableSchema + 'static, R: TableReplication + 'static> Worker for SyncWor
 	}
 
 	async fn work(&mut self, must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
-		if let Some(partition) = self.pop_task() {
-			self.syncer.sync_partition(&partition, must_exit).await?;
-			Ok(WorkerState::Busy)
-		} else {
-			Ok(WorkerState::Idle)
+		let mut partitions = vec![];
+		while partitions.len() < self.params.concurrency {
+			match self.pop_task() {
+				Some(partition) => partitions.push(partition),
+				None => break,
+			}
+		}
+		if partitions.is_empty() {
+			return Ok(WorkerState::Idle);
+		}
+
+		let results = join_all(partitions.into_iter().map(|partition| {
+			let syncer = self.syncer.clone();
+			let mut must_exit = must_exit.clone();
+			async move { syncer.sync_partition(&partition, &mut must_exit).await }
+		}))
+		.await;
+		for res in results {
+			res?;
 		}
+		Ok(WorkerState::Busy)
 	}
 
 	async fn wait_for_work(&mut self, must_exit: &watch::Receiver<bool>) -> WorkerState {
@@ -607,8 +784,18 @@ impl<F: TableSchema + 'static, R: TableReplication + 'static> Worker for SyncWor
 					self.add_full_sync();
 				}
 			},
+			_ = self.params_rx.changed() => {
+				let params = *self.params_rx.borrow();
+				if params != self.params {
+					debug!("({}) Sync parameters changed: {:?}", F::TABLE_NAME, params);
+					self.set_params(params);
+				}
+			},
 			_ = tokio::time::sleep_until(self.next_full_sync.into()) => {
 				self.add_full_sync();
+			},
+			_ = sleep_until_opt(self.next_incremental_sync) => {
+				self.add_incremental_sync();
 			}
 		}
 		match self.todo.is_empty() {
@@ -620,6 +807,13 @@ impl<F: TableSchema + 'static, R: TableReplication + 'static> Worker for SyncWor
 
 // ---- UTIL ----
 
+async fn sleep_until_opt(deadline: Option<Instant>) {
+	match deadline {
+		Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+		None => futures::future::pending().await,
+	}
+}
+
 fn hash_of<T: Serialize>(x: &T) -> Result<Hash, Error> {
 	Ok(blake2sum(&rmp_to_vec_all_named(x)?[..]))
 }
@@ -648,3 +842,64 @@ fn join_ordered<'a, K: Ord + Eq, V1, V2>(
 	}
 	ret
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use garage_util::config::TableSyncTableConfig;
+
+	#[test]
+	fn test_sync_params_from_config() {
+		let mut config = TableSyncConfig {
+			interval_secs: Some(3600),
+			incremental_interval_secs: Some(60),
+			concurrency: None,
+			tables: Default::default(),
+		};
+		config.tables.insert(
+			"object".into(),
+			TableSyncTableConfig {
+				interval_secs: None,
+				incremental_interval_secs: Some(0),
+				concurrency: Some(4),
+			},
+		);
+		config.tables.insert(
+			"version".into(),
+			TableSyncTableConfig {
+				interval_secs: Some(0),
+				incremental_interval_secs: None,
+				concurrency: None,
+			},
+		);
+
+		assert_eq!(
+			SyncParams::from_config(&TableSyncConfig::default(), "object"),
+			SyncParams::default()
+		);
+		assert_eq!(
+			SyncParams::from_config(&config, "block_ref"),
+			SyncParams {
+				interval: Duration::from_secs(3600),
+				incremental_interval: Some(Duration::from_secs(60)),
+				concurrency: 1,
+			}
+		);
+		assert_eq!(
+			SyncParams::from_config(&config, "version"),
+			SyncParams {
+				interval: ANTI_ENTROPY_INTERVAL,
+				incremental_interval: Some(Duration::from_secs(60)),
+				concurrency: 1,
+			}
+		);
+		assert_eq!(
+			SyncParams::from_config(&config, "object"),
+			SyncParams {
+				interval: Duration::from_secs(3600),
+				incremental_interval: None,
+				concurrency: 4,
+			}
+		);
+	}
+}
@@ -24,6 +24,7 @@ use garage_rpc::*;
 use crate::crdt::Crdt;
 use crate::data::*;
 use crate::gc::*;
+use crate::index::*;
 use crate::merkle::*;
 use crate::replication::*;
 use crate::schema::*;
@@ -54,6 +55,16 @@ pub(crate) enum TableRpc<F: TableSchema> {
 		enumeration_order: EnumerationOrder,
 	},
 
+	// Read index range: read all entries with a given key in a secondary index,
+	// possibly starting at a certain (partition key, sort key) offset
+	ReadIndexRange {
+		index: String,
+		index_key: ByteBuf,
+		begin: Option<(F::P, F::S)>,
+		filter: Option<F::Filter>,
+		limit: usize,
+	},
+
 	Update(Vec<Arc<ByteBuf>>),
 }
 
@@ -390,6 +401,164 @@ where
 		Ok(ret_vec)
 	}
 
+	/// Get the entries that have a given key in one of the secondary indexes
+	/// of the table, ordered by the hash of their partition key and by their sort key.
+	/// As the entries of an index key can be in any partition, all nodes storing
+	/// the table are queried. Unlike get_range, this does not repair entries
+	/// that differ between nodes, and an entry whose index keys changed
+	/// might still be returned for its previous index keys until all nodes
+	/// are in sync.
+	pub async fn get_index_range(
+		self: &Arc<Self>,
+		index: &str,
+		index_key: &[u8],
+		begin: Option<(F::P, F::S)>,
+		filter: Option<F::Filter>,
+		limit: usize,
+	) -> Result<Vec<F::E>, Error> {
+		let tracer = opentelemetry::global::tracer("garage_table");
+		let span = tracer.start(format!("{} get_index_range", F::TABLE_NAME));
+
+		let res = self
+			.get_index_range_internal(index, index_key, begin, filter, limit)
+			.bound_record_duration(&self.data.metrics.get_request_duration)
+			.with_context(Context::current_with_span(span))
+			.await?;
+
+		self.data.metrics.get_request_counter.add(1);
+
+		Ok(res)
+	}
+
+	async fn get_index_range_internal(
+		self: &Arc<Self>,
+		index: &str,
+		index_key: &[u8],
+		begin: Option<(F::P, F::S)>,
+		filter: Option<F::Filter>,
+		limit: usize,
+	) -> Result<Vec<F::E>, Error> {
+		if !F::INDEXES.contains(&index) {
+			return Err(Error::Message(format!(
+				"Table {} has no index {}",
+				F::TABLE_NAME,
+				index
+			)));
+		}
+
+		let partition_nodes = self
+			.data
+			.replication
+			.partitions()
+			.into_iter()
+			.map(|(_, begin)| self.data.replication.read_nodes(&begin))
+			.collect::<Vec<_>>();
+		let mut who = partition_nodes.concat();
+		who.sort();
+		who.dedup();
+
+		// Each node returns at most `page_limit` matching entries. As the
+		// entries of a node that are after its last returned entry might be
+		// missing from the merged response, and some entries might not be
+		// indexed under this key anymore, the range is read in pages until
+		// `limit` entries are found or there are no more entries
+		let page_limit = std::cmp::max(limit, 2);
+		let mut ret = vec![];
+		let mut begin = begin;
+		let mut after: Option<Vec<u8>> = None;
+		loop {
+			let rpc = TableRpc::<F>::ReadIndexRange {
+				index: index.to_string(),
+				index_key: ByteBuf::from(index_key.to_vec()),
+				begin: begin.take(),
+				filter: filter.clone(),
+				limit: page_limit,
+			};
+			let (entries, end) = self
+				.read_index_range_page(&who, &partition_nodes, rpc, page_limit)
+				.await?;
+
+			let next_begin = end
+				.as_ref()
+				.and_then(|k| entries.get(k))
+				.map(|e| (e.partition_key().clone(), e.sort_key().clone()));
+
+			ret.extend(
+				entries
+					.into_iter()
+					.filter(|(k, _)| after.as_ref().map(|a| k > a).unwrap_or(true))
+					.map(|(_, e)| e)
+					.filter(|e| {
+						entry_index_keys::<F>(index, e)
+							.iter()
+							.any(|k| k.as_slice() == index_key)
+					}),
+			);
+
+			match (end, next_begin) {
+				(Some(end), Some(next_begin)) if ret.len() < limit => {
+					begin = Some(next_begin);
+					after = Some(end);
+				}
+				_ => break,
+			}
+		}
+
+		ret.truncate(limit);
+		Ok(ret)
+	}
+
+	async fn read_index_range_page(
+		&self,
+		who: &[Uuid],
+		partition_nodes: &[Vec<Uuid>],
+		rpc: TableRpc<F>,
+		page_limit: usize,
+	) -> Result<(BTreeMap<Vec<u8>, F::E>, Option<Vec<u8>>), Error> {
+		let resps = self
+			.system
+			.rpc
+			.call_many(
+				&self.endpoint,
+				who,
+				rpc,
+				RequestStrategy::with_priority(PRIO_NORMAL),
+			)
+			.await?;
+
+		let mut responses = vec![];
+		let mut successes = vec![];
+		let mut errors = vec![];
+		for (node, resp) in resps {
+			match resp {
+				Ok(TableRpc::Update(entries)) => {
+					successes.push(node);
+					let entries = entries
+						.iter()
+						.map(|bytes| self.data.decode_entry(bytes.as_slice()))
+						.collect::<Result<Vec<_>, _>>()?;
+					responses.push(entries);
+				}
+				Ok(resp) => return Err(Error::unexpected_rpc_message(resp)),
+				Err(e) => errors.push(format!("{}", e)),
+			}
+		}
+
+		// We need a read quorum in every partition, as matching entries
+		// could be in any of them
+		let quorum = self.data.replication.read_quorum();
+		let min_successes = partition_nodes
+			.iter()
+			.map(|nodes| nodes.iter().filter(|n| successes.contains(n)).count())
+			.min()
+			.unwrap_or(0);
+		if min_successes < quorum {
+			return Err(Error::Quorum(quorum, min_successes, who.len(), errors));
+		}
+
+		Ok(merge_index_range::<F>(responses, page_limit))
+	}
+
 	// =============== UTILITY FUNCTION FOR CLIENT OPERATIONS ===============
 
 	async fn repair_on_read(&self, who: &[Uuid], what: F::E) -> Result<(), Error> {
@@ -439,6 +608,18 @@ where
 				)?;
 				Ok(TableRpc::Update(values))
 			}
+			TableRpc::ReadIndexRange {
+				index,
+				index_key,
+				begin,
+				filter,
+				limit,
+			} => {
+				let values = self
+					.data
+					.read_index_range(index, index_key, begin, filter, *limit)?;
+				Ok(TableRpc::Update(values))
+			}
 			TableRpc::Update(pairs) => {
 				self.data.update_many(pairs)?;
 				Ok(TableRpc::Ok)
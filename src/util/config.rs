@@ -1,4 +1,5 @@
 //! Contains type and functions related to Garage configuration file
+use std::collections::HashMap;
 use std::io::Read;
 use std::net::SocketAddr;
 use std::path::PathBuf;
@@ -12,8 +13,14 @@ use crate::error::Error;
 pub struct Config {
 	/// Path where to store metadata. Should be fast, but low volume
 	pub metadata_dir: PathBuf,
-	/// Path where to store data. Can be slower, but need higher volume
-	pub data_dir: PathBuf,
+	/// Path where to store data. Can be slower, but need higher volume.
+	/// Can also be a list of directories, each with its own capacity,
+	/// in which case data blocks are spread among them.
+	pub data_dir: DataDirEnum,
+	/// Path where to move data blocks that have not been read for the
+	/// number of days configured on their bucket. Can be slower than data_dir.
+	#[serde(default)]
+	pub cold_data_dir: Option<PathBuf>,
 
 	/// Size of data blocks to save to disk
 	#[serde(default = "default_block_size")]
@@ -21,12 +28,47 @@ pub struct Config {
 
 	/// Replication mode. Supported values:
 	/// - none, 1 -> no replication
-	/// - 2 -> 2-way replication
-	/// - 3 -> 3-way replication
-	// (we can add more aliases for this later)
+	/// - N (up to 6) -> N-way replication, with majority quorums
+	/// - N-degraded -> N-way replication, reads on a single node
+	/// - N-dangerous -> N-way replication, reads and writes on a single node
 	pub replication_mode: String,
 
-	/// Zstd compression level used on data blocks
+	/// Maximum number of copies of data blocks that buckets can require,
+	/// if it is higher than the replication factor of replication_mode
+	#[serde(default)]
+	pub max_replication_factor: Option<usize>,
+
+	/// Erasure coding of data blocks. If set, data blocks are split in shards
+	/// stored on different nodes instead of being replicated as specified
+	/// by replication_mode, which then only applies to metadata
+	#[serde(default)]
+	pub erasure_coding: Option<ErasureCodingConfig>,
+
+	/// Minimum free space on the filesystems of metadata_dir and data_dir,
+	/// either as a size (e.g. "10G") or as a percentage (e.g. "5%").
+	/// Below it, the node refuses to store new data blocks
+	#[serde(default)]
+	pub min_free_space: Option<String>,
+
+	/// Size of the in-memory cache of blocks read through this node (e.g. "256M"),
+	/// or None to disable it
+	#[serde(default)]
+	pub block_cache_size: Option<String>,
+
+	/// Read and write data blocks with direct IO (O_DIRECT), bypassing the page cache
+	#[serde(default)]
+	pub block_direct_io: bool,
+	/// Preallocate the space of data block files with fallocate before writing them
+	#[serde(default)]
+	pub block_preallocate: bool,
+	/// Drop data block files from the page cache once they have been written
+	#[serde(default)]
+	pub block_fadvise_dontneed: bool,
+
+	/// Codec used to compress data blocks
+	#[serde(default)]
+	pub compression_codec: CompressionCodecConfig,
+	/// Zstd compression level used on data blocks, or None to disable compression
 	#[serde(
 		deserialize_with = "deserialize_compression",
 		default = "default_compression"
@@ -70,6 +112,23 @@ pub struct Config {
 	#[serde(default = "default_sled_flush_every_ms")]
 	pub sled_flush_every_ms: u64,
 
+	/// Directory in which snapshots of the metadata database are written,
+	/// by default the snapshots directory in metadata_dir
+	#[serde(default)]
+	pub metadata_snapshots_dir: Option<PathBuf>,
+	/// Number of snapshots of the metadata database that are kept,
+	/// older ones are deleted when a new snapshot is taken (0 to keep all of them)
+	#[serde(default = "default_metadata_snapshots_keep")]
+	pub metadata_snapshots_keep: usize,
+
+	/// Configuration of the anti-entropy synchronization of metadata tables
+	#[serde(default)]
+	pub table_sync: TableSyncConfig,
+
+	/// Configuration of the log of the changes made to metadata tables on this node
+	#[serde(default)]
+	pub change_log: ChangeLogConfig,
+
 	// -- APIs
 	/// Configuration for S3 api
 	pub s3_api: S3ApiConfig,
@@ -85,6 +144,87 @@ pub struct Config {
 	pub admin: AdminConfig,
 }
 
+/// Value of the `data_dir` parameter: either a single path,
+/// or a list of directories with their capacity
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum DataDirEnum {
+	Single(PathBuf),
+	Multiple(Vec<DataDir>),
+}
+
+/// A data directory, when several of them are used on the same node
+#[derive(Deserialize, Debug, Clone)]
+pub struct DataDir {
+	/// Path to the data directory
+	pub path: PathBuf,
+	/// Capacity of the drive (required if read_only is false), e.g. "2T"
+	#[serde(default)]
+	pub capacity: Option<String>,
+	/// Whether this directory should be used only for reading existing blocks,
+	/// new blocks being written to the other directories
+	#[serde(default)]
+	pub read_only: bool,
+}
+
+/// Erasure coding parameters for data blocks
+#[derive(Deserialize, Debug, Clone)]
+pub struct ErasureCodingConfig {
+	/// Number of shards containing the data of a block
+	pub data_shards: usize,
+	/// Number of parity shards computed for each block
+	pub parity_shards: usize,
+}
+
+/// Codec used to compress data blocks
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionCodecConfig {
+	Zstd,
+	Lz4,
+}
+
+impl Default for CompressionCodecConfig {
+	fn default() -> Self {
+		CompressionCodecConfig::Zstd
+	}
+}
+
+/// Configuration of the anti-entropy synchronization of metadata tables,
+/// with optional overrides for individual tables
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct TableSyncConfig {
+	/// Interval between two full synchronizations of all partitions, in seconds
+	pub interval_secs: Option<u64>,
+	/// Interval between two synchronizations of the partitions that
+	/// changed since they were last synchronized, in seconds
+	pub incremental_interval_secs: Option<u64>,
+	/// Number of partitions that are synchronized at the same time
+	pub concurrency: Option<usize>,
+	/// Settings for individual tables, indexed by table name
+	#[serde(default)]
+	pub tables: HashMap<String, TableSyncTableConfig>,
+}
+
+/// Anti-entropy settings for a single table,
+/// overriding the global settings of `TableSyncConfig`
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct TableSyncTableConfig {
+	pub interval_secs: Option<u64>,
+	pub incremental_interval_secs: Option<u64>,
+	pub concurrency: Option<usize>,
+}
+
+/// Configuration of the log of the changes made to metadata tables on this node
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ChangeLogConfig {
+	/// Names of the tables whose changes are recorded in the log
+	#[serde(default)]
+	pub tables: Vec<String>,
+	/// Duration for which changes are kept in the log, in seconds (default: 7 days)
+	pub retention_secs: Option<u64>,
+}
+
 /// Configuration for S3 api
 #[derive(Deserialize, Debug, Clone)]
 pub struct S3ApiConfig {
@@ -164,6 +304,9 @@ fn default_sled_cache_capacity() -> u64 {
 fn default_sled_flush_every_ms() -> u64 {
 	2000
 }
+fn default_metadata_snapshots_keep() -> usize {
+	3
+}
 fn default_block_size() -> usize {
 	1048576
 }
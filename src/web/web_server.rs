@@ -203,8 +203,10 @@ impl WebServer {
 
 		let ret_doc = match *req.method() {
 			Method::OPTIONS => handle_options_for_bucket(req, &bucket),
-			Method::HEAD => handle_head(self.garage.clone(), req, bucket_id, &key, None).await,
-			Method::GET => handle_get(self.garage.clone(), req, bucket_id, &key, None).await,
+			Method::HEAD => {
+				handle_head(self.garage.clone(), req, bucket_id, &key, None, None).await
+			}
+			Method::GET => handle_get(self.garage.clone(), req, &bucket, &key, None, None).await,
 			_ => Err(ApiError::bad_request("HTTP method not supported")),
 		}
 		.map_err(Error::from);
@@ -235,7 +237,15 @@ impl WebServer {
 					.body(Body::empty())
 					.unwrap();
 
-				match handle_get(self.garage.clone(), &req2, bucket_id, &error_document, None).await
+				match handle_get(
+					self.garage.clone(),
+					&req2,
+					&bucket,
+					&error_document,
+					None,
+					None,
+				)
+				.await
 				{
 					Ok(mut error_doc) => {
 						// The error won't be logged back in handle_request,